@@ -1,13 +1,14 @@
 use std::time::{Duration, SystemTime};
 
 use bytes::Bytes;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use futures_executor::block_on;
 use http::Request;
 use opentelemetry::{
     trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState},
     Array, InstrumentationScope, KeyValue, Value,
 };
-use opentelemetry_datadog::{new_pipeline, ApiVersion};
+use opentelemetry_datadog::{new_pipeline, ApiVersion, DatadogExporter};
 use opentelemetry_http::HttpClient;
 use opentelemetry_sdk::{
     trace::{SpanData, SpanExporter},
@@ -195,13 +196,17 @@ fn generate_traces(number_of_traces: usize, spans_per_trace: usize) -> Vec<SpanD
     result
 }
 
-fn criterion_benchmark(c: &mut Criterion) {
-    let exporter = new_pipeline()
+fn build_exporter(api_version: ApiVersion) -> DatadogExporter {
+    new_pipeline()
         .with_service_name("trace-demo")
-        .with_api_version(ApiVersion::Version05)
+        .with_api_version(api_version)
         .with_http_client(DummyClient)
         .build_exporter()
-        .unwrap();
+        .unwrap()
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let exporter = build_exporter(ApiVersion::Version05);
 
     let patterns: [(usize, usize); 5] = [(128, 4), (256, 4), (512, 4), (512, 2), (512, 1)];
 
@@ -211,10 +216,46 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         c.bench_function(
             format!("export {number_of_traces} traces with {spans_per_trace} spans").as_str(),
-            |b| b.iter(|| exporter.export(black_box(data_ref.clone()))),
+            |b| {
+                b.iter_batched(
+                    || data_ref.clone(),
+                    |batch| block_on(exporter.export(black_box(batch))),
+                    BatchSize::LargeInput,
+                )
+            },
         );
     }
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// Exports with the buffers of the previous exports, against exports with a new exporter whose
+/// buffers grow from empty, which the buffers reuse saves.
+fn buffers_reuse_benchmark(c: &mut Criterion) {
+    let data = generate_traces(512, 4);
+    for api_version in [ApiVersion::Version03, ApiVersion::Version05] {
+        let mut group = c.benchmark_group(format!("export 512 traces with {api_version:?}"));
+        let exporter = build_exporter(api_version);
+        block_on(exporter.export(data.clone())).unwrap();
+        group.bench_function("reused buffers", |b| {
+            b.iter_batched(
+                || data.clone(),
+                |batch| block_on(exporter.export(black_box(batch))),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function("new exporter", |b| {
+            b.iter_batched(
+                || (build_exporter(api_version), data.clone()),
+                // the exporter is returned so that it's dropped outside of the measure
+                |(exporter, batch)| {
+                    let result = block_on(exporter.export(black_box(batch)));
+                    (exporter, result)
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, criterion_benchmark, buffers_reuse_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,100 @@
+use bytes::Bytes;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Number of buffers kept by the pool, enough for the concurrent exports of a batch processor.
+const MAX_POOLED_BUFFERS: usize = 4;
+
+/// Buffers larger than this are dropped rather than pooled, so that an unusually large batch
+/// doesn't keep its memory for the lifetime of the exporter.
+const MAX_POOLED_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// Encoding storage reused from one export to the next.
+///
+/// The encoders take their output buffers from the pool, and the exporter gives the payloads
+/// back once the agent answered, so that the steady state of the exporter encodes without
+/// growing new allocations. The pool also remembers the size of the last v0.5 dictionary, to
+/// allocate the string interner at the right size up front.
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    dictionary_len: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Takes an empty buffer from the pool, or a new one when the pool is empty.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.buffers
+            .lock()
+            .ok()
+            .and_then(|mut buffers| buffers.pop())
+            .unwrap_or_default()
+    }
+
+    /// Gives a buffer back to the pool.
+    pub(crate) fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buffer.clear();
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < MAX_POOLED_BUFFERS {
+                buffers.push(buffer);
+            }
+        }
+    }
+
+    /// Gives the buffer of a sent payload back to the pool, unless the http client still holds
+    /// a reference to it.
+    pub(crate) fn recycle(&self, payload: Bytes) {
+        if payload.is_unique() {
+            self.put(Vec::from(payload));
+        }
+    }
+
+    /// Number of strings in the last v0.5 dictionary.
+    pub(crate) fn dictionary_len(&self) -> usize {
+        self.dictionary_len.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_dictionary_len(&self, len: usize) {
+        self.dictionary_len.store(len, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse_buffers() {
+        let pool = BufferPool::default();
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(b"payload");
+        let ptr = buffer.as_ptr();
+        pool.put(buffer);
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr(), ptr);
+
+        // the payload is only recycled once the client dropped it
+        let payload = Bytes::from(buffer);
+        let client = payload.clone();
+        pool.recycle(payload);
+        assert_eq!(pool.buffers.lock().unwrap().len(), 0);
+        pool.recycle(client);
+        let recycled = pool.take();
+        assert_eq!(recycled.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_bounded_pool() {
+        let pool = BufferPool::default();
+        for _ in 0..MAX_POOLED_BUFFERS + 2 {
+            pool.put(Vec::with_capacity(8));
+        }
+        pool.put(Vec::with_capacity(MAX_POOLED_CAPACITY + 1));
+        assert_eq!(pool.buffers.lock().unwrap().len(), MAX_POOLED_BUFFERS);
+    }
+}
@@ -0,0 +1,273 @@
+//! Detection of the container running the process, sent to the agent to tag the traces.
+//!
+//! Same detection as the other Datadog tracers:
+//! <https://github.com/DataDog/dd-trace-go/blob/v1.62.0/internal/container_linux.go>
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::sync::OnceLock;
+
+const CGROUP_PATH: &str = "/proc/self/cgroup";
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+const CGROUP_MOUNT_PATH: &str = "/sys/fs/cgroup";
+const CGROUP_NAMESPACE_PATH: &str = "/proc/self/ns/cgroup";
+
+/// Inode of the initial cgroup namespace, the one of the host
+/// <https://github.com/torvalds/linux/blob/v6.8/include/linux/proc_ns.h#L45>
+const HOST_CGROUP_NAMESPACE_INODE: u64 = 0xEFFF_FFFB;
+
+/// Files bind-mounted by the container runtime in the directory of the container
+const CONTAINER_MOUNTED_FILES: [&str; 3] = ["hostname", "hosts", "resolv.conf"];
+
+/// Identity of the container of the process, detected once.
+#[derive(Debug, Default)]
+pub(crate) struct ContainerInfo {
+    /// The container id, from the cgroups or the mounts of the process
+    pub(crate) container_id: Option<String>,
+    /// `ci-<container id>`, or `in-<cgroup inode>` when the container id can't be found
+    pub(crate) entity_id: Option<String>,
+}
+
+/// The container of the process, `None` fields outside of containers or Linux.
+pub(crate) fn container_info() -> &'static ContainerInfo {
+    static CONTAINER_INFO: OnceLock<ContainerInfo> = OnceLock::new();
+    CONTAINER_INFO.get_or_init(detect)
+}
+
+#[cfg(target_os = "linux")]
+fn detect() -> ContainerInfo {
+    let cgroup = std::fs::read_to_string(CGROUP_PATH).unwrap_or_default();
+    let container_id = container_id_from_cgroup(&cgroup)
+        .map(str::to_string)
+        .or_else(|| {
+            let mountinfo = std::fs::read_to_string(MOUNTINFO_PATH).unwrap_or_default();
+            container_id_from_mountinfo(&mountinfo).map(str::to_string)
+        });
+    let entity_id = match &container_id {
+        Some(container_id) => Some(format!("ci-{}", container_id)),
+        None => cgroup_inode(&cgroup).map(|inode| format!("in-{}", inode)),
+    };
+    ContainerInfo {
+        container_id,
+        entity_id,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn detect() -> ContainerInfo {
+    ContainerInfo::default()
+}
+
+/// Inode of the cgroup node of the process, from the unified hierarchy of cgroup v2 or the
+/// `memory` controller of cgroup v1.
+///
+/// `None` in the cgroup namespace of the host, where the node doesn't identify a container.
+#[cfg(target_os = "linux")]
+fn cgroup_inode(cgroup: &str) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    if is_host_cgroup_namespace() {
+        return None;
+    }
+    let node = cgroup_node(cgroup)?;
+    std::fs::metadata(node).ok().map(|metadata| metadata.ino())
+}
+
+#[cfg(target_os = "linux")]
+fn is_host_cgroup_namespace() -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(CGROUP_NAMESPACE_PATH)
+        .is_ok_and(|metadata| metadata.ino() == HOST_CGROUP_NAMESPACE_INODE)
+}
+
+/// Path of the cgroup node of the process.
+///
+/// In a private cgroup namespace, the node of the container is the root `/` of the hierarchy
+/// mounted at `/sys/fs/cgroup`.
+fn cgroup_node(cgroup: &str) -> Option<String> {
+    let mut v1_memory = None;
+    for line in cgroup.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (Some(id), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let path = path.trim_end_matches('/');
+        if id == "0" && controllers.is_empty() {
+            return Some(format!("{}{}", CGROUP_MOUNT_PATH, path));
+        }
+        if controllers
+            .split(',')
+            .any(|controller| controller == "memory")
+        {
+            v1_memory = Some(format!("{}/memory{}", CGROUP_MOUNT_PATH, path));
+        }
+    }
+    v1_memory
+}
+
+/// Finds the container id at the end of the cgroup path of a process, such as
+/// `/docker/<id>` or `/kubepods.slice/.../cri-containerd-<id>.scope`.
+fn container_id_from_cgroup(cgroup: &str) -> Option<&str> {
+    cgroup.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let id = fields.next()?;
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let path = fields.nth(1)?;
+        container_id_suffix(path.strip_suffix(".scope").unwrap_or(path))
+    })
+}
+
+/// Finds the container id in the mounts of the runtime files, such as
+/// `/var/lib/docker/containers/<id>/hostname`, when cgroup v2 hides it from the cgroup path.
+fn container_id_from_mountinfo(mountinfo: &str) -> Option<&str> {
+    mountinfo
+        .lines()
+        .flat_map(|line| line.split_whitespace())
+        .find_map(|field| {
+            let segments: Vec<&str> = field.split('/').collect();
+            segments.windows(3).find_map(|window| {
+                let [parent, id, file] = window else {
+                    return None;
+                };
+                // the sandboxes hold the pause containers of the Kubernetes pods
+                let is_container = *parent != "sandboxes"
+                    && CONTAINER_MOUNTED_FILES.contains(file)
+                    && container_id_suffix(id).is_some_and(|suffix| suffix.len() == id.len());
+                is_container.then_some(*id)
+            })
+        })
+}
+
+/// The container id ending the given string: an UUID, 64 hex digits, or an ECS task id made of
+/// 32 hex digits and a number.
+fn container_id_suffix(path: &str) -> Option<&str> {
+    let is_hex = |s: &str| s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+
+    if let Some(id) = path.get(path.len().saturating_sub(64)..) {
+        if id.len() == 64 && is_hex(id) {
+            return Some(id);
+        }
+    }
+    if let Some(id) = path.get(path.len().saturating_sub(36)..) {
+        let groups: Vec<&str> = id.split(['-', '_']).collect();
+        let is_uuid = id.len() == 36
+            && groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+            && groups.iter().all(|group| is_hex(group));
+        if is_uuid {
+            return Some(id);
+        }
+    }
+    let (task, number) = path.rsplit_once('-')?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let task = task.get(task.len().checked_sub(32)?..)?;
+    is_hex(task).then(|| &path[path.len() - number.len() - 33..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTAINER_ID: &str = "3726184226f5d3147c25fdeab5b60097e378e8a720503a5e19ecfdf29f869860";
+
+    #[test]
+    fn test_container_id_from_cgroup() {
+        let cases = [
+            // docker, cgroup v1
+            (
+                format!(
+                    "12:memory:/docker/{id}\n11:cpu,cpuacct:/docker/{id}\n1:name=systemd:/docker/{id}",
+                    id = CONTAINER_ID
+                ),
+                Some(CONTAINER_ID),
+            ),
+            // kubernetes with containerd and the systemd driver
+            (
+                format!(
+                    "0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod2d3da189_6407_48e3_9ab6_78188d75e609.slice/cri-containerd-{}.scope",
+                    CONTAINER_ID
+                ),
+                Some(CONTAINER_ID),
+            ),
+            // not a container id
+            (
+                "1:name=systemd:/system.slice/garden.service/garden/6f265890-5165-7fab-6b52-18d1".to_string(),
+                None,
+            ),
+            (
+                "1:name=systemd:/ecs/34dc0b5e626f2c5c4c5170e34b10e765-1234567890".to_string(),
+                Some("34dc0b5e626f2c5c4c5170e34b10e765-1234567890"),
+            ),
+            (
+                "1:name=systemd:/uuid/34dc0b5e-626f-2c5c-4c51-70e34b10e765".to_string(),
+                Some("34dc0b5e-626f-2c5c-4c51-70e34b10e765"),
+            ),
+            // cgroup v2 namespaces hide the path
+            ("0::/".to_string(), None),
+            ("".to_string(), None),
+        ];
+        for (cgroup, expected) in cases {
+            assert_eq!(container_id_from_cgroup(&cgroup), expected, "{}", cgroup);
+        }
+    }
+
+    #[test]
+    fn test_container_id_from_mountinfo() {
+        let mountinfo = format!(
+            "608 554 0:184 / / rw,relatime master:289 - overlay overlay rw\n\
+             655 608 254:1 /var/lib/docker/containers/{id}/resolv.conf /etc/resolv.conf rw,relatime - ext4 /dev/vda1 rw\n\
+             656 608 254:1 /var/lib/docker/containers/{id}/hostname /etc/hostname rw,relatime - ext4 /dev/vda1 rw",
+            id = CONTAINER_ID
+        );
+        assert_eq!(container_id_from_mountinfo(&mountinfo), Some(CONTAINER_ID));
+
+        let sandbox = format!(
+            "1 0 0:1 /var/lib/containerd/io.containerd.grpc.v1.cri/sandboxes/{}/hostname /etc/hostname rw - ext4 /dev/vda1 rw",
+            CONTAINER_ID
+        );
+        assert_eq!(container_id_from_mountinfo(&sandbox), None);
+        assert_eq!(
+            container_id_from_mountinfo("608 554 0:184 / / rw - overlay overlay rw"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cgroup_node() {
+        assert_eq!(
+            cgroup_node("0::/kubepods/pod1/container1").as_deref(),
+            Some("/sys/fs/cgroup/kubepods/pod1/container1")
+        );
+        assert_eq!(
+            cgroup_node("4:memory:/kubepods/pod1\n1:name=systemd:/kubepods/pod1").as_deref(),
+            Some("/sys/fs/cgroup/memory/kubepods/pod1")
+        );
+        // private cgroup namespaces
+        assert_eq!(cgroup_node("0::/").as_deref(), Some("/sys/fs/cgroup"));
+        assert_eq!(
+            cgroup_node("4:memory:/\n1:name=systemd:/").as_deref(),
+            Some("/sys/fs/cgroup/memory")
+        );
+        assert_eq!(cgroup_node(""), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_cgroup_inode_of_namespace_root() {
+        use std::os::unix::fs::MetadataExt;
+
+        let expected = if is_host_cgroup_namespace() {
+            None
+        } else {
+            std::fs::metadata(CGROUP_MOUNT_PATH)
+                .ok()
+                .map(|metadata| metadata.ino())
+        };
+        assert_eq!(cgroup_inode("0::/"), expected);
+    }
+}
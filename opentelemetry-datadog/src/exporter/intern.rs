@@ -163,12 +163,21 @@ pub(crate) struct StringInterner<'a> {
 }
 
 impl<'a> StringInterner<'a> {
+    #[cfg(test)]
     pub(crate) fn new() -> StringInterner<'a> {
+        Self::with_capacity(128)
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> StringInterner<'a> {
         StringInterner {
-            data: IndexSet::with_capacity_and_hasher(128, BuildHasherDefault::default()),
+            data: IndexSet::with_capacity_and_hasher(capacity, BuildHasherDefault::default()),
         }
     }
 
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
     pub(crate) fn intern(&mut self, data: &'a str) -> u32 {
         if let Some(idx) = self.data.get_index_of(&InternValue::RegularString(data)) {
             return idx as u32;
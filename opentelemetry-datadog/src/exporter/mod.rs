@@ -1,31 +1,66 @@
+pub(crate) mod buffers;
+mod container;
 mod intern;
-mod model;
+pub(crate) mod model;
+mod processor;
+mod stats;
+mod telemetry;
+#[cfg(all(unix, feature = "uds-client"))]
+mod uds;
 
 pub use model::ApiVersion;
 pub use model::Error;
-pub use model::FieldMappingFn;
+pub use model::{
+    semconv_name_mapping, semconv_resource_mapping, semconv_service_name_mapping, FieldMappingFn,
+};
+pub use processor::DatadogSpanProcessor;
+#[cfg(all(unix, feature = "uds-client"))]
+pub use uds::UdsClient;
 
+use crate::exporter::buffers::BufferPool;
+use crate::exporter::model::obfuscation::Obfuscation;
 use crate::exporter::model::FieldMapping;
-use http::{Method, Request, Uri};
-use opentelemetry::{Key, KeyValue};
-use opentelemetry_http::{HttpClient, ResponseExt};
+use crate::exporter::processor::partial_flush_from_env;
+use crate::exporter::stats::{StatsBucket, StatsConcentrator};
+use crate::exporter::telemetry::{DropReason, Endpoint, ExporterMetrics};
+#[cfg(feature = "agent-sampling")]
+use crate::sampling::{
+    rules_sampler_from_env, DatadogAgentSampler, DatadogRulesSampler, SamplingRule,
+};
+use futures_timer::Delay;
+use http::{Method, Request, StatusCode, Uri};
+use opentelemetry::metrics::Meter;
+use opentelemetry::{otel_warn, Key, KeyValue};
+use opentelemetry_http::{Bytes, HttpClient, ResponseExt};
+#[cfg(feature = "agent-sampling")]
+use opentelemetry_sdk::trace::TracerProviderBuilder;
 use opentelemetry_sdk::{
     error::{OTelSdkError, OTelSdkResult},
     resource::{ResourceDetector, SdkProvidedResourceDetector},
-    trace::{Config, SdkTracerProvider, TraceError},
+    trace::{BatchSpanProcessor, Config, SdkTracerProvider, SimpleSpanProcessor, TraceError},
     trace::{SpanData, SpanExporter},
     Resource,
 };
 use opentelemetry_semantic_conventions as semcov;
 use std::borrow::Cow;
 use std::fmt::{Debug, Formatter};
-use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use url::Url;
 
 use self::model::unified_tags::UnifiedTags;
 
 /// Default Datadog collector endpoint
 const DEFAULT_AGENT_ENDPOINT: &str = "http://127.0.0.1:8126";
+const DEFAULT_AGENT_HOST: &str = "127.0.0.1";
+const DEFAULT_AGENT_PORT: &str = "8126";
+
+/// Environment variables configuring the Datadog collector endpoint, shared by all Datadog tracers
+pub(crate) const DD_TRACE_AGENT_URL_ENV_VAR: &str = "DD_TRACE_AGENT_URL";
+const DD_AGENT_HOST_ENV_VAR: &str = "DD_AGENT_HOST";
+const DD_TRACE_AGENT_PORT_ENV_VAR: &str = "DD_TRACE_AGENT_PORT";
 
 /// Header name used to inform the Datadog agent of the number of traces in the payload
 const DATADOG_TRACE_COUNT_HEADER: &str = "X-Datadog-Trace-Count";
@@ -34,11 +69,43 @@ const DATADOG_TRACE_COUNT_HEADER: &str = "X-Datadog-Trace-Count";
 const DATADOG_META_LANG_HEADER: &str = "Datadog-Meta-Lang";
 const DATADOG_META_TRACER_VERSION_HEADER: &str = "Datadog-Meta-Tracer-Version";
 
+/// Header names used to inform the Datadog agent of the container of the process, to add the
+/// container and Kubernetes tags to the traces
+const DATADOG_CONTAINER_ID_HEADER: &str = "Datadog-Container-ID";
+const DATADOG_ENTITY_ID_HEADER: &str = "Datadog-Entity-ID";
+
+/// Header names used to inform the Datadog agent that the trace metrics are computed by the
+/// exporter, and how many unsampled traces and spans were dropped
+const DATADOG_CLIENT_COMPUTED_STATS_HEADER: &str = "Datadog-Client-Computed-Stats";
+const DATADOG_CLIENT_DROPPED_P0_TRACES_HEADER: &str = "Datadog-Client-Dropped-P0-Traces";
+const DATADOG_CLIENT_DROPPED_P0_SPANS_HEADER: &str = "Datadog-Client-Dropped-P0-Spans";
+
+/// Header name used to inform the Datadog agent that the `_top_level` metric is set by the
+/// exporter, so that the agent doesn't compute it again
+const DATADOG_CLIENT_COMPUTED_TOP_LEVEL_HEADER: &str = "Datadog-Client-Computed-Top-Level";
+
+/// Path of the Datadog agent endpoint receiving the trace metrics
+const DATADOG_STATS_PATH: &str = "/v0.6/stats";
+
+/// Default maximum size of a trace payload, well below the 25 MiB accepted by the agent
+const DEFAULT_MAX_PAYLOAD_SIZE: usize = 10 * 1024 * 1024;
+
+/// Default number of times a failed trace payload is sent again
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled on every retry up to `MAX_RETRY_BACKOFF`
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
 // Struct to hold the mapping between Opentelemetry spans and datadog spans.
 pub struct Mapping {
     resource: Option<FieldMapping>,
     name: Option<FieldMapping>,
     service_name: Option<FieldMapping>,
+    /// Infer the span type from the semantic conventions
+    semconv_span_type: bool,
+    obfuscation: Obfuscation,
+    span_kind_measuring: bool,
 }
 
 impl Mapping {
@@ -51,11 +118,26 @@ impl Mapping {
             resource,
             name,
             service_name,
+            semconv_span_type: false,
+            obfuscation: Obfuscation::default(),
+            span_kind_measuring: true,
         }
     }
     pub fn empty() -> Self {
         Self::new(None, None, None)
     }
+
+    /// Map the fields without custom mapping from the semantic conventions.
+    pub(crate) fn with_semconv(mut self) -> Self {
+        self.name
+            .get_or_insert_with(|| Arc::new(model::semconv_name_mapping));
+        self.resource
+            .get_or_insert_with(|| Arc::new(model::semconv_resource_mapping));
+        self.service_name
+            .get_or_insert_with(|| Arc::new(model::semconv_service_name_mapping));
+        self.semconv_span_type = true;
+        self
+    }
 }
 
 /// Datadog span exporter
@@ -67,6 +149,116 @@ pub struct DatadogExporter {
     mapping: Mapping,
     unified_tags: UnifiedTags,
     resource: Option<Resource>,
+    #[cfg(feature = "agent-sampling")]
+    agent_sampler: Option<DatadogAgentSampler>,
+    stats: Option<Arc<ClientStats>>,
+    max_payload_size: usize,
+    max_retries: u32,
+    buffers: BufferPool,
+    metrics: Option<ExporterMetrics>,
+}
+
+/// Trace metrics computed by the exporter, see [`DatadogPipelineBuilder::with_stats_computation`].
+///
+/// The complete buckets are sent by a background thread every bucket duration, and the
+/// remaining ones on shutdown.
+struct ClientStats {
+    concentrator: StatsConcentrator,
+    request_url: Uri,
+    client: Arc<dyn HttpClient>,
+    model_config: ModelConfig,
+    unified_tags: UnifiedTags,
+    metrics: Option<ExporterMetrics>,
+    /// Unsampled traces not yet reported in the headers of a trace payload
+    dropped: Mutex<DroppedTraces>,
+    /// Stops the flush thread when dropped
+    stop: Mutex<Option<mpsc::Sender<()>>>,
+}
+
+impl ClientStats {
+    /// Starts the thread sending the complete stats buckets every bucket duration.
+    fn start(mut self) -> Result<Arc<Self>, TraceError> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        self.stop = Mutex::new(Some(stop));
+        let stats = Arc::new(self);
+        let weak = Arc::downgrade(&stats);
+        thread::Builder::new()
+            .name("opentelemetry-datadog-stats".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(stats::DEFAULT_BUCKET_DURATION)
+                {
+                    let Some(stats) = weak.upgrade() else {
+                        break;
+                    };
+                    if let Err(err) = futures_executor::block_on(stats.send(false)) {
+                        otel_warn!(name: "DatadogExporter.SendStatsFailed", error = format!("{}", err));
+                    }
+                }
+            })
+            .map_err(|e| TraceError::Other(Box::new(e)))?;
+        Ok(stats)
+    }
+
+    /// Stops the flush thread.
+    fn stop(&self) {
+        if let Ok(mut stop) = self.stop.lock() {
+            stop.take();
+        }
+    }
+
+    fn add_dropped(&self, traces: usize, spans: usize) {
+        if let Ok(mut dropped) = self.dropped.lock() {
+            dropped.traces += traces;
+            dropped.spans += spans;
+        }
+    }
+
+    /// Takes the unsampled traces to report with the next trace payload.
+    fn take_dropped(&self) -> DroppedTraces {
+        self.dropped
+            .lock()
+            .map(|mut dropped| std::mem::take(&mut *dropped))
+            .unwrap_or_default()
+    }
+
+    fn build_request(&self, buckets: &[StatsBucket]) -> Result<http::Request<Bytes>, OTelSdkError> {
+        let data = self
+            .concentrator
+            .encode(buckets, &self.model_config, &self.unified_tags)
+            .map_err(|e| OTelSdkError::InternalFailure(format!("{:?}", e)))?;
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.request_url.clone())
+            .header(http::header::CONTENT_TYPE, "application/msgpack")
+            .header(DATADOG_META_LANG_HEADER, "rust")
+            .header(
+                DATADOG_META_TRACER_VERSION_HEADER,
+                env!("CARGO_PKG_VERSION"),
+            );
+        with_container_headers(req)
+            .body(data.into())
+            .map_err(|e| OTelSdkError::InternalFailure(format!("{:?}", e)))
+    }
+
+    /// Sends the complete stats buckets to the agent, or all of them when `force` is set.
+    async fn send(&self, force: bool) -> OTelSdkResult {
+        let buckets = self.concentrator.flush(SystemTime::now(), force);
+        if buckets.is_empty() {
+            return Ok(());
+        }
+        let request = self.build_request(&buckets)?;
+        let start = Instant::now();
+        let response = self.client.send_bytes(request).await;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_response(Endpoint::Stats, &response, start.elapsed());
+        }
+        response
+            .map_err(|e| OTelSdkError::InternalFailure(format!("HTTP request failed: {}", e)))?
+            .error_for_status()
+            .map(|_| ())
+            .map_err(|e| OTelSdkError::InternalFailure(format!("HTTP response error: {}", e)))
+    }
 }
 
 impl DatadogExporter {
@@ -86,16 +278,28 @@ impl DatadogExporter {
             mapping,
             unified_tags,
             resource: None,
+            #[cfg(feature = "agent-sampling")]
+            agent_sampler: None,
+            stats: None,
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            buffers: BufferPool::default(),
+            metrics: None,
         }
     }
 
-    fn build_request(
-        &self,
-        mut batch: Vec<SpanData>,
-    ) -> Result<http::Request<Vec<u8>>, OTelSdkError> {
-        let traces: Vec<&[SpanData]> = group_into_traces(&mut batch);
-        let trace_count = traces.len();
-        let data = self
+    /// The sampler fed with the sampling rates returned by the Datadog agent, if agent sampling
+    /// was enabled with [`DatadogPipelineBuilder::with_agent_sampling`].
+    ///
+    /// Use it as the tracer provider sampler when building the pipeline manually.
+    #[cfg(feature = "agent-sampling")]
+    pub fn agent_sampler(&self) -> Option<DatadogAgentSampler> {
+        self.agent_sampler.clone()
+    }
+
+    fn encode(&self, traces: &[&[SpanData]]) -> Result<Bytes, OTelSdkError> {
+        let start = Instant::now();
+        let encoded = self
             .api_version
             .encode(
                 &self.model_config,
@@ -103,9 +307,30 @@ impl DatadogExporter {
                 &self.mapping,
                 &self.unified_tags,
                 self.resource.as_ref(),
+                &self.buffers,
             )
-            .map_err(|e| OTelSdkError::InternalFailure(format!("{:?}", e)))?;
-        let req = Request::builder()
+            .map(Bytes::from)
+            .map_err(|e| OTelSdkError::InternalFailure(format!("{:?}", e)));
+        if let Some(metrics) = &self.metrics {
+            metrics.record_encode(start.elapsed());
+        }
+        encoded
+    }
+
+    /// Records dropped traces in the exporter health metrics.
+    fn record_dropped(&self, reason: DropReason, traces: usize, spans: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_dropped(reason, traces, spans);
+        }
+    }
+
+    fn build_request(
+        &self,
+        trace_count: usize,
+        data: Bytes,
+        dropped: &DroppedTraces,
+    ) -> Result<http::Request<Bytes>, OTelSdkError> {
+        let mut req = Request::builder()
             .method(Method::POST)
             .uri(self.request_url.clone())
             .header(http::header::CONTENT_TYPE, self.api_version.content_type())
@@ -115,10 +340,92 @@ impl DatadogExporter {
                 DATADOG_META_TRACER_VERSION_HEADER,
                 env!("CARGO_PKG_VERSION"),
             )
+            .header(DATADOG_CLIENT_COMPUTED_TOP_LEVEL_HEADER, "yes");
+        req = with_container_headers(req);
+        if self.stats.is_some() {
+            req = req
+                .header(DATADOG_CLIENT_COMPUTED_STATS_HEADER, "yes")
+                .header(DATADOG_CLIENT_DROPPED_P0_TRACES_HEADER, dropped.traces)
+                .header(DATADOG_CLIENT_DROPPED_P0_SPANS_HEADER, dropped.spans);
+        }
+        let req = req
             .body(data)
             .map_err(|e| OTelSdkError::InternalFailure(format!("{:?}", e)));
         Ok(req)?
     }
+
+    /// Sends a trace payload, retrying with an exponential backoff on connection errors, `429`
+    /// and `5xx` responses.
+    ///
+    /// The backoff uses a timer that works with any executor, as the exporter doesn't depend on
+    /// an async runtime.
+    async fn send_with_retry(
+        &self,
+        trace_count: usize,
+        data: &Bytes,
+        dropped: &DroppedTraces,
+    ) -> Result<http::Response<Bytes>, OTelSdkError> {
+        let mut backoff = INITIAL_RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let request = self.build_request(trace_count, data.clone(), dropped)?;
+            let start = Instant::now();
+            let result = self.client.send_bytes(request).await;
+            if let Some(metrics) = &self.metrics {
+                metrics.record_response(Endpoint::Traces, &result, start.elapsed());
+            }
+            let retryable = match &result {
+                Ok(response) => {
+                    response.status() == StatusCode::TOO_MANY_REQUESTS
+                        || response.status().is_server_error()
+                }
+                Err(_) => true,
+            };
+            if !retryable || attempt >= self.max_retries {
+                return result.map_err(|e| {
+                    OTelSdkError::InternalFailure(format!("HTTP request failed: {}", e))
+                });
+            }
+            attempt += 1;
+            Delay::new(backoff).await;
+            backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+        }
+    }
+}
+
+/// Adds the headers identifying the container of the process, when running in a container.
+pub(crate) fn with_container_headers(mut req: http::request::Builder) -> http::request::Builder {
+    let container = container::container_info();
+    if let Some(container_id) = &container.container_id {
+        req = req.header(DATADOG_CONTAINER_ID_HEADER, container_id);
+    }
+    if let Some(entity_id) = &container.entity_id {
+        req = req.header(DATADOG_ENTITY_ID_HEADER, entity_id);
+    }
+    req
+}
+
+/// Number of traces and spans dropped by the exporter.
+#[derive(Debug, Default)]
+struct DroppedTraces {
+    traces: usize,
+    spans: usize,
+}
+
+#[cfg(feature = "agent-sampling")]
+fn is_unsampled(trace: &[SpanData]) -> bool {
+    use crate::propagator::DatadogTraceState;
+
+    trace
+        .first()
+        .is_some_and(|span| !span.span_context.trace_state().priority_sampling_enabled())
+}
+
+#[cfg(not(feature = "agent-sampling"))]
+fn is_unsampled(trace: &[SpanData]) -> bool {
+    trace
+        .first()
+        .is_some_and(|span| !span.span_context.is_sampled())
 }
 
 impl Debug for DatadogExporter {
@@ -134,6 +441,10 @@ impl Debug for DatadogExporter {
                 "service_name_mapping",
                 &mapping_debug(&self.mapping.service_name),
             )
+            .field("semconv_span_type", &self.mapping.semconv_span_type)
+            .field("obfuscation", &self.mapping.obfuscation)
+            .field("span_kind_measuring", &self.mapping.span_kind_measuring)
+            .field("metrics", &self.metrics.is_some())
             .finish()
     }
 }
@@ -151,40 +462,104 @@ pub struct DatadogPipelineBuilder {
     client: Option<Arc<dyn HttpClient>>,
     mapping: Mapping,
     unified_tags: UnifiedTags,
+    #[cfg(feature = "agent-sampling")]
+    agent_sampling: bool,
+    #[cfg(feature = "agent-sampling")]
+    sampling_rules: Option<Vec<SamplingRule>>,
+    stats_computation: bool,
+    max_payload_size: usize,
+    max_retries: u32,
+    partial_flush: Option<usize>,
+    meter: Option<Meter>,
 }
 
 impl Default for DatadogPipelineBuilder {
     fn default() -> Self {
         DatadogPipelineBuilder {
-            agent_endpoint: DEFAULT_AGENT_ENDPOINT.to_string(),
+            agent_endpoint: agent_endpoint_from_env(),
             trace_config: None,
             mapping: Mapping::empty(),
             api_version: ApiVersion::Version05,
             unified_tags: UnifiedTags::new(),
-            #[cfg(all(
-                not(feature = "reqwest-client"),
-                not(feature = "reqwest-blocking-client"),
-                not(feature = "surf-client"),
-            ))]
+            #[cfg(feature = "agent-sampling")]
+            agent_sampling: false,
+            #[cfg(feature = "agent-sampling")]
+            sampling_rules: None,
+            stats_computation: false,
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            partial_flush: partial_flush_from_env(),
+            meter: None,
             client: None,
-            #[cfg(all(
-                not(feature = "reqwest-client"),
-                not(feature = "reqwest-blocking-client"),
-                feature = "surf-client"
-            ))]
-            client: Some(Arc::new(surf::Client::new())),
-            #[cfg(all(
-                not(feature = "surf-client"),
-                not(feature = "reqwest-blocking-client"),
-                feature = "reqwest-client"
-            ))]
-            client: Some(Arc::new(reqwest::Client::new())),
-            #[cfg(feature = "reqwest-blocking-client")]
-            client: Some(Arc::new(reqwest::blocking::Client::new())),
         }
     }
 }
 
+pub(crate) fn non_empty_env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Collector endpoint configured by `DD_TRACE_AGENT_URL`, or by `DD_AGENT_HOST` and
+/// `DD_TRACE_AGENT_PORT`.
+pub(crate) fn agent_endpoint_from_env() -> String {
+    if let Some(url) = non_empty_env_var(DD_TRACE_AGENT_URL_ENV_VAR) {
+        return url;
+    }
+    let host = non_empty_env_var(DD_AGENT_HOST_ENV_VAR);
+    let port = non_empty_env_var(DD_TRACE_AGENT_PORT_ENV_VAR);
+    if host.is_none() && port.is_none() {
+        return DEFAULT_AGENT_ENDPOINT.to_string();
+    }
+    let host = host.unwrap_or_else(|| DEFAULT_AGENT_HOST.to_string());
+    let port = port.unwrap_or_else(|| DEFAULT_AGENT_PORT.to_string());
+    if host.contains(':') && !host.starts_with('[') {
+        // IPv6 address
+        format!("http://[{}]:{}", host, port)
+    } else {
+        format!("http://{}:{}", host, port)
+    }
+}
+
+/// Selects the agent endpoint the requests are built from, and the client sending them.
+///
+/// `unix://` endpoints are served by the `UdsClient` unless a client was given.
+pub(crate) fn agent_transport(
+    agent_endpoint: &str,
+    client: Option<Arc<dyn HttpClient>>,
+) -> (&str, Option<Arc<dyn HttpClient>>) {
+    #[cfg(all(unix, feature = "uds-client"))]
+    if let Some(socket_path) = uds::socket_path(agent_endpoint) {
+        let client = client.unwrap_or_else(|| Arc::new(UdsClient::new(socket_path)));
+        return (uds::UDS_BASE_ENDPOINT, Some(client));
+    }
+    (agent_endpoint, client.or_else(default_http_client))
+}
+
+/// Http client used when none is given to [`DatadogPipelineBuilder::with_http_client`].
+pub(crate) fn default_http_client() -> Option<Arc<dyn HttpClient>> {
+    #[cfg(all(
+        not(feature = "reqwest-client"),
+        not(feature = "reqwest-blocking-client"),
+        not(feature = "surf-client"),
+    ))]
+    let client = None;
+    #[cfg(all(
+        not(feature = "reqwest-client"),
+        not(feature = "reqwest-blocking-client"),
+        feature = "surf-client"
+    ))]
+    let client: Option<Arc<dyn HttpClient>> = Some(Arc::new(surf::Client::new()));
+    #[cfg(all(
+        not(feature = "surf-client"),
+        not(feature = "reqwest-blocking-client"),
+        feature = "reqwest-client"
+    ))]
+    let client: Option<Arc<dyn HttpClient>> = Some(Arc::new(reqwest::Client::new()));
+    #[cfg(feature = "reqwest-blocking-client")]
+    let client: Option<Arc<dyn HttpClient>> = Some(Arc::new(reqwest::blocking::Client::new()));
+    client
+}
+
 impl Debug for DatadogPipelineBuilder {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("DatadogExporter")
@@ -197,6 +572,11 @@ impl Debug for DatadogPipelineBuilder {
                 "service_name_mapping",
                 &mapping_debug(&self.mapping.service_name),
             )
+            .field("semconv_span_type", &self.mapping.semconv_span_type)
+            .field("obfuscation", &self.mapping.obfuscation)
+            .field("span_kind_measuring", &self.mapping.span_kind_measuring)
+            .field("partial_flush", &self.partial_flush)
+            .field("meter", &self.meter)
             .finish()
     }
 }
@@ -263,21 +643,53 @@ impl DatadogPipelineBuilder {
         Ok(endpoint.as_str().parse().map_err::<Error, _>(Into::into)?)
     }
 
+    fn build_transport(&self) -> (Cow<'_, str>, Option<Arc<dyn HttpClient>>) {
+        let (agent_endpoint, client) = agent_transport(&self.agent_endpoint, self.client.clone());
+        (Cow::Borrowed(agent_endpoint), client)
+    }
+
     fn build_exporter_with_service_name(
         self,
         service_name: String,
     ) -> Result<DatadogExporter, TraceError> {
-        if let Some(client) = self.client {
+        let (agent_endpoint, client) = self.build_transport();
+        let agent_endpoint = agent_endpoint.into_owned();
+        if let Some(client) = client {
+            #[cfg(feature = "agent-sampling")]
+            let agent_sampler = self.agent_sampling.then(|| {
+                DatadogAgentSampler::new(&service_name, self.unified_tags.env.value.as_deref())
+            });
+
             let model_config = ModelConfig { service_name };
 
-            let exporter = DatadogExporter::new(
+            let mut exporter = DatadogExporter::new(
                 model_config,
-                Self::build_endpoint(&self.agent_endpoint, self.api_version.path())?,
+                Self::build_endpoint(&agent_endpoint, self.api_version.path())?,
                 self.api_version,
                 client,
                 self.mapping,
                 self.unified_tags,
             );
+            #[cfg(feature = "agent-sampling")]
+            {
+                exporter.agent_sampler = agent_sampler;
+            }
+            exporter.max_payload_size = self.max_payload_size;
+            exporter.max_retries = self.max_retries;
+            exporter.metrics = self.meter.as_ref().map(ExporterMetrics::new);
+            if self.stats_computation {
+                let stats = ClientStats {
+                    concentrator: StatsConcentrator::new(stats::DEFAULT_BUCKET_DURATION),
+                    request_url: Self::build_endpoint(&agent_endpoint, DATADOG_STATS_PATH)?,
+                    client: exporter.client.clone(),
+                    model_config: exporter.model_config.clone(),
+                    unified_tags: exporter.unified_tags.clone(),
+                    metrics: exporter.metrics.clone(),
+                    dropped: Mutex::default(),
+                    stop: Mutex::default(),
+                };
+                exporter.stats = Some(stats.start()?);
+            }
             Ok(exporter)
         } else {
             Err(Error::NoHttpClient.into())
@@ -287,37 +699,72 @@ impl DatadogPipelineBuilder {
     /// Install the Datadog trace exporter pipeline using a simple span processor.
     pub fn install_simple(mut self) -> Result<SdkTracerProvider, TraceError> {
         let (config, service_name) = self.build_config_and_service_name();
+        #[cfg(feature = "agent-sampling")]
+        let sampling_rules = self.sampling_rules.take();
+        let partial_flush = self.partial_flush;
         let exporter = self.build_exporter_with_service_name(service_name)?;
-        Ok(SdkTracerProvider::builder()
-            .with_simple_exporter(exporter)
-            .with_resource(config.resource.into_owned())
-            .build())
+        let builder = SdkTracerProvider::builder();
+        #[cfg(feature = "agent-sampling")]
+        let builder = with_datadog_sampler(builder, &exporter, sampling_rules);
+        let builder = match partial_flush {
+            Some(min_spans) => {
+                let service_mapping = exporter.mapping.service_mapping(&exporter.model_config);
+                builder.with_span_processor(
+                    DatadogSpanProcessor::new(SimpleSpanProcessor::new(exporter))
+                        .with_partial_flush_min_spans(min_spans)
+                        .with_service_mapping(service_mapping),
+                )
+            }
+            None => builder.with_simple_exporter(exporter),
+        };
+        Ok(builder.with_resource(config.resource.into_owned()).build())
     }
 
     /// Install the Datadog trace exporter pipeline using a batch span processor with the specified
     /// runtime.
     pub fn install_batch(mut self) -> Result<SdkTracerProvider, TraceError> {
         let (config, service_name) = self.build_config_and_service_name();
+        #[cfg(feature = "agent-sampling")]
+        let sampling_rules = self.sampling_rules.take();
+        let partial_flush = self.partial_flush;
         let exporter = self.build_exporter_with_service_name(service_name)?;
-        Ok(SdkTracerProvider::builder()
-            .with_batch_exporter(exporter)
-            .with_resource(config.resource.into_owned())
-            .build())
+        let builder = SdkTracerProvider::builder();
+        #[cfg(feature = "agent-sampling")]
+        let builder = with_datadog_sampler(builder, &exporter, sampling_rules);
+        let builder = match partial_flush {
+            Some(min_spans) => {
+                let service_mapping = exporter.mapping.service_mapping(&exporter.model_config);
+                builder.with_span_processor(
+                    DatadogSpanProcessor::new(BatchSpanProcessor::builder(exporter).build())
+                        .with_partial_flush_min_spans(min_spans)
+                        .with_service_mapping(service_mapping),
+                )
+            }
+            None => builder.with_batch_exporter(exporter),
+        };
+        Ok(builder.with_resource(config.resource.into_owned()).build())
     }
 
-    /// Assign the service name under which to group traces
+    /// Assign the service name under which to group traces.
+    ///
+    /// Defaults to the `DD_SERVICE` environment variable, or to the `service.name` resource
+    /// detected by the SDK.
     pub fn with_service_name<T: Into<String>>(mut self, service_name: T) -> Self {
         self.unified_tags.set_service(Some(service_name.into()));
         self
     }
 
-    /// Assign the version under which to group traces
+    /// Assign the version under which to group traces.
+    ///
+    /// Defaults to the `DD_VERSION` environment variable.
     pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
         self.unified_tags.set_version(Some(version.into()));
         self
     }
 
-    /// Assign the env under which to group traces
+    /// Assign the env under which to group traces.
+    ///
+    /// Defaults to the `DD_ENV` environment variable.
     pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
         self.unified_tags.set_env(Some(env.into()));
         self
@@ -326,6 +773,14 @@ impl DatadogPipelineBuilder {
     /// Assign the Datadog collector endpoint.
     ///
     /// The endpoint of the datadog agent, by default it is `http://127.0.0.1:8126`.
+    ///
+    /// The default can be changed with the `DD_TRACE_AGENT_URL` environment variable, or with
+    /// the `DD_AGENT_HOST` and `DD_TRACE_AGENT_PORT` environment variables.
+    ///
+    /// With the `uds-client` feature, `unix://` endpoints such as
+    /// `unix:///var/run/datadog/apm.socket` are reached through the Unix domain socket at the
+    /// endpoint path, using the `UdsClient` unless another client is set with
+    /// [`with_http_client`](Self::with_http_client).
     pub fn with_agent_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
         self.agent_endpoint = endpoint.into();
         self
@@ -343,6 +798,68 @@ impl DatadogPipelineBuilder {
         self
     }
 
+    /// Sample traces with the rates returned by the Datadog agent.
+    ///
+    /// The exporter reads the `rate_by_service` field of the agent responses and feeds it to a
+    /// [`DatadogAgentSampler`], which is installed as the tracer provider sampler by
+    /// [`install_simple`] and [`install_batch`].
+    ///
+    /// [`install_simple`]: DatadogPipelineBuilder::install_simple
+    /// [`install_batch`]: DatadogPipelineBuilder::install_batch
+    #[cfg(feature = "agent-sampling")]
+    pub fn with_agent_sampling(mut self) -> Self {
+        self.agent_sampling = true;
+        self
+    }
+
+    /// Sample traces with user-defined rules, applied in order by a [`DatadogRulesSampler`]
+    /// installed as the tracer provider sampler by [`install_simple`] and [`install_batch`].
+    ///
+    /// Defaults to the rules of the `DD_TRACE_SAMPLING_RULES` and `DD_TRACE_SAMPLE_RATE`
+    /// environment variables. With [`with_agent_sampling`], traces matching no rule are sampled
+    /// with the agent rates.
+    ///
+    /// [`install_simple`]: DatadogPipelineBuilder::install_simple
+    /// [`install_batch`]: DatadogPipelineBuilder::install_batch
+    /// [`with_agent_sampling`]: DatadogPipelineBuilder::with_agent_sampling
+    #[cfg(feature = "agent-sampling")]
+    pub fn with_sampling_rules(mut self, rules: Vec<SamplingRule>) -> Self {
+        self.sampling_rules = Some(rules);
+        self
+    }
+
+    /// Compute the APM trace metrics in the exporter.
+    ///
+    /// Spans are aggregated into 10 seconds buckets sent to the `/v0.6/stats` endpoint of the
+    /// agent. As the agent doesn't need to see every span anymore, unsampled traces are dropped
+    /// by the exporter instead of being sent to the agent.
+    ///
+    /// Complete buckets are flushed every 10 seconds by a background thread, and the remaining
+    /// ones when the exporter is shut down.
+    pub fn with_stats_computation(mut self, enabled: bool) -> Self {
+        self.stats_computation = enabled;
+        self
+    }
+
+    /// Set the maximum size in bytes of the payloads sent to the agent, 10 MiB by default.
+    ///
+    /// Larger batches are split at trace boundaries into several requests. Traces that don't fit
+    /// in a payload on their own are dropped, and the export returns an error once the rest of
+    /// the batch is sent.
+    pub fn with_max_payload_size(mut self, max_payload_size: usize) -> Self {
+        self.max_payload_size = max_payload_size;
+        self
+    }
+
+    /// Set how many times a payload is sent again after a connection error, or a `429` or `5xx`
+    /// response from the agent, 3 by default.
+    ///
+    /// Retries are delayed by an exponential backoff starting at 100 milliseconds.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
     /// Set version of Datadog trace ingestion API
     pub fn with_api_version(mut self, api_version: ApiVersion) -> Self {
         self.api_version = api_version;
@@ -353,7 +870,7 @@ impl DatadogPipelineBuilder {
     /// See [`FieldMappingFn`] for details.
     pub fn with_resource_mapping<F>(mut self, f: F) -> Self
     where
-        F: for<'a> Fn(&'a SpanData, &'a ModelConfig) -> &'a str + Send + Sync + 'static,
+        F: for<'a> Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str> + Send + Sync + 'static,
     {
         self.mapping.resource = Some(Arc::new(f));
         self
@@ -363,7 +880,7 @@ impl DatadogPipelineBuilder {
     /// See [`FieldMappingFn`] for details.
     pub fn with_name_mapping<F>(mut self, f: F) -> Self
     where
-        F: for<'a> Fn(&'a SpanData, &'a ModelConfig) -> &'a str + Send + Sync + 'static,
+        F: for<'a> Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str> + Send + Sync + 'static,
     {
         self.mapping.name = Some(Arc::new(f));
         self
@@ -373,11 +890,139 @@ impl DatadogPipelineBuilder {
     /// See [`FieldMappingFn`] for details.
     pub fn with_service_name_mapping<F>(mut self, f: F) -> Self
     where
-        F: for<'a> Fn(&'a SpanData, &'a ModelConfig) -> &'a str + Send + Sync + 'static,
+        F: for<'a> Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str> + Send + Sync + 'static,
     {
         self.mapping.service_name = Some(Arc::new(f));
         self
     }
+
+    /// Name spans from the OpenTelemetry semantic conventions, the way the Datadog agent names
+    /// the spans it receives through OTLP.
+    ///
+    /// |field name|value|
+    /// |---------------|-------------|
+    /// |service name| `service.name` span attribute, or the configured service name|
+    /// |name| operation such as `http.server.request`, `postgresql.query` or `kafka.publish`|
+    /// |resource| `METHOD route` for http servers, the statement for databases, or the span name|
+    /// |type| `span.type` span attribute, or inferred from the span kind and `db.system`|
+    ///
+    /// The `operation.name`, `resource.name` and `span.type` span attributes override the
+    /// inferred values.
+    ///
+    /// The name, resource and service name are mapped by [`semconv_name_mapping`],
+    /// [`semconv_resource_mapping`] and [`semconv_service_name_mapping`], set for the fields
+    /// without custom mapping. Mappings set with [`with_name_mapping`], [`with_resource_mapping`]
+    /// and [`with_service_name_mapping`], before or after this call, take precedence. These
+    /// mappings can also be set on their own, to follow the semantic conventions for some fields
+    /// only.
+    ///
+    /// [`semconv_name_mapping`]: crate::semconv_name_mapping
+    /// [`semconv_resource_mapping`]: crate::semconv_resource_mapping
+    /// [`semconv_service_name_mapping`]: crate::semconv_service_name_mapping
+    /// [`with_name_mapping`]: DatadogPipelineBuilder::with_name_mapping
+    /// [`with_resource_mapping`]: DatadogPipelineBuilder::with_resource_mapping
+    /// [`with_service_name_mapping`]: DatadogPipelineBuilder::with_service_name_mapping
+    pub fn with_semconv_mapping(mut self) -> Self {
+        self.mapping = self.mapping.with_semconv();
+        self
+    }
+
+    /// Obfuscate the resource of SQL and Cassandra spans, disabled by default.
+    ///
+    /// String and number literals, booleans, nulls and bind parameters are replaced by `?`,
+    /// comments are removed, and lists of literals such as `IN (1, 2, 3)` become `IN ( ? )`. The
+    /// obfuscated query is also written as the `sql.query` tag.
+    ///
+    /// The database is read from the `db.system` attribute, or from the span type when the
+    /// attribute is missing or unknown.
+    pub fn with_sql_obfuscation(mut self, enabled: bool) -> Self {
+        self.mapping.obfuscation.sql = enabled;
+        self
+    }
+
+    /// Quantize the resource of Redis spans to their command names, disabled by default.
+    ///
+    /// `SET user:42 secret` becomes `SET`. Only the first 3 commands of a pipeline are kept.
+    pub fn with_redis_obfuscation(mut self, enabled: bool) -> Self {
+        self.mapping.obfuscation.redis = enabled;
+        self
+    }
+
+    /// Obfuscate the JSON query used as resource of MongoDB spans, disabled by default.
+    ///
+    /// Every value is replaced by `"?"`, the keys and the structure of the query are kept.
+    pub fn with_mongodb_obfuscation(mut self, enabled: bool) -> Self {
+        self.mapping.obfuscation.mongodb = enabled;
+        self
+    }
+
+    /// Obfuscate the JSON body used as resource of Elasticsearch and OpenSearch spans, disabled
+    /// by default.
+    ///
+    /// Every value is replaced by `"?"`, the keys and the structure of the body are kept.
+    pub fn with_elasticsearch_obfuscation(mut self, enabled: bool) -> Self {
+        self.mapping.obfuscation.elasticsearch = enabled;
+        self
+    }
+
+    /// Mark client and server spans as measured, enabled by default.
+    ///
+    /// Measured spans feed the trace metrics of the Datadog UI even when they are not the entry
+    /// point of their service. When disabled, only the spans whose trace state enables
+    /// [`DatadogTraceState::with_measuring`] are measured.
+    ///
+    /// [`DatadogTraceState::with_measuring`]: crate::DatadogTraceState::with_measuring
+    pub fn with_span_kind_measuring(mut self, enabled: bool) -> Self {
+        self.mapping.span_kind_measuring = enabled;
+        self
+    }
+
+    /// Buffer the spans of each trace until the trace is finished, and partially flush traces
+    /// once `min_spans` of their spans ended, see [`DatadogSpanProcessor`].
+    ///
+    /// Defaults to the `DD_TRACE_PARTIAL_FLUSH_ENABLED` and `DD_TRACE_PARTIAL_FLUSH_MIN_SPANS`
+    /// environment variables, with 1000 spans by default. Only applies to
+    /// [`install_simple`](Self::install_simple) and [`install_batch`](Self::install_batch).
+    pub fn with_partial_flush(mut self, min_spans: usize) -> Self {
+        self.partial_flush = Some(min_spans);
+        self
+    }
+
+    /// Record the health of the exporter with the given meter: the spans and traces sent to the
+    /// agent or dropped, the encoding and request durations, the payload sizes and the response
+    /// status classes.
+    ///
+    /// The instruments are named `datadog.exporter.*`, see the crate documentation for the list.
+    pub fn with_meter(mut self, meter: Meter) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+}
+
+/// Installs the rules sampler, falling back to the agent sampler, or the agent sampler alone.
+#[cfg(feature = "agent-sampling")]
+fn with_datadog_sampler(
+    builder: TracerProviderBuilder,
+    exporter: &DatadogExporter,
+    sampling_rules: Option<Vec<SamplingRule>>,
+) -> TracerProviderBuilder {
+    let service_name = &exporter.model_config.service_name;
+    let rules_sampler = match sampling_rules {
+        Some(rules) => Some(DatadogRulesSampler::new(service_name, rules)),
+        None => rules_sampler_from_env(service_name),
+    };
+    match (rules_sampler, exporter.agent_sampler()) {
+        (Some(rules_sampler), Some(agent_sampler)) => {
+            builder.with_sampler(rules_sampler.with_agent_sampler(agent_sampler))
+        }
+        (Some(rules_sampler), None) => builder.with_sampler(rules_sampler),
+        (None, Some(agent_sampler)) => builder.with_sampler(agent_sampler),
+        (None, None) => builder,
+    }
+}
+
+fn span_count(traces: &[&[SpanData]]) -> usize {
+    traces.iter().map(|trace| trace.len()).sum()
 }
 
 fn group_into_traces(spans: &mut [SpanData]) -> Vec<&[SpanData]> {
@@ -403,34 +1048,134 @@ fn group_into_traces(spans: &mut [SpanData]) -> Vec<&[SpanData]> {
     traces
 }
 
-async fn send_request(
-    client: Arc<dyn HttpClient>,
-    request: http::Request<Vec<u8>>,
-) -> OTelSdkResult {
-    #[allow(deprecated)]
-    let response = client
-        .send(request)
-        .await
-        .map_err(|e| OTelSdkError::InternalFailure(format!("HTTP request failed: {}", e)))?;
-
-    response
-        .error_for_status()
-        .map_err(|e| OTelSdkError::InternalFailure(format!("HTTP response error: {}", e)))?;
-
-    Ok(())
-}
-
 impl SpanExporter for DatadogExporter {
     /// Export spans to datadog-agent
-    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
-        let request = match self.build_request(batch) {
-            Ok(req) => req,
-            Err(err) => return Err(err),
-        };
+    async fn export(&self, mut batch: Vec<SpanData>) -> OTelSdkResult {
+        model::tag_inject_errors(&mut batch);
+        let mut traces: Vec<&[SpanData]> = group_into_traces(&mut batch);
+        let mut dropped = DroppedTraces::default();
+        if let Some(stats) = &self.stats {
+            for trace in &traces {
+                stats
+                    .concentrator
+                    .add_trace(trace, &self.mapping, &self.model_config);
+            }
+            // the agent doesn't need unsampled traces when the exporter computes the metrics
+            traces.retain(|trace| {
+                let unsampled = is_unsampled(trace);
+                if unsampled {
+                    dropped.traces += 1;
+                    dropped.spans += trace.len();
+                }
+                !unsampled
+            });
+            self.record_dropped(DropReason::Unsampled, dropped.traces, dropped.spans);
+            // reported with the next trace payload when all the traces were dropped
+            stats.add_dropped(dropped.traces, dropped.spans);
+        }
+
+        // payloads still to send, popped from the end to keep the traces in order
+        let mut pending: Vec<&[&[SpanData]]> = Vec::new();
+        if !traces.is_empty() {
+            pending.push(&traces);
+        }
+        let mut failed = DroppedTraces::default();
+        let mut result = Ok(());
+        while let Some(chunk) = pending.pop() {
+            let data = self.encode(chunk)?;
+            if data.len() > self.max_payload_size {
+                if let [trace] = chunk {
+                    otel_warn!(
+                        name: "DatadogExporter.TraceTooLarge",
+                        dropped_spans = trace.len(),
+                        payload_size = data.len()
+                    );
+                    self.record_dropped(DropReason::PayloadTooLarge, 1, trace.len());
+                    failed.traces += 1;
+                    failed.spans += trace.len();
+                    result = Err(OTelSdkError::InternalFailure(format!(
+                        "Trace of {} spans dropped, its payload of {} bytes is larger than the maximum payload size of {} bytes",
+                        trace.len(),
+                        data.len(),
+                        self.max_payload_size
+                    )));
+                } else {
+                    let (first, second) = chunk.split_at(chunk.len() / 2);
+                    pending.extend([second, first]);
+                }
+                self.buffers.recycle(data);
+                continue;
+            }
+
+            if let Some(metrics) = &self.metrics {
+                metrics.record_payload_size(data.len());
+            }
+            // the unsampled traces are only reported once
+            let dropped = self
+                .stats
+                .as_ref()
+                .map(|stats| stats.take_dropped())
+                .unwrap_or_default();
+            let response = self.send_with_retry(chunk.len(), &data, &dropped).await;
+            match response {
+                Ok(response)
+                    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && chunk.len() > 1 =>
+                {
+                    let (first, second) = chunk.split_at(chunk.len() / 2);
+                    pending.extend([second, first]);
+                }
+                Ok(response) => match response.error_for_status() {
+                    Ok(_response) => {
+                        if let Some(metrics) = &self.metrics {
+                            metrics.record_sent(chunk.len(), span_count(chunk));
+                        }
+                        #[cfg(feature = "agent-sampling")]
+                        if let Some(sampler) = &self.agent_sampler {
+                            sampler.rates().update_from_response(_response.body());
+                        }
+                    }
+                    Err(err) => {
+                        self.record_dropped(
+                            DropReason::ExportFailed,
+                            chunk.len(),
+                            span_count(chunk),
+                        );
+                        failed.traces += chunk.len();
+                        failed.spans += span_count(chunk);
+                        result = Err(OTelSdkError::InternalFailure(format!(
+                            "HTTP response error: {}",
+                            err
+                        )));
+                    }
+                },
+                Err(err) => {
+                    self.record_dropped(DropReason::ExportFailed, chunk.len(), span_count(chunk));
+                    failed.traces += chunk.len();
+                    failed.spans += span_count(chunk);
+                    result = Err(err);
+                }
+            }
+            self.buffers.recycle(data);
+        }
+        if failed.traces > 0 {
+            otel_warn!(
+                name: "DatadogExporter.TracesDropped",
+                dropped_traces = failed.traces,
+                dropped_spans = failed.spans
+            );
+        }
+
+        result
+    }
 
-        let client = self.client.clone();
-        send_request(client, request).await
+    fn shutdown(&mut self) -> OTelSdkResult {
+        let Some(stats) = &self.stats else {
+            return Ok(());
+        };
+        stats.stop();
+        futures_executor::block_on(stats.send(true))
     }
+
     fn set_resource(&mut self, resource: &Resource) {
         self.resource = Some(resource.clone());
     }
@@ -439,7 +1184,7 @@ impl SpanExporter for DatadogExporter {
 /// Helper struct to custom the mapping between Opentelemetry spans and datadog spans.
 ///
 /// This struct will be passed to [`FieldMappingFn`]
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 #[non_exhaustive]
 pub struct ModelConfig {
     pub service_name: String,
@@ -461,6 +1206,7 @@ mod tests {
 
     use crate::exporter::model::tests::get_span;
     use bytes::Bytes;
+    use opentelemetry::trace::SpanKind;
 
     #[test]
     fn test_out_of_order_group() {
@@ -507,6 +1253,42 @@ mod tests {
         assert!(invalid.is_err())
     }
 
+    #[test]
+    fn test_agent_endpoint_from_env() {
+        temp_env::with_vars_unset(
+            [
+                DD_TRACE_AGENT_URL_ENV_VAR,
+                DD_AGENT_HOST_ENV_VAR,
+                DD_TRACE_AGENT_PORT_ENV_VAR,
+            ],
+            || {
+                assert_eq!(agent_endpoint_from_env(), DEFAULT_AGENT_ENDPOINT);
+                temp_env::with_var(DD_AGENT_HOST_ENV_VAR, Some("datadog-agent"), || {
+                    assert_eq!(agent_endpoint_from_env(), "http://datadog-agent:8126");
+                });
+                temp_env::with_vars(
+                    [
+                        (DD_AGENT_HOST_ENV_VAR, Some("::1")),
+                        (DD_TRACE_AGENT_PORT_ENV_VAR, Some("8127")),
+                    ],
+                    || assert_eq!(agent_endpoint_from_env(), "http://[::1]:8127"),
+                );
+                temp_env::with_vars(
+                    [
+                        (DD_TRACE_AGENT_URL_ENV_VAR, Some("http://agent:9126")),
+                        (DD_AGENT_HOST_ENV_VAR, Some("datadog-agent")),
+                    ],
+                    || {
+                        assert_eq!(agent_endpoint_from_env(), "http://agent:9126");
+                        // explicit configuration wins over the environment
+                        let builder = new_pipeline().with_agent_endpoint("http://localhost:8126");
+                        assert_eq!(builder.agent_endpoint, "http://localhost:8126");
+                    },
+                );
+            },
+        );
+    }
+
     #[derive(Debug)]
     struct DummyClient;
 
@@ -529,6 +1311,357 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reuse_payload_buffers() {
+        let exporter = new_pipeline()
+            .with_api_version(Version05)
+            .with_http_client(DummyClient)
+            .build_exporter()
+            .unwrap();
+        let result =
+            futures_executor::block_on(exporter.export(vec![get_span(1, 0, 1), get_span(2, 0, 2)]));
+        assert!(result.is_ok());
+
+        // both the payload and the spans encoded before the dictionary are back in the pool
+        let payload = exporter.buffers.take();
+        let spans = exporter.buffers.take();
+        assert!(payload.capacity() > 0 && spans.capacity() > 0);
+        assert!(exporter.buffers.dictionary_len() > 0);
+    }
+
+    /// Client answering with the given statuses in order, then `200`.
+    #[derive(Debug, Default)]
+    struct ScriptedClient {
+        statuses: std::sync::Mutex<Vec<u16>>,
+        trace_counts: std::sync::Mutex<Vec<usize>>,
+        headers: std::sync::Mutex<Vec<http::HeaderMap>>,
+        stats_requests: std::sync::Mutex<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for ScriptedClient {
+        async fn send_bytes(
+            &self,
+            request: Request<Bytes>,
+        ) -> Result<http::Response<Bytes>, opentelemetry_http::HttpError> {
+            self.headers.lock().unwrap().push(request.headers().clone());
+            if request.uri().path().ends_with(DATADOG_STATS_PATH) {
+                *self.stats_requests.lock().unwrap() += 1;
+                return Ok(http::Response::builder().status(200).body(Bytes::new())?);
+            }
+            let trace_count = request.headers()[DATADOG_TRACE_COUNT_HEADER]
+                .to_str()?
+                .parse()?;
+            self.trace_counts.lock().unwrap().push(trace_count);
+            let mut statuses = self.statuses.lock().unwrap();
+            let status = if statuses.is_empty() {
+                200
+            } else {
+                statuses.remove(0)
+            };
+            Ok(http::Response::builder()
+                .status(status)
+                .body(Bytes::new())?)
+        }
+    }
+
+    fn export_with(
+        client: ScriptedClient,
+        configure: impl FnOnce(DatadogPipelineBuilder) -> DatadogPipelineBuilder,
+    ) -> (OTelSdkResult, Vec<usize>) {
+        let client = Arc::new(client);
+        let mut builder = configure(new_pipeline());
+        builder.client = Some(client.clone());
+        let exporter = builder.build_exporter().unwrap();
+        let batch = (1..=4).map(|id| get_span(id, 0, id as u64)).collect();
+        let result = futures_executor::block_on(exporter.export(batch));
+        let trace_counts = client.trace_counts.lock().unwrap().clone();
+        (result, trace_counts)
+    }
+
+    #[test]
+    fn test_semconv_mapping_precedence() {
+        let mut span = get_span(7, 1, 99);
+        span.span_kind = SpanKind::Server;
+        span.attributes = vec![
+            KeyValue::new("http.request.method", "GET"),
+            KeyValue::new("http.route", "/users/{id}"),
+        ];
+        let config = ModelConfig {
+            service_name: "my_app".to_string(),
+        };
+
+        // custom mappings take precedence, whichever the order
+        for builder in [
+            new_pipeline()
+                .with_service_name_mapping(|_, _| "custom".into())
+                .with_semconv_mapping(),
+            new_pipeline()
+                .with_semconv_mapping()
+                .with_service_name_mapping(|_, _| "custom".into()),
+        ] {
+            let names = builder.mapping.span_names(&span, &config);
+            assert_eq!(names.service, "custom");
+            assert_eq!(names.name, "http.server.request");
+            assert_eq!(names.resource, "GET /users/{id}");
+            assert_eq!(names.span_type, "web");
+        }
+
+        // the semantic conventions mappings on their own
+        let builder = new_pipeline().with_name_mapping(semconv_name_mapping);
+        let names = builder.mapping.span_names(&span, &config);
+        assert_eq!(names.service, "my_app");
+        assert_eq!(names.name, "http.server.request");
+        assert_eq!(names.resource, span.name);
+        assert_eq!(names.span_type, "");
+    }
+
+    #[test]
+    fn test_request_headers() {
+        let header = |headers: &http::HeaderMap, name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        let client = Arc::new(ScriptedClient::default());
+        let mut builder = new_pipeline();
+        builder.client = Some(client.clone());
+        let exporter = builder.build_exporter().unwrap();
+        let result = futures_executor::block_on(exporter.export(vec![get_span(1, 0, 1)]));
+        assert!(result.is_ok());
+        let headers = client.headers.lock().unwrap()[0].clone();
+        assert_eq!(
+            header(&headers, DATADOG_TRACE_COUNT_HEADER).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            header(&headers, DATADOG_META_LANG_HEADER).as_deref(),
+            Some("rust")
+        );
+        assert_eq!(
+            header(&headers, DATADOG_CLIENT_COMPUTED_TOP_LEVEL_HEADER).as_deref(),
+            Some("yes")
+        );
+        assert_eq!(header(&headers, DATADOG_CLIENT_COMPUTED_STATS_HEADER), None);
+
+        let client = Arc::new(ScriptedClient::default());
+        let mut builder = new_pipeline().with_stats_computation(true);
+        builder.client = Some(client.clone());
+        let mut exporter = builder.build_exporter().unwrap();
+        // no trace payload is sent when all the traces are dropped
+        let result = futures_executor::block_on(exporter.export(vec![get_span(1, 0, 1)]));
+        assert!(result.is_ok());
+        assert!(client.headers.lock().unwrap().is_empty());
+
+        // the dropped traces are reported with the next payload
+        let result = futures_executor::block_on(exporter.export(vec![sampled_span(2)]));
+        assert!(result.is_ok());
+        let headers = client.headers.lock().unwrap()[0].clone();
+        assert_eq!(
+            header(&headers, DATADOG_CLIENT_COMPUTED_TOP_LEVEL_HEADER).as_deref(),
+            Some("yes")
+        );
+        assert_eq!(
+            header(&headers, DATADOG_CLIENT_COMPUTED_STATS_HEADER).as_deref(),
+            Some("yes")
+        );
+        assert_eq!(
+            header(&headers, DATADOG_CLIENT_DROPPED_P0_TRACES_HEADER).as_deref(),
+            Some("1")
+        );
+
+        // the remaining stats buckets are sent on shutdown
+        assert_eq!(*client.stats_requests.lock().unwrap(), 0);
+        assert!(exporter.shutdown().is_ok());
+        assert_eq!(*client.stats_requests.lock().unwrap(), 1);
+    }
+
+    /// A span kept by the sampler, with the priority read by agent sampling.
+    fn sampled_span(trace_id: u128) -> SpanData {
+        use opentelemetry::trace::{SpanContext, TraceFlags, TraceState};
+
+        #[cfg(feature = "agent-sampling")]
+        let trace_state = {
+            use crate::propagator::DatadogTraceState;
+            TraceState::default().with_priority_sampling(true)
+        };
+        #[cfg(not(feature = "agent-sampling"))]
+        let trace_state = TraceState::default();
+
+        let mut span = get_span(trace_id, 0, trace_id as u64);
+        span.span_context = SpanContext::new(
+            span.span_context.trace_id(),
+            span.span_context.span_id(),
+            TraceFlags::SAMPLED,
+            false,
+            trace_state,
+        );
+        span
+    }
+
+    #[test]
+    fn test_split_oversized_batch() {
+        let single_trace_size = DatadogPipelineBuilder::default()
+            .with_http_client(DummyClient)
+            .build_exporter()
+            .unwrap()
+            .encode(&[&[get_span(1, 0, 1)]])
+            .unwrap()
+            .len();
+
+        let (result, trace_counts) = export_with(ScriptedClient::default(), |builder| {
+            builder.with_max_payload_size(single_trace_size * 2 + 1)
+        });
+        assert!(result.is_ok());
+        assert_eq!(trace_counts, vec![2, 2]);
+
+        // traces larger than the maximum payload size are dropped and reported
+        let (result, trace_counts) = export_with(ScriptedClient::default(), |builder| {
+            builder.with_max_payload_size(single_trace_size - 1)
+        });
+        assert!(
+            matches!(&result, Err(OTelSdkError::InternalFailure(message)) if message.contains("larger than the maximum payload size")),
+            "{:?}",
+            result
+        );
+        assert!(trace_counts.is_empty());
+    }
+
+    #[test]
+    fn test_split_on_payload_too_large() {
+        let client = ScriptedClient {
+            statuses: std::sync::Mutex::new(vec![413]),
+            ..Default::default()
+        };
+        let (result, trace_counts) = export_with(client, |builder| builder);
+        assert!(result.is_ok());
+        assert_eq!(trace_counts, vec![4, 2, 2]);
+    }
+
+    #[test]
+    fn test_retry() {
+        let client = ScriptedClient {
+            statuses: std::sync::Mutex::new(vec![503, 429]),
+            ..Default::default()
+        };
+        let (result, trace_counts) = export_with(client, |builder| builder);
+        assert!(result.is_ok());
+        assert_eq!(trace_counts, vec![4, 4, 4]);
+
+        let client = ScriptedClient {
+            statuses: std::sync::Mutex::new(vec![503, 503]),
+            ..Default::default()
+        };
+        let (result, trace_counts) = export_with(client, |builder| builder.with_max_retries(1));
+        assert!(result.is_err());
+        assert_eq!(trace_counts, vec![4, 4]);
+
+        // client errors are not retried
+        let client = ScriptedClient {
+            statuses: std::sync::Mutex::new(vec![400]),
+            ..Default::default()
+        };
+        let (result, trace_counts) = export_with(client, |builder| builder);
+        assert!(result.is_err());
+        assert_eq!(trace_counts, vec![4]);
+    }
+
+    #[test]
+    fn test_exporter_metrics() {
+        use opentelemetry::metrics::MeterProvider;
+        use opentelemetry_sdk::metrics::data::{Histogram, Sum};
+        use opentelemetry_sdk::metrics::{
+            InMemoryMetricExporter, PeriodicReader, SdkMeterProvider,
+        };
+
+        let metric_exporter = InMemoryMetricExporter::default();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(metric_exporter.clone()).build())
+            .build();
+        let meter = meter_provider.meter("opentelemetry-datadog");
+
+        let (result, _) = export_with(ScriptedClient::default(), |builder| {
+            builder.with_meter(meter.clone())
+        });
+        assert!(result.is_ok());
+        let client = ScriptedClient {
+            statuses: std::sync::Mutex::new(vec![503, 400]),
+            ..Default::default()
+        };
+        let (result, _) = export_with(client, |builder| builder.with_meter(meter.clone()));
+        assert!(result.is_err());
+
+        meter_provider.force_flush().unwrap();
+        let metrics = metric_exporter.get_finished_metrics().unwrap();
+        let metrics: Vec<_> = metrics
+            .iter()
+            .flat_map(|resource| &resource.scope_metrics)
+            .flat_map(|scope| &scope.metrics)
+            .collect();
+        let sum = |name: &str, attribute: Option<(&'static str, &'static str)>| -> u64 {
+            let metric = metrics.iter().find(|metric| metric.name == name).unwrap();
+            let sum = metric.data.as_any().downcast_ref::<Sum<u64>>().unwrap();
+            sum.data_points
+                .iter()
+                .filter(|point| {
+                    attribute.map_or(true, |(key, value)| {
+                        point.attributes.contains(&KeyValue::new(key, value))
+                    })
+                })
+                .map(|point| point.value)
+                .sum()
+        };
+        let histogram_count = |name: &str| -> u64 {
+            let metric = metrics.iter().find(|metric| metric.name == name).unwrap();
+            let data = metric.data.as_any();
+            match data.downcast_ref::<Histogram<f64>>() {
+                Some(histogram) => histogram.data_points.iter().map(|p| p.count).sum(),
+                None => data
+                    .downcast_ref::<Histogram<u64>>()
+                    .unwrap()
+                    .data_points
+                    .iter()
+                    .map(|p| p.count)
+                    .sum(),
+            }
+        };
+
+        assert_eq!(sum("datadog.exporter.traces.sent", None), 4);
+        assert_eq!(sum("datadog.exporter.spans.sent", None), 4);
+        assert_eq!(
+            sum(
+                "datadog.exporter.traces.dropped",
+                Some(("reason", "export_failed"))
+            ),
+            4
+        );
+        assert_eq!(
+            sum(
+                "datadog.exporter.http.responses",
+                Some(("status_class", "2xx"))
+            ),
+            1
+        );
+        assert_eq!(
+            sum(
+                "datadog.exporter.http.responses",
+                Some(("status_class", "5xx"))
+            ),
+            1
+        );
+        assert_eq!(
+            sum(
+                "datadog.exporter.http.responses",
+                Some(("status_class", "4xx"))
+            ),
+            1
+        );
+        assert_eq!(histogram_count("datadog.exporter.encode.duration"), 2);
+        assert_eq!(histogram_count("datadog.exporter.payload.size"), 2);
+        assert_eq!(histogram_count("datadog.exporter.http.duration"), 3);
+    }
+
     #[test]
     fn test_custom_http_client() {
         new_pipeline()
@@ -537,6 +1670,20 @@ mod tests {
             .unwrap();
     }
 
+    #[cfg(all(unix, feature = "uds-client"))]
+    #[test]
+    fn test_unix_socket_agent_endpoint() {
+        let exporter = new_pipeline()
+            .with_agent_endpoint("unix:///var/run/datadog/apm.socket")
+            .build_exporter()
+            .unwrap();
+        assert_eq!(
+            exporter.request_url.to_string(),
+            "http://localhost/v0.5/traces"
+        );
+        assert!(format!("{:?}", exporter.client).contains("/var/run/datadog/apm.socket"));
+    }
+
     #[test]
     fn test_install_simple() {
         new_pipeline()
@@ -0,0 +1,266 @@
+//! Span events and span links, which Datadog spans hold as JSON encoded meta tags.
+//!
+//! The format is the one used by the Datadog agent when converting OTLP spans, see
+//! <https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/api/otlp.go#L571>
+
+use opentelemetry::{Array, KeyValue, Value};
+use opentelemetry_sdk::trace::SpanData;
+use serde_json::{json, Map};
+use std::time::SystemTime;
+
+static DD_SPAN_LINKS_KEY: &str = "_dd.span_links";
+static EVENTS_KEY: &str = "events";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/tags.go#L53
+static ERROR_MESSAGE_KEY: &str = "error.message";
+static ERROR_TYPE_KEY: &str = "error.type";
+static ERROR_STACK_KEY: &str = "error.stack";
+
+// https://github.com/open-telemetry/semantic-conventions/blob/v1.24.0/docs/exceptions/exceptions-spans.md
+static EXCEPTION_EVENT_NAME: &str = "exception";
+static EXCEPTION_ERROR_TAGS: [(&str, &str); 3] = [
+    ("exception.message", ERROR_MESSAGE_KEY),
+    ("exception.type", ERROR_TYPE_KEY),
+    ("exception.stacktrace", ERROR_STACK_KEY),
+];
+
+// Bit set in the link flags when the trace flags are known
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/proto/datadog/trace/span.proto#L23
+const LINK_FLAGS_SET: u32 = 1 << 31;
+
+/// Meta tags holding the links and events of a span.
+///
+/// The attributes of the last exception event also fill the `error.*` tags, unless the span
+/// already has them.
+pub(crate) fn span_event_tags(span: &SpanData) -> Vec<(&'static str, String)> {
+    let mut tags = Vec::new();
+
+    if !span.links.is_empty() {
+        let links: Vec<_> = span
+            .links
+            .iter()
+            .map(|link| {
+                let span_context = &link.span_context;
+                let mut encoded = Map::new();
+                encoded.insert(
+                    "trace_id".into(),
+                    json!(format!("{:032x}", span_context.trace_id())),
+                );
+                encoded.insert(
+                    "span_id".into(),
+                    json!(format!("{:016x}", span_context.span_id())),
+                );
+                let trace_state = span_context.trace_state().header();
+                if !trace_state.is_empty() {
+                    encoded.insert("tracestate".into(), json!(trace_state));
+                }
+                encoded.insert(
+                    "flags".into(),
+                    json!(u32::from(span_context.trace_flags().to_u8()) | LINK_FLAGS_SET),
+                );
+                if !link.attributes.is_empty() {
+                    encoded.insert("attributes".into(), link_attributes(&link.attributes));
+                }
+                if link.dropped_attributes_count > 0 {
+                    encoded.insert(
+                        "dropped_attributes_count".into(),
+                        json!(link.dropped_attributes_count),
+                    );
+                }
+                serde_json::Value::Object(encoded)
+            })
+            .collect();
+        tags.push((
+            DD_SPAN_LINKS_KEY,
+            serde_json::Value::Array(links).to_string(),
+        ));
+    }
+
+    if !span.events.is_empty() {
+        let events: Vec<_> = span
+            .events
+            .iter()
+            .map(|event| {
+                let mut encoded = Map::new();
+                encoded.insert(
+                    "time_unix_nano".into(),
+                    json!(event
+                        .timestamp
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|x| x.as_nanos() as u64)
+                        .unwrap_or(0)),
+                );
+                encoded.insert("name".into(), json!(event.name));
+                if !event.attributes.is_empty() {
+                    let attributes = event
+                        .attributes
+                        .iter()
+                        .map(|kv| (kv.key.to_string(), json_value(&kv.value)))
+                        .collect();
+                    encoded.insert("attributes".into(), serde_json::Value::Object(attributes));
+                }
+                if event.dropped_attributes_count > 0 {
+                    encoded.insert(
+                        "dropped_attributes_count".into(),
+                        json!(event.dropped_attributes_count),
+                    );
+                }
+                serde_json::Value::Object(encoded)
+            })
+            .collect();
+        tags.push((EVENTS_KEY, serde_json::Value::Array(events).to_string()));
+    }
+
+    if let Some(exception) = span
+        .events
+        .iter()
+        .rev()
+        .find(|event| event.name == EXCEPTION_EVENT_NAME)
+    {
+        for (exception_key, error_key) in EXCEPTION_ERROR_TAGS {
+            if span
+                .attributes
+                .iter()
+                .any(|kv| kv.key.as_str() == error_key)
+            {
+                continue;
+            }
+            if let Some(kv) = exception
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == exception_key)
+            {
+                tags.push((error_key, kv.value.as_str().into_owned()));
+            }
+        }
+    }
+
+    tags
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Bool(value) => json!(value),
+        Value::I64(value) => json!(value),
+        Value::F64(value) => json!(value),
+        Value::String(value) => json!(value.as_str()),
+        Value::Array(Array::Bool(values)) => json!(values),
+        Value::Array(Array::I64(values)) => json!(values),
+        Value::Array(Array::F64(values)) => json!(values),
+        Value::Array(Array::String(values)) => {
+            json!(values
+                .iter()
+                .map(|value| value.as_str())
+                .collect::<Vec<_>>())
+        }
+        other => json!(other.as_str()),
+    }
+}
+
+// Link attributes are strings, arrays are flattened with the index appended to their key.
+fn link_attributes(attributes: &[KeyValue]) -> serde_json::Value {
+    let mut encoded = Map::new();
+    for kv in attributes {
+        match json_value(&kv.value) {
+            serde_json::Value::Array(values) => {
+                for (idx, value) in values.into_iter().enumerate() {
+                    let value = match value {
+                        serde_json::Value::String(value) => value,
+                        other => other.to_string(),
+                    };
+                    encoded.insert(format!("{}.{}", kv.key, idx), json!(value));
+                }
+            }
+            _ => {
+                encoded.insert(kv.key.to_string(), json!(kv.value.as_str()));
+            }
+        }
+    }
+    serde_json::Value::Object(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::model::tests::get_span;
+    use opentelemetry::trace::{Event, Link, SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+    #[test]
+    fn test_no_events_or_links() {
+        assert!(span_event_tags(&get_span(7, 1, 99)).is_empty());
+    }
+
+    #[test]
+    fn test_span_links() {
+        let mut span = get_span(7, 1, 99);
+        span.links.links.push(Link::new(
+            SpanContext::new(
+                TraceId::from_u128(0x640cfd8d00000000_0000000000000007),
+                SpanId::from_u64(42),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::from_key_value([("dd", "s:1")]).unwrap(),
+            ),
+            vec![
+                KeyValue::new("link.kind", "follows_from"),
+                KeyValue::new("ids", Value::Array(vec![1i64, 2].into())),
+            ],
+            0,
+        ));
+
+        let tags = span_event_tags(&span);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].0, DD_SPAN_LINKS_KEY);
+        let links: serde_json::Value = serde_json::from_str(&tags[0].1).unwrap();
+        assert_eq!(
+            links,
+            json!([{
+                "trace_id": "640cfd8d000000000000000000000007",
+                "span_id": "000000000000002a",
+                "tracestate": "dd=s:1",
+                "flags": 0x8000_0001u32,
+                "attributes": {"link.kind": "follows_from", "ids.0": "1", "ids.1": "2"},
+            }])
+        );
+    }
+
+    #[test]
+    fn test_span_events_and_exception() {
+        let mut span = get_span(7, 1, 99);
+        span.events.events.push(Event::new(
+            "cache miss",
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1),
+            vec![KeyValue::new("retry", true)],
+            0,
+        ));
+        span.events.events.push(Event::new(
+            EXCEPTION_EVENT_NAME,
+            SystemTime::UNIX_EPOCH,
+            vec![
+                KeyValue::new("exception.message", "connection refused"),
+                KeyValue::new("exception.type", "io::Error"),
+                KeyValue::new("exception.stacktrace", "main.rs:42"),
+            ],
+            0,
+        ));
+        // tags set on the span win over the exception event
+        span.attributes
+            .push(KeyValue::new(ERROR_TYPE_KEY, "ConnectionError"));
+
+        let tags = span_event_tags(&span);
+        let events: serde_json::Value = serde_json::from_str(&tags[0].1).unwrap();
+        assert_eq!(tags[0].0, EVENTS_KEY);
+        assert_eq!(
+            events[0],
+            json!({"time_unix_nano": 1_000_000, "name": "cache miss", "attributes": {"retry": true}})
+        );
+        assert_eq!(events[1]["name"], EXCEPTION_EVENT_NAME);
+        assert_eq!(
+            tags[1..],
+            [
+                (ERROR_MESSAGE_KEY, "connection refused".to_string()),
+                (ERROR_STACK_KEY, "main.rs:42".to_string()),
+            ]
+        );
+    }
+}
@@ -1,16 +1,28 @@
+use self::events::span_event_tags;
+use self::obfuscation::Obfuscator;
+use crate::exporter::buffers::BufferPool;
 use crate::exporter::ModelConfig;
+use crate::propagator::{self, DatadogTraceState};
 use http::uri;
+use opentelemetry::trace::{SpanId, SpanKind, TraceId};
+use opentelemetry::KeyValue;
 use opentelemetry_sdk::{
     trace::{self, SpanData},
     ExportError, Resource,
 };
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::Arc;
 use url::ParseError;
 
 use self::unified_tags::UnifiedTags;
 
 use super::Mapping;
 
+mod events;
+pub(crate) mod obfuscation;
+mod semconv;
 pub mod unified_tags;
 mod v03;
 mod v05;
@@ -22,6 +34,103 @@ static SAMPLING_PRIORITY_KEY: &str = "_sampling_priority_v1";
 
 // https://github.com/DataDog/datadog-agent/blob/ec96f3c24173ec66ba235bda7710504400d9a000/pkg/trace/traceutil/span.go#L20
 static DD_MEASURED_KEY: &str = "_dd.measured";
+static DD_TOP_LEVEL_KEY: &str = "_top_level";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/span.go#L77
+static DD_TRACE_ID_HIGH_KEY: &str = "_dd.p.tid";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/tags.go#L106
+static DD_ORIGIN_KEY: &str = "_dd.origin";
+
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/agent/obfuscate.go#L20
+static SQL_QUERY_KEY: &str = "sql.query";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L85
+static DD_PROPAGATION_ERROR_KEY: &str = "_dd.propagation_error";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L1135
+static DD_PARENT_ID_KEY: &str = "_dd.parent_id";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/sampler.go#L179
+static DD_RULE_PSR_KEY: &str = "_dd.rule_psr";
+static DD_LIMIT_PSR_KEY: &str = "_dd.limit_psr";
+
+/// Metrics holding the rates of the sampling rule and of the rate limiter that sampled the trace,
+/// written on its root span.
+fn sampling_rate_metrics(span: &SpanData) -> Vec<(&'static str, f64)> {
+    if span.parent_span_id != SpanId::INVALID {
+        return Vec::new();
+    }
+    let trace_state = span.span_context.trace_state();
+    [
+        (DD_RULE_PSR_KEY, trace_state.sampling_rule_rate()),
+        (DD_LIMIT_PSR_KEY, trace_state.sampling_limit_rate()),
+    ]
+    .into_iter()
+    .filter_map(|(key, rate)| Some((key, rate?)))
+    .collect()
+}
+
+/// The Datadog id of a trace, the lower 64 bits of its id, as written in spans and sent in the
+/// `x-datadog-trace-id` header.
+pub(crate) fn datadog_trace_id(trace_id: TraceId) -> u64 {
+    u128::from_be_bytes(trace_id.to_bytes()) as u64
+}
+
+/// Meta tags describing a whole trace chunk, written on its first span.
+///
+/// This holds the hex encoded upper 64 bits of 128-bit trace ids, as Datadog spans only hold the
+/// lower 64 bits of the trace id, the `_dd.p.*` tags propagated with the trace, the error
+/// encountered while propagating them and the last Datadog parent extracted from W3C headers.
+fn trace_chunk_tags(trace: &[trace::SpanData]) -> Vec<(String, String)> {
+    let Some(span) = trace.first() else {
+        return Vec::new();
+    };
+    let trace_state = span.span_context.trace_state();
+
+    let mut tags = trace_state.propagated_tags();
+    let high = (u128::from_be_bytes(span.span_context.trace_id().to_bytes()) >> 64) as u64;
+    if high != 0 {
+        tags.push((DD_TRACE_ID_HIGH_KEY.to_string(), format!("{:016x}", high)));
+    }
+    if let Some(error) = trace_state.propagation_error() {
+        tags.push((DD_PROPAGATION_ERROR_KEY.to_string(), error.to_string()));
+    }
+    if let Some(last_parent_id) = trace_state.last_parent_id() {
+        tags.push((DD_PARENT_ID_KEY.to_string(), last_parent_id.to_string()));
+    }
+    tags
+}
+
+/// Writes the errors encountered while injecting the `_dd.p.*` tags of traces as the
+/// `_dd.propagation_error` tag of their local root span, the span of the batch whose parent is
+/// not part of it.
+pub(crate) fn tag_inject_errors(batch: &mut [trace::SpanData]) {
+    if !propagator::has_inject_errors() {
+        return;
+    }
+    let span_ids: HashSet<SpanId> = batch
+        .iter()
+        .map(|span| span.span_context.span_id())
+        .collect();
+    for span in batch
+        .iter_mut()
+        .filter(|span| !span_ids.contains(&span.parent_span_id))
+    {
+        if let Some(error) = propagator::take_inject_error(span.span_context.trace_id()) {
+            // the errors of the extraction are already written with the trace chunk tags
+            if span
+                .span_context
+                .trace_state()
+                .propagation_error()
+                .is_none()
+            {
+                span.attributes
+                    .push(KeyValue::new(DD_PROPAGATION_ERROR_KEY, error));
+            }
+        }
+    }
+}
 
 /// Custom mapping between opentelemetry spans and datadog spans.
 ///
@@ -35,10 +144,14 @@ static DD_MEASURED_KEY: &str = "_dd.measured";
 /// |resource| opentelemetry name|
 ///
 /// The function takes a reference to [`SpanData`]() and a reference to [`ModelConfig`]() as parameters.
-/// It should return a `&str` which will be used as the value for the field.
+/// It should return a `Cow<str>` which will be used as the value for the field, borrowed from the
+/// span or the configuration, or built by the function.
 ///
 /// If no custom mapping is provided. Default mapping detailed above will be used.
 ///
+/// [`semconv_name_mapping`], [`semconv_resource_mapping`] and [`semconv_service_name_mapping`]
+/// map the fields from the OpenTelemetry semantic conventions instead.
+///
 /// For example,
 /// ```no_run
 /// use opentelemetry::global;
@@ -49,7 +162,7 @@ static DD_MEASURED_KEY: &str = "_dd.measured";
 ///         .with_service_name("my_app")
 ///         .with_api_version(ApiVersion::Version05)
 ///         // the custom mapping below will change the all spans' name to datadog spans
-///         .with_name_mapping(|span, model_config|{"datadog spans"})
+///         .with_name_mapping(|span, model_config|{"datadog spans".into()})
 ///         .with_agent_endpoint("http://localhost:8126")
 ///         .install_batch()?;
 ///     global::set_tracer_provider(provider.clone());
@@ -58,7 +171,8 @@ static DD_MEASURED_KEY: &str = "_dd.measured";
 ///     Ok(())
 /// }
 /// ```
-pub type FieldMappingFn = dyn for<'a> Fn(&'a SpanData, &'a ModelConfig) -> &'a str + Send + Sync;
+pub type FieldMappingFn =
+    dyn for<'a> Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str> + Send + Sync;
 
 pub(crate) type FieldMapping = std::sync::Arc<FieldMappingFn>;
 
@@ -77,6 +191,213 @@ fn default_resource_mapping<'a>(span: &'a SpanData, _config: &'a ModelConfig) ->
     span.name.as_ref()
 }
 
+/// [`FieldMappingFn`] naming the operation of the span from the OpenTelemetry semantic
+/// conventions, such as `http.server.request`, `postgresql.query` or `kafka.publish`, like the
+/// Datadog agent names the spans it receives through OTLP.
+///
+/// The `operation.name` span attribute overrides the inferred name.
+pub fn semconv_name_mapping<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Cow<'a, str> {
+    semconv::operation_name(span)
+}
+
+/// [`FieldMappingFn`] naming the resource of the span from the OpenTelemetry semantic
+/// conventions, such as `GET /users/{id}` for http servers or the statement for databases, and
+/// otherwise the span name.
+///
+/// The `resource.name` span attribute overrides the inferred name.
+pub fn semconv_resource_mapping<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Cow<'a, str> {
+    semconv::resource_name(span)
+}
+
+/// [`FieldMappingFn`] naming the service of the span from its `service.name` attribute, or from
+/// the configured service name.
+pub fn semconv_service_name_mapping<'a>(
+    span: &'a SpanData,
+    config: &'a ModelConfig,
+) -> Cow<'a, str> {
+    semconv::service_name(span, &config.service_name)
+}
+
+fn default_span_type_mapping(span: &SpanData) -> Cow<'_, str> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == "span.type")
+        .map(|kv| kv.value.as_str())
+        .unwrap_or_default()
+}
+
+impl Mapping {
+    pub(crate) fn service_name<'a>(
+        &self,
+        span: &'a SpanData,
+        config: &'a ModelConfig,
+    ) -> Cow<'a, str> {
+        map_service_name(self.service_name.as_ref(), span, config)
+    }
+
+    /// The part of the mapping naming the services, with the configuration it needs.
+    pub(crate) fn service_mapping(&self, config: &ModelConfig) -> ServiceMapping {
+        ServiceMapping {
+            service_name: self.service_name.clone(),
+            model_config: Arc::new(config.clone()),
+        }
+    }
+
+    pub(crate) fn name<'a>(&self, span: &'a SpanData, config: &'a ModelConfig) -> Cow<'a, str> {
+        match &self.name {
+            Some(f) => f(span, config),
+            None => default_name_mapping(span, config).into(),
+        }
+    }
+
+    pub(crate) fn resource<'a>(&self, span: &'a SpanData, config: &'a ModelConfig) -> Cow<'a, str> {
+        match &self.resource {
+            Some(f) => f(span, config),
+            None => default_resource_mapping(span, config).into(),
+        }
+    }
+
+    /// The span type, from the `span.type` attribute, or empty if the span has none.
+    pub(crate) fn span_type<'a>(&self, span: &'a SpanData) -> Cow<'a, str> {
+        if self.semconv_span_type {
+            semconv::span_type(span)
+        } else {
+            default_span_type_mapping(span)
+        }
+    }
+
+    /// Whether the span is measured, feeding the trace metrics even when it's not top-level.
+    ///
+    /// Client and server spans are measured unless disabled with
+    /// [`DatadogPipelineBuilder::with_span_kind_measuring`], other spans when their trace state
+    /// enables measuring.
+    ///
+    /// [`DatadogPipelineBuilder::with_span_kind_measuring`]: crate::DatadogPipelineBuilder::with_span_kind_measuring
+    pub(crate) fn measured(&self, span: &SpanData) -> bool {
+        span.span_context.trace_state().measuring_enabled()
+            || (self.span_kind_measuring
+                && matches!(span.span_kind, SpanKind::Client | SpanKind::Server))
+    }
+
+    pub(crate) fn span_names<'a>(
+        &self,
+        span: &'a SpanData,
+        config: &'a ModelConfig,
+    ) -> SpanNames<'a> {
+        let span_type = self.span_type(span);
+        let resource = self.resource(span, config);
+        let obfuscator = self.obfuscation.obfuscator(span, &span_type);
+        SpanNames {
+            service: self.service_name(span, config),
+            name: self.name(span, config),
+            resource: match obfuscator {
+                Some(obfuscator) => obfuscator.apply(&resource).into(),
+                None => resource,
+            },
+            span_type,
+            sql_query: obfuscator == Some(Obfuscator::Sql),
+        }
+    }
+}
+
+fn map_service_name<'a>(
+    service_name: Option<&FieldMapping>,
+    span: &'a SpanData,
+    config: &'a ModelConfig,
+) -> Cow<'a, str> {
+    match service_name {
+        Some(f) => f(span, config),
+        None => default_service_name_mapping(span, config).into(),
+    }
+}
+
+/// Names the service of the spans like the [`Mapping`] of the exporter, so that the
+/// [`DatadogSpanProcessor`] tells the top-level spans of partially flushed traces.
+///
+/// [`DatadogSpanProcessor`]: crate::DatadogSpanProcessor
+#[derive(Clone, Default)]
+pub(crate) struct ServiceMapping {
+    service_name: Option<FieldMapping>,
+    model_config: Arc<ModelConfig>,
+}
+
+impl ServiceMapping {
+    /// Whether every span belongs to the configured service.
+    pub(crate) fn is_constant(&self) -> bool {
+        self.service_name.is_none()
+    }
+
+    pub(crate) fn service_name<'a>(&'a self, span: &'a SpanData) -> Cow<'a, str> {
+        map_service_name(self.service_name.as_ref(), span, &self.model_config)
+    }
+}
+
+impl Debug for ServiceMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServiceMapping")
+            .field("custom_mapping", &self.service_name.is_some())
+            .field("model_config", &self.model_config)
+            .finish()
+    }
+}
+
+/// The names given to a Datadog span by the [`Mapping`].
+pub(crate) struct SpanNames<'a> {
+    pub(crate) service: Cow<'a, str>,
+    pub(crate) name: Cow<'a, str>,
+    pub(crate) resource: Cow<'a, str>,
+    pub(crate) span_type: Cow<'a, str>,
+    /// Whether the resource is an obfuscated SQL query, also written as the `sql.query` meta
+    pub(crate) sql_query: bool,
+}
+
+/// Flags the spans of a trace chunk that are top-level, the entry point of a service in the trace.
+///
+/// A span is top-level when it has no parent, when its parent is not part of the chunk, or when
+/// its parent belongs to another service. Spans of partially flushed traces, whose parent was
+/// passed on in another chunk, are flagged through their trace state by the
+/// [`DatadogSpanProcessor`] instead. The storage is reused from one chunk to the next.
+/// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/traceutil/trace.go#L110
+///
+/// [`DatadogSpanProcessor`]: crate::DatadogSpanProcessor
+#[derive(Default)]
+pub(crate) struct TopLevelSpans<'a> {
+    services: HashMap<SpanId, &'a str>,
+    top_level: Vec<bool>,
+}
+
+impl<'a> TopLevelSpans<'a> {
+    /// Flags the spans of a trace chunk, given the names of its spans in the same order.
+    pub(crate) fn flag(
+        &mut self,
+        trace: &[trace::SpanData],
+        names: &'a [SpanNames<'_>],
+    ) -> &[bool] {
+        self.services.clear();
+        self.services.extend(
+            trace
+                .iter()
+                .zip(names)
+                .map(|(span, names)| (span.span_context.span_id(), &*names.service)),
+        );
+
+        self.top_level.clear();
+        self.top_level
+            .extend(trace.iter().zip(names).map(|(span, names)| {
+                if span.parent_span_id == SpanId::INVALID {
+                    return true;
+                }
+                if let Some(top_level) = span.span_context.trace_state().top_level() {
+                    return top_level;
+                }
+                self.services
+                    .get(&span.parent_span_id)
+                    .map_or(true, |parent_service| *parent_service != names.service)
+            }));
+        &self.top_level
+    }
+}
+
 /// Wrap type for errors from opentelemetry datadog exporter
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -149,46 +470,28 @@ impl ApiVersion {
     pub(crate) fn encode(
         self,
         model_config: &ModelConfig,
-        traces: Vec<&[trace::SpanData]>,
+        traces: &[&[trace::SpanData]],
         mapping: &Mapping,
         unified_tags: &UnifiedTags,
         resource: Option<&Resource>,
+        buffers: &BufferPool,
     ) -> Result<Vec<u8>, Error> {
         match self {
             Self::Version03 => v03::encode(
                 model_config,
                 traces,
-                |span, config| match &mapping.service_name {
-                    Some(f) => f(span, config),
-                    None => default_service_name_mapping(span, config),
-                },
-                |span, config| match &mapping.name {
-                    Some(f) => f(span, config),
-                    None => default_name_mapping(span, config),
-                },
-                |span, config| match &mapping.resource {
-                    Some(f) => f(span, config),
-                    None => default_resource_mapping(span, config),
-                },
+                mapping,
+                unified_tags,
                 resource,
+                buffers,
             ),
             Self::Version05 => v05::encode(
                 model_config,
                 traces,
-                |span, config| match &mapping.service_name {
-                    Some(f) => f(span, config),
-                    None => default_service_name_mapping(span, config),
-                },
-                |span, config| match &mapping.name {
-                    Some(f) => f(span, config),
-                    None => default_name_mapping(span, config),
-                },
-                |span, config| match &mapping.resource {
-                    Some(f) => f(span, config),
-                    None => default_resource_mapping(span, config),
-                },
+                mapping,
                 unified_tags,
                 resource,
+                buffers,
             ),
         }
     }
@@ -197,6 +500,7 @@ impl ApiVersion {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::testing::{decode_v03, decode_v05, DecodedSpan};
     use base64::{engine::general_purpose::STANDARD, Engine};
     use opentelemetry::InstrumentationScope;
     use opentelemetry::{
@@ -258,16 +562,320 @@ pub(crate) mod tests {
             .build();
         let encoded = STANDARD.encode(ApiVersion::Version03.encode(
             &model_config,
-            traces.iter().map(|x| &x[..]).collect(),
+            &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
             &Mapping::empty(),
             &UnifiedTags::new(),
             Some(&resource),
+            &BufferPool::default(),
         )?);
 
         assert_eq!(encoded.as_str(), "kZGMpHR5cGWjd2Vip3NlcnZpY2Wsc2VydmljZV9uYW1lpG5hbWWpY29tcG9uZW\
         50qHJlc291cmNlqHJlc291cmNlqHRyYWNlX2lkzwAAAAAAAAAHp3NwYW5faWTPAAAAAAAAAGOpcGFyZW50X2lkzwAAAA\
         AAAAABpXN0YXJ00wAAAAAAAAAAqGR1cmF0aW9u0wAAAAA7msoApWVycm9y0gAAAACkbWV0YYKpaG9zdC5uYW1lpHRlc3\
-        Spc3Bhbi50eXBlo3dlYqdtZXRyaWNzgbVfc2FtcGxpbmdfcHJpb3JpdHlfdjHLAAAAAAAAAAA=");
+        Spc3Bhbi50eXBlo3dlYqdtZXRyaWNzg7Vfc2FtcGxpbmdfcHJpb3JpdHlfdjHLAAAAAAAAAACqX3RvcF9sZXZlbMs/8A\
+        AAAAAAAKxfZGQubWVhc3VyZWTLP/AAAAAAAAA=");
+
+        let decoded = decode_v03(&STANDARD.decode(encoded)?)?;
+        assert_eq!(decoded.len(), 1);
+        let span = &decoded[0][0];
+        assert_eq!((span.trace_id, span.span_id, span.parent_id), (7, 99, 1));
+        assert_eq!(span.service, "service_name");
+        assert_eq!(span.name, "component");
+        assert_eq!(span.span_type, "web");
+        assert_eq!(span.meta["host.name"], "test");
+        assert_eq!(span.metrics["_sampling_priority_v1"], 0.0);
+        assert_eq!(span.metrics["_top_level"], 1.0);
+        assert_eq!(span.metrics["_dd.measured"], 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_128_bit_trace_id() -> Result<(), Box<dyn std::error::Error>> {
+        let traces = [vec![
+            get_span(0x640cfd8d00000000_0000000000000007, 1, 99),
+            get_span(0x640cfd8d00000000_0000000000000007, 99, 100),
+        ]];
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+            ..Default::default()
+        };
+
+        for version in [ApiVersion::Version03, ApiVersion::Version05] {
+            let encoded = version.encode(
+                &model_config,
+                &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                None,
+                &BufferPool::default(),
+            )?;
+            let count = |needle: &[u8]| {
+                encoded
+                    .windows(needle.len())
+                    .filter(|window| *window == needle)
+                    .count()
+            };
+            // written once per trace chunk, and interned once in v05
+            assert_eq!(count(DD_TRACE_ID_HIGH_KEY.as_bytes()), 1);
+            assert_eq!(count(b"640cfd8d00000000"), 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_propagated_tags_and_origin() -> Result<(), Box<dyn std::error::Error>> {
+        let trace_state = crate::DatadogTraceStateBuilder::default()
+            .with_origin("synthetics")
+            .with_propagated_tag("_dd.p.dm", "-4")
+            .build();
+        let mut traces = [vec![get_span(7, 1, 99), get_span(7, 99, 100)]];
+        for span in traces[0].iter_mut() {
+            span.span_context = SpanContext::new(
+                span.span_context.trace_id(),
+                span.span_context.span_id(),
+                TraceFlags::default(),
+                false,
+                trace_state.clone(),
+            );
+        }
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+            ..Default::default()
+        };
+
+        let count = |encoded: &[u8], needle: &str| {
+            encoded
+                .windows(needle.len())
+                .filter(|window| *window == needle.as_bytes())
+                .count()
+        };
+
+        let encoded = ApiVersion::Version03.encode(
+            &model_config,
+            &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            &Mapping::empty(),
+            &UnifiedTags::new(),
+            None,
+            &BufferPool::default(),
+        )?;
+        // origin is set on every span, propagated tags on the first span of the chunk
+        assert_eq!(count(&encoded, DD_ORIGIN_KEY), 2);
+        assert_eq!(count(&encoded, "_dd.p.dm"), 1);
+
+        let encoded = ApiVersion::Version05.encode(
+            &model_config,
+            &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            &Mapping::empty(),
+            &UnifiedTags::new(),
+            None,
+            &BufferPool::default(),
+        )?;
+        assert_eq!(count(&encoded, DD_ORIGIN_KEY), 1);
+        assert_eq!(count(&encoded, "synthetics"), 1);
+        assert_eq!(count(&encoded, "_dd.p.dm"), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_sampling_rate_metrics() -> Result<(), Box<dyn std::error::Error>> {
+        let trace_state = crate::DatadogTraceStateBuilder::default()
+            .with_sampling_rule_rate(0.5)
+            .with_sampling_limit_rate(1.0)
+            .build();
+        let mut traces = [vec![get_span(7, 0, 99), get_span(7, 99, 100)]];
+        for span in traces[0].iter_mut() {
+            span.span_context = SpanContext::new(
+                span.span_context.trace_id(),
+                span.span_context.span_id(),
+                TraceFlags::SAMPLED,
+                false,
+                trace_state.clone(),
+            );
+        }
+
+        let root = &traces[0][0];
+        assert_eq!(
+            sampling_rate_metrics(root),
+            [(DD_RULE_PSR_KEY, 0.5), (DD_LIMIT_PSR_KEY, 1.0)]
+        );
+        // children inherit the trace state, the rates are only written on the root span
+        assert!(sampling_rate_metrics(&traces[0][1]).is_empty());
+
+        for api_version in [ApiVersion::Version03, ApiVersion::Version05] {
+            let encoded = api_version.encode(
+                &ModelConfig::default(),
+                &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                None,
+                &BufferPool::default(),
+            )?;
+            let count = encoded
+                .windows(DD_RULE_PSR_KEY.len())
+                .filter(|window| *window == DD_RULE_PSR_KEY.as_bytes())
+                .count();
+            assert_eq!(count, 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_global_tags() -> Result<(), Box<dyn std::error::Error>> {
+        let traces = [vec![get_span(7, 1, 99), get_span(7, 99, 100)]];
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+            ..Default::default()
+        };
+        let mut unified_tags = UnifiedTags::new();
+        unified_tags.global_tags = vec![("team".to_string(), "apm".to_string())];
+
+        for (version, expected) in [(ApiVersion::Version03, 2), (ApiVersion::Version05, 1)] {
+            let encoded = version.encode(
+                &model_config,
+                &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                &Mapping::empty(),
+                &unified_tags,
+                None,
+                &BufferPool::default(),
+            )?;
+            // written on every span, and interned once in v05
+            let count = encoded
+                .windows(b"team".len())
+                .filter(|window| *window == b"team")
+                .count();
+            assert_eq!(count, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_semconv_mapping() -> Result<(), Box<dyn std::error::Error>> {
+        let mut span = get_span(7, 1, 99);
+        span.span_kind = SpanKind::Server;
+        span.attributes = vec![
+            KeyValue::new("http.request.method", "GET"),
+            KeyValue::new("http.route", "/users/{id}"),
+        ];
+        let traces = [vec![span]];
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+            ..Default::default()
+        };
+        let mapping = Mapping::empty().with_semconv();
+
+        for version in [ApiVersion::Version03, ApiVersion::Version05] {
+            let encoded = version.encode(
+                &model_config,
+                &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                &mapping,
+                &UnifiedTags::new(),
+                None,
+                &BufferPool::default(),
+            )?;
+            for expected in ["http.server.request", "GET /users/{id}", "web"] {
+                assert!(encoded
+                    .windows(expected.len())
+                    .any(|window| window == expected.as_bytes()));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_top_level_and_measured() -> Result<(), Box<dyn std::error::Error>> {
+        let mut root = get_span(7, 0, 1);
+        root.span_kind = SpanKind::Server;
+        let mut internal = get_span(7, 1, 2);
+        internal.span_kind = SpanKind::Internal;
+        // a client span of another service
+        let mut client = get_span(7, 1, 3);
+        client
+            .attributes
+            .push(KeyValue::new("service.name", "other_service"));
+        let traces = [vec![root, internal, client]];
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+            ..Default::default()
+        };
+        let mut mapping = Mapping::empty().with_semconv();
+
+        for span_kind_measuring in [true, false] {
+            mapping.span_kind_measuring = span_kind_measuring;
+            let encoded = ApiVersion::Version05.encode(
+                &model_config,
+                &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                &mapping,
+                &UnifiedTags::new(),
+                None,
+                &BufferPool::default(),
+            )?;
+            let decoded = decode_v05(&encoded)?;
+            let flags: Vec<_> = decoded[0]
+                .iter()
+                .map(|span| {
+                    (
+                        span.metrics.contains_key("_top_level"),
+                        span.metrics.contains_key("_dd.measured"),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                flags,
+                vec![
+                    (true, span_kind_measuring),
+                    (false, false),
+                    (true, span_kind_measuring)
+                ]
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_sql_obfuscation() -> Result<(), Box<dyn std::error::Error>> {
+        let mut span = get_span(7, 1, 99);
+        span.span_kind = SpanKind::Client;
+        span.attributes = vec![
+            KeyValue::new("db.system", "postgresql"),
+            KeyValue::new("db.statement", "SELECT * FROM users WHERE id = 42"),
+        ];
+        let traces = [vec![span]];
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+            ..Default::default()
+        };
+        let mut mapping = Mapping::empty().with_semconv();
+
+        for (sql, resource) in [
+            (false, "SELECT * FROM users WHERE id = 42"),
+            (true, "SELECT * FROM users WHERE id = ?"),
+        ] {
+            mapping.obfuscation.sql = sql;
+            let sql_query = sql.then(|| resource.to_string());
+            for api_version in [ApiVersion::Version03, ApiVersion::Version05] {
+                let encoded = api_version.encode(
+                    &model_config,
+                    &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                    &mapping,
+                    &UnifiedTags::new(),
+                    None,
+                    &BufferPool::default(),
+                )?;
+                let decoded = match api_version {
+                    ApiVersion::Version03 => decode_v03(&encoded)?,
+                    ApiVersion::Version05 => decode_v05(&encoded)?,
+                };
+                assert_eq!(decoded[0][0].resource, resource);
+                assert_eq!(decoded[0][0].meta.get("sql.query"), sql_query.as_ref());
+            }
+        }
 
         Ok(())
     }
@@ -279,7 +887,7 @@ pub(crate) mod tests {
             service_name: "service_name".to_string(),
             ..Default::default()
         };
-        let resource = Resource::builder()
+        let resource = Resource::builder_empty()
             .with_attribute(KeyValue::new("host.name", "test"))
             .build();
 
@@ -288,21 +896,77 @@ pub(crate) mod tests {
         unified_tags.set_version(Some(String::from("test-version")));
         unified_tags.set_service(Some(String::from("test-service")));
 
-        let _encoded = STANDARD.encode(ApiVersion::Version05.encode(
+        let encoded = ApiVersion::Version05.encode(
             &model_config,
-            traces.iter().map(|x| &x[..]).collect(),
+            &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
             &Mapping::empty(),
             &unified_tags,
             Some(&resource),
-        )?);
-
-        // TODO: Need someone to generate the expected result or instructions to do so.
-        // assert_eq!(encoded.as_str(), "kp6jd2VirHNlcnZpY2VfbmFtZaljb21wb25lbnSocmVzb3VyY2WpaG9zdC5uYW\
-        // 1lpHRlc3Snc2VydmljZax0ZXN0LXNlcnZpY2WjZW52qHRlc3QtZW52p3ZlcnNpb26sdGVzdC12ZXJzaW9uqXNwYW4udH\
-        // lwZbVfc2FtcGxpbmdfcHJpb3JpdHlfdjGRkZzOAAAAAc4AAAACzgAAAAPPAAAAAAAAAAfPAAAAAAAAAGPPAAAAAAAAAA\
-        // HTAAAAAAAAAADTAAAAADuaygDSAAAAAIXOAAAABM4AAAAFzgAAAAbOAAAAB84AAAAIzgAAAAnOAAAACs4AAAALzgAAAA\
-        // zOAAAAAIHOAAAADcsAAAAAAAAAAM4AAAAA");
+            &BufferPool::default(),
+        )?;
+
+        let decoded = decode_v05(&encoded)?;
+        assert_eq!(
+            decoded,
+            vec![vec![DecodedSpan {
+                service: "service_name".to_string(),
+                name: "component".to_string(),
+                resource: "resource".to_string(),
+                trace_id: 7,
+                span_id: 99,
+                parent_id: 1,
+                start: 0,
+                duration: 1_000_000_000,
+                error: 0,
+                meta: [
+                    ("host.name", "test"),
+                    ("service", "test-service"),
+                    ("env", "test-env"),
+                    ("version", "test-version"),
+                    ("span.type", "web"),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+                metrics: [
+                    // the trace state of the span has no priority sampling
+                    (
+                        "_sampling_priority_v1",
+                        if cfg!(feature = "agent-sampling") {
+                            0.0
+                        } else {
+                            1.0
+                        },
+                    ),
+                    // a client span without parent in the chunk
+                    ("_top_level", 1.0),
+                    ("_dd.measured", 1.0),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+                span_type: "web".to_string(),
+            }]]
+        );
 
         Ok(())
     }
+
+    #[test]
+    fn test_tag_inject_errors() {
+        let trace_id = 0xdead_beef;
+        propagator::record_inject_error(TraceId::from_u128(trace_id), "inject_max_size");
+
+        // the local root span has a remote parent
+        let mut batch = vec![get_span(trace_id, 2, 3), get_span(trace_id, 99, 2)];
+        tag_inject_errors(&mut batch);
+        let error = KeyValue::new(DD_PROPAGATION_ERROR_KEY, "inject_max_size");
+        assert!(!batch[0].attributes.contains(&error));
+        assert!(batch[1].attributes.contains(&error));
+
+        // the error is only written once
+        let mut batch = vec![get_span(trace_id, 99, 4)];
+        tag_inject_errors(&mut batch);
+        assert!(!batch[0].attributes.contains(&error));
+    }
 }
@@ -0,0 +1,479 @@
+//! Obfuscation of the resource names of database spans.
+//!
+//! Database spans are commonly named after their statement, which holds literals such as user
+//! ids or emails. Replacing the literals keeps personal data out of Datadog, and keeps the number
+//! of distinct resources low. The rules follow the Datadog agent obfuscator:
+//! <https://github.com/DataDog/datadog-agent/tree/7.52.0/pkg/obfuscate>
+
+use crate::exporter::model::semconv;
+use opentelemetry_sdk::trace::SpanData;
+
+/// Placeholder replacing the obfuscated literals
+const OBFUSCATED: &str = "?";
+
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/obfuscate/redis.go#L18
+const REDIS_TRUNCATION_MARK: &str = "...";
+const REDIS_MAX_COMMANDS: usize = 3;
+const REDIS_COMPOUND_COMMANDS: [&str; 6] =
+    ["CLIENT", "CLUSTER", "COMMAND", "CONFIG", "DEBUG", "SCRIPT"];
+
+/// Obfuscation rules applied to resource names, all disabled by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Obfuscation {
+    pub(crate) sql: bool,
+    pub(crate) redis: bool,
+    pub(crate) mongodb: bool,
+    pub(crate) elasticsearch: bool,
+}
+
+/// The obfuscator applied to the resource of a span.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Obfuscator {
+    Sql,
+    Redis,
+    Json,
+}
+
+impl Obfuscation {
+    /// The enabled obfuscator for the database of the span, from its `db.system` attribute, or
+    /// from its span type when the database is unknown.
+    pub(crate) fn obfuscator(&self, span: &SpanData, span_type: &str) -> Option<Obfuscator> {
+        let db_type = semconv::db_system(span).map(|db_system| semconv::db_type(&db_system));
+        match db_type
+            .filter(|db_type| *db_type != "db")
+            .unwrap_or(span_type)
+        {
+            "sql" | "cassandra" if self.sql => Some(Obfuscator::Sql),
+            "redis" if self.redis => Some(Obfuscator::Redis),
+            "mongodb" if self.mongodb => Some(Obfuscator::Json),
+            "elasticsearch" if self.elasticsearch => Some(Obfuscator::Json),
+            _ => None,
+        }
+    }
+}
+
+impl Obfuscator {
+    pub(crate) fn apply(self, resource: &str) -> String {
+        match self {
+            Obfuscator::Sql => obfuscate_sql(resource),
+            Obfuscator::Redis => quantize_redis(resource),
+            Obfuscator::Json => obfuscate_json(resource),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SqlToken<'a> {
+    /// Keywords, identifiers and operators, kept as is
+    Text(&'a str),
+    /// String and number literals, booleans, nulls and bind parameters
+    Literal,
+    Comma,
+    Open,
+    Close,
+}
+
+fn tokenize_sql(query: &str) -> Vec<SqlToken<'_>> {
+    let bytes = query.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+        match c {
+            c if c.is_ascii_whitespace() => {
+                pos += 1;
+                continue;
+            }
+            b'-' if bytes.get(pos + 1) == Some(&b'-') => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                pos = query[pos + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |end| pos + 2 + end + 2);
+                continue;
+            }
+            b',' => {
+                tokens.push(SqlToken::Comma);
+                pos += 1;
+            }
+            b'(' => {
+                tokens.push(SqlToken::Open);
+                pos += 1;
+            }
+            b')' => {
+                tokens.push(SqlToken::Close);
+                pos += 1;
+            }
+            b'\'' => {
+                // quotes are escaped by doubling them
+                pos += 1;
+                while pos < bytes.len() {
+                    if bytes[pos] == b'\'' {
+                        if bytes.get(pos + 1) == Some(&b'\'') {
+                            pos += 2;
+                            continue;
+                        }
+                        pos += 1;
+                        break;
+                    }
+                    pos += 1;
+                }
+                tokens.push(SqlToken::Literal);
+            }
+            b'"' | b'`' | b'[' => {
+                // quoted identifiers
+                let end = if c == b'[' { b']' } else { c };
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != end {
+                    pos += 1;
+                }
+                pos = (pos + 1).min(bytes.len());
+                tokens.push(SqlToken::Text(&query[start..pos]));
+            }
+            c if c.is_ascii_digit()
+                || (c == b'.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit)) =>
+            {
+                while pos < bytes.len()
+                    && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'.')
+                {
+                    // exponent sign
+                    if matches!(bytes[pos], b'e' | b'E')
+                        && matches!(bytes.get(pos + 1), Some(b'+' | b'-'))
+                    {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+                tokens.push(SqlToken::Literal);
+            }
+            b'?' | b'$' | b':' | b'@' | b'%'
+                if bytes
+                    .get(pos + 1)
+                    .is_some_and(|next| next.is_ascii_alphanumeric() || *next == b'_')
+                    || c == b'?' =>
+            {
+                // bind parameters: ?, $1, :name, @name, %s
+                pos += 1;
+                while pos < bytes.len()
+                    && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_')
+                {
+                    pos += 1;
+                }
+                tokens.push(SqlToken::Literal);
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 => {
+                while pos < bytes.len()
+                    && (bytes[pos].is_ascii_alphanumeric()
+                        || matches!(bytes[pos], b'_' | b'.' | b'$')
+                        || bytes[pos] >= 0x80)
+                {
+                    pos += 1;
+                }
+                let word = &query[start..pos];
+                if ["null", "true", "false"]
+                    .iter()
+                    .any(|literal| word.eq_ignore_ascii_case(literal))
+                {
+                    tokens.push(SqlToken::Literal);
+                } else {
+                    tokens.push(SqlToken::Text(word));
+                }
+            }
+            _ => {
+                // operators
+                while pos < bytes.len()
+                    && !bytes[pos].is_ascii_alphanumeric()
+                    && !bytes[pos].is_ascii_whitespace()
+                    && !matches!(bytes[pos], b',' | b'(' | b')' | b'\'' | b'"' | b'`' | b'_')
+                    && bytes[pos] < 0x80
+                {
+                    pos += 1;
+                }
+                if pos == start {
+                    pos += 1;
+                }
+                tokens.push(SqlToken::Text(&query[start..pos]));
+            }
+        }
+    }
+    tokens
+}
+
+/// Replaces the literals of a SQL query, and collapses lists of literals such as `IN` lists or
+/// `VALUES` rows into a single `( ? )` group.
+pub(crate) fn obfuscate_sql(query: &str) -> String {
+    let mut tokens: Vec<SqlToken<'_>> = Vec::new();
+    let mut groups = Vec::new();
+    for token in tokenize_sql(query) {
+        match token {
+            SqlToken::Open => {
+                groups.push(tokens.len());
+                tokens.push(token);
+            }
+            SqlToken::Close => {
+                let Some(start) = groups.pop() else {
+                    tokens.push(token);
+                    continue;
+                };
+                let literals_only = tokens[start + 1..]
+                    .iter()
+                    .all(|token| matches!(token, SqlToken::Literal | SqlToken::Comma));
+                if literals_only && start + 1 < tokens.len() {
+                    tokens.truncate(start);
+                    // consecutive groups such as `VALUES ( ? ), ( ? )` are merged
+                    if tokens.ends_with(&[
+                        SqlToken::Open,
+                        SqlToken::Literal,
+                        SqlToken::Close,
+                        SqlToken::Comma,
+                    ]) {
+                        tokens.pop();
+                        continue;
+                    }
+                    tokens.extend([SqlToken::Open, SqlToken::Literal, SqlToken::Close]);
+                } else {
+                    tokens.push(token);
+                }
+            }
+            _ => tokens.push(token),
+        }
+    }
+
+    let mut obfuscated = String::with_capacity(query.len());
+    for token in tokens {
+        let text = match token {
+            SqlToken::Text(text) => text,
+            SqlToken::Literal => OBFUSCATED,
+            SqlToken::Comma => {
+                obfuscated.push(',');
+                continue;
+            }
+            SqlToken::Open => "(",
+            SqlToken::Close => ")",
+        };
+        if !obfuscated.is_empty() {
+            obfuscated.push(' ');
+        }
+        obfuscated.push_str(text);
+    }
+    obfuscated
+}
+
+/// Keeps the command names of a Redis query, at most 3 of them.
+pub(crate) fn quantize_redis(query: &str) -> String {
+    let mut commands = Vec::new();
+    let mut truncated = false;
+    for line in query.lines() {
+        if commands.len() == REDIS_MAX_COMMANDS {
+            break;
+        }
+        let mut args = line.split_whitespace();
+        let Some(command) = args.next() else {
+            continue;
+        };
+        if command.ends_with(REDIS_TRUNCATION_MARK) {
+            truncated = true;
+            continue;
+        }
+        let mut command = command.to_uppercase();
+        if REDIS_COMPOUND_COMMANDS.contains(&command.as_str()) {
+            if let Some(subcommand) = args.next() {
+                if subcommand.ends_with(REDIS_TRUNCATION_MARK) {
+                    truncated = true;
+                    continue;
+                }
+                command.push(' ');
+                command.push_str(&subcommand.to_uppercase());
+            }
+        }
+        commands.push(command);
+        truncated = false;
+    }
+    if commands.len() == REDIS_MAX_COMMANDS || truncated {
+        commands.push(REDIS_TRUNCATION_MARK.to_string());
+    }
+    commands.join(" ")
+}
+
+/// Replaces every value of a JSON document, or of each line of a newline delimited JSON body,
+/// keeping the keys in their order and the structure.
+pub(crate) fn obfuscate_json(body: &str) -> String {
+    let mut obfuscated = String::with_capacity(body.len());
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        if !obfuscated.is_empty() {
+            obfuscated.push('\n');
+        }
+        if serde_json::from_str::<serde_json::Value>(line).is_ok() {
+            obfuscate_json_line(line, &mut obfuscated);
+        } else {
+            obfuscated.push_str(OBFUSCATED);
+        }
+    }
+    obfuscated
+}
+
+/// Walks a valid JSON document, copying the structure and the keys in their order, and replacing
+/// the values with `"?"`.
+fn obfuscate_json_line(line: &str, obfuscated: &mut String) {
+    // whether each open container is an object
+    let mut objects = Vec::new();
+    let mut expects_key = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '{' | '[' => {
+                objects.push(c == '{');
+                expects_key = c == '{';
+                obfuscated.push(c);
+            }
+            '}' | ']' => {
+                objects.pop();
+                expects_key = false;
+                obfuscated.push(c);
+            }
+            ',' => {
+                expects_key = objects.last() == Some(&true);
+                obfuscated.push(c);
+            }
+            ':' => {
+                expects_key = false;
+                obfuscated.push(c);
+            }
+            '"' => {
+                let mut end = line.len();
+                while let Some((idx, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => {
+                            end = idx + 1;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                if expects_key {
+                    obfuscated.push_str(&line[start..end]);
+                } else {
+                    obfuscated.push_str("\"?\"");
+                }
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                // numbers, booleans and nulls
+                while chars
+                    .next_if(|(_, c)| !matches!(c, ',' | ']' | '}') && !c.is_whitespace())
+                    .is_some()
+                {}
+                obfuscated.push_str("\"?\"");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::model::tests::get_span;
+    use opentelemetry::KeyValue;
+
+    #[test]
+    fn test_obfuscate_sql() {
+        for (query, expected) in [
+            (
+                "SELECT * FROM users WHERE id = 42 AND email = 'john@doe.com'",
+                "SELECT * FROM users WHERE id = ? AND email = ?",
+            ),
+            (
+                "select name from users where id in (1, 2, 3) and active = true",
+                "select name from users where id in ( ? ) and active = ?",
+            ),
+            (
+                "INSERT INTO t (a, b) VALUES (1, 'it''s'), (2, NULL)",
+                "INSERT INTO t ( a, b ) VALUES ( ? )",
+            ),
+            (
+                "UPDATE \"users\" SET score = -1.5e+3 WHERE id = $1 -- comment",
+                "UPDATE \"users\" SET score = - ? WHERE id = ?",
+            ),
+            (
+                "SELECT count(*) FROM t1 /* hint */ WHERE x >= :min",
+                "SELECT count ( * ) FROM t1 WHERE x >= ?",
+            ),
+        ] {
+            assert_eq!(obfuscate_sql(query), expected);
+        }
+    }
+
+    #[test]
+    fn test_quantize_redis() {
+        assert_eq!(quantize_redis("SET user:42 secret"), "SET");
+        assert_eq!(quantize_redis("client list\nget key"), "CLIENT LIST GET");
+        assert_eq!(
+            quantize_redis("GET a\nSET b c\nDEL d\nINCR e"),
+            "GET SET DEL ..."
+        );
+        assert_eq!(quantize_redis("GET a\nSET..."), "GET ...");
+    }
+
+    #[test]
+    fn test_obfuscate_json() {
+        assert_eq!(
+            obfuscate_json(
+                r#"{"find":"users","filter":{"email":"john@doe.com","age":{"$gt":30}}}"#
+            ),
+            r#"{"find":"?","filter":{"email":"?","age":{"$gt":"?"}}}"#
+        );
+        assert_eq!(
+            obfuscate_json("{\"index\":{}}\n{\"name\":\"john\",\"tags\":[1,2]}\n"),
+            "{\"index\":{}}\n{\"name\":\"?\",\"tags\":[\"?\",\"?\"]}"
+        );
+        assert_eq!(
+            obfuscate_json(r#"{ "z": "a\"b", "a" : [ true, null, -1.5e3 ], "m": {} }"#),
+            r#"{"z":"?","a":["?","?","?"],"m":{}}"#
+        );
+        assert_eq!(obfuscate_json("db.users.find({email: 'x'})"), "?");
+    }
+
+    #[test]
+    fn test_obfuscator_selection() {
+        let span_with = |attributes: Vec<KeyValue>| {
+            let mut span = get_span(7, 1, 99);
+            span.attributes = attributes;
+            span
+        };
+        let all = Obfuscation {
+            sql: true,
+            redis: true,
+            mongodb: true,
+            elasticsearch: true,
+        };
+
+        // disabled by default
+        let span = span_with(vec![KeyValue::new("db.system", "postgresql")]);
+        assert_eq!(Obfuscation::default().obfuscator(&span, "sql"), None);
+
+        // the database of the span takes precedence over its type
+        assert_eq!(all.obfuscator(&span, ""), Some(Obfuscator::Sql));
+        let span = span_with(vec![KeyValue::new("db.system", "redis")]);
+        assert_eq!(all.obfuscator(&span, "sql"), Some(Obfuscator::Redis));
+        let span = span_with(vec![KeyValue::new("db.system", "opensearch")]);
+        assert_eq!(all.obfuscator(&span, "custom"), Some(Obfuscator::Json));
+
+        // falling back to the span type for unknown databases
+        let span = span_with(vec![KeyValue::new("db.system", "exotic")]);
+        assert_eq!(all.obfuscator(&span, "sql"), Some(Obfuscator::Sql));
+        let span = span_with(vec![]);
+        assert_eq!(all.obfuscator(&span, "mongodb"), Some(Obfuscator::Json));
+        assert_eq!(all.obfuscator(&span, "web"), None);
+
+        let sql_disabled = Obfuscation { sql: false, ..all };
+        assert_eq!(sql_disabled.obfuscator(&span, "cassandra"), None);
+    }
+}
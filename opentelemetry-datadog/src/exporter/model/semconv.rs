@@ -0,0 +1,377 @@
+//! Datadog span naming from the OpenTelemetry semantic conventions.
+//!
+//! The rules are the ones applied by the Datadog agent to the spans it receives through OTLP, so
+//! spans look the same whichever way they reach Datadog.
+//! <https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/traceutil/otel_util.go>
+
+use opentelemetry::trace::SpanKind;
+use opentelemetry_sdk::trace::SpanData;
+use std::borrow::Cow;
+
+// Datadog specific attributes overriding the semantic conventions
+static OPERATION_NAME_KEY: &str = "operation.name";
+static RESOURCE_NAME_KEY: &str = "resource.name";
+static SERVICE_NAME_KEY: &str = "service.name";
+static SPAN_TYPE_KEY: &str = "span.type";
+
+// Current and deprecated names of the semantic conventions attributes
+static HTTP_METHOD_KEYS: [&str; 2] = ["http.request.method", "http.method"];
+static HTTP_ROUTE_KEY: &str = "http.route";
+static DB_SYSTEM_KEY: &str = "db.system";
+static DB_STATEMENT_KEYS: [&str; 2] = ["db.query.text", "db.statement"];
+static MESSAGING_SYSTEM_KEY: &str = "messaging.system";
+static MESSAGING_OPERATION_KEY: &str = "messaging.operation";
+static MESSAGING_DESTINATION_KEYS: [&str; 2] =
+    ["messaging.destination.name", "messaging.destination"];
+static RPC_SYSTEM_KEY: &str = "rpc.system";
+static RPC_SERVICE_KEY: &str = "rpc.service";
+static RPC_METHOD_KEY: &str = "rpc.method";
+static FAAS_INVOKED_PROVIDER_KEY: &str = "faas.invoked_provider";
+static FAAS_INVOKED_NAME_KEY: &str = "faas.invoked_name";
+static FAAS_TRIGGER_KEY: &str = "faas.trigger";
+static GRAPHQL_OPERATION_TYPE_KEY: &str = "graphql.operation.type";
+static GRAPHQL_OPERATION_NAME_KEY: &str = "graphql.operation.name";
+static NETWORK_PROTOCOL_NAME_KEY: &str = "network.protocol.name";
+
+static AWS_RPC_SYSTEM: &str = "aws-api";
+static HTTP_OTHER_METHOD: &str = "_OTHER";
+
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/traceutil/otel_util.go#L239
+static SQL_DB_SYSTEMS: [&str; 36] = [
+    "adabas",
+    "cache",
+    "clickhouse",
+    "cloudscape",
+    "cockroachdb",
+    "coldfusion",
+    "db2",
+    "derby",
+    "edb",
+    "filemaker",
+    "firebird",
+    "firstsql",
+    "h2",
+    "hanadb",
+    "hsqldb",
+    "informix",
+    "ingres",
+    "instantdb",
+    "interbase",
+    "mariadb",
+    "maxdb",
+    "mssql",
+    "mysql",
+    "netezza",
+    "oracle",
+    "other_sql",
+    "pervasive",
+    "pointbase",
+    "postgresql",
+    "progress",
+    "redshift",
+    "sqlite",
+    "sybase",
+    "teradata",
+    "trino",
+    "vertica",
+];
+
+fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<Cow<'a, str>> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| kv.value.as_str())
+}
+
+fn first_attribute<'a>(span: &'a SpanData, keys: &[&str]) -> Option<Cow<'a, str>> {
+    keys.iter().find_map(|key| attribute(span, key))
+}
+
+fn span_kind_name(kind: &SpanKind) -> &'static str {
+    match kind {
+        SpanKind::Client => "client",
+        SpanKind::Server => "server",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal => "internal",
+    }
+}
+
+/// Operation name, such as `http.server.request` or `postgresql.query`.
+pub(crate) fn operation_name(span: &SpanData) -> Cow<'_, str> {
+    if let Some(name) = attribute(span, OPERATION_NAME_KEY) {
+        return name;
+    }
+
+    let is_client = span.span_kind == SpanKind::Client;
+    let is_server = span.span_kind == SpanKind::Server;
+
+    if first_attribute(span, &HTTP_METHOD_KEYS).is_some() {
+        if is_server {
+            return "http.server.request".into();
+        }
+        if is_client {
+            return "http.client.request".into();
+        }
+    }
+
+    if is_client {
+        if let Some(db_system) = attribute(span, DB_SYSTEM_KEY) {
+            return format!("{}.query", db_system).to_lowercase().into();
+        }
+    }
+
+    if span.span_kind != SpanKind::Internal {
+        if let (Some(system), Some(operation)) = (
+            attribute(span, MESSAGING_SYSTEM_KEY),
+            attribute(span, MESSAGING_OPERATION_KEY),
+        ) {
+            return format!("{}.{}", system, operation).to_lowercase().into();
+        }
+    }
+
+    if let Some(rpc_system) = attribute(span, RPC_SYSTEM_KEY) {
+        if is_client && rpc_system == AWS_RPC_SYSTEM {
+            return match attribute(span, RPC_SERVICE_KEY) {
+                Some(service) => format!("aws.{}.request", service).to_lowercase().into(),
+                None => "aws.client.request".into(),
+            };
+        }
+        if is_client {
+            return format!("{}.client.request", rpc_system)
+                .to_lowercase()
+                .into();
+        }
+        if is_server {
+            return format!("{}.server.request", rpc_system)
+                .to_lowercase()
+                .into();
+        }
+    }
+
+    if is_client {
+        if let (Some(provider), Some(name)) = (
+            attribute(span, FAAS_INVOKED_PROVIDER_KEY),
+            attribute(span, FAAS_INVOKED_NAME_KEY),
+        ) {
+            return format!("{}.{}.invoke", provider, name)
+                .to_lowercase()
+                .into();
+        }
+    }
+    if is_server {
+        if let Some(trigger) = attribute(span, FAAS_TRIGGER_KEY) {
+            return format!("{}.invoke", trigger).to_lowercase().into();
+        }
+    }
+
+    if attribute(span, GRAPHQL_OPERATION_TYPE_KEY).is_some() {
+        return "graphql.server.request".into();
+    }
+
+    if let Some(protocol) = attribute(span, NETWORK_PROTOCOL_NAME_KEY) {
+        if is_server {
+            return format!("{}.server.request", protocol).to_lowercase().into();
+        }
+        if is_client {
+            return format!("{}.client.request", protocol).to_lowercase().into();
+        }
+    }
+
+    span_kind_name(&span.span_kind).into()
+}
+
+/// Resource name, such as `GET /users/{id}` or the database statement.
+pub(crate) fn resource_name(span: &SpanData) -> Cow<'_, str> {
+    if let Some(resource) = attribute(span, RESOURCE_NAME_KEY) {
+        return resource;
+    }
+
+    if let Some(method) = first_attribute(span, &HTTP_METHOD_KEYS) {
+        let method = if method == HTTP_OTHER_METHOD {
+            "HTTP".into()
+        } else {
+            method
+        };
+        if span.span_kind == SpanKind::Server {
+            if let Some(route) = attribute(span, HTTP_ROUTE_KEY) {
+                return format!("{} {}", method, route).into();
+            }
+        }
+        return method;
+    }
+
+    if let Some(operation) = attribute(span, MESSAGING_OPERATION_KEY) {
+        return match first_attribute(span, &MESSAGING_DESTINATION_KEYS) {
+            Some(destination) => format!("{} {}", operation, destination).into(),
+            None => operation,
+        };
+    }
+
+    if let Some(method) = attribute(span, RPC_METHOD_KEY) {
+        return match attribute(span, RPC_SERVICE_KEY) {
+            Some(service) => format!("{} {}", method, service).into(),
+            None => method,
+        };
+    }
+
+    if let Some(operation_type) = attribute(span, GRAPHQL_OPERATION_TYPE_KEY) {
+        return match attribute(span, GRAPHQL_OPERATION_NAME_KEY) {
+            Some(name) => format!("{} {}", operation_type, name).into(),
+            None => operation_type,
+        };
+    }
+
+    if attribute(span, DB_SYSTEM_KEY).is_some() {
+        if let Some(statement) = first_attribute(span, &DB_STATEMENT_KEYS) {
+            return statement;
+        }
+    }
+
+    span.name.as_ref().into()
+}
+
+/// Service name, from the `service.name` span attribute when the span overrides it.
+pub(crate) fn service_name<'a>(span: &'a SpanData, default: &'a str) -> Cow<'a, str> {
+    attribute(span, SERVICE_NAME_KEY).unwrap_or(default.into())
+}
+
+/// Span type, such as `web` or `sql`, driving how Datadog renders the span.
+pub(crate) fn span_type(span: &SpanData) -> Cow<'_, str> {
+    if let Some(span_type) = attribute(span, SPAN_TYPE_KEY) {
+        return span_type;
+    }
+
+    match span.span_kind {
+        SpanKind::Server => "web".into(),
+        SpanKind::Client => match attribute(span, DB_SYSTEM_KEY) {
+            Some(db_system) => db_type(&db_system).into(),
+            None => "http".into(),
+        },
+        _ => "custom".into(),
+    }
+}
+
+/// The database system of the span, from its `db.system` attribute.
+pub(crate) fn db_system(span: &SpanData) -> Option<Cow<'_, str>> {
+    attribute(span, DB_SYSTEM_KEY)
+}
+
+/// The span type of the spans of a database system.
+pub(crate) fn db_type(db_system: &str) -> &'static str {
+    match db_system {
+        "redis" => "redis",
+        "memcached" => "memcached",
+        "cassandra" => "cassandra",
+        "mongodb" => "mongodb",
+        "elasticsearch" | "opensearch" => "elasticsearch",
+        system if SQL_DB_SYSTEMS.contains(&system) => "sql",
+        _ => "db",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::model::tests::get_span;
+    use opentelemetry::KeyValue;
+
+    fn span_with(kind: SpanKind, attributes: Vec<KeyValue>) -> SpanData {
+        let mut span = get_span(7, 1, 99);
+        span.span_kind = kind;
+        span.attributes = attributes;
+        span
+    }
+
+    #[test]
+    fn test_http_spans() {
+        let server = span_with(
+            SpanKind::Server,
+            vec![
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("http.route", "/users/{id}"),
+            ],
+        );
+        assert_eq!(operation_name(&server), "http.server.request");
+        assert_eq!(resource_name(&server), "GET /users/{id}");
+        assert_eq!(span_type(&server), "web");
+
+        let client = span_with(
+            SpanKind::Client,
+            vec![KeyValue::new("http.method", "_OTHER")],
+        );
+        assert_eq!(operation_name(&client), "http.client.request");
+        assert_eq!(resource_name(&client), "HTTP");
+        assert_eq!(span_type(&client), "http");
+    }
+
+    #[test]
+    fn test_db_spans() {
+        let span = span_with(
+            SpanKind::Client,
+            vec![
+                KeyValue::new("db.system", "postgresql"),
+                KeyValue::new("db.statement", "SELECT * FROM users"),
+            ],
+        );
+        assert_eq!(operation_name(&span), "postgresql.query");
+        assert_eq!(resource_name(&span), "SELECT * FROM users");
+        assert_eq!(span_type(&span), "sql");
+
+        let span = span_with(SpanKind::Client, vec![KeyValue::new("db.system", "redis")]);
+        assert_eq!(resource_name(&span), "resource");
+        assert_eq!(span_type(&span), "redis");
+    }
+
+    #[test]
+    fn test_messaging_and_rpc_spans() {
+        let span = span_with(
+            SpanKind::Producer,
+            vec![
+                KeyValue::new("messaging.system", "Kafka"),
+                KeyValue::new("messaging.operation", "publish"),
+                KeyValue::new("messaging.destination.name", "orders"),
+            ],
+        );
+        assert_eq!(operation_name(&span), "kafka.publish");
+        assert_eq!(resource_name(&span), "publish orders");
+        assert_eq!(span_type(&span), "custom");
+
+        let span = span_with(
+            SpanKind::Client,
+            vec![
+                KeyValue::new("rpc.system", "aws-api"),
+                KeyValue::new("rpc.service", "DynamoDB"),
+                KeyValue::new("rpc.method", "GetItem"),
+            ],
+        );
+        assert_eq!(operation_name(&span), "aws.dynamodb.request");
+        assert_eq!(resource_name(&span), "GetItem DynamoDB");
+
+        let span = span_with(SpanKind::Server, vec![KeyValue::new("rpc.system", "grpc")]);
+        assert_eq!(operation_name(&span), "grpc.server.request");
+    }
+
+    #[test]
+    fn test_overrides_and_fallbacks() {
+        let span = span_with(
+            SpanKind::Server,
+            vec![
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("operation.name", "custom.operation"),
+                KeyValue::new("resource.name", "custom resource"),
+                KeyValue::new("service.name", "custom-service"),
+                KeyValue::new("span.type", "worker"),
+            ],
+        );
+        assert_eq!(operation_name(&span), "custom.operation");
+        assert_eq!(resource_name(&span), "custom resource");
+        assert_eq!(service_name(&span, "service"), "custom-service");
+        assert_eq!(span_type(&span), "worker");
+
+        let span = span_with(SpanKind::Internal, vec![]);
+        assert_eq!(operation_name(&span), "internal");
+        assert_eq!(resource_name(&span), "resource");
+        assert_eq!(service_name(&span, "service"), "service");
+    }
+}
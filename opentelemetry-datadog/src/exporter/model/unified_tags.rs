@@ -1,8 +1,14 @@
+/// Environment variable holding the tags added to every span
+const DD_TAGS_ENV_VAR: &str = "DD_TAGS";
+
 /// Unified tags - See: https://docs.datadoghq.com/getting_started/tagging/unified_service_tagging
+#[derive(Clone)]
 pub struct UnifiedTags {
     pub service: UnifiedTagField,
     pub env: UnifiedTagField,
     pub version: UnifiedTagField,
+    /// Global tags read from `DD_TAGS`, written in the meta of every span
+    pub global_tags: Vec<(String, String)>,
 }
 
 impl UnifiedTags {
@@ -11,6 +17,9 @@ impl UnifiedTags {
             service: UnifiedTagField::new(UnifiedTagEnum::Service),
             env: UnifiedTagField::new(UnifiedTagEnum::Env),
             version: UnifiedTagField::new(UnifiedTagEnum::Version),
+            global_tags: std::env::var(DD_TAGS_ENV_VAR)
+                .map(|tags| parse_global_tags(&tags))
+                .unwrap_or_default(),
         }
     }
     pub fn set_service(&mut self, service: Option<String>) {
@@ -30,6 +39,20 @@ impl UnifiedTags {
     }
 }
 
+/// Parses `DD_TAGS`, a list of `key:value` tags separated by commas, or by spaces when there is
+/// no comma. Same as https://github.com/DataDog/dd-trace-go/blob/v1.62.0/internal/env.go#L46
+fn parse_global_tags(tags: &str) -> Vec<(String, String)> {
+    let separator = if tags.contains(',') { ',' } else { ' ' };
+    tags.split(separator)
+        .filter_map(|tag| {
+            let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+            let key = key.trim();
+            (!key.is_empty()).then(|| (key.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+#[derive(Clone)]
 pub struct UnifiedTagField {
     pub value: Option<String>,
     pub kind: UnifiedTagEnum,
@@ -53,6 +76,7 @@ impl UnifiedTagField {
     }
 }
 
+#[derive(Clone)]
 pub enum UnifiedTagEnum {
     Service,
     Version,
@@ -107,6 +131,32 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_global_tags() {
+        temp_env::with_var("DD_TAGS", Some("team:apm, region:eu-west-1,flag"), || {
+            assert_eq!(
+                UnifiedTags::new().global_tags,
+                vec![
+                    ("team".to_string(), "apm".to_string()),
+                    ("region".to_string(), "eu-west-1".to_string()),
+                    ("flag".to_string(), String::new()),
+                ]
+            );
+        });
+        temp_env::with_var("DD_TAGS", Some("team:apm  url:http://host"), || {
+            assert_eq!(
+                UnifiedTags::new().global_tags,
+                vec![
+                    ("team".to_string(), "apm".to_string()),
+                    ("url".to_string(), "http://host".to_string()),
+                ]
+            );
+        });
+        temp_env::with_var_unset("DD_TAGS", || {
+            assert!(UnifiedTags::new().global_tags.is_empty());
+        });
+    }
+
     #[test]
     fn test_version() {
         temp_env::with_var("DD_VERSION", Some("test-version-1.2.3"), || {
@@ -1,30 +1,52 @@
-use crate::exporter::model::{Error, SAMPLING_PRIORITY_KEY};
-use crate::exporter::ModelConfig;
+use crate::exporter::buffers::BufferPool;
+use crate::exporter::model::unified_tags::UnifiedTags;
+use crate::exporter::model::{
+    datadog_trace_id, sampling_rate_metrics, span_event_tags, trace_chunk_tags, Error, SpanNames,
+    TopLevelSpans, DD_MEASURED_KEY, DD_ORIGIN_KEY, DD_TOP_LEVEL_KEY, SAMPLING_PRIORITY_KEY,
+    SQL_QUERY_KEY,
+};
+use crate::exporter::{Mapping, ModelConfig};
+use crate::propagator::DatadogTraceState;
 use opentelemetry::trace::Status;
 use opentelemetry_sdk::trace::SpanData;
 use opentelemetry_sdk::Resource;
 use std::time::SystemTime;
 
-pub(crate) fn encode<S, N, R>(
+pub(crate) fn encode(
     model_config: &ModelConfig,
-    traces: Vec<&[SpanData]>,
-    get_service_name: S,
-    get_name: N,
-    get_resource: R,
+    traces: &[&[SpanData]],
+    mapping: &Mapping,
+    unified_tags: &UnifiedTags,
     resource: Option<&Resource>,
-) -> Result<Vec<u8>, Error>
-where
-    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-{
-    let mut encoded = Vec::new();
+    buffers: &BufferPool,
+) -> Result<Vec<u8>, Error> {
+    // in the order of the spans of all traces
+    let span_names: Vec<SpanNames<'_>> = traces
+        .iter()
+        .flat_map(|trace| trace.iter())
+        .map(|span| mapping.span_names(span, model_config))
+        .collect();
+    let mut top_level_spans = TopLevelSpans::default();
+    let mut span_names = &span_names[..];
+
+    let mut encoded = buffers.take();
     rmp::encode::write_array_len(&mut encoded, traces.len() as u32)?;
 
-    for trace in traces.into_iter() {
+    for trace in traces {
         rmp::encode::write_array_len(&mut encoded, trace.len() as u32)?;
 
-        for span in trace {
+        let (trace_names, rest) = span_names.split_at(trace.len());
+        span_names = rest;
+        let top_level = top_level_spans.flag(trace, trace_names);
+        let chunk_tags = trace_chunk_tags(trace);
+        for (idx, ((span, names), top_level)) in
+            trace.iter().zip(trace_names).zip(top_level).enumerate()
+        {
+            // only the first span of the chunk holds the trace chunk tags
+            let span_chunk_tags = if idx == 0 { &chunk_tags[..] } else { &[] };
+            let origin = span.span_context.trace_state().origin();
+            let event_tags = span_event_tags(span);
+
             // Safe until the year 2262 when Datadog will need to change their API
             let start = span
                 .start_time
@@ -38,36 +60,26 @@ where
                 .map(|x| x.as_nanos() as i64)
                 .unwrap_or(0);
 
-            let mut span_type_found = false;
-            for kv in &span.attributes {
-                if kv.key.as_str() == "span.type" {
-                    span_type_found = true;
-                    rmp::encode::write_map_len(&mut encoded, 12)?;
-                    rmp::encode::write_str(&mut encoded, "type")?;
-                    rmp::encode::write_str(&mut encoded, kv.value.as_str().as_ref())?;
-                    break;
-                }
-            }
-
-            if !span_type_found {
+            if names.span_type.is_empty() {
                 rmp::encode::write_map_len(&mut encoded, 11)?;
+            } else {
+                rmp::encode::write_map_len(&mut encoded, 12)?;
+                rmp::encode::write_str(&mut encoded, "type")?;
+                rmp::encode::write_str(&mut encoded, &names.span_type)?;
             }
 
             // Datadog span name is OpenTelemetry component name - see module docs for more information
             rmp::encode::write_str(&mut encoded, "service")?;
-            rmp::encode::write_str(&mut encoded, get_service_name(span, model_config))?;
+            rmp::encode::write_str(&mut encoded, &names.service)?;
 
             rmp::encode::write_str(&mut encoded, "name")?;
-            rmp::encode::write_str(&mut encoded, get_name(span, model_config))?;
+            rmp::encode::write_str(&mut encoded, &names.name)?;
 
             rmp::encode::write_str(&mut encoded, "resource")?;
-            rmp::encode::write_str(&mut encoded, get_resource(span, model_config))?;
+            rmp::encode::write_str(&mut encoded, &names.resource)?;
 
             rmp::encode::write_str(&mut encoded, "trace_id")?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u128::from_be_bytes(span.span_context.trace_id().to_bytes()) as u64,
-            )?;
+            rmp::encode::write_u64(&mut encoded, datadog_trace_id(span.span_context.trace_id()))?;
 
             rmp::encode::write_str(&mut encoded, "span_id")?;
             rmp::encode::write_u64(
@@ -99,8 +111,19 @@ where
             rmp::encode::write_str(&mut encoded, "meta")?;
             rmp::encode::write_map_len(
                 &mut encoded,
-                (span.attributes.len() + resource.map(|r| r.len()).unwrap_or(0)) as u32,
+                (span.attributes.len()
+                    + resource.map(|r| r.len()).unwrap_or(0)
+                    + unified_tags.global_tags.len()
+                    + span_chunk_tags.len()
+                    + event_tags.len()
+                    + origin.is_some() as usize
+                    + names.sql_query as usize) as u32,
             )?;
+            // global tags come first, so that the span's own tags take precedence
+            for (key, value) in &unified_tags.global_tags {
+                rmp::encode::write_str(&mut encoded, key)?;
+                rmp::encode::write_str(&mut encoded, value)?;
+            }
             if let Some(resource) = resource {
                 for (key, value) in resource.iter() {
                     rmp::encode::write_str(&mut encoded, key.as_str())?;
@@ -111,9 +134,34 @@ where
                 rmp::encode::write_str(&mut encoded, kv.key.as_str())?;
                 rmp::encode::write_str(&mut encoded, kv.value.as_str().as_ref())?;
             }
+            for (key, value) in span_chunk_tags {
+                rmp::encode::write_str(&mut encoded, key)?;
+                rmp::encode::write_str(&mut encoded, value)?;
+            }
+            for (key, value) in &event_tags {
+                rmp::encode::write_str(&mut encoded, key)?;
+                rmp::encode::write_str(&mut encoded, value)?;
+            }
+            if let Some(origin) = origin {
+                rmp::encode::write_str(&mut encoded, DD_ORIGIN_KEY)?;
+                rmp::encode::write_str(&mut encoded, origin)?;
+            }
+            if names.sql_query {
+                rmp::encode::write_str(&mut encoded, SQL_QUERY_KEY)?;
+                rmp::encode::write_str(&mut encoded, &names.resource)?;
+            }
 
+            let rate_metrics = sampling_rate_metrics(span);
+            let measured = mapping.measured(span);
             rmp::encode::write_str(&mut encoded, "metrics")?;
-            rmp::encode::write_map_len(&mut encoded, 1)?;
+            rmp::encode::write_map_len(
+                &mut encoded,
+                1 + rate_metrics.len() as u32 + *top_level as u32 + measured as u32,
+            )?;
+            for (key, rate) in rate_metrics {
+                rmp::encode::write_str(&mut encoded, key)?;
+                rmp::encode::write_f64(&mut encoded, rate)?;
+            }
             rmp::encode::write_str(&mut encoded, SAMPLING_PRIORITY_KEY)?;
             rmp::encode::write_f64(
                 &mut encoded,
@@ -123,6 +171,14 @@ where
                     0.0
                 },
             )?;
+            if *top_level {
+                rmp::encode::write_str(&mut encoded, DD_TOP_LEVEL_KEY)?;
+                rmp::encode::write_f64(&mut encoded, 1.0)?;
+            }
+            if measured {
+                rmp::encode::write_str(&mut encoded, DD_MEASURED_KEY)?;
+                rmp::encode::write_f64(&mut encoded, 1.0)?;
+            }
         }
     }
 
@@ -1,6 +1,11 @@
+use crate::exporter::buffers::BufferPool;
 use crate::exporter::intern::StringInterner;
-use crate::exporter::model::{DD_MEASURED_KEY, SAMPLING_PRIORITY_KEY};
-use crate::exporter::{Error, ModelConfig};
+use crate::exporter::model::{
+    datadog_trace_id, sampling_rate_metrics, span_event_tags, trace_chunk_tags, SpanNames,
+    TopLevelSpans, DD_MEASURED_KEY, DD_ORIGIN_KEY, DD_TOP_LEVEL_KEY, SAMPLING_PRIORITY_KEY,
+    SQL_QUERY_KEY,
+};
+use crate::exporter::{Error, Mapping, ModelConfig};
 use crate::propagator::DatadogTraceState;
 use opentelemetry::trace::Status;
 use opentelemetry_sdk::trace::SpanData;
@@ -10,7 +15,6 @@ use std::time::SystemTime;
 use super::unified_tags::{UnifiedTagField, UnifiedTags};
 
 const SPAN_NUM_ELEMENTS: u32 = 12;
-const METRICS_LEN: u32 = 2;
 const GIT_META_TAGS_COUNT: u32 = if matches!(
     (
         option_env!("DD_GIT_REPOSITORY_URL"),
@@ -68,40 +72,51 @@ const GIT_META_TAGS_COUNT: u32 = if matches!(
 //
 // 		The dictionary in this case would be []string{""}, having only the empty string at index 0.
 //
-pub(crate) fn encode<S, N, R>(
+pub(crate) fn encode(
     model_config: &ModelConfig,
-    traces: Vec<&[SpanData]>,
-    get_service_name: S,
-    get_name: N,
-    get_resource: R,
+    traces: &[&[SpanData]],
+    mapping: &Mapping,
     unified_tags: &UnifiedTags,
     resource: Option<&Resource>,
-) -> Result<Vec<u8>, Error>
-where
-    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-{
-    let mut interner = StringInterner::new();
-    let mut encoded_traces = encode_traces(
+    buffers: &BufferPool,
+) -> Result<Vec<u8>, Error> {
+    let chunk_tags: Vec<Vec<(String, String)>> =
+        traces.iter().map(|trace| trace_chunk_tags(trace)).collect();
+    // names and event tags in the order of the spans of all traces
+    let span_names: Vec<SpanNames<'_>> = traces
+        .iter()
+        .flat_map(|trace| trace.iter())
+        .map(|span| mapping.span_names(span, model_config))
+        .collect();
+    let event_tags: Vec<Vec<(&str, String)>> = traces
+        .iter()
+        .flat_map(|trace| trace.iter().map(span_event_tags))
+        .collect();
+    let mut interner = StringInterner::with_capacity(buffers.dictionary_len().max(128));
+    // the spans are encoded before the dictionary they refer to, which comes first in the payload
+    let mut encoded_traces = buffers.take();
+    let result = encode_traces(
+        &mut encoded_traces,
         &mut interner,
-        model_config,
-        get_service_name,
-        get_name,
-        get_resource,
-        &traces,
+        traces,
+        mapping,
+        &span_names,
+        &chunk_tags,
+        &event_tags,
         unified_tags,
         resource,
-    )?;
-
-    let mut payload = Vec::with_capacity(traces.len() * 512);
-    rmp::encode::write_array_len(&mut payload, 2)?;
-
-    interner.write_dictionary(&mut payload)?;
-
-    payload.append(&mut encoded_traces);
-
-    Ok(payload)
+    );
+    let payload = result.and_then(|()| {
+        let mut payload = buffers.take();
+        payload.reserve(encoded_traces.len() + interner.len() * 16);
+        rmp::encode::write_array_len(&mut payload, 2)?;
+        interner.write_dictionary(&mut payload)?;
+        payload.extend_from_slice(&encoded_traces);
+        Ok(payload)
+    });
+    buffers.set_dictionary_len(interner.len());
+    buffers.put(encoded_traces);
+    payload
 }
 
 fn write_unified_tags<'a>(
@@ -134,44 +149,44 @@ fn get_sampling_priority(_span: &SpanData) -> f64 {
 
 #[cfg(feature = "agent-sampling")]
 fn get_sampling_priority(span: &SpanData) -> f64 {
-    if span.span_context.trace_state().priority_sampling_enabled() {
-        1.0
-    } else {
-        0.0
-    }
-}
-
-fn get_measuring(span: &SpanData) -> f64 {
-    if span.span_context.trace_state().measuring_enabled() {
-        1.0
-    } else {
-        0.0
-    }
+    span.span_context
+        .trace_state()
+        .sampling_priority()
+        .unwrap_or_default()
+        .into()
 }
 
 #[allow(clippy::too_many_arguments)]
-fn encode_traces<'interner, S, N, R>(
+fn encode_traces<'interner>(
+    encoded: &mut Vec<u8>,
     interner: &mut StringInterner<'interner>,
-    model_config: &'interner ModelConfig,
-    get_service_name: S,
-    get_name: N,
-    get_resource: R,
     traces: &'interner [&[SpanData]],
+    mapping: &Mapping,
+    span_names: &'interner [SpanNames<'interner>],
+    chunk_tags: &'interner [Vec<(String, String)>],
+    event_tags: &'interner [Vec<(&'static str, String)>],
     unified_tags: &'interner UnifiedTags,
     resource: Option<&'interner Resource>,
-) -> Result<Vec<u8>, Error>
-where
-    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-{
-    let mut encoded = Vec::new();
-    rmp::encode::write_array_len(&mut encoded, traces.len() as u32)?;
+) -> Result<(), Error> {
+    rmp::encode::write_array_len(encoded, traces.len() as u32)?;
+
+    let mut top_level_spans = TopLevelSpans::default();
+    let mut span_names = span_names;
+    let mut event_tags = event_tags.iter();
+    for (trace, chunk_tags) in traces.iter().zip(chunk_tags) {
+        rmp::encode::write_array_len(encoded, trace.len() as u32)?;
 
-    for trace in traces.iter() {
-        rmp::encode::write_array_len(&mut encoded, trace.len() as u32)?;
+        let (trace_names, rest) = span_names.split_at(trace.len());
+        span_names = rest;
+        let top_level = top_level_spans.flag(trace, trace_names);
+        for (idx, ((span, names), top_level)) in
+            trace.iter().zip(trace_names).zip(top_level).enumerate()
+        {
+            // only the first span of the chunk holds the trace chunk tags
+            let span_chunk_tags = if idx == 0 { &chunk_tags[..] } else { &[] };
+            let origin = span.span_context.trace_state().origin();
+            let span_event_tags = event_tags.next().map(Vec::as_slice).unwrap_or_default();
 
-        for span in trace.iter() {
             // Safe until the year 2262 when Datadog will need to change their API
             let start = span
                 .start_time
@@ -185,41 +200,23 @@ where
                 .map(|x| x.as_nanos() as i64)
                 .unwrap_or(0);
 
-            let mut span_type = interner.intern("");
-            for kv in &span.attributes {
-                if kv.key.as_str() == "span.type" {
-                    span_type = interner.intern_value(&kv.value);
-                    break;
-                }
-            }
+            let span_type = interner.intern(&names.span_type);
 
             // Datadog span name is OpenTelemetry component name - see module docs for more information
-            rmp::encode::write_array_len(&mut encoded, SPAN_NUM_ELEMENTS)?;
-            rmp::encode::write_u32(
-                &mut encoded,
-                interner.intern(get_service_name(span, model_config)),
-            )?;
-            rmp::encode::write_u32(&mut encoded, interner.intern(get_name(span, model_config)))?;
-            rmp::encode::write_u32(
-                &mut encoded,
-                interner.intern(get_resource(span, model_config)),
-            )?;
+            rmp::encode::write_array_len(encoded, SPAN_NUM_ELEMENTS)?;
+            rmp::encode::write_u32(encoded, interner.intern(&names.service))?;
+            rmp::encode::write_u32(encoded, interner.intern(&names.name))?;
+            rmp::encode::write_u32(encoded, interner.intern(&names.resource))?;
+            rmp::encode::write_u64(encoded, datadog_trace_id(span.span_context.trace_id()))?;
             rmp::encode::write_u64(
-                &mut encoded,
-                u128::from_be_bytes(span.span_context.trace_id().to_bytes()) as u64,
-            )?;
-            rmp::encode::write_u64(
-                &mut encoded,
+                encoded,
                 u64::from_be_bytes(span.span_context.span_id().to_bytes()),
             )?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u64::from_be_bytes(span.parent_span_id.to_bytes()),
-            )?;
-            rmp::encode::write_i64(&mut encoded, start)?;
-            rmp::encode::write_i64(&mut encoded, duration)?;
+            rmp::encode::write_u64(encoded, u64::from_be_bytes(span.parent_span_id.to_bytes()))?;
+            rmp::encode::write_i64(encoded, start)?;
+            rmp::encode::write_i64(encoded, duration)?;
             rmp::encode::write_i32(
-                &mut encoded,
+                encoded,
                 match span.status {
                     Status::Error { .. } => 1,
                     _ => 0,
@@ -227,46 +224,88 @@ where
             )?;
 
             rmp::encode::write_map_len(
-                &mut encoded,
-                (span.attributes.len() + resource.map(|r| r.len()).unwrap_or(0)) as u32
+                encoded,
+                (span.attributes.len()
+                    + resource.map(|r| r.len()).unwrap_or(0)
+                    + unified_tags.global_tags.len()) as u32
                     + unified_tags.compute_attribute_size()
-                    + GIT_META_TAGS_COUNT,
+                    + GIT_META_TAGS_COUNT
+                    + span_chunk_tags.len() as u32
+                    + span_event_tags.len() as u32
+                    + origin.is_some() as u32
+                    + names.sql_query as u32,
             )?;
+            // global tags come first, so that the span's own tags take precedence
+            for (key, value) in &unified_tags.global_tags {
+                rmp::encode::write_u32(encoded, interner.intern(key))?;
+                rmp::encode::write_u32(encoded, interner.intern(value))?;
+            }
             if let Some(resource) = resource {
                 for (key, value) in resource.iter() {
-                    rmp::encode::write_u32(&mut encoded, interner.intern(key.as_str()))?;
-                    rmp::encode::write_u32(&mut encoded, interner.intern_value(value))?;
+                    rmp::encode::write_u32(encoded, interner.intern(key.as_str()))?;
+                    rmp::encode::write_u32(encoded, interner.intern_value(value))?;
                 }
             }
 
-            write_unified_tags(&mut encoded, interner, unified_tags)?;
+            write_unified_tags(encoded, interner, unified_tags)?;
 
             for kv in span.attributes.iter() {
-                rmp::encode::write_u32(&mut encoded, interner.intern(kv.key.as_str()))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern_value(&kv.value))?;
+                rmp::encode::write_u32(encoded, interner.intern(kv.key.as_str()))?;
+                rmp::encode::write_u32(encoded, interner.intern_value(&kv.value))?;
+            }
+
+            for (key, value) in span_chunk_tags {
+                rmp::encode::write_u32(encoded, interner.intern(key))?;
+                rmp::encode::write_u32(encoded, interner.intern(value))?;
+            }
+            for (key, value) in span_event_tags {
+                rmp::encode::write_u32(encoded, interner.intern(key))?;
+                rmp::encode::write_u32(encoded, interner.intern(value))?;
+            }
+            if let Some(origin) = origin {
+                rmp::encode::write_u32(encoded, interner.intern(DD_ORIGIN_KEY))?;
+                rmp::encode::write_u32(encoded, interner.intern(origin))?;
+            }
+            if names.sql_query {
+                rmp::encode::write_u32(encoded, interner.intern(SQL_QUERY_KEY))?;
+                rmp::encode::write_u32(encoded, interner.intern(&names.resource))?;
             }
 
             if let (Some(repository_url), Some(commit_sha)) = (
                 option_env!("DD_GIT_REPOSITORY_URL"),
                 option_env!("DD_GIT_COMMIT_SHA"),
             ) {
-                rmp::encode::write_u32(&mut encoded, interner.intern("git.repository_url"))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern(repository_url))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern("git.commit.sha"))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern(commit_sha))?;
+                rmp::encode::write_u32(encoded, interner.intern("git.repository_url"))?;
+                rmp::encode::write_u32(encoded, interner.intern(repository_url))?;
+                rmp::encode::write_u32(encoded, interner.intern("git.commit.sha"))?;
+                rmp::encode::write_u32(encoded, interner.intern(commit_sha))?;
             }
 
-            rmp::encode::write_map_len(&mut encoded, METRICS_LEN)?;
-            rmp::encode::write_u32(&mut encoded, interner.intern(SAMPLING_PRIORITY_KEY))?;
+            let rate_metrics = sampling_rate_metrics(span);
+            let measured = mapping.measured(span);
+            rmp::encode::write_map_len(
+                encoded,
+                1 + rate_metrics.len() as u32 + *top_level as u32 + measured as u32,
+            )?;
+            for (key, rate) in rate_metrics {
+                rmp::encode::write_u32(encoded, interner.intern(key))?;
+                rmp::encode::write_f64(encoded, rate)?;
+            }
+            rmp::encode::write_u32(encoded, interner.intern(SAMPLING_PRIORITY_KEY))?;
             let sampling_priority = get_sampling_priority(span);
-            rmp::encode::write_f64(&mut encoded, sampling_priority)?;
+            rmp::encode::write_f64(encoded, sampling_priority)?;
 
-            rmp::encode::write_u32(&mut encoded, interner.intern(DD_MEASURED_KEY))?;
-            let measuring = get_measuring(span);
-            rmp::encode::write_f64(&mut encoded, measuring)?;
-            rmp::encode::write_u32(&mut encoded, span_type)?;
+            if *top_level {
+                rmp::encode::write_u32(encoded, interner.intern(DD_TOP_LEVEL_KEY))?;
+                rmp::encode::write_f64(encoded, 1.0)?;
+            }
+            if measured {
+                rmp::encode::write_u32(encoded, interner.intern(DD_MEASURED_KEY))?;
+                rmp::encode::write_f64(encoded, 1.0)?;
+            }
+            rmp::encode::write_u32(encoded, span_type)?;
         }
     }
 
-    Ok(encoded)
+    Ok(())
 }
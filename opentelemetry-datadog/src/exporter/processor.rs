@@ -0,0 +1,420 @@
+use crate::exporter::model::ServiceMapping;
+use crate::exporter::non_empty_env_var;
+use crate::propagator::DatadogTraceState;
+use opentelemetry::trace::{Span as _, SpanContext, SpanId, TraceId};
+use opentelemetry::Context;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use opentelemetry_sdk::Resource;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Default number of finished spans of a trace triggering a partial flush, same as the other
+/// Datadog tracers
+pub(crate) const DEFAULT_PARTIAL_FLUSH_MIN_SPANS: usize = 1000;
+
+/// Environment variables enabling the partial flush, shared by all Datadog tracers
+const DD_TRACE_PARTIAL_FLUSH_ENABLED_ENV_VAR: &str = "DD_TRACE_PARTIAL_FLUSH_ENABLED";
+const DD_TRACE_PARTIAL_FLUSH_MIN_SPANS_ENV_VAR: &str = "DD_TRACE_PARTIAL_FLUSH_MIN_SPANS";
+
+/// Span processor buffering the spans of each trace, so that traces reach the exporter whole.
+///
+/// The spans of a trace are passed to the inner processor, usually a batch processor, once every
+/// started span of the trace ended. Long-running traces are partially flushed: as soon as
+/// [`with_partial_flush_min_spans`] spans of a trace ended, they are passed on as a trace chunk
+/// while the trace goes on, so that traces with many spans neither pile up in memory nor
+/// overflow the queue of the batch processor.
+///
+/// Every chunk holds the trace state of its trace, so the exporter writes the sampling priority
+/// and the `_dd.p.*` propagated tags on each of them. The spans whose parent is passed on in
+/// another chunk are flagged as top-level or not from the service of their parent across the
+/// whole trace, rather than as the entry point of their chunk. Installed by
+/// [`DatadogPipelineBuilder::with_partial_flush`], the processor names the services with the
+/// mapping of the exporter, otherwise every span is taken to belong to the configured service.
+///
+/// Spans that are never ended hold their trace until the processor is flushed or shut down.
+///
+/// ```no_run
+/// use opentelemetry_datadog::DatadogSpanProcessor;
+/// use opentelemetry_sdk::trace::{BatchSpanProcessor, SdkTracerProvider};
+///
+/// fn main() -> Result<(), opentelemetry_sdk::trace::TraceError> {
+///     let exporter = opentelemetry_datadog::new_pipeline()
+///         .with_service_name("my_app")
+///         .build_exporter()?;
+///     let processor = DatadogSpanProcessor::new(BatchSpanProcessor::builder(exporter).build())
+///         .with_partial_flush_min_spans(500);
+///     let provider = SdkTracerProvider::builder()
+///         .with_span_processor(processor)
+///         .build();
+///     Ok(())
+/// }
+/// ```
+///
+/// [`with_partial_flush_min_spans`]: DatadogSpanProcessor::with_partial_flush_min_spans
+/// [`DatadogPipelineBuilder::with_partial_flush`]: crate::DatadogPipelineBuilder::with_partial_flush
+#[derive(Debug)]
+pub struct DatadogSpanProcessor<P> {
+    inner: P,
+    traces: Mutex<HashMap<TraceId, PendingTrace>>,
+    partial_flush_min_spans: usize,
+    service_mapping: ServiceMapping,
+}
+
+/// Spans of a trace that are still to pass on.
+#[derive(Debug, Default)]
+struct PendingTrace {
+    /// Spans started and not ended yet
+    open: usize,
+    finished: Vec<SpanData>,
+    /// Service of every span started in the trace, passed on or not
+    services: HashMap<SpanId, String>,
+}
+
+impl PendingTrace {
+    /// Takes the ended spans as a chunk, flagging the spans whose parent isn't part of the chunk
+    /// as top-level when their parent belongs to another service.
+    fn take_chunk(&mut self) -> Vec<SpanData> {
+        let mut chunk = std::mem::take(&mut self.finished);
+        let chunk_spans: HashSet<SpanId> = chunk
+            .iter()
+            .map(|span| span.span_context.span_id())
+            .collect();
+        for span in &mut chunk {
+            if chunk_spans.contains(&span.parent_span_id) {
+                continue;
+            }
+            let (Some(parent_service), Some(service)) = (
+                self.services.get(&span.parent_span_id),
+                self.services.get(&span.span_context.span_id()),
+            ) else {
+                // root span, or child of a remote span
+                continue;
+            };
+            let cx = &span.span_context;
+            span.span_context = SpanContext::new(
+                cx.trace_id(),
+                cx.span_id(),
+                cx.trace_flags(),
+                cx.is_remote(),
+                cx.trace_state().with_top_level(parent_service != service),
+            );
+        }
+        chunk
+    }
+}
+
+impl<P: SpanProcessor> DatadogSpanProcessor<P> {
+    /// Buffer the traces before passing them to `inner`, partially flushing traces once 1000
+    /// spans ended.
+    pub fn new(inner: P) -> Self {
+        DatadogSpanProcessor {
+            inner,
+            traces: Mutex::new(HashMap::new()),
+            partial_flush_min_spans: DEFAULT_PARTIAL_FLUSH_MIN_SPANS,
+            service_mapping: ServiceMapping::default(),
+        }
+    }
+
+    /// Name the services of the spans like the exporter, to flag the top-level spans.
+    pub(crate) fn with_service_mapping(mut self, service_mapping: ServiceMapping) -> Self {
+        self.service_mapping = service_mapping;
+        self
+    }
+
+    /// The service of the span, left empty when every span belongs to the configured service.
+    fn service_name(&self, span: &SpanData) -> String {
+        if self.service_mapping.is_constant() {
+            String::new()
+        } else {
+            self.service_mapping.service_name(span).into_owned()
+        }
+    }
+
+    /// Set the number of ended spans of an unfinished trace that triggers a partial flush.
+    pub fn with_partial_flush_min_spans(mut self, min_spans: usize) -> Self {
+        self.partial_flush_min_spans = min_spans.max(1);
+        self
+    }
+
+    /// Passes on the ended spans of every trace, finished or not.
+    fn flush_pending(&self) {
+        let spans: Vec<SpanData> = match self.traces.lock() {
+            Ok(mut traces) => {
+                traces.retain(|_, trace| trace.open > 0);
+                traces
+                    .values_mut()
+                    .flat_map(PendingTrace::take_chunk)
+                    .collect()
+            }
+            Err(_) => return,
+        };
+        for span in spans {
+            self.inner.on_end(span);
+        }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for DatadogSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        // spans dropped by the sampler never end
+        if span.is_recording() {
+            // the service of the span as started, for its children ending before it
+            let service = if self.service_mapping.is_constant() {
+                String::new()
+            } else {
+                span.exported_data()
+                    .map(|data| self.service_name(&data))
+                    .unwrap_or_default()
+            };
+            if let Ok(mut traces) = self.traces.lock() {
+                let span_context = span.span_context();
+                let trace = traces.entry(span_context.trace_id()).or_default();
+                trace.open += 1;
+                trace.services.insert(span_context.span_id(), service);
+            }
+        }
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let service = self.service_name(&span);
+        let chunk = {
+            let Ok(mut traces) = self.traces.lock() else {
+                return;
+            };
+            let trace_id = span.span_context.trace_id();
+            let Some(trace) = traces.get_mut(&trace_id) else {
+                // started before the processor, passed on as is
+                drop(traces);
+                self.inner.on_end(span);
+                return;
+            };
+            trace.open = trace.open.saturating_sub(1);
+            trace.services.insert(span.span_context.span_id(), service);
+            trace.finished.push(span);
+            if trace.open == 0 {
+                traces.remove(&trace_id).map(|mut trace| trace.take_chunk())
+            } else if trace.finished.len() >= self.partial_flush_min_spans {
+                Some(trace.take_chunk())
+            } else {
+                None
+            }
+        };
+
+        for span in chunk.into_iter().flatten() {
+            self.inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.flush_pending();
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.flush_pending();
+        self.inner.shutdown()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource);
+    }
+}
+
+/// Partial flush configured by `DD_TRACE_PARTIAL_FLUSH_ENABLED` and
+/// `DD_TRACE_PARTIAL_FLUSH_MIN_SPANS`.
+pub(crate) fn partial_flush_from_env() -> Option<usize> {
+    let enabled = non_empty_env_var(DD_TRACE_PARTIAL_FLUSH_ENABLED_ENV_VAR)
+        .is_some_and(|enabled| enabled.eq_ignore_ascii_case("true") || enabled == "1");
+    enabled.then(|| {
+        non_empty_env_var(DD_TRACE_PARTIAL_FLUSH_MIN_SPANS_ENV_VAR)
+            .and_then(|min_spans| min_spans.parse().ok())
+            .unwrap_or(DEFAULT_PARTIAL_FLUSH_MIN_SPANS)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::{Mapping, ModelConfig};
+    use opentelemetry::trace::{TraceContextExt, Tracer, TracerProvider};
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use std::sync::Arc;
+
+    /// Processor recording the ended spans, and the flushes.
+    #[derive(Debug, Default, Clone)]
+    struct RecordingProcessor {
+        spans: Arc<Mutex<Vec<String>>>,
+        /// The top-level flag set on each span
+        top_level: Arc<Mutex<HashMap<String, Option<bool>>>>,
+    }
+
+    impl SpanProcessor for RecordingProcessor {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            let top_level = span.span_context.trace_state().top_level();
+            self.top_level
+                .lock()
+                .unwrap()
+                .insert(span.name.to_string(), top_level);
+            self.spans.lock().unwrap().push(span.name.into_owned());
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            self.spans.lock().unwrap().push("flush".to_string());
+            Ok(())
+        }
+
+        fn shutdown(&self) -> OTelSdkResult {
+            Ok(())
+        }
+    }
+
+    fn provider_with(min_spans: usize) -> (SdkTracerProvider, RecordingProcessor) {
+        provider_with_mapping(min_spans, ServiceMapping::default())
+    }
+
+    fn provider_with_mapping(
+        min_spans: usize,
+        service_mapping: ServiceMapping,
+    ) -> (SdkTracerProvider, RecordingProcessor) {
+        let recording = RecordingProcessor::default();
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(
+                DatadogSpanProcessor::new(recording.clone())
+                    .with_partial_flush_min_spans(min_spans)
+                    .with_service_mapping(service_mapping),
+            )
+            .build();
+        (provider, recording)
+    }
+
+    fn ended(recording: &RecordingProcessor) -> Vec<String> {
+        recording.spans.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_pass_whole_traces() {
+        let (provider, recording) = provider_with(10);
+        let tracer = provider.tracer("test");
+
+        tracer.in_span("root", |cx| {
+            tracer.in_span("child", |_| {});
+            tracer.in_span("other", |_| {});
+            // the trace isn't finished yet
+            assert!(ended(&recording).is_empty());
+            cx.span().end();
+        });
+        assert_eq!(ended(&recording), ["child", "other", "root"]);
+    }
+
+    #[test]
+    fn test_partial_flush() {
+        let (provider, recording) = provider_with(2);
+        let tracer = provider.tracer("test");
+
+        tracer.in_span("root", |_| {
+            tracer.in_span("first", |_| {});
+            assert!(ended(&recording).is_empty());
+            tracer.in_span("second", |_| {});
+            assert_eq!(ended(&recording), ["first", "second"]);
+            tracer.in_span("third", |_| {});
+            assert_eq!(ended(&recording), ["first", "second"]);
+        });
+        assert_eq!(ended(&recording), ["first", "second", "third", "root"]);
+    }
+
+    #[test]
+    fn test_flush_unfinished_traces() {
+        let (provider, recording) = provider_with(10);
+        let tracer = provider.tracer("test");
+
+        let root = tracer.start("root");
+        let cx = Context::current_with_span(root);
+        tracer.start_with_context("child", &cx).end();
+        provider.force_flush().unwrap();
+        assert_eq!(ended(&recording), ["child", "flush"]);
+
+        // the root span still ends its trace
+        drop(cx);
+        assert_eq!(ended(&recording), ["child", "flush", "root"]);
+    }
+
+    #[test]
+    fn test_partial_flush_from_env() {
+        temp_env::with_vars(
+            [
+                (DD_TRACE_PARTIAL_FLUSH_ENABLED_ENV_VAR, Some("true")),
+                (DD_TRACE_PARTIAL_FLUSH_MIN_SPANS_ENV_VAR, Some("300")),
+            ],
+            || assert_eq!(partial_flush_from_env(), Some(300)),
+        );
+        temp_env::with_vars(
+            [
+                (DD_TRACE_PARTIAL_FLUSH_ENABLED_ENV_VAR, Some("1")),
+                (DD_TRACE_PARTIAL_FLUSH_MIN_SPANS_ENV_VAR, None),
+            ],
+            || {
+                assert_eq!(
+                    partial_flush_from_env(),
+                    Some(DEFAULT_PARTIAL_FLUSH_MIN_SPANS)
+                )
+            },
+        );
+        temp_env::with_vars(
+            [
+                (DD_TRACE_PARTIAL_FLUSH_ENABLED_ENV_VAR, None::<&str>),
+                (DD_TRACE_PARTIAL_FLUSH_MIN_SPANS_ENV_VAR, Some("300")),
+            ],
+            || assert_eq!(partial_flush_from_env(), None),
+        );
+    }
+
+    #[test]
+    fn test_top_level_of_partially_flushed_spans() {
+        let mapping = Mapping::empty().with_semconv();
+        let config = ModelConfig {
+            service_name: "my_app".to_string(),
+        };
+        let (provider, recording) = provider_with_mapping(2, mapping.service_mapping(&config));
+        let tracer = provider.tracer("test");
+
+        tracer.in_span("root", |_| {
+            tracer
+                .span_builder("other_service")
+                .with_attributes([KeyValue::new("service.name", "db")])
+                .start(&tracer)
+                .end();
+            tracer.in_span("same_service", |_| {});
+            // flushed before their parent, and flagged from the service of the parent
+            assert_eq!(ended(&recording), ["other_service", "same_service"]);
+        });
+
+        let top_level = recording.top_level.lock().unwrap().clone();
+        assert_eq!(top_level["other_service"], Some(true));
+        assert_eq!(top_level["same_service"], Some(false));
+        // the root span is top-level in its own chunk
+        assert_eq!(top_level["root"], None);
+    }
+
+    #[test]
+    fn test_top_level_with_the_default_mapping() {
+        let (provider, recording) = provider_with(1);
+        let tracer = provider.tracer("test");
+
+        tracer.in_span("root", |_| {
+            tracer
+                .span_builder("child")
+                .with_attributes([KeyValue::new("service.name", "db")])
+                .start(&tracer)
+                .end();
+        });
+
+        // every span belongs to the configured service
+        let top_level = recording.top_level.lock().unwrap().clone();
+        assert_eq!(top_level["child"], Some(false));
+    }
+}
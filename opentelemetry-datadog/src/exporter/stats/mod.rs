@@ -0,0 +1,357 @@
+//! Client side computation of the APM trace metrics.
+//!
+//! Finished spans are aggregated into time buckets, and flushed to the `/v0.6/stats` endpoint of
+//! the Datadog agent. The agent then doesn't need to see every span to compute the request, error
+//! and duration metrics, so unsampled traces can be dropped by the exporter.
+//!
+//! Payload format sourced from <https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/proto/datadog/trace/stats.proto>
+
+mod sketch;
+
+use crate::exporter::model::{unified_tags::UnifiedTags, Error, SpanNames, TopLevelSpans};
+use crate::exporter::{Mapping, ModelConfig};
+use crate::propagator::DatadogTraceState;
+use opentelemetry::trace::Status;
+use opentelemetry::Value;
+use opentelemetry_sdk::trace::SpanData;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use self::sketch::DDSketch;
+
+/// Default duration of the stats buckets, same as the other Datadog tracers
+pub(crate) const DEFAULT_BUCKET_DURATION: Duration = Duration::from_secs(10);
+
+const HTTP_STATUS_CODE_KEYS: [&str; 2] = ["http.response.status_code", "http.status_code"];
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/stats.go#L170
+const SYNTHETICS_ORIGIN_PREFIX: &str = "synthetics";
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct AggregationKey {
+    service: String,
+    name: String,
+    resource: String,
+    span_type: String,
+    http_status_code: u32,
+    synthetics: bool,
+    top_level: bool,
+}
+
+#[derive(Debug, Default)]
+struct GroupedStats {
+    hits: u64,
+    top_level_hits: u64,
+    errors: u64,
+    duration: u64,
+    ok_summary: DDSketch,
+    error_summary: DDSketch,
+}
+
+/// Stats of all the spans that ended during a time bucket.
+#[derive(Debug)]
+pub(crate) struct StatsBucket {
+    start: u64,
+    duration: u64,
+    stats: HashMap<AggregationKey, GroupedStats>,
+}
+
+/// Aggregates finished spans into time buckets.
+#[derive(Debug)]
+pub(crate) struct StatsConcentrator {
+    bucket_duration: Duration,
+    buckets: Mutex<BTreeMap<u64, HashMap<AggregationKey, GroupedStats>>>,
+    sequence: AtomicU64,
+}
+
+fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn http_status_code(span: &SpanData) -> u32 {
+    span.attributes
+        .iter()
+        .find(|kv| HTTP_STATUS_CODE_KEYS.contains(&kv.key.as_str()))
+        .and_then(|kv| match &kv.value {
+            Value::I64(code) => u32::try_from(*code).ok(),
+            Value::String(code) => code.as_str().parse().ok(),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+impl StatsConcentrator {
+    pub(crate) fn new(bucket_duration: Duration) -> Self {
+        StatsConcentrator {
+            bucket_duration,
+            buckets: Mutex::new(BTreeMap::new()),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Add the top-level and measured spans of a trace chunk to the stats.
+    pub(crate) fn add_trace(&self, trace: &[SpanData], mapping: &Mapping, config: &ModelConfig) {
+        let bucket_duration = self.bucket_duration.as_nanos() as u64;
+        let names: Vec<SpanNames<'_>> = trace
+            .iter()
+            .map(|span| mapping.span_names(span, config))
+            .collect();
+        let mut top_level_spans = TopLevelSpans::default();
+        let top_level = top_level_spans.flag(trace, &names);
+
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return;
+        };
+        for ((span, names), &top_level) in trace.iter().zip(&names).zip(top_level) {
+            if !top_level && !mapping.measured(span) {
+                continue;
+            }
+            let trace_state = span.span_context.trace_state();
+
+            let end = nanos_since_epoch(span.end_time);
+            let duration = span
+                .end_time
+                .duration_since(span.start_time)
+                .map(|x| x.as_nanos() as u64)
+                .unwrap_or(0);
+            let is_error = matches!(span.status, Status::Error { .. });
+
+            let key = AggregationKey {
+                service: names.service.to_string(),
+                name: names.name.to_string(),
+                resource: names.resource.to_string(),
+                span_type: names.span_type.to_string(),
+                http_status_code: http_status_code(span),
+                synthetics: trace_state
+                    .origin()
+                    .is_some_and(|origin| origin.starts_with(SYNTHETICS_ORIGIN_PREFIX)),
+                top_level,
+            };
+
+            let stats = buckets
+                .entry(end - end % bucket_duration)
+                .or_default()
+                .entry(key)
+                .or_default();
+            stats.hits += 1;
+            stats.duration += duration;
+            if top_level {
+                stats.top_level_hits += 1;
+            }
+            if is_error {
+                stats.errors += 1;
+                stats.error_summary.add(duration as f64);
+            } else {
+                stats.ok_summary.add(duration as f64);
+            }
+        }
+    }
+
+    /// Removes and returns the buckets that are complete at the given time.
+    ///
+    /// An extra bucket duration is waited for before flushing a bucket, so spans exported late by
+    /// the span processor still make it in. When `force` is set all buckets are returned.
+    pub(crate) fn flush(&self, now: SystemTime, force: bool) -> Vec<StatsBucket> {
+        let bucket_duration = self.bucket_duration.as_nanos() as u64;
+        let now = nanos_since_epoch(now);
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return vec![];
+        };
+
+        let kept = match now.checked_sub(2 * bucket_duration) {
+            _ if force => BTreeMap::new(),
+            Some(oldest_open) => buckets.split_off(&(oldest_open + 1)),
+            None => return vec![],
+        };
+        std::mem::replace(&mut *buckets, kept)
+            .into_iter()
+            .map(|(start, stats)| StatsBucket {
+                start,
+                duration: bucket_duration,
+                stats,
+            })
+            .collect()
+    }
+
+    /// Encodes the buckets as a msgpack `ClientStatsPayload`.
+    pub(crate) fn encode(
+        &self,
+        buckets: &[StatsBucket],
+        model_config: &ModelConfig,
+        unified_tags: &UnifiedTags,
+    ) -> Result<Vec<u8>, Error> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut encoded = Vec::new();
+
+        rmp::encode::write_map_len(&mut encoded, 9)?;
+        write_str_field(&mut encoded, "Hostname", "")?;
+        write_str_field(
+            &mut encoded,
+            "Env",
+            unified_tags.env.value.as_deref().unwrap_or_default(),
+        )?;
+        write_str_field(
+            &mut encoded,
+            "Version",
+            unified_tags.version.value.as_deref().unwrap_or_default(),
+        )?;
+        write_str_field(&mut encoded, "Service", &model_config.service_name)?;
+        write_str_field(&mut encoded, "Lang", "rust")?;
+        write_str_field(&mut encoded, "TracerVersion", env!("CARGO_PKG_VERSION"))?;
+        write_str_field(&mut encoded, "RuntimeID", "")?;
+        rmp::encode::write_str(&mut encoded, "Sequence")?;
+        rmp::encode::write_u64(&mut encoded, sequence)?;
+
+        rmp::encode::write_str(&mut encoded, "Stats")?;
+        rmp::encode::write_array_len(&mut encoded, buckets.len() as u32)?;
+        for bucket in buckets {
+            rmp::encode::write_map_len(&mut encoded, 3)?;
+            rmp::encode::write_str(&mut encoded, "Start")?;
+            rmp::encode::write_u64(&mut encoded, bucket.start)?;
+            rmp::encode::write_str(&mut encoded, "Duration")?;
+            rmp::encode::write_u64(&mut encoded, bucket.duration)?;
+
+            rmp::encode::write_str(&mut encoded, "Stats")?;
+            rmp::encode::write_array_len(&mut encoded, bucket.stats.len() as u32)?;
+            for (key, stats) in &bucket.stats {
+                rmp::encode::write_map_len(&mut encoded, 13)?;
+                write_str_field(&mut encoded, "Service", &key.service)?;
+                write_str_field(&mut encoded, "Name", &key.name)?;
+                write_str_field(&mut encoded, "Resource", &key.resource)?;
+                write_str_field(&mut encoded, "Type", &key.span_type)?;
+                rmp::encode::write_str(&mut encoded, "HTTPStatusCode")?;
+                rmp::encode::write_u32(&mut encoded, key.http_status_code)?;
+                rmp::encode::write_str(&mut encoded, "Synthetics")?;
+                rmp::encode::write_bool(&mut encoded, key.synthetics)
+                    .map_err(|_| Error::MessagePackError)?;
+                rmp::encode::write_str(&mut encoded, "Hits")?;
+                rmp::encode::write_u64(&mut encoded, stats.hits)?;
+                rmp::encode::write_str(&mut encoded, "TopLevelHits")?;
+                rmp::encode::write_u64(&mut encoded, stats.top_level_hits)?;
+                rmp::encode::write_str(&mut encoded, "Errors")?;
+                rmp::encode::write_u64(&mut encoded, stats.errors)?;
+                rmp::encode::write_str(&mut encoded, "Duration")?;
+                rmp::encode::write_u64(&mut encoded, stats.duration)?;
+                rmp::encode::write_str(&mut encoded, "OkSummary")?;
+                rmp::encode::write_bin(&mut encoded, &stats.ok_summary.encode())?;
+                rmp::encode::write_str(&mut encoded, "ErrorSummary")?;
+                rmp::encode::write_bin(&mut encoded, &stats.error_summary.encode())?;
+                write_str_field(&mut encoded, "DBType", "")?;
+            }
+        }
+
+        Ok(encoded)
+    }
+}
+
+fn write_str_field(encoded: &mut Vec<u8>, key: &str, value: &str) -> Result<(), Error> {
+    rmp::encode::write_str(encoded, key)?;
+    rmp::encode::write_str(encoded, value)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::model::tests::get_span;
+    use opentelemetry::KeyValue;
+
+    fn concentrator_with(traces: &[Vec<SpanData>]) -> StatsConcentrator {
+        let concentrator = StatsConcentrator::new(DEFAULT_BUCKET_DURATION);
+        let config = ModelConfig {
+            service_name: "service".to_string(),
+        };
+        for trace in traces {
+            concentrator.add_trace(trace, &Mapping::empty(), &config);
+        }
+        concentrator
+    }
+
+    #[test]
+    fn test_aggregate_top_level_spans() {
+        let mut error = get_span(1, 0, 3);
+        error.status = Status::error("failed");
+        error
+            .attributes
+            .push(KeyValue::new("http.response.status_code", 500));
+        // spans 1 and 3 are roots, span 2 is a measured client child of span 1 of the same service
+        let concentrator =
+            concentrator_with(&[vec![get_span(1, 0, 1), get_span(1, 1, 2)], vec![error]]);
+
+        let buckets = concentrator.flush(SystemTime::now(), true);
+        assert_eq!(buckets.len(), 1);
+        let bucket = &buckets[0];
+        assert_eq!(bucket.start, 0);
+        assert_eq!(bucket.stats.len(), 3);
+
+        let mut stats: Vec<_> = bucket.stats.iter().collect();
+        stats.sort_by_key(|(key, _)| (key.http_status_code, !key.top_level));
+        let (ok_key, ok) = stats[0];
+        assert_eq!(ok_key.service, "service");
+        assert_eq!(ok_key.name, "component");
+        assert_eq!(ok_key.resource, "resource");
+        assert_eq!(ok_key.span_type, "web");
+        assert!(ok_key.top_level);
+        assert_eq!(ok.hits, 1);
+        assert_eq!(ok.top_level_hits, 1);
+        assert_eq!(ok.errors, 0);
+        assert_eq!(ok.duration, 1_000_000_000);
+
+        let (measured_key, measured) = stats[1];
+        assert!(!measured_key.top_level);
+        assert_eq!(measured.hits, 1);
+        assert_eq!(measured.top_level_hits, 0);
+
+        let (error_key, error) = stats[2];
+        assert_eq!(error_key.http_status_code, 500);
+        assert_eq!(error.hits, 1);
+        assert_eq!(error.errors, 1);
+    }
+
+    #[test]
+    fn test_flush_complete_buckets() {
+        let concentrator = concentrator_with(&[vec![get_span(1, 0, 1)]]);
+        // the span ended one second after the epoch, its bucket is kept for an extra duration
+        let bucket_end = SystemTime::UNIX_EPOCH + DEFAULT_BUCKET_DURATION;
+        assert!(concentrator.flush(bucket_end, false).is_empty());
+        assert_eq!(
+            concentrator
+                .flush(bucket_end + DEFAULT_BUCKET_DURATION, false)
+                .len(),
+            1
+        );
+        assert!(concentrator.flush(SystemTime::now(), true).is_empty());
+    }
+
+    #[test]
+    fn test_encode() -> Result<(), Error> {
+        let concentrator = concentrator_with(&[vec![get_span(1, 0, 1)]]);
+        let buckets = concentrator.flush(SystemTime::now(), true);
+        let encoded = concentrator.encode(
+            &buckets,
+            &ModelConfig {
+                service_name: "service".to_string(),
+            },
+            &UnifiedTags::new(),
+        )?;
+
+        let mut reader = encoded.as_slice();
+        assert_eq!(rmp::decode::read_map_len(&mut reader).unwrap(), 9);
+        for field in [
+            "Stats",
+            "Sequence",
+            "OkSummary",
+            "TopLevelHits",
+            "component",
+        ] {
+            assert!(encoded
+                .windows(field.len())
+                .any(|window| window == field.as_bytes()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+// Same relative accuracy as the sketches built by the Datadog agent
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/stats/statsraw.go#L19
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Minimal [DDSketch] with a logarithmic index mapping and an unbounded sparse store.
+///
+/// Only positive values are tracked, as the sketch holds span durations. Values that cannot be
+/// indexed, such as zero, are counted in the zero bucket.
+///
+/// [DDSketch]: https://arxiv.org/abs/1908.10693
+#[derive(Clone, Debug)]
+pub(crate) struct DDSketch {
+    gamma: f64,
+    multiplier: f64,
+    min_indexable_value: f64,
+    bins: BTreeMap<i32, f64>,
+    zero_count: f64,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        let multiplier = 1.0 / gamma.ln();
+        DDSketch {
+            gamma,
+            multiplier,
+            min_indexable_value: f64::MIN_POSITIVE * gamma,
+            bins: BTreeMap::new(),
+            zero_count: 0.0,
+        }
+    }
+}
+
+impl DDSketch {
+    pub(crate) fn add(&mut self, value: f64) {
+        if value < self.min_indexable_value {
+            self.zero_count += 1.0;
+        } else {
+            *self.bins.entry(self.index(value)).or_default() += 1.0;
+        }
+    }
+
+    /// Index of the bin holding the value, the bin `i` holding the values in
+    /// `[gamma^i, gamma^(i+1))` like the logarithmic mapping of the agent, which rounds the same
+    /// way.
+    /// https://github.com/DataDog/sketches-go/blob/v1.4.4/ddsketch/mapping/logarithmic_mapping.go#L55
+    fn index(&self, value: f64) -> i32 {
+        let index = value.ln() * self.multiplier;
+        if index >= 0.0 {
+            index as i32
+        } else {
+            index as i32 - 1
+        }
+    }
+
+    /// Approximation of the value at the given rank, within the relative accuracy of the sketch.
+    #[cfg(test)]
+    pub(crate) fn quantile(&self, quantile: f64) -> Option<f64> {
+        let count = self.zero_count + self.bins.values().sum::<f64>();
+        if count == 0.0 {
+            return None;
+        }
+        let rank = quantile * (count - 1.0);
+        if rank < self.zero_count {
+            return Some(0.0);
+        }
+        let mut seen = self.zero_count;
+        for (index, bin_count) in &self.bins {
+            seen += bin_count;
+            if seen > rank {
+                // the value the agent gives to the bin, within the relative accuracy of the
+                // values of the bin
+                return Some(self.gamma.powi(*index) * (1.0 + RELATIVE_ACCURACY));
+            }
+        }
+        None
+    }
+
+    /// Serializes the sketch with the protobuf [schema] expected by the Datadog agent.
+    ///
+    /// The positive values are written as a contiguous store.
+    ///
+    /// [schema]: https://github.com/DataDog/sketches-go/blob/v1.4.4/ddsketch/pb/ddsketch.proto
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut mapping = Vec::with_capacity(18);
+        // gamma = 1, indexOffset = 2, the interpolation is left to its NONE default
+        write_double_field(&mut mapping, 1, self.gamma);
+        write_double_field(&mut mapping, 2, 0.0);
+
+        let mut store = Vec::new();
+        if let (Some((&first, _)), Some((&last, _))) =
+            (self.bins.first_key_value(), self.bins.last_key_value())
+        {
+            let len = (last - first + 1) as usize;
+            let mut counts = vec![0.0; len];
+            for (index, count) in &self.bins {
+                counts[(index - first) as usize] = *count;
+            }
+            // contiguousBinCounts = 2, packed
+            write_tag(&mut store, 2, WIRE_TYPE_LEN);
+            write_varint(&mut store, (len * 8) as u64);
+            for count in counts {
+                store.extend_from_slice(&count.to_le_bytes());
+            }
+            // contiguousBinIndexOffset = 3
+            write_tag(&mut store, 3, WIRE_TYPE_VARINT);
+            write_varint(&mut store, zigzag(first));
+        }
+
+        let mut sketch = Vec::with_capacity(mapping.len() + store.len() + 16);
+        // mapping = 1, positiveValues = 2, zeroCount = 4
+        write_len_field(&mut sketch, 1, &mapping);
+        write_len_field(&mut sketch, 2, &store);
+        if self.zero_count > 0.0 {
+            write_double_field(&mut sketch, 4, self.zero_count);
+        }
+        sketch
+    }
+}
+
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_I64: u8 = 1;
+const WIRE_TYPE_LEN: u8 = 2;
+
+fn write_tag(buffer: &mut Vec<u8>, field: u8, wire_type: u8) {
+    buffer.push((field << 3) | wire_type);
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+fn write_double_field(buffer: &mut Vec<u8>, field: u8, value: f64) {
+    write_tag(buffer, field, WIRE_TYPE_I64);
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_len_field(buffer: &mut Vec<u8>, field: u8, value: &[u8]) {
+    write_tag(buffer, field, WIRE_TYPE_LEN);
+    write_varint(buffer, value.len() as u64);
+    buffer.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles_within_relative_accuracy() {
+        let mut sketch = DDSketch::default();
+        for value in 1..=1000 {
+            sketch.add(value as f64 * 1_000.0);
+        }
+
+        for (quantile, expected) in [(0.5, 500_500.0), (0.9, 900_100.0), (0.99, 990_010.0)] {
+            let value = sketch.quantile(quantile).unwrap();
+            assert!(
+                (value - expected).abs() <= expected * RELATIVE_ACCURACY,
+                "q{} = {}, expected {}",
+                quantile,
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_index_of_the_agent_mapping() {
+        let sketch = DDSketch::default();
+        // indexes of the logarithmic mapping of the agent with a 1% relative accuracy
+        for (value, index) in [
+            (1.0, 0),
+            (1.5, 20),
+            (100.0, 230),
+            (0.5, -35),
+            (1_000_000.0, 690),
+            (1_234_567.0, 701),
+            (1_000_000_000.0, 1036),
+        ] {
+            assert_eq!(sketch.index(value), index, "index of {}", value);
+            assert!(sketch.gamma.powi(index) <= value && value < sketch.gamma.powi(index + 1));
+        }
+    }
+
+    #[test]
+    fn test_zero_count() {
+        let mut sketch = DDSketch::default();
+        sketch.add(0.0);
+        sketch.add(0.0);
+        sketch.add(10.0);
+        assert_eq!(sketch.zero_count, 2.0);
+        assert_eq!(sketch.quantile(0.0), Some(0.0));
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut sketch = DDSketch::default();
+        sketch.add(0.0);
+        sketch.add(1.0);
+        sketch.add(1.0);
+        let encoded = sketch.encode();
+
+        let mut expected = vec![0x0a, 18, 0x09];
+        expected.extend_from_slice(&sketch.gamma.to_le_bytes());
+        expected.push(0x11);
+        expected.extend_from_slice(&0.0f64.to_le_bytes());
+        // a single bin at index 0 holding two values
+        expected.extend_from_slice(&[0x12, 12, 0x12, 8]);
+        expected.extend_from_slice(&2.0f64.to_le_bytes());
+        expected.extend_from_slice(&[0x18, 0]);
+        expected.push(0x21);
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn test_zigzag() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+        assert_eq!(zigzag(-2), 3);
+    }
+}
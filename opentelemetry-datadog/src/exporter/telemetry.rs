@@ -0,0 +1,173 @@
+//! Health metrics of the exporter itself, recorded with the `Meter` given to
+//! [`DatadogPipelineBuilder::with_meter`](crate::DatadogPipelineBuilder::with_meter).
+
+use opentelemetry::metrics::{Counter, Histogram, Meter};
+use opentelemetry::KeyValue;
+use opentelemetry_http::{Bytes, HttpError};
+use std::time::Duration;
+
+const REASON_KEY: &str = "reason";
+const ENDPOINT_KEY: &str = "endpoint";
+const STATUS_CLASS_KEY: &str = "status_class";
+
+/// Agent endpoints the exporter sends requests to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Endpoint {
+    Traces,
+    Stats,
+}
+
+impl Endpoint {
+    fn as_str(self) -> &'static str {
+        match self {
+            Endpoint::Traces => "traces",
+            Endpoint::Stats => "stats",
+        }
+    }
+}
+
+/// Why the exporter dropped traces instead of sending them to the agent.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum DropReason {
+    /// Unsampled traces, only counted in the metrics computed by the exporter
+    Unsampled,
+    /// Single traces larger than the maximum payload size
+    PayloadTooLarge,
+    /// Traces of payloads the agent didn't accept, after the retries
+    ExportFailed,
+}
+
+impl DropReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            DropReason::Unsampled => "unsampled",
+            DropReason::PayloadTooLarge => "payload_too_large",
+            DropReason::ExportFailed => "export_failed",
+        }
+    }
+}
+
+/// Instruments of the exporter health metrics.
+#[derive(Clone, Debug)]
+pub(crate) struct ExporterMetrics {
+    spans_sent: Counter<u64>,
+    spans_dropped: Counter<u64>,
+    traces_sent: Counter<u64>,
+    traces_dropped: Counter<u64>,
+    encode_duration: Histogram<f64>,
+    payload_size: Histogram<u64>,
+    http_duration: Histogram<f64>,
+    http_responses: Counter<u64>,
+}
+
+impl ExporterMetrics {
+    pub(crate) fn new(meter: &Meter) -> Self {
+        ExporterMetrics {
+            spans_sent: meter
+                .u64_counter("datadog.exporter.spans.sent")
+                .with_description("Spans accepted by the Datadog agent")
+                .with_unit("{span}")
+                .build(),
+            spans_dropped: meter
+                .u64_counter("datadog.exporter.spans.dropped")
+                .with_description("Spans dropped by the exporter, by reason")
+                .with_unit("{span}")
+                .build(),
+            traces_sent: meter
+                .u64_counter("datadog.exporter.traces.sent")
+                .with_description("Traces accepted by the Datadog agent")
+                .with_unit("{trace}")
+                .build(),
+            traces_dropped: meter
+                .u64_counter("datadog.exporter.traces.dropped")
+                .with_description("Traces dropped by the exporter, by reason")
+                .with_unit("{trace}")
+                .build(),
+            encode_duration: meter
+                .f64_histogram("datadog.exporter.encode.duration")
+                .with_description("Time spent encoding a trace payload")
+                .with_unit("s")
+                .build(),
+            payload_size: meter
+                .u64_histogram("datadog.exporter.payload.size")
+                .with_description("Size of the trace payloads sent to the agent")
+                .with_unit("By")
+                .build(),
+            http_duration: meter
+                .f64_histogram("datadog.exporter.http.duration")
+                .with_description("Duration of each request to the agent")
+                .with_unit("s")
+                .build(),
+            http_responses: meter
+                .u64_counter("datadog.exporter.http.responses")
+                .with_description("Responses of the agent, by endpoint and status class")
+                .with_unit("{response}")
+                .build(),
+        }
+    }
+
+    pub(crate) fn record_sent(&self, traces: usize, spans: usize) {
+        self.traces_sent.add(traces as u64, &[]);
+        self.spans_sent.add(spans as u64, &[]);
+    }
+
+    pub(crate) fn record_dropped(&self, reason: DropReason, traces: usize, spans: usize) {
+        let attributes = [KeyValue::new(REASON_KEY, reason.as_str())];
+        self.traces_dropped.add(traces as u64, &attributes);
+        self.spans_dropped.add(spans as u64, &attributes);
+    }
+
+    pub(crate) fn record_encode(&self, duration: Duration) {
+        self.encode_duration.record(duration.as_secs_f64(), &[]);
+    }
+
+    pub(crate) fn record_payload_size(&self, size: usize) {
+        self.payload_size.record(size as u64, &[]);
+    }
+
+    /// Records a request to the agent, with `error` as status class when no response came back.
+    pub(crate) fn record_response(
+        &self,
+        endpoint: Endpoint,
+        response: &Result<http::Response<Bytes>, HttpError>,
+        duration: Duration,
+    ) {
+        let attributes = [
+            KeyValue::new(ENDPOINT_KEY, endpoint.as_str()),
+            KeyValue::new(
+                STATUS_CLASS_KEY,
+                match response {
+                    Ok(response) => status_class(response.status()),
+                    Err(_) => "error",
+                },
+            ),
+        ];
+        self.http_responses.add(1, &attributes);
+        self.http_duration
+            .record(duration.as_secs_f64(), &attributes[..1]);
+    }
+}
+
+fn status_class(status: http::StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::StatusCode;
+
+    #[test]
+    fn test_status_class() {
+        assert_eq!(status_class(StatusCode::OK), "2xx");
+        assert_eq!(status_class(StatusCode::ACCEPTED), "2xx");
+        assert_eq!(status_class(StatusCode::PAYLOAD_TOO_LARGE), "4xx");
+        assert_eq!(status_class(StatusCode::SERVICE_UNAVAILABLE), "5xx");
+    }
+}
@@ -0,0 +1,347 @@
+//! Transport to the Datadog agent over a Unix domain socket.
+//!
+//! Agents deployed as a Kubernetes daemonset usually expose the trace intake on
+//! `unix:///var/run/datadog/apm.socket` only.
+
+use http::{HeaderName, HeaderValue, Request, Response};
+use opentelemetry_http::{Bytes, HttpClient, HttpError};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+
+/// Scheme of the agent endpoints reached through a Unix domain socket
+const UDS_SCHEME: &str = "unix";
+
+/// Base of the request urls sent through the socket, only their path and query are used
+pub(crate) const UDS_BASE_ENDPOINT: &str = "http://localhost";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The agent answers with a handful of headers
+const MAX_RESPONSE_HEADERS: usize = 32;
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+type ResponseSender = futures_channel::oneshot::Sender<Result<Response<Bytes>, HttpError>>;
+
+/// Requests sent to the worker thread, with the sender of their response
+type RequestSender = Sender<(Request<Bytes>, ResponseSender)>;
+
+/// Returns the socket path of `unix://` agent endpoints.
+pub(crate) fn socket_path(agent_endpoint: &str) -> Option<PathBuf> {
+    let url = Url::parse(agent_endpoint).ok()?;
+    if url.scheme() != UDS_SCHEME {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+/// [`HttpClient`] sending requests to the Datadog agent through a Unix domain socket.
+///
+/// The client is selected by [`DatadogPipelineBuilder`] for `unix://` agent endpoints, it can
+/// also be given to [`DatadogPipelineBuilder::with_http_client`] directly.
+///
+/// Requests are sent with blocking I/O on a new connection, one after the other on a worker
+/// thread of the client so that they don't block the executor, and the client doesn't depend on
+/// an async runtime. The worker is started by the first request and shared by the clones of the
+/// client, it stops once they are all dropped. Only the path and query of the request uri are
+/// used.
+///
+/// [`DatadogPipelineBuilder`]: crate::DatadogPipelineBuilder
+/// [`DatadogPipelineBuilder::with_http_client`]: crate::DatadogPipelineBuilder::with_http_client
+#[derive(Clone, Debug)]
+pub struct UdsClient {
+    socket_path: PathBuf,
+    timeout: Duration,
+    worker: Arc<Mutex<Option<RequestSender>>>,
+}
+
+impl UdsClient {
+    /// Create a client connecting to the socket at the given path.
+    pub fn new<P: Into<PathBuf>>(socket_path: P) -> Self {
+        UdsClient {
+            socket_path: socket_path.into(),
+            timeout: DEFAULT_TIMEOUT,
+            worker: Arc::default(),
+        }
+    }
+
+    /// Set the read and write timeout of the socket, 10 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        // the worker of the clones keeps their timeout
+        self.worker = Arc::default();
+        self
+    }
+
+    /// Sender of the requests to the worker thread, started on the first call.
+    fn worker(&self) -> Result<RequestSender, HttpError> {
+        let mut worker = self
+            .worker
+            .lock()
+            .map_err(|_| "the Unix domain socket worker lock is poisoned")?;
+        if let Some(sender) = worker.as_ref() {
+            return Ok(sender.clone());
+        }
+
+        let (sender, receiver) = mpsc::channel::<(Request<Bytes>, ResponseSender)>();
+        let socket_path = self.socket_path.clone();
+        let timeout = self.timeout;
+        std::thread::Builder::new()
+            .name("opentelemetry-datadog-uds".to_string())
+            .spawn(move || {
+                for (request, response) in receiver {
+                    let _ = response.send(send_blocking(&socket_path, timeout, request));
+                }
+            })?;
+        Ok(worker.insert(sender).clone())
+    }
+}
+
+fn send_blocking(
+    socket_path: &PathBuf,
+    timeout: Duration,
+    request: Request<Bytes>,
+) -> Result<Response<Bytes>, HttpError> {
+    let mut stream = UnixStream::connect(socket_path)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    stream.write_all(&encode_request(request))?;
+    stream.flush()?;
+    read_response(&mut stream)
+}
+
+#[async_trait::async_trait]
+impl HttpClient for UdsClient {
+    async fn send_bytes(&self, request: Request<Bytes>) -> Result<Response<Bytes>, HttpError> {
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        self.worker()?
+            .send((request, sender))
+            .map_err(|_| "the Unix domain socket worker stopped")?;
+        receiver
+            .await
+            .map_err(|_| "the Unix domain socket request was aborted")?
+    }
+}
+
+fn encode_request(request: Request<Bytes>) -> Vec<u8> {
+    let (parts, body) = request.into_parts();
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    let mut encoded = Vec::with_capacity(256 + body.len());
+    encoded.extend_from_slice(parts.method.as_str().as_bytes());
+    encoded.push(b' ');
+    encoded.extend_from_slice(path.as_bytes());
+    encoded.extend_from_slice(b" HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n");
+    encoded.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+    for (name, value) in &parts.headers {
+        if name == http::header::HOST
+            || name == http::header::CONNECTION
+            || name == http::header::CONTENT_LENGTH
+        {
+            continue;
+        }
+        encoded.extend_from_slice(name.as_str().as_bytes());
+        encoded.extend_from_slice(b": ");
+        encoded.extend_from_slice(value.as_bytes());
+        encoded.extend_from_slice(b"\r\n");
+    }
+    encoded.extend_from_slice(b"\r\n");
+    encoded.extend_from_slice(&body);
+    encoded
+}
+
+/// Reads the response until its body is complete, the connection may be kept open by the agent.
+fn read_response(stream: &mut impl Read) -> Result<Response<Bytes>, HttpError> {
+    let mut response = Vec::with_capacity(READ_BUFFER_SIZE);
+    let mut buffer = [0; READ_BUFFER_SIZE];
+    loop {
+        let len = stream.read(&mut buffer)?;
+        response.extend_from_slice(&buffer[..len]);
+        let closed = len == 0;
+        if let Some(response) = decode_response(&response, closed)? {
+            return Ok(response);
+        }
+        if closed {
+            return Err("incomplete HTTP response".into());
+        }
+    }
+}
+
+/// Decodes the response, `None` until it is complete. The end of a body without length nor
+/// chunks is the end of the connection, given by `closed`.
+fn decode_response(response: &[u8], closed: bool) -> Result<Option<Response<Bytes>>, HttpError> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_RESPONSE_HEADERS];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let head_len = match parsed.parse(response)? {
+        httparse::Status::Complete(len) => len,
+        httparse::Status::Partial => return Ok(None),
+    };
+    let status = parsed.code.unwrap_or_default();
+
+    let mut builder = Response::builder().status(status);
+    let mut chunked = false;
+    let mut content_length = None;
+    for header in parsed.headers.iter() {
+        let name = HeaderName::from_bytes(header.name.as_bytes())?;
+        let value = HeaderValue::from_bytes(header.value)?;
+        if name == http::header::TRANSFER_ENCODING {
+            chunked = value.as_bytes().eq_ignore_ascii_case(b"chunked");
+        } else if name == http::header::CONTENT_LENGTH {
+            content_length = value.to_str()?.parse::<usize>().ok();
+        }
+        builder = builder.header(name, value);
+    }
+
+    let body = &response[head_len..];
+    let body = if status == 204 || status == 304 || (100..200).contains(&status) {
+        Some(Vec::new())
+    } else if chunked {
+        decode_chunked(body)?
+    } else {
+        match content_length {
+            Some(len) if len <= body.len() => Some(body[..len].to_vec()),
+            Some(_) => None,
+            None if closed => Some(body.to_vec()),
+            None => None,
+        }
+    };
+    body.map(|body| Ok(builder.body(Bytes::from(body))?))
+        .transpose()
+}
+
+/// Decodes a chunked body, `None` until its last chunk is received.
+fn decode_chunked(mut body: &[u8]) -> Result<Option<Vec<u8>>, HttpError> {
+    let mut decoded = Vec::with_capacity(body.len());
+    loop {
+        let Some(line_end) = body.windows(2).position(|window| window == b"\r\n") else {
+            return Ok(None);
+        };
+        let size = std::str::from_utf8(&body[..line_end])?;
+        // chunk extensions are ignored
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(Some(decoded));
+        }
+        if body.len() < size + 2 {
+            return Ok(None);
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    #[test]
+    fn test_socket_path() {
+        assert_eq!(
+            socket_path("unix:///var/run/datadog/apm.socket"),
+            Some(PathBuf::from("/var/run/datadog/apm.socket"))
+        );
+        assert_eq!(socket_path("http://127.0.0.1:8126"), None);
+        assert_eq!(socket_path("not an endpoint"), None);
+    }
+
+    #[test]
+    fn test_decode_chunked_response() {
+        let response: &[u8] =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4;ext=1\r\nrate\r\n6\r\n_by_se\r\n0\r\n\r\n";
+        let decoded = decode_response(response, false).unwrap().unwrap();
+        assert_eq!(decoded.status(), 200);
+        assert_eq!(decoded.body().as_ref(), b"rate_by_se");
+
+        // the response is incomplete until the last chunk
+        assert!(decode_response(&response[..60], false).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_response_framing() {
+        let response: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK";
+        assert!(decode_response(&response[..10], false).unwrap().is_none());
+        assert!(decode_response(&response[..38], false).unwrap().is_none());
+        let decoded = decode_response(response, false).unwrap().unwrap();
+        assert_eq!(decoded.body().as_ref(), b"OK");
+
+        // without length nor chunks, the body ends with the connection
+        let response: &[u8] = b"HTTP/1.1 200 OK\r\n\r\nOK";
+        assert!(decode_response(response, false).unwrap().is_none());
+        let decoded = decode_response(response, true).unwrap().unwrap();
+        assert_eq!(decoded.body().as_ref(), b"OK");
+
+        let decoded = decode_response(b"HTTP/1.1 204 No Content\r\n\r\n", false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.status(), 204);
+        assert!(decoded.body().is_empty());
+    }
+
+    #[test]
+    fn test_send_through_socket() {
+        let socket_path = std::env::temp_dir().join(format!(
+            "opentelemetry-datadog-{}.socket",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let (done, client_done) = std::sync::mpsc::channel::<()>();
+
+        // the agent keeps the connections open until the client is done
+        let agent = thread::spawn(move || {
+            let responses: [&[u8]; 2] = [
+                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK",
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nOK\r\n0\r\n\r\n",
+            ];
+            let mut requests = Vec::new();
+            let mut streams = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"payload") {
+                    let len = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..len]);
+                }
+                stream.write_all(response).unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+                streams.push(stream);
+            }
+            client_done.recv().unwrap();
+            requests
+        });
+
+        let client = UdsClient::new(&socket_path).with_timeout(Duration::from_secs(5));
+        for client in [client.clone(), client] {
+            let request = Request::post("http://localhost/v0.5/traces")
+                .header("X-Datadog-Trace-Count", "1")
+                .body(Bytes::from_static(b"payload"))
+                .unwrap();
+            let response = futures_executor::block_on(client.send_bytes(request)).unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.body().as_ref(), b"OK");
+        }
+        done.send(()).unwrap();
+
+        for request in agent.join().unwrap() {
+            assert!(request.starts_with("POST /v0.5/traces HTTP/1.1\r\n"));
+            assert!(request.contains("Content-Length: 7\r\n"));
+            assert!(request.contains("x-datadog-trace-count: 1\r\n"));
+        }
+        let _ = std::fs::remove_file(&socket_path);
+    }
+}
@@ -73,6 +73,49 @@
 //!
 //! Users can always use their own http clients by implementing `HttpClient` trait.
 //!
+//! ## Unix domain sockets
+//!
+//! With the `uds-client` feature, agents listening on a Unix domain socket are reached by giving
+//! a `unix://` endpoint to the pipeline, the [`UdsClient`] is then used as http client.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), opentelemetry_sdk::trace::TraceError> {
+//! let provider = opentelemetry_datadog::new_pipeline()
+//!     .with_agent_endpoint("unix:///var/run/datadog/apm.socket")
+//!     .install_batch()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Metrics
+//!
+//! With the `metrics` feature, the `DogStatsdExporter` writes the metrics of a
+//! `SdkMeterProvider` to the DogStatsD server of the agent, see its documentation for the
+//! conversion of the OpenTelemetry aggregations.
+//!
+//! ## Logs
+//!
+//! With the `logs` feature, the `DatadogLogExporter` sends the log records of a `SdkLoggerProvider` to
+//! the Datadog logs intake, or through the agent, with the `dd.trace_id` and `dd.span_id` of
+//! their span so that Datadog links them to the traces.
+//!
+//! ## Exporter health
+//!
+//! Given a `Meter` with [`DatadogPipelineBuilder::with_meter`], the exporter records its own
+//! health, to alert when traces are not reaching the agent:
+//!
+//! | Instrument | Kind | Attributes |
+//! |---|---|---|
+//! | `datadog.exporter.spans.sent`, `datadog.exporter.traces.sent` | counter | |
+//! | `datadog.exporter.spans.dropped`, `datadog.exporter.traces.dropped` | counter | `reason`: `unsampled`, `payload_too_large` or `export_failed` |
+//! | `datadog.exporter.encode.duration` | histogram, seconds | |
+//! | `datadog.exporter.payload.size` | histogram, bytes | |
+//! | `datadog.exporter.http.duration` | histogram, seconds | `endpoint`: `traces` or `stats` |
+//! | `datadog.exporter.http.responses` | counter | `endpoint`, `status_class`: `2xx`, `4xx`, `5xx`, ... or `error` |
+//!
+//! Each retry counts as a request. Unsampled traces are only dropped when the exporter computes
+//! the trace metrics, see [`DatadogPipelineBuilder::with_stats_computation`].
+//!
 //! ## Kitchen Sink Full Configuration
 //!
 //! Example showing how to override all configuration options. See the
@@ -148,52 +191,159 @@
 //!     let _ = provider.shutdown(); // sending remaining spans before exit
 //!
 //! ```
+#![cfg_attr(
+    all(unix, feature = "uds-client"),
+    doc = "[`UdsClient`]: crate::UdsClient"
+)]
+// without the `uds-client` feature, the client has no page to link to
+#![cfg_attr(
+    not(all(unix, feature = "uds-client")),
+    doc = "[`UdsClient`]: #unix-domain-sockets"
+)]
 
 mod exporter;
+#[cfg(feature = "logs")]
+mod logs;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod sampling;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+#[cfg(all(unix, feature = "uds-client"))]
+pub use exporter::UdsClient;
 pub use exporter::{
-    new_pipeline, ApiVersion, DatadogExporter, DatadogPipelineBuilder, Error, FieldMappingFn,
-    ModelConfig,
+    new_pipeline, semconv_name_mapping, semconv_resource_mapping, semconv_service_name_mapping,
+    ApiVersion, DatadogExporter, DatadogPipelineBuilder, DatadogSpanProcessor, Error,
+    FieldMappingFn, ModelConfig,
 };
-pub use propagator::{DatadogPropagator, DatadogTraceState, DatadogTraceStateBuilder};
+#[cfg(feature = "logs")]
+pub use logs::{DatadogLogExporter, DatadogLogExporterBuilder};
+#[cfg(feature = "metrics")]
+pub use metrics::{DogStatsdExporter, DogStatsdExporterBuilder};
+pub use propagator::{
+    DatadogPropagator, DatadogTraceState, DatadogTraceStateBuilder, PropagationStyle,
+};
+#[cfg(feature = "agent-sampling")]
+pub use sampling::{DatadogAgentSampler, DatadogRulesSampler, SamplingRule};
 
 mod propagator {
+    use crate::exporter::model::datadog_trace_id;
     use opentelemetry::{
+        otel_warn,
         propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
         trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
         Context,
     };
-    use std::sync::OnceLock;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use std::sync::{Mutex, OnceLock};
 
     const DATADOG_TRACE_ID_HEADER: &str = "x-datadog-trace-id";
     const DATADOG_PARENT_ID_HEADER: &str = "x-datadog-parent-id";
     const DATADOG_SAMPLING_PRIORITY_HEADER: &str = "x-datadog-sampling-priority";
+    const DATADOG_TAGS_HEADER: &str = "x-datadog-tags";
+    const DATADOG_ORIGIN_HEADER: &str = "x-datadog-origin";
+
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L78
+    const DATADOG_TRACE_ID_HIGH_TAG: &str = "_dd.p.tid";
+    const DATADOG_PROPAGATED_TAG_PREFIX: &str = "_dd.p.";
+
+    // Default of `DD_TRACE_X_DATADOG_TAGS_MAX_LENGTH` in the Datadog tracers, see
+    // https://docs.datadoghq.com/tracing/trace_collection/library_config/
+    const DATADOG_TAGS_MAX_LENGTH: usize = 512;
+    const PROPAGATION_ERROR_EXTRACT_MAX_SIZE: &str = "extract_max_size";
+    const PROPAGATION_ERROR_INJECT_MAX_SIZE: &str = "inject_max_size";
+    const PROPAGATION_ERROR_DECODING: &str = "decoding_error";
+    const PROPAGATION_ERROR_ENCODING: &str = "encoding_error";
+    const PROPAGATION_ERROR_MALFORMED_TID: &str = "malformed_tid";
 
     const TRACE_FLAG_DEFERRED: TraceFlags = TraceFlags::new(0x02);
-    #[cfg(feature = "agent-sampling")]
-    const TRACE_STATE_PRIORITY_SAMPLING: &str = "psr";
-    const TRACE_STATE_MEASURE: &str = "m";
+    // Keys of the Datadog state in the trace state, in the `tenant@vendor` form under the `dd`
+    // vendor so that they can't collide with the members of other vendors
+    const TRACE_STATE_PRIORITY_SAMPLING: &str = "psr@dd";
+    const TRACE_STATE_MEASURE: &str = "m@dd";
+    const TRACE_STATE_ORIGIN: &str = "o@dd";
+    const TRACE_STATE_PROPAGATED_TAGS: &str = "t@dd";
+    const TRACE_STATE_PROPAGATION_ERROR: &str = "e@dd";
+    const TRACE_STATE_LAST_PARENT_ID: &str = "p@dd";
+    const TRACE_STATE_RULE_RATE: &str = "rr@dd";
+    const TRACE_STATE_LIMIT_RATE: &str = "lr@dd";
+    const TRACE_STATE_TOP_LEVEL: &str = "tl@dd";
+
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/sampler.go#L374
+    const DATADOG_DECISION_MAKER_TAG: &str = "_dd.p.dm";
+
+    // Datadog vendor member of the W3C `tracestate` header
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L960
+    const W3C_TRACE_STATE_DATADOG_KEY: &str = "dd";
+    const W3C_DATADOG_PRIORITY: &str = "s";
+    const W3C_DATADOG_ORIGIN: &str = "o";
+    const W3C_DATADOG_LAST_PARENT_ID: &str = "p";
+    const W3C_DATADOG_TAG_PREFIX: &str = "t.";
+    const W3C_TRACE_STATE_VALUE_MAX_LENGTH: usize = 256;
+    const W3C_TRACE_PARENT_HEADER: &str = "traceparent";
+    const W3C_TRACE_STATE_HEADER: &str = "tracestate";
+
+    // Trace state keys holding the Datadog state, they are replaced by the `dd` member when
+    // injecting the W3C `tracestate` header.
+    const TRACE_STATE_DATADOG_KEYS: [&str; 10] = [
+        TRACE_STATE_PRIORITY_SAMPLING,
+        TRACE_STATE_MEASURE,
+        TRACE_STATE_ORIGIN,
+        TRACE_STATE_PROPAGATED_TAGS,
+        TRACE_STATE_PROPAGATION_ERROR,
+        TRACE_STATE_LAST_PARENT_ID,
+        TRACE_STATE_RULE_RATE,
+        TRACE_STATE_LIMIT_RATE,
+        TRACE_STATE_TOP_LEVEL,
+        W3C_TRACE_STATE_DATADOG_KEY,
+    ];
     const TRACE_STATE_TRUE_VALUE: &str = "1";
     const TRACE_STATE_FALSE_VALUE: &str = "0";
 
     // TODO Replace this with LazyLock when MSRV is 1.80+
-    static TRACE_CONTEXT_HEADER_FIELDS: OnceLock<[String; 3]> = OnceLock::new();
+    static TRACE_CONTEXT_HEADER_FIELDS: OnceLock<[String; 5]> = OnceLock::new();
+    static W3C_HEADER_FIELDS: OnceLock<[String; 2]> = OnceLock::new();
+    static ALL_HEADER_FIELDS: OnceLock<[String; 7]> = OnceLock::new();
 
-    fn trace_context_header_fields() -> &'static [String; 3] {
+    fn trace_context_header_fields() -> &'static [String; 5] {
         TRACE_CONTEXT_HEADER_FIELDS.get_or_init(|| {
             [
                 DATADOG_TRACE_ID_HEADER.to_owned(),
                 DATADOG_PARENT_ID_HEADER.to_owned(),
                 DATADOG_SAMPLING_PRIORITY_HEADER.to_owned(),
+                DATADOG_TAGS_HEADER.to_owned(),
+                DATADOG_ORIGIN_HEADER.to_owned(),
+            ]
+        })
+    }
+
+    fn w3c_header_fields() -> &'static [String; 2] {
+        W3C_HEADER_FIELDS.get_or_init(|| {
+            [
+                W3C_TRACE_PARENT_HEADER.to_owned(),
+                W3C_TRACE_STATE_HEADER.to_owned(),
             ]
         })
     }
 
+    fn all_header_fields() -> &'static [String; 7] {
+        ALL_HEADER_FIELDS.get_or_init(|| {
+            let [a, b, c, d, e] = trace_context_header_fields().clone();
+            let [f, g] = w3c_header_fields().clone();
+            [a, b, c, d, e, f, g]
+        })
+    }
+
     #[derive(Default)]
     pub struct DatadogTraceStateBuilder {
         #[cfg(feature = "agent-sampling")]
-        priority_sampling: bool,
+        sampling_priority: i8,
         measuring: bool,
+        origin: Option<String>,
+        propagated_tags: Vec<(String, String)>,
+        rule_rate: Option<f64>,
+        limit_rate: Option<f64>,
     }
 
     fn boolean_to_trace_state_flag(value: bool) -> &'static str {
@@ -208,12 +358,234 @@ mod propagator {
         value == TRACE_STATE_TRUE_VALUE
     }
 
+    // Propagated tags are stored in a single trace state entry, using the same encoding as the
+    // `dd` member of the W3C `tracestate` header: `dm:-4;usr.id:xyz`. Keys are stored without the
+    // `_dd.p.` prefix, and `=` in values is replaced by `~` as it is not allowed in trace state values.
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L1002
+    fn encode_propagated_tags<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+        let mut encoded = String::new();
+        for (key, value) in tags {
+            let key = key
+                .strip_prefix(DATADOG_PROPAGATED_TAG_PREFIX)
+                .unwrap_or(key);
+            if !encoded.is_empty() {
+                encoded.push(';');
+            }
+            encoded.push_str(key);
+            encoded.push(':');
+            encoded.extend(value.chars().map(|c| match c {
+                '=' => '~',
+                ';' | '~' => '_',
+                c => c,
+            }));
+        }
+        encoded
+    }
+
+    fn decode_propagated_tags(encoded: &str) -> impl Iterator<Item = (String, String)> + '_ {
+        encoded
+            .split(';')
+            .filter_map(|tag| tag.split_once(':'))
+            .map(|(key, value)| {
+                (
+                    format!("{}{}", DATADOG_PROPAGATED_TAG_PREFIX, key),
+                    value.replace('~', "="),
+                )
+            })
+    }
+
+    fn with_propagation_error(trace_state: TraceState, error: &str) -> TraceState {
+        trace_state
+            .insert(TRACE_STATE_PROPAGATION_ERROR, error)
+            .unwrap_or(trace_state)
+    }
+
+    // Validation rules of the `x-datadog-tags` header content
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L570
+    fn is_valid_tag_key(key: &str) -> bool {
+        !key.is_empty()
+            && key
+                .bytes()
+                .all(|b| (0x21..=0x7e).contains(&b) && b != b',' && b != b'=')
+    }
+
+    fn is_valid_tag_value(value: &str) -> bool {
+        !value.is_empty()
+            && value
+                .bytes()
+                .all(|b| (0x20..=0x7e).contains(&b) && b != b',')
+    }
+
+    /// Parses the `_dd.p.*` tags of a `x-datadog-tags` header, other tags are ignored.
+    ///
+    /// Returns the propagation error to record when the header is too long or malformed, in which
+    /// case none of the tags are kept.
+    fn parse_datadog_tags(header: &str) -> Result<Vec<(&str, &str)>, &'static str> {
+        if header.len() > DATADOG_TAGS_MAX_LENGTH {
+            return Err(PROPAGATION_ERROR_EXTRACT_MAX_SIZE);
+        }
+
+        let mut tags = Vec::new();
+        for tag in header.split(',').filter(|tag| !tag.is_empty()) {
+            let (key, value) = tag.split_once('=').ok_or(PROPAGATION_ERROR_DECODING)?;
+            if !is_valid_tag_key(key) || !is_valid_tag_value(value) {
+                return Err(PROPAGATION_ERROR_DECODING);
+            }
+            if key.starts_with(DATADOG_PROPAGATED_TAG_PREFIX) {
+                tags.push((key, value));
+            }
+        }
+        Ok(tags)
+    }
+
+    // Values are also injected without `=`, so that the header can be split unambiguously.
+    fn is_valid_injected_tag(key: &str, value: &str) -> bool {
+        is_valid_tag_key(key) && is_valid_tag_value(value) && !value.contains('=')
+    }
+
+    /// Maximum number of traces whose injection error is kept until their local root span is
+    /// exported, the oldest errors are forgotten first.
+    const INJECT_ERRORS_MAX_TRACES: usize = 256;
+
+    /// Errors encountered while injecting the `_dd.p.*` tags of traces. They are written on the
+    /// local root span of the trace by the exporter, as it can't be reached from the context of
+    /// the injected span.
+    static INJECT_ERRORS: Mutex<Vec<(TraceId, &'static str)>> = Mutex::new(Vec::new());
+
+    pub(crate) fn record_inject_error(trace_id: TraceId, error: &'static str) {
+        if let Ok(mut errors) = INJECT_ERRORS.lock() {
+            errors.retain(|(id, _)| *id != trace_id);
+            if errors.len() >= INJECT_ERRORS_MAX_TRACES {
+                errors.remove(0);
+            }
+            errors.push((trace_id, error));
+        }
+    }
+
+    /// Whether the injection of the propagated tags of any trace failed.
+    pub(crate) fn has_inject_errors() -> bool {
+        INJECT_ERRORS
+            .lock()
+            .map(|errors| !errors.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// Takes the error encountered while injecting the propagated tags of a trace.
+    pub(crate) fn take_inject_error(trace_id: TraceId) -> Option<&'static str> {
+        let mut errors = INJECT_ERRORS.lock().ok()?;
+        let idx = errors.iter().position(|(id, _)| *id == trace_id)?;
+        Some(errors.remove(idx).1)
+    }
+
+    fn is_valid_trace_id_high(value: &str) -> bool {
+        value.len() == 16
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    }
+
+    // Characters of the `dd` member values that would break the `tracestate` header are replaced
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L1002
+    fn sanitize_w3c_value(value: &str) -> String {
+        value
+            .chars()
+            .map(|c| match c {
+                '=' => '~',
+                ',' | ';' | '~' => '_',
+                c if (' '..='~').contains(&c) => c,
+                _ => '_',
+            })
+            .collect()
+    }
+
+    /// Builds the value of the `dd` member of the W3C `tracestate` header, e.g.
+    /// `s:1;o:synthetics;p:00f067aa0ba902b7;t.dm:-4`.
+    fn compose_w3c_datadog_member(
+        sampling_priority: Option<SamplingPriority>,
+        span_context: &SpanContext,
+    ) -> String {
+        let trace_state = span_context.trace_state();
+        let mut member = Vec::new();
+        if let Some(sampling_priority) = sampling_priority {
+            member.push(format!(
+                "{}:{}",
+                W3C_DATADOG_PRIORITY, sampling_priority as i32
+            ));
+        }
+        if let Some(origin) = trace_state.origin() {
+            member.push(format!(
+                "{}:{}",
+                W3C_DATADOG_ORIGIN,
+                sanitize_w3c_value(origin)
+            ));
+        }
+        member.push(format!(
+            "{}:{:016x}",
+            W3C_DATADOG_LAST_PARENT_ID,
+            u64::from_be_bytes(span_context.span_id().to_bytes())
+        ));
+
+        let mut len = member.iter().map(|entry| entry.len() + 1).sum::<usize>();
+        for (key, value) in trace_state.propagated_tags() {
+            let key = key.trim_start_matches(DATADOG_PROPAGATED_TAG_PREFIX);
+            let entry = format!(
+                "{}{}:{}",
+                W3C_DATADOG_TAG_PREFIX,
+                key,
+                sanitize_w3c_value(&value)
+            );
+            // tags that don't fit in the member are dropped
+            if len + entry.len() > W3C_TRACE_STATE_VALUE_MAX_LENGTH {
+                continue;
+            }
+            len += entry.len() + 1;
+            member.push(entry);
+        }
+        member.join(";")
+    }
+
+    /// Content of the `dd` member of the W3C `tracestate` header.
+    #[derive(Default)]
+    struct W3CDatadogMember<'a> {
+        sampling_priority: Option<i32>,
+        origin: Option<String>,
+        last_parent_id: Option<&'a str>,
+        propagated_tags: Vec<(&'a str, String)>,
+    }
+
+    fn parse_w3c_datadog_member(member: &str) -> W3CDatadogMember<'_> {
+        let mut parsed = W3CDatadogMember::default();
+        for (key, value) in member.split(';').filter_map(|entry| entry.split_once(':')) {
+            match key {
+                W3C_DATADOG_PRIORITY => parsed.sampling_priority = value.parse().ok(),
+                W3C_DATADOG_ORIGIN => parsed.origin = Some(value.replace('~', "=")),
+                W3C_DATADOG_LAST_PARENT_ID => parsed.last_parent_id = Some(value),
+                _ => {
+                    if let Some(tag) = key.strip_prefix(W3C_DATADOG_TAG_PREFIX) {
+                        // the upper bits of the trace id are already part of the `traceparent`
+                        if tag != "tid" {
+                            parsed.propagated_tags.push((tag, value.replace('~', "=")));
+                        }
+                    }
+                }
+            }
+        }
+        parsed
+    }
+
     #[allow(clippy::needless_update)]
     impl DatadogTraceStateBuilder {
         #[cfg(feature = "agent-sampling")]
         pub fn with_priority_sampling(self, enabled: bool) -> Self {
+            self.with_sampling_priority(enabled as i8)
+        }
+
+        /// Set the sampling priority of the trace: `-1` when rejected and `2` when kept by a user
+        /// decision such as a sampling rule, `0` and `1` when decided by the agent rates.
+        #[cfg(feature = "agent-sampling")]
+        pub fn with_sampling_priority(self, priority: i8) -> Self {
             Self {
-                priority_sampling: enabled,
+                sampling_priority: priority,
                 ..self
             }
         }
@@ -225,6 +597,47 @@ mod propagator {
             }
         }
 
+        /// Set the origin of the trace, e.g. `synthetics` for traces started by Datadog Synthetic tests.
+        pub fn with_origin<T: Into<String>>(self, origin: T) -> Self {
+            Self {
+                origin: Some(origin.into()),
+                ..self
+            }
+        }
+
+        /// Add a `_dd.p.*` tag propagated to downstream services through the `x-datadog-tags` header.
+        pub fn with_propagated_tag<K: Into<String>, V: Into<String>>(
+            mut self,
+            key: K,
+            value: V,
+        ) -> Self {
+            self.propagated_tags.push((key.into(), value.into()));
+            self
+        }
+
+        /// Set the `_dd.p.dm` tag, the mechanism that made the sampling decision of the trace.
+        pub fn with_decision_maker(self, mechanism: u8) -> Self {
+            self.with_propagated_tag(DATADOG_DECISION_MAKER_TAG, format!("-{}", mechanism))
+        }
+
+        /// Set the sample rate of the sampling rule matching the trace, reported as the
+        /// `_dd.rule_psr` metric of its root span.
+        pub fn with_sampling_rule_rate(self, rate: f64) -> Self {
+            Self {
+                rule_rate: Some(rate),
+                ..self
+            }
+        }
+
+        /// Set the effective rate of the sampling rate limiter, reported as the `_dd.limit_psr`
+        /// metric of the root span of the trace.
+        pub fn with_sampling_limit_rate(self, rate: f64) -> Self {
+            Self {
+                limit_rate: Some(rate),
+                ..self
+            }
+        }
+
         pub fn build(self) -> TraceState {
             #[cfg(not(feature = "agent-sampling"))]
             let values = [(
@@ -239,11 +652,28 @@ mod propagator {
                 ),
                 (
                     TRACE_STATE_PRIORITY_SAMPLING,
-                    boolean_to_trace_state_flag(self.priority_sampling),
+                    &*self.sampling_priority.to_string(),
                 ),
             ];
 
-            TraceState::from_key_value(values).unwrap_or_default()
+            let mut trace_state = TraceState::from_key_value(values).unwrap_or_default();
+            if let Some(origin) = &self.origin {
+                trace_state = trace_state.with_origin(origin);
+            }
+            for (key, value) in &self.propagated_tags {
+                trace_state = trace_state.with_propagated_tag(key, value);
+            }
+            for (key, rate) in [
+                (TRACE_STATE_RULE_RATE, self.rule_rate),
+                (TRACE_STATE_LIMIT_RATE, self.limit_rate),
+            ] {
+                if let Some(rate) = rate {
+                    trace_state = trace_state
+                        .insert(key, rate.to_string())
+                        .unwrap_or(trace_state);
+                }
+            }
+            trace_state
         }
     }
 
@@ -257,6 +687,55 @@ mod propagator {
 
         #[cfg(feature = "agent-sampling")]
         fn priority_sampling_enabled(&self) -> bool;
+
+        /// The sampling priority of the trace, from `-1` to `2`.
+        #[cfg(feature = "agent-sampling")]
+        fn sampling_priority(&self) -> Option<i8>;
+
+        /// Set the origin of the trace, propagated through the `x-datadog-origin` header.
+        fn with_origin(&self, origin: &str) -> TraceState;
+
+        fn origin(&self) -> Option<&str>;
+
+        /// Set a `_dd.p.*` tag, propagated through the `x-datadog-tags` header.
+        ///
+        /// Tags without the `_dd.p.` prefix are ignored. Tags that can't be written in the header,
+        /// such as values containing `,` or `=`, are rejected with the `encoding_error`
+        /// propagation error.
+        fn with_propagated_tag(&self, key: &str, value: &str) -> TraceState;
+
+        /// The `_dd.p.*` tags propagated with the trace, keys include the `_dd.p.` prefix.
+        fn propagated_tags(&self) -> Vec<(String, String)>;
+
+        /// The error encountered while extracting or injecting the propagated tags, to be reported
+        /// as the `_dd.propagation_error` tag.
+        fn propagation_error(&self) -> Option<&str>;
+
+        /// The id of the last Datadog span of the trace, as extracted from the `p` field of the
+        /// `dd` member of the W3C `tracestate` header.
+        fn last_parent_id(&self) -> Option<&str>;
+
+        /// Set the `_dd.p.dm` tag, the mechanism that made the sampling decision of the trace,
+        /// such as `1` for the agent rates or `3` for sampling rules.
+        fn with_decision_maker(&self, mechanism: u8) -> TraceState;
+
+        /// The mechanism that made the sampling decision, from the `_dd.p.dm` tag.
+        fn decision_maker(&self) -> Option<u8>;
+
+        /// The sample rate of the sampling rule matching the trace.
+        fn sampling_rule_rate(&self) -> Option<f64>;
+
+        /// The effective rate of the sampling rate limiter when the trace was sampled.
+        fn sampling_limit_rate(&self) -> Option<f64>;
+
+        /// Set whether the span is top-level, for spans whose parent is not part of their trace
+        /// chunk, as set by the [`DatadogSpanProcessor`] on partially flushed traces.
+        ///
+        /// [`DatadogSpanProcessor`]: crate::DatadogSpanProcessor
+        fn with_top_level(&self, top_level: bool) -> TraceState;
+
+        /// Whether the span is top-level, when it was decided outside of its trace chunk.
+        fn top_level(&self) -> Option<bool>;
     }
 
     impl DatadogTraceState for TraceState {
@@ -282,10 +761,86 @@ mod propagator {
 
         #[cfg(feature = "agent-sampling")]
         fn priority_sampling_enabled(&self) -> bool {
-            self.get(TRACE_STATE_PRIORITY_SAMPLING)
-                .map(trace_flag_to_boolean)
+            self.sampling_priority()
+                .is_some_and(|priority| priority > 0)
+        }
+
+        #[cfg(feature = "agent-sampling")]
+        fn sampling_priority(&self) -> Option<i8> {
+            self.get(TRACE_STATE_PRIORITY_SAMPLING)?.parse().ok()
+        }
+
+        fn with_origin(&self, origin: &str) -> TraceState {
+            self.insert(TRACE_STATE_ORIGIN, origin)
+                .unwrap_or_else(|_err| self.clone())
+        }
+
+        fn origin(&self) -> Option<&str> {
+            self.get(TRACE_STATE_ORIGIN)
+        }
+
+        fn with_propagated_tag(&self, key: &str, value: &str) -> TraceState {
+            if !key.starts_with(DATADOG_PROPAGATED_TAG_PREFIX) || key == DATADOG_TRACE_ID_HIGH_TAG {
+                return self.clone();
+            }
+            if !is_valid_injected_tag(key, value) {
+                return with_propagation_error(self.clone(), PROPAGATION_ERROR_ENCODING);
+            }
+            let tags = self.propagated_tags();
+            let encoded = encode_propagated_tags(
+                tags.iter()
+                    .filter(|(k, _)| k != key)
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .chain(std::iter::once((key, value))),
+            );
+            self.insert(TRACE_STATE_PROPAGATED_TAGS, encoded)
+                .unwrap_or_else(|_err| self.clone())
+        }
+
+        fn propagated_tags(&self) -> Vec<(String, String)> {
+            self.get(TRACE_STATE_PROPAGATED_TAGS)
+                .map(|encoded| decode_propagated_tags(encoded).collect())
                 .unwrap_or_default()
         }
+
+        fn propagation_error(&self) -> Option<&str> {
+            self.get(TRACE_STATE_PROPAGATION_ERROR)
+        }
+
+        fn last_parent_id(&self) -> Option<&str> {
+            self.get(TRACE_STATE_LAST_PARENT_ID)
+        }
+
+        fn with_decision_maker(&self, mechanism: u8) -> TraceState {
+            self.with_propagated_tag(DATADOG_DECISION_MAKER_TAG, &format!("-{}", mechanism))
+        }
+
+        fn decision_maker(&self) -> Option<u8> {
+            self.propagated_tags()
+                .into_iter()
+                .find(|(key, _)| key == DATADOG_DECISION_MAKER_TAG)
+                .and_then(|(_, value)| value.strip_prefix('-')?.parse().ok())
+        }
+
+        fn sampling_rule_rate(&self) -> Option<f64> {
+            self.get(TRACE_STATE_RULE_RATE)?.parse().ok()
+        }
+
+        fn sampling_limit_rate(&self) -> Option<f64> {
+            self.get(TRACE_STATE_LIMIT_RATE)?.parse().ok()
+        }
+
+        fn with_top_level(&self, top_level: bool) -> TraceState {
+            self.insert(
+                TRACE_STATE_TOP_LEVEL,
+                boolean_to_trace_state_flag(top_level),
+            )
+            .unwrap_or_else(|_err| self.clone())
+        }
+
+        fn top_level(&self) -> Option<bool> {
+            self.get(TRACE_STATE_TOP_LEVEL).map(trace_flag_to_boolean)
+        }
     }
 
     enum SamplingPriority {
@@ -302,6 +857,21 @@ mod propagator {
         SamplingPriority,
     }
 
+    /// Header formats the [`DatadogPropagator`] can extract and inject.
+    ///
+    /// The styles are tried in order when extracting, and all of them are injected. See
+    /// [Datadog propagation styles](https://docs.datadoghq.com/tracing/trace_collection/trace_context_propagation/).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub enum PropagationStyle {
+        /// The `x-datadog-trace-id`, `x-datadog-parent-id`, `x-datadog-sampling-priority`,
+        /// `x-datadog-origin` and `x-datadog-tags` headers.
+        Datadog,
+        /// The W3C `traceparent` and `tracestate` headers, with the Datadog state held in the
+        /// `dd` member of `tracestate`.
+        TraceContext,
+    }
+
     /// Extracts and injects `SpanContext`s into `Extractor`s or `Injector`s using Datadog's header format.
     ///
     /// The Datadog header format does not have an explicit spec, but can be divined from the client libraries,
@@ -316,28 +886,45 @@ mod propagator {
     /// global::set_text_map_propagator(DatadogPropagator::default());
     /// ```
     ///
+    /// The propagator can also read and write the Datadog state in the `dd` member of the W3C
+    /// `tracestate` header, see [`PropagationStyle`] and [`DatadogPropagator::with_styles`].
+    ///
     /// [dd-trace-go]: https://github.com/DataDog/dd-trace-go/blob/v1.28.0/ddtrace/tracer/textmap.go#L293
-    #[derive(Clone, Debug, Default)]
+    #[derive(Clone, Debug)]
     pub struct DatadogPropagator {
-        _private: (),
+        styles: Vec<PropagationStyle>,
+    }
+
+    impl Default for DatadogPropagator {
+        fn default() -> Self {
+            DatadogPropagator {
+                styles: vec![PropagationStyle::Datadog],
+            }
+        }
     }
 
+    /// The trace state and flags of an extracted context, given its sampling priority or `None`
+    /// when the sampling decision is deferred.
     #[cfg(not(feature = "agent-sampling"))]
-    fn create_trace_state_and_flags(trace_flags: TraceFlags) -> (TraceState, TraceFlags) {
+    fn create_trace_state_and_flags(sampling_priority: Option<i32>) -> (TraceState, TraceFlags) {
+        let trace_flags = match sampling_priority {
+            Some(priority) if priority > 0 => TraceFlags::SAMPLED,
+            Some(_) => TraceFlags::default(),
+            None => TRACE_FLAG_DEFERRED,
+        };
         (TraceState::default(), trace_flags)
     }
 
     #[cfg(feature = "agent-sampling")]
-    fn create_trace_state_and_flags(trace_flags: TraceFlags) -> (TraceState, TraceFlags) {
-        if trace_flags & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED {
-            (TraceState::default(), trace_flags)
-        } else {
-            (
+    fn create_trace_state_and_flags(sampling_priority: Option<i32>) -> (TraceState, TraceFlags) {
+        match sampling_priority {
+            Some(priority) => (
                 DatadogTraceStateBuilder::default()
-                    .with_priority_sampling(trace_flags.is_sampled())
+                    .with_sampling_priority(priority.clamp(-1, 2) as i8)
                     .build(),
                 TraceFlags::SAMPLED,
-            )
+            ),
+            None => (TraceState::default(), TRACE_FLAG_DEFERRED),
         }
     }
 
@@ -347,6 +934,31 @@ mod propagator {
             DatadogPropagator::default()
         }
 
+        /// Creates a new `DatadogPropagator` using the given propagation styles.
+        ///
+        /// All styles are injected, and the context is extracted from the first style, in the
+        /// given order, whose headers hold a valid context.
+        ///
+        /// ```
+        /// use opentelemetry_datadog::{DatadogPropagator, PropagationStyle};
+        ///
+        /// let propagator = DatadogPropagator::with_styles([
+        ///     PropagationStyle::TraceContext,
+        ///     PropagationStyle::Datadog,
+        /// ]);
+        /// ```
+        pub fn with_styles<I: IntoIterator<Item = PropagationStyle>>(styles: I) -> Self {
+            let mut deduplicated = Vec::new();
+            for style in styles {
+                if !deduplicated.contains(&style) {
+                    deduplicated.push(style);
+                }
+            }
+            DatadogPropagator {
+                styles: deduplicated,
+            }
+        }
+
         fn extract_trace_id(&self, trace_id: &str) -> Result<TraceId, ExtractError> {
             trace_id
                 .parse::<u64>()
@@ -382,7 +994,7 @@ mod propagator {
             &self,
             extractor: &dyn Extractor,
         ) -> Result<SpanContext, ExtractError> {
-            let trace_id =
+            let mut trace_id =
                 self.extract_trace_id(extractor.get(DATADOG_TRACE_ID_HEADER).unwrap_or(""))?;
             // If we have a trace_id but can't get the parent span, we default it to invalid instead of completely erroring
             // out so that the rest of the spans aren't completely lost
@@ -394,18 +1006,49 @@ mod propagator {
                     .get(DATADOG_SAMPLING_PRIORITY_HEADER)
                     .unwrap_or(""),
             );
-            let sampled = match sampling_priority {
-                Ok(SamplingPriority::UserReject) | Ok(SamplingPriority::AutoReject) => {
-                    TraceFlags::default()
-                }
-                Ok(SamplingPriority::UserKeep) | Ok(SamplingPriority::AutoKeep) => {
-                    TraceFlags::SAMPLED
-                }
-                // Treat the sampling as DEFERRED instead of erroring on extracting the span context
-                Err(_) => TRACE_FLAG_DEFERRED,
-            };
+            // Treat the sampling as DEFERRED instead of erroring on extracting the span context
+            let (mut trace_state, trace_flags) = create_trace_state_and_flags(
+                sampling_priority.ok().map(|priority| priority as i32),
+            );
+
+            if let Some(origin) = extractor.get(DATADOG_ORIGIN_HEADER) {
+                trace_state = trace_state.with_origin(origin);
+            }
 
-            let (trace_state, trace_flags) = create_trace_state_and_flags(sampled);
+            match extractor.get(DATADOG_TAGS_HEADER).map(parse_datadog_tags) {
+                Some(Ok(tags)) => {
+                    let mut propagated_tags = Vec::with_capacity(tags.len());
+                    for (key, value) in tags {
+                        if key != DATADOG_TRACE_ID_HIGH_TAG {
+                            propagated_tags.push((key, value));
+                        } else if is_valid_trace_id_high(value) {
+                            // The upper 64 bits of 128-bit trace ids are propagated as 16 lowercase
+                            // hex characters.
+                            let high = u64::from_str_radix(value, 16).unwrap_or_default();
+                            let low = u128::from_be_bytes(trace_id.to_bytes());
+                            trace_id = TraceId::from(((high as u128) << 64) | low);
+                        } else {
+                            trace_state = with_propagation_error(
+                                trace_state,
+                                PROPAGATION_ERROR_MALFORMED_TID,
+                            );
+                        }
+                    }
+                    if !propagated_tags.is_empty() {
+                        let encoded = encode_propagated_tags(propagated_tags.into_iter());
+                        trace_state = match trace_state.insert(TRACE_STATE_PROPAGATED_TAGS, encoded)
+                        {
+                            Ok(trace_state) => trace_state,
+                            Err(_) => with_propagation_error(
+                                trace_state,
+                                PROPAGATION_ERROR_EXTRACT_MAX_SIZE,
+                            ),
+                        };
+                    }
+                }
+                Some(Err(error)) => trace_state = with_propagation_error(trace_state, error),
+                None => {}
+            }
 
             Ok(SpanContext::new(
                 trace_id,
@@ -415,6 +1058,104 @@ mod propagator {
                 trace_state,
             ))
         }
+
+        fn extract_w3c_span_context(&self, extractor: &dyn Extractor) -> Option<SpanContext> {
+            let cx = TraceContextPropagator::new().extract(extractor);
+            let span_context = cx.span().span_context().clone();
+            if !span_context.is_valid() {
+                return None;
+            }
+
+            let w3c_trace_state = span_context.trace_state();
+            let member = w3c_trace_state
+                .get(W3C_TRACE_STATE_DATADOG_KEY)
+                .map(parse_w3c_datadog_member)
+                .unwrap_or_default();
+
+            // The `traceparent` sampled flag wins when it disagrees with the sampling priority
+            // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go#L1144
+            let sampled = span_context.is_sampled();
+            let sampling_priority = match member.sampling_priority {
+                Some(priority) if (priority > 0) == sampled => priority,
+                _ => sampled as i32,
+            };
+            let (mut trace_state, trace_flags) =
+                create_trace_state_and_flags(Some(sampling_priority));
+
+            // keep the other vendors members
+            let mut vendors = w3c_trace_state.clone();
+            for key in TRACE_STATE_DATADOG_KEYS {
+                vendors = vendors.delete(key).unwrap_or(vendors);
+            }
+            for entry in vendors.header().split(',').rev() {
+                if let Some((key, value)) = entry.split_once('=') {
+                    trace_state = trace_state.insert(key, value).unwrap_or(trace_state);
+                }
+            }
+
+            if let Some(origin) = &member.origin {
+                trace_state = trace_state.with_origin(origin);
+            }
+            if let Some(last_parent_id) = member.last_parent_id {
+                trace_state = trace_state
+                    .insert(TRACE_STATE_LAST_PARENT_ID, last_parent_id)
+                    .unwrap_or(trace_state);
+            }
+            if !member.propagated_tags.is_empty() {
+                let encoded = encode_propagated_tags(
+                    member
+                        .propagated_tags
+                        .iter()
+                        .map(|(key, value)| (*key, value.as_str())),
+                );
+                trace_state = trace_state
+                    .insert(TRACE_STATE_PROPAGATED_TAGS, encoded)
+                    .unwrap_or(trace_state);
+            }
+
+            Some(SpanContext::new(
+                span_context.trace_id(),
+                span_context.span_id(),
+                trace_flags,
+                true,
+                trace_state,
+            ))
+        }
+
+        fn inject_w3c(&self, span_context: &SpanContext, injector: &mut dyn Injector) {
+            let sampling_priority = (span_context.trace_flags() & TRACE_FLAG_DEFERRED
+                != TRACE_FLAG_DEFERRED)
+                .then(|| get_sampling_priority(span_context));
+            let trace_flags = match &sampling_priority {
+                Some(SamplingPriority::AutoKeep) | Some(SamplingPriority::UserKeep) => {
+                    TraceFlags::SAMPLED
+                }
+                Some(_) => TraceFlags::default(),
+                None => span_context.trace_flags() & TraceFlags::SAMPLED,
+            };
+
+            let member = compose_w3c_datadog_member(sampling_priority, span_context);
+            let mut trace_state = span_context.trace_state().clone();
+            for key in TRACE_STATE_DATADOG_KEYS {
+                trace_state = trace_state.delete(key).unwrap_or(trace_state);
+            }
+            // the `dd` member is inserted first in the list, as the most recently updated member
+            let trace_state = trace_state
+                .insert(W3C_TRACE_STATE_DATADOG_KEY, member)
+                .unwrap_or(trace_state);
+
+            let w3c_span_context = SpanContext::new(
+                span_context.trace_id(),
+                span_context.span_id(),
+                trace_flags,
+                span_context.is_remote(),
+                trace_state,
+            );
+            TraceContextPropagator::new().inject_context(
+                &Context::new().with_remote_span_context(w3c_span_context),
+                injector,
+            );
+        }
     }
 
     #[cfg(not(feature = "agent-sampling"))]
@@ -428,10 +1169,69 @@ mod propagator {
 
     #[cfg(feature = "agent-sampling")]
     fn get_sampling_priority(span_context: &SpanContext) -> SamplingPriority {
-        if span_context.trace_state().priority_sampling_enabled() {
-            SamplingPriority::AutoKeep
-        } else {
-            SamplingPriority::AutoReject
+        match span_context.trace_state().sampling_priority() {
+            Some(-1) => SamplingPriority::UserReject,
+            Some(2) => SamplingPriority::UserKeep,
+            Some(priority) if priority > 0 => SamplingPriority::AutoKeep,
+            _ => SamplingPriority::AutoReject,
+        }
+    }
+
+    impl DatadogPropagator {
+        fn inject_datadog(&self, span_context: &SpanContext, injector: &mut dyn Injector) {
+            injector.set(
+                DATADOG_TRACE_ID_HEADER,
+                datadog_trace_id(span_context.trace_id()).to_string(),
+            );
+
+            let trace_state = span_context.trace_state();
+            if let Some(origin) = trace_state.origin() {
+                injector.set(DATADOG_ORIGIN_HEADER, origin.to_string());
+            }
+
+            let mut tags = Vec::new();
+            let trace_id_high =
+                (u128::from_be_bytes(span_context.trace_id().to_bytes()) >> 64) as u64;
+            if trace_id_high != 0 {
+                tags.push(format!(
+                    "{}={:016x}",
+                    DATADOG_TRACE_ID_HIGH_TAG, trace_id_high
+                ));
+            }
+            let mut error = None;
+            for (key, value) in trace_state.propagated_tags() {
+                if !is_valid_injected_tag(&key, &value) {
+                    error = Some(PROPAGATION_ERROR_ENCODING);
+                    break;
+                }
+                tags.push(format!("{}={}", key, value));
+            }
+            let tags = tags.join(",");
+            if error.is_none() && tags.len() > DATADOG_TAGS_MAX_LENGTH {
+                error = Some(PROPAGATION_ERROR_INJECT_MAX_SIZE);
+            }
+            // like the Datadog tracers, none of the tags are injected when one can't be
+            match error {
+                Some(error) => {
+                    otel_warn!(name: "DatadogPropagator.InjectTags", error = error);
+                    record_inject_error(span_context.trace_id(), error);
+                }
+                None if !tags.is_empty() => injector.set(DATADOG_TAGS_HEADER, tags),
+                None => {}
+            }
+            injector.set(
+                DATADOG_PARENT_ID_HEADER,
+                u64::from_be_bytes(span_context.span_id().to_bytes()).to_string(),
+            );
+
+            if span_context.trace_flags() & TRACE_FLAG_DEFERRED != TRACE_FLAG_DEFERRED {
+                let sampling_priority = get_sampling_priority(span_context);
+
+                injector.set(
+                    DATADOG_SAMPLING_PRIORITY_HEADER,
+                    (sampling_priority as i32).to_string(),
+                );
+            }
         }
     }
 
@@ -440,34 +1240,35 @@ mod propagator {
             let span = cx.span();
             let span_context = span.span_context();
             if span_context.is_valid() {
-                injector.set(
-                    DATADOG_TRACE_ID_HEADER,
-                    (u128::from_be_bytes(span_context.trace_id().to_bytes()) as u64).to_string(),
-                );
-                injector.set(
-                    DATADOG_PARENT_ID_HEADER,
-                    u64::from_be_bytes(span_context.span_id().to_bytes()).to_string(),
-                );
-
-                if span_context.trace_flags() & TRACE_FLAG_DEFERRED != TRACE_FLAG_DEFERRED {
-                    let sampling_priority = get_sampling_priority(span_context);
-
-                    injector.set(
-                        DATADOG_SAMPLING_PRIORITY_HEADER,
-                        (sampling_priority as i32).to_string(),
-                    );
+                for style in &self.styles {
+                    match style {
+                        PropagationStyle::Datadog => self.inject_datadog(span_context, injector),
+                        PropagationStyle::TraceContext => self.inject_w3c(span_context, injector),
+                    }
                 }
             }
         }
 
         fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
-            self.extract_span_context(extractor)
+            self.styles
+                .iter()
+                .find_map(|style| match style {
+                    PropagationStyle::Datadog => self.extract_span_context(extractor).ok(),
+                    PropagationStyle::TraceContext => self.extract_w3c_span_context(extractor),
+                })
                 .map(|sc| cx.with_remote_span_context(sc))
-                .unwrap_or_else(|_| cx.clone())
+                .unwrap_or_else(|| cx.clone())
         }
 
         fn fields(&self) -> FieldIter<'_> {
-            FieldIter::new(trace_context_header_fields())
+            let datadog = self.styles.contains(&PropagationStyle::Datadog);
+            let w3c = self.styles.contains(&PropagationStyle::TraceContext);
+            match (datadog, w3c) {
+                (true, true) => FieldIter::new(all_header_fields()),
+                (false, true) => FieldIter::new(w3c_header_fields()),
+                (true, false) => FieldIter::new(trace_context_header_fields()),
+                (false, false) => FieldIter::new(&[]),
+            }
         }
     }
 
@@ -489,6 +1290,8 @@ mod propagator {
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TRACE_FLAG_DEFERRED, true, TraceState::default())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(false).build())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(true).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "-1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_sampling_priority(-1).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_sampling_priority(2).build())),
             ];
             #[cfg(not(feature = "agent-sampling"))]
             return vec![
@@ -513,6 +1316,8 @@ mod propagator {
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TRACE_FLAG_DEFERRED, true, TraceState::default())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(false).build())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(true).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "-1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_sampling_priority(-1).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_sampling_priority(2).build())),
             ];
             #[cfg(not(feature = "agent-sampling"))]
             return vec![
@@ -556,6 +1361,400 @@ mod propagator {
             assert!(!context.has_active_span())
         }
 
+        #[test]
+        fn test_extract_128_bit_trace_id() {
+            let propagator = DatadogPropagator::default();
+            let extract = |tags: &str| {
+                let map: HashMap<String, String> = [
+                    (DATADOG_TRACE_ID_HEADER, "1234"),
+                    (DATADOG_PARENT_ID_HEADER, "12"),
+                    (DATADOG_TAGS_HEADER, tags),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+                propagator.extract(&map).span().span_context().trace_id()
+            };
+
+            assert_eq!(
+                extract("_dd.p.dm=-4,_dd.p.tid=640cfd8d00000000"),
+                TraceId::from_u128(0x640cfd8d00000000_00000000000004d2)
+            );
+            // malformed values are ignored and the lower 64 bits are kept
+            assert_eq!(
+                extract("_dd.p.tid=640CFD8D00000000"),
+                TraceId::from_u128(1234)
+            );
+            assert_eq!(extract("_dd.p.tid=640cfd8d"), TraceId::from_u128(1234));
+            assert_eq!(extract("garbage"), TraceId::from_u128(1234));
+        }
+
+        fn extract_headers(headers: &[(&str, &str)]) -> SpanContext {
+            let map: HashMap<String, String> = headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            DatadogPropagator::default()
+                .extract(&map)
+                .span()
+                .span_context()
+                .clone()
+        }
+
+        #[test]
+        fn test_extract_origin_and_tags() {
+            let span_context = extract_headers(&[
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+                (DATADOG_ORIGIN_HEADER, "synthetics"),
+                (
+                    DATADOG_TAGS_HEADER,
+                    "_dd.p.dm=-4,_dd.p.usr.id=dXNlcg==,other=ignored",
+                ),
+            ]);
+            let trace_state = span_context.trace_state();
+
+            assert_eq!(trace_state.origin(), Some("synthetics"));
+            assert_eq!(
+                trace_state.propagated_tags(),
+                vec![
+                    ("_dd.p.dm".to_string(), "-4".to_string()),
+                    ("_dd.p.usr.id".to_string(), "dXNlcg==".to_string()),
+                ]
+            );
+            assert_eq!(trace_state.propagation_error(), None);
+        }
+
+        #[test]
+        fn test_extract_tags_errors() {
+            let extract_error = |tags: &str| {
+                extract_headers(&[
+                    (DATADOG_TRACE_ID_HEADER, "1234"),
+                    (DATADOG_PARENT_ID_HEADER, "12"),
+                    (DATADOG_TAGS_HEADER, tags),
+                ])
+                .trace_state()
+                .propagation_error()
+                .map(str::to_string)
+            };
+
+            assert_eq!(
+                extract_error(&format!("_dd.p.dm=-4,_dd.p.long={}", "a".repeat(512))),
+                Some(PROPAGATION_ERROR_EXTRACT_MAX_SIZE.to_string())
+            );
+            assert_eq!(
+                extract_error("_dd.p.dm=-4,_dd.p.invalid"),
+                Some(PROPAGATION_ERROR_DECODING.to_string())
+            );
+            assert_eq!(
+                extract_error("_dd.p.dm=-4,_dd.p.invalid=\u{e9}"),
+                Some(PROPAGATION_ERROR_DECODING.to_string())
+            );
+            assert_eq!(
+                extract_error("_dd.p.tid=xyz"),
+                Some(PROPAGATION_ERROR_MALFORMED_TID.to_string())
+            );
+            assert_eq!(extract_error("_dd.p.dm=-4"), None);
+        }
+
+        #[test]
+        fn test_inject_origin_and_tags() {
+            let propagator = DatadogPropagator::default();
+            let trace_state = DatadogTraceStateBuilder::default()
+                .with_origin("synthetics")
+                .with_propagated_tag("_dd.p.dm", "-4")
+                .with_propagated_tag("_dd.p.usr.id", "dXNlcg")
+                .with_propagated_tag("not_propagated", "value")
+                .build();
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(
+                &Context::current_with_span(TestSpan(SpanContext::new(
+                    TraceId::from_u128(1234),
+                    SpanId::from_u64(12),
+                    TRACE_FLAG_DEFERRED,
+                    true,
+                    trace_state,
+                ))),
+                &mut injector,
+            );
+
+            assert_eq!(
+                injector.get(DATADOG_ORIGIN_HEADER),
+                Some(&"synthetics".to_string())
+            );
+            assert_eq!(
+                injector.get(DATADOG_TAGS_HEADER),
+                Some(&"_dd.p.dm=-4,_dd.p.usr.id=dXNlcg".to_string())
+            );
+        }
+
+        #[test]
+        fn test_inject_tags_errors() {
+            let propagator = DatadogPropagator::default();
+            let inject = |trace_id: u128, trace_state: TraceState| {
+                let mut injector: HashMap<String, String> = HashMap::new();
+                propagator.inject_context(
+                    &Context::current_with_span(TestSpan(SpanContext::new(
+                        TraceId::from_u128(trace_id),
+                        SpanId::from_u64(12),
+                        TRACE_FLAG_DEFERRED,
+                        true,
+                        trace_state,
+                    ))),
+                    &mut injector,
+                );
+                injector
+            };
+
+            // invalid tags are rejected when set
+            for value in ["a=b", "a,b"] {
+                let trace_state = DatadogTraceStateBuilder::default()
+                    .with_propagated_tag("_dd.p.dm", "-4")
+                    .with_propagated_tag("_dd.p.usr.id", value)
+                    .build();
+                assert_eq!(
+                    trace_state.propagation_error(),
+                    Some(PROPAGATION_ERROR_ENCODING)
+                );
+                let injector = inject(4321, trace_state);
+                assert_eq!(
+                    injector.get(DATADOG_TAGS_HEADER),
+                    Some(&"_dd.p.dm=-4".to_string())
+                );
+            }
+
+            // extracted values holding `=` can't be injected
+            let mut extractor: HashMap<String, String> = HashMap::new();
+            extractor.insert(DATADOG_TRACE_ID_HEADER.to_string(), "4322".to_string());
+            extractor.insert(DATADOG_PARENT_ID_HEADER.to_string(), "12".to_string());
+            extractor.insert(
+                DATADOG_TAGS_HEADER.to_string(),
+                "_dd.p.dm=-4,_dd.p.usr.id=dXNlcg==".to_string(),
+            );
+            let context = propagator.extract(&extractor);
+            let trace_state = context.span().span_context().trace_state().clone();
+            let injector = inject(4322, trace_state);
+            assert_eq!(injector.get(DATADOG_TAGS_HEADER), None);
+            assert_eq!(
+                take_inject_error(TraceId::from_u128(4322)),
+                Some(PROPAGATION_ERROR_ENCODING)
+            );
+
+            // the tags fit in the trace state, but not in the header with their `_dd.p.` prefix
+            let trace_state = (b'a'..=b'z')
+                .flat_map(|first| (b'a'..=b'b').map(move |second| [first, second]))
+                .take(51)
+                .fold(DatadogTraceStateBuilder::default(), |builder, key| {
+                    let key = std::str::from_utf8(&key).unwrap();
+                    builder.with_propagated_tag(format!("_dd.p.{}", key), "1")
+                })
+                .build();
+            assert_eq!(trace_state.propagated_tags().len(), 51);
+            let injector = inject(4323, trace_state);
+            assert_eq!(injector.get(DATADOG_TAGS_HEADER), None);
+            assert_eq!(
+                take_inject_error(TraceId::from_u128(4323)),
+                Some(PROPAGATION_ERROR_INJECT_MAX_SIZE)
+            );
+            // the error is only written once
+            assert_eq!(take_inject_error(TraceId::from_u128(4323)), None);
+        }
+
+        #[test]
+        fn test_propagated_tags_trace_state() {
+            let trace_state = TraceState::default()
+                .with_propagated_tag("_dd.p.dm", "-4")
+                .with_propagated_tag("_dd.p.dm", "-3")
+                .with_propagated_tag("_dd.p.tid", "640cfd8d00000000")
+                .with_propagated_tag("_dd.p.eq", "a=b");
+
+            assert_eq!(
+                trace_state.propagated_tags(),
+                vec![("_dd.p.dm".to_string(), "-3".to_string())]
+            );
+            assert_eq!(
+                trace_state.propagation_error(),
+                Some(PROPAGATION_ERROR_ENCODING)
+            );
+        }
+
+        #[test]
+        fn test_extract_w3c() {
+            let propagator = DatadogPropagator::with_styles([PropagationStyle::TraceContext]);
+            let map: HashMap<String, String> = [
+                (
+                    W3C_TRACE_PARENT_HEADER,
+                    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                ),
+                (
+                    W3C_TRACE_STATE_HEADER,
+                    "dd=s:2;o:rum;p:0123456789abcdef;t.dm:-4;t.usr.id:baz64~~,congo=t61rcWkgMzE,o=vendor,t=vendor",
+                ),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+            let context = propagator.extract(&map);
+            let span_context = context.span().span_context().clone();
+            let trace_state = span_context.trace_state();
+
+            assert_eq!(
+                span_context.trace_id(),
+                TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+            );
+            assert_eq!(
+                span_context.span_id(),
+                SpanId::from_hex("00f067aa0ba902b7").unwrap()
+            );
+            assert!(span_context.is_sampled());
+            assert!(span_context.is_remote());
+            #[cfg(feature = "agent-sampling")]
+            assert!(trace_state.priority_sampling_enabled());
+            assert_eq!(trace_state.origin(), Some("rum"));
+            assert_eq!(trace_state.last_parent_id(), Some("0123456789abcdef"));
+            assert_eq!(
+                trace_state.propagated_tags(),
+                vec![
+                    ("_dd.p.dm".to_string(), "-4".to_string()),
+                    ("_dd.p.usr.id".to_string(), "baz64==".to_string()),
+                ]
+            );
+            assert_eq!(trace_state.get("congo"), Some("t61rcWkgMzE"));
+            // members of other vendors named like the Datadog fields are kept as is
+            assert_eq!(trace_state.get("o"), Some("vendor"));
+            assert_eq!(trace_state.get("t"), Some("vendor"));
+            assert_eq!(trace_state.get(W3C_TRACE_STATE_DATADOG_KEY), None);
+        }
+
+        #[test]
+        fn test_extract_w3c_priority_mismatch() {
+            let propagator = DatadogPropagator::with_styles([PropagationStyle::TraceContext]);
+            let map: HashMap<String, String> = [
+                (
+                    W3C_TRACE_PARENT_HEADER,
+                    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+                ),
+                (W3C_TRACE_STATE_HEADER, "dd=s:2"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+            let span_context = propagator.extract(&map).span().span_context().clone();
+            // the traceparent sampled flag wins over the priority of the dd member
+            #[cfg(feature = "agent-sampling")]
+            assert!(!span_context.trace_state().priority_sampling_enabled());
+            #[cfg(not(feature = "agent-sampling"))]
+            assert!(!span_context.is_sampled());
+        }
+
+        #[test]
+        fn test_inject_w3c() {
+            let propagator = DatadogPropagator::with_styles([PropagationStyle::TraceContext]);
+            let trace_state = DatadogTraceStateBuilder::default()
+                .with_origin("synthetics")
+                .with_propagated_tag("_dd.p.dm", "-4")
+                .build()
+                .insert("o", "vendor")
+                .and_then(|trace_state| trace_state.insert("congo", "t61rcWkgMzE"))
+                .unwrap();
+            #[cfg(feature = "agent-sampling")]
+            let trace_state = trace_state.with_priority_sampling(true);
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(
+                &Context::current_with_span(TestSpan(SpanContext::new(
+                    TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+                    SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+                    TraceFlags::SAMPLED,
+                    false,
+                    trace_state,
+                ))),
+                &mut injector,
+            );
+
+            assert_eq!(
+                injector.get(W3C_TRACE_PARENT_HEADER),
+                Some(&"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string())
+            );
+            assert_eq!(
+                injector.get(W3C_TRACE_STATE_HEADER),
+                Some(
+                    &"dd=s:1;o:synthetics;p:00f067aa0ba902b7;t.dm:-4,congo=t61rcWkgMzE,o=vendor"
+                        .to_string()
+                )
+            );
+            assert_eq!(injector.get(DATADOG_TRACE_ID_HEADER), None);
+        }
+
+        #[test]
+        fn test_extract_first_valid_style() {
+            let propagator = DatadogPropagator::with_styles([
+                PropagationStyle::TraceContext,
+                PropagationStyle::Datadog,
+            ]);
+            let extract = |headers: &[(&str, &str)]| {
+                let map: HashMap<String, String> = headers
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                propagator.extract(&map).span().span_context().trace_id()
+            };
+
+            assert_eq!(
+                extract(&[
+                    (DATADOG_TRACE_ID_HEADER, "1234"),
+                    (DATADOG_PARENT_ID_HEADER, "12"),
+                ]),
+                TraceId::from_u128(1234)
+            );
+            assert_eq!(
+                extract(&[
+                    (DATADOG_TRACE_ID_HEADER, "1234"),
+                    (DATADOG_PARENT_ID_HEADER, "12"),
+                    (
+                        W3C_TRACE_PARENT_HEADER,
+                        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                    ),
+                ]),
+                TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+            );
+            assert_eq!(propagator.fields().count(), 7);
+        }
+
+        #[test]
+        fn test_inject_128_bit_trace_id() {
+            let propagator = DatadogPropagator::default();
+            let trace_id = 0x640cfd8d00000000_00000000000004d2u128;
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(
+                &Context::current_with_span(TestSpan(SpanContext::new(
+                    TraceId::from_u128(trace_id),
+                    SpanId::from_u64(12),
+                    TRACE_FLAG_DEFERRED,
+                    true,
+                    TraceState::default(),
+                ))),
+                &mut injector,
+            );
+
+            assert_eq!(
+                injector.get(DATADOG_TRACE_ID_HEADER),
+                Some(&"1234".to_string())
+            );
+            assert_eq!(
+                injector.get(DATADOG_TAGS_HEADER),
+                Some(&"_dd.p.tid=640cfd8d00000000".to_string())
+            );
+
+            let context = propagator.extract(&injector);
+            assert_eq!(
+                context.span().span_context().trace_id(),
+                TraceId::from_u128(trace_id)
+            );
+        }
+
         #[test]
         fn test_inject() {
             let propagator = DatadogPropagator::default();
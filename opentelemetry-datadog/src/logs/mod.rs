@@ -0,0 +1,788 @@
+//! Logs exporter sending log records as JSON to the Datadog logs intake, directly or through the
+//! agent.
+//!
+//! See <https://docs.datadoghq.com/api/latest/logs/#send-logs> for the format.
+
+use crate::exporter::model::datadog_trace_id;
+use crate::exporter::model::unified_tags::UnifiedTags;
+use crate::exporter::{
+    agent_endpoint_from_env, agent_transport, default_http_client, non_empty_env_var,
+    with_container_headers,
+};
+use crate::Error;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http::{Method, Request, Uri};
+use opentelemetry::logs::{AnyValue, Severity};
+use opentelemetry::{otel_warn, InstrumentationScope, Key};
+use opentelemetry_http::{Bytes, HttpClient, ResponseExt};
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::logs::{LogBatch, SdkLogRecord};
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions as semcov;
+use serde_json::{Map, Value};
+use std::fmt::{Debug, Formatter};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DD_API_KEY_ENV_VAR: &str = "DD_API_KEY";
+const DD_SITE_ENV_VAR: &str = "DD_SITE";
+
+const DEFAULT_SITE: &str = "datadoghq.com";
+const DEFAULT_SOURCE: &str = "rust";
+
+const LOGS_INTAKE_PATH: &str = "/api/v2/logs";
+const LOGS_INTAKE_SUBDOMAIN: &str = "http-intake.logs";
+
+/// The agent forwards the requests of its EVP proxy to the intake named by the subdomain header,
+/// adding the API key of the agent.
+const EVP_PROXY_LOGS_PATH: &str = "/evp_proxy/v2/api/v2/logs";
+const EVP_SUBDOMAIN_HEADER: &str = "X-Datadog-EVP-Subdomain";
+
+const DD_API_KEY_HEADER: &str = "DD-API-KEY";
+
+/// Limits of the logs intake, for the uncompressed payloads
+const MAX_PAYLOAD_SIZE: usize = 5 * 1024 * 1024;
+const MAX_PAYLOAD_ENTRIES: usize = 1000;
+const MAX_ENTRY_SIZE: usize = 1024 * 1024;
+
+/// Reserved attributes of the Datadog logs, written after the log attributes so that they win
+const MESSAGE_KEY: &str = "message";
+const STATUS_KEY: &str = "status";
+const TIMESTAMP_KEY: &str = "timestamp";
+const SERVICE_KEY: &str = "service";
+const HOSTNAME_KEY: &str = "hostname";
+const SOURCE_KEY: &str = "ddsource";
+const TAGS_KEY: &str = "ddtags";
+const LOGGER_NAME_KEY: &str = "logger.name";
+const TRACE_ID_KEY: &str = "dd.trace_id";
+const SPAN_ID_KEY: &str = "dd.span_id";
+
+/// [`LogExporter`](opentelemetry_sdk::logs::LogExporter) sending log records to the Datadog logs
+/// intake as gzipped JSON batches.
+///
+/// Log records emitted inside a span hold the `dd.trace_id` and `dd.span_id` attributes, the
+/// 64-bit ids sent by the trace exporter and the [`DatadogPropagator`], so that Datadog links
+/// the logs to their traces. The severity of the records is mapped to the log status:
+///
+/// | Severity number | Status |
+/// |-----------------|--------|
+/// | 1-4 | `trace` |
+/// | 5-8 | `debug` |
+/// | 9-12 | `info` |
+/// | 13-16 | `warn` |
+/// | 17-20 | `error` |
+/// | 21-24 | `fatal` |
+///
+/// The logs are sent to `https://http-intake.logs.<site>/api/v2/logs` when an API key is
+/// configured, to the endpoint set with [`DatadogLogExporterBuilder::with_endpoint`] if any, and
+/// otherwise through the EVP proxy of the Datadog agent, which adds its own API key.
+///
+/// ```no_run
+/// # fn main() -> Result<(), opentelemetry_datadog::Error> {
+/// use opentelemetry_sdk::logs::{BatchLogProcessor, SdkLoggerProvider};
+///
+/// let exporter = opentelemetry_datadog::DatadogLogExporter::builder()
+///     .with_service_name("my_app")
+///     .with_api_key("<DD_API_KEY>")
+///     .with_site("datadoghq.eu")
+///     .build()?;
+/// let provider = SdkLoggerProvider::builder()
+///     .with_log_processor(BatchLogProcessor::builder(exporter).build())
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`DatadogPropagator`]: crate::DatadogPropagator
+pub struct DatadogLogExporter {
+    client: Arc<dyn HttpClient>,
+    request_url: Uri,
+    api_key: Option<String>,
+    through_agent: bool,
+    unified_tags: UnifiedTags,
+    hostname: Option<String>,
+    source: String,
+    compression: bool,
+    resource: Option<Resource>,
+}
+
+impl DatadogLogExporter {
+    /// Create a builder configuring a [`DatadogLogExporter`].
+    pub fn builder() -> DatadogLogExporterBuilder {
+        DatadogLogExporterBuilder::default()
+    }
+
+    /// Encodes the records as JSON objects, dropping the ones larger than the intake limit.
+    fn encode(&self, batch: &LogBatch<'_>) -> Vec<Vec<u8>> {
+        let resource_attribute = |key: &'static str| {
+            self.resource
+                .as_ref()
+                .and_then(|resource| resource.get(&Key::from_static_str(key)))
+                .map(|value| value.as_str().into_owned())
+        };
+        let constant = ConstantFields {
+            service: self
+                .unified_tags
+                .service()
+                .or_else(|| resource_attribute(semcov::resource::SERVICE_NAME)),
+            hostname: self
+                .hostname
+                .clone()
+                .or_else(|| resource_attribute(semcov::resource::HOST_NAME)),
+            tags: ddtags(&self.unified_tags),
+        };
+
+        let mut entries = Vec::new();
+        for (record, scope) in batch.iter() {
+            let entry = self.encode_record(record, scope, &constant);
+            let entry = match serde_json::to_vec(&Value::Object(entry)) {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            if entry.len() > MAX_ENTRY_SIZE {
+                otel_warn!(
+                    name: "DatadogLogExporter.LogTooLarge",
+                    size = entry.len()
+                );
+                continue;
+            }
+            entries.push(entry);
+        }
+        entries
+    }
+
+    fn encode_record(
+        &self,
+        record: &SdkLogRecord,
+        scope: &InstrumentationScope,
+        constant: &ConstantFields,
+    ) -> Map<String, Value> {
+        let mut entry = Map::new();
+        if let Some(resource) = &self.resource {
+            for (key, value) in resource.iter() {
+                entry.insert(key.to_string(), Value::String(value.as_str().into_owned()));
+            }
+        }
+        for (key, value) in record.attributes_iter() {
+            entry.insert(key.to_string(), json_value(value));
+        }
+
+        let message = match record.body() {
+            Some(AnyValue::String(body)) => body.to_string(),
+            Some(body) => json_value(body).to_string(),
+            None => String::new(),
+        };
+        entry.insert(MESSAGE_KEY.to_string(), Value::String(message));
+        entry.insert(STATUS_KEY.to_string(), Value::String(status(record)));
+        let timestamp = record
+            .timestamp()
+            .or_else(|| record.observed_timestamp())
+            .unwrap_or_else(SystemTime::now);
+        if let Ok(timestamp) = timestamp.duration_since(UNIX_EPOCH) {
+            entry.insert(
+                TIMESTAMP_KEY.to_string(),
+                Value::from(timestamp.as_millis() as u64),
+            );
+        }
+        if let Some(service) = &constant.service {
+            entry.insert(SERVICE_KEY.to_string(), Value::String(service.clone()));
+        }
+        if let Some(hostname) = &constant.hostname {
+            entry.insert(HOSTNAME_KEY.to_string(), Value::String(hostname.clone()));
+        }
+        entry.insert(SOURCE_KEY.to_string(), Value::String(self.source.clone()));
+        if !constant.tags.is_empty() {
+            entry.insert(TAGS_KEY.to_string(), Value::String(constant.tags.clone()));
+        }
+        let logger_name = record
+            .target()
+            .map(|target| target.to_string())
+            .unwrap_or_else(|| scope.name().to_string());
+        if !logger_name.is_empty() {
+            entry.insert(LOGGER_NAME_KEY.to_string(), Value::String(logger_name));
+        }
+        if let Some(trace_context) = record.trace_context() {
+            // lower 64 bits of the trace id, like the `x-datadog-trace-id` header
+            let trace_id = datadog_trace_id(trace_context.trace_id);
+            let span_id = u64::from_be_bytes(trace_context.span_id.to_bytes());
+            entry.insert(
+                TRACE_ID_KEY.to_string(),
+                Value::String(trace_id.to_string()),
+            );
+            entry.insert(SPAN_ID_KEY.to_string(), Value::String(span_id.to_string()));
+        }
+        entry
+    }
+
+    fn build_request(&self, payload: &[u8]) -> Result<Request<Bytes>, OTelSdkError> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(self.request_url.clone())
+            .header(http::header::CONTENT_TYPE, "application/json");
+        if let Some(api_key) = &self.api_key {
+            req = req.header(DD_API_KEY_HEADER, api_key);
+        }
+        if self.through_agent {
+            req = with_container_headers(req.header(EVP_SUBDOMAIN_HEADER, LOGS_INTAKE_SUBDOMAIN));
+        }
+        let body = if self.compression {
+            req = req.header(http::header::CONTENT_ENCODING, "gzip");
+            gzip(payload)
+                .map_err(|e| OTelSdkError::InternalFailure(format!("gzip failed: {}", e)))?
+        } else {
+            payload.to_vec()
+        };
+        req.body(Bytes::from(body))
+            .map_err(|e| OTelSdkError::InternalFailure(format!("{:?}", e)))
+    }
+}
+
+/// Fields shared by all the records of a batch.
+struct ConstantFields {
+    service: Option<String>,
+    hostname: Option<String>,
+    tags: String,
+}
+
+impl Debug for DatadogLogExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatadogLogExporter")
+            .field("request_url", &self.request_url)
+            .field("through_agent", &self.through_agent)
+            .field("hostname", &self.hostname)
+            .field("source", &self.source)
+            .field("compression", &self.compression)
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl opentelemetry_sdk::logs::LogExporter for DatadogLogExporter {
+    async fn export(&self, batch: LogBatch<'_>) -> OTelSdkResult {
+        let entries = self.encode(&batch);
+        let mut failed = 0;
+        let mut last_error = None;
+        for payload in pack_entries(&entries) {
+            let request = self.build_request(&payload)?;
+            let result = match self.client.send_bytes(request).await {
+                Ok(response) => response
+                    .error_for_status()
+                    .map(|_| ())
+                    .map_err(|e| format!("HTTP response error: {}", e)),
+                Err(e) => Err(format!("HTTP request failed: {}", e)),
+            };
+            if let Err(err) = result {
+                failed += 1;
+                last_error = Some(err);
+            }
+        }
+        match last_error {
+            Some(err) => Err(OTelSdkError::InternalFailure(format!(
+                "failed to send {} log payloads: {}",
+                failed, err
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = Some(resource.clone());
+    }
+}
+
+/// Builder of [`DatadogLogExporter`].
+pub struct DatadogLogExporterBuilder {
+    api_key: Option<String>,
+    site: Option<String>,
+    endpoint: Option<String>,
+    agent_endpoint: Option<String>,
+    unified_tags: UnifiedTags,
+    hostname: Option<String>,
+    source: String,
+    compression: bool,
+    client: Option<Arc<dyn HttpClient>>,
+}
+
+impl Default for DatadogLogExporterBuilder {
+    fn default() -> Self {
+        DatadogLogExporterBuilder {
+            api_key: non_empty_env_var(DD_API_KEY_ENV_VAR),
+            site: non_empty_env_var(DD_SITE_ENV_VAR),
+            endpoint: None,
+            agent_endpoint: None,
+            unified_tags: UnifiedTags::new(),
+            hostname: None,
+            source: DEFAULT_SOURCE.to_string(),
+            compression: true,
+            client: None,
+        }
+    }
+}
+
+impl Debug for DatadogLogExporterBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatadogLogExporterBuilder")
+            .field("site", &self.site)
+            .field("endpoint", &self.endpoint)
+            .field("agent_endpoint", &self.agent_endpoint)
+            .field("hostname", &self.hostname)
+            .field("source", &self.source)
+            .field("compression", &self.compression)
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+impl DatadogLogExporterBuilder {
+    /// Assign the API key sending the logs to the intake.
+    ///
+    /// Defaults to the `DD_API_KEY` environment variable. Without API key, the logs are sent
+    /// through the agent.
+    pub fn with_api_key<T: Into<String>>(mut self, api_key: T) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Assign the Datadog site of the logs intake, such as `datadoghq.eu`.
+    ///
+    /// Defaults to the `DD_SITE` environment variable, or to `datadoghq.com`.
+    pub fn with_site<T: Into<String>>(mut self, site: T) -> Self {
+        self.site = Some(site.into());
+        self
+    }
+
+    /// Assign the url of the logs intake, overriding the url derived from the site, to send the
+    /// logs through a proxy.
+    ///
+    /// The logs are sent to this endpoint with or without API key, so that a proxy can add it.
+    pub fn with_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Send the logs through the agent at the given endpoint, even when an API key is configured.
+    ///
+    /// Without API key, the logs are sent to the agent configured by the `DD_TRACE_AGENT_URL`,
+    /// `DD_AGENT_HOST` and `DD_TRACE_AGENT_PORT` environment variables, or to
+    /// `http://localhost:8126`.
+    pub fn with_agent_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.agent_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Assign the `service` of the logs.
+    ///
+    /// Defaults to the `DD_SERVICE` environment variable, or to the `service.name` resource.
+    pub fn with_service_name<T: Into<String>>(mut self, service_name: T) -> Self {
+        self.unified_tags.set_service(Some(service_name.into()));
+        self
+    }
+
+    /// Assign the `version` tag of the logs.
+    ///
+    /// Defaults to the `DD_VERSION` environment variable.
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.unified_tags.set_version(Some(version.into()));
+        self
+    }
+
+    /// Assign the `env` tag of the logs.
+    ///
+    /// Defaults to the `DD_ENV` environment variable.
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.unified_tags.set_env(Some(env.into()));
+        self
+    }
+
+    /// Assign the `hostname` of the logs, defaults to the `host.name` resource.
+    pub fn with_hostname<T: Into<String>>(mut self, hostname: T) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Assign the `ddsource` of the logs, `rust` by default.
+    pub fn with_source<T: Into<String>>(mut self, source: T) -> Self {
+        self.source = source.into();
+        self
+    }
+
+    /// Compress the payloads with gzip, enabled by default.
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Choose the http client used by the exporter to send the logs.
+    pub fn with_http_client<T: HttpClient + 'static>(mut self, client: T) -> Self {
+        self.client = Some(Arc::new(client));
+        self
+    }
+
+    /// Build the exporter.
+    ///
+    /// Agent endpoints such as `unix:///var/run/datadog/apm.socket` are reached through the
+    /// `UdsClient` of the `uds-client` feature, unless a client is set with
+    /// [`with_http_client`], and are rejected without the feature.
+    ///
+    /// [`with_http_client`]: DatadogLogExporterBuilder::with_http_client
+    pub fn build(self) -> Result<DatadogLogExporter, Error> {
+        let through_agent =
+            self.agent_endpoint.is_some() || (self.api_key.is_none() && self.endpoint.is_none());
+        let (request_url, client) = if through_agent {
+            let agent_endpoint = self.agent_endpoint.unwrap_or_else(agent_endpoint_from_env);
+            #[cfg(not(all(unix, feature = "uds-client")))]
+            if agent_endpoint.starts_with("unix:") && self.client.is_none() {
+                return Err(Error::InvalidUri(format!(
+                    "{}: Unix domain sockets require the uds-client feature",
+                    agent_endpoint
+                )));
+            }
+            let (agent_endpoint, client) = agent_transport(&agent_endpoint, self.client);
+            (agent_logs_url(agent_endpoint), client)
+        } else {
+            let request_url = self.endpoint.unwrap_or_else(|| {
+                format!(
+                    "https://{}.{}{}",
+                    LOGS_INTAKE_SUBDOMAIN,
+                    self.site.as_deref().unwrap_or(DEFAULT_SITE),
+                    LOGS_INTAKE_PATH
+                )
+            });
+            (request_url, self.client.or_else(default_http_client))
+        };
+        let client = client.ok_or(Error::NoHttpClient)?;
+        Ok(DatadogLogExporter {
+            client,
+            request_url: request_url.parse()?,
+            // the agent adds its own API key
+            api_key: self.api_key.filter(|_| !through_agent),
+            through_agent,
+            unified_tags: self.unified_tags,
+            hostname: self.hostname,
+            source: self.source,
+            compression: self.compression,
+            resource: None,
+        })
+    }
+}
+
+fn agent_logs_url(agent_endpoint: &str) -> String {
+    format!(
+        "{}{}",
+        agent_endpoint.trim_end_matches('/'),
+        EVP_PROXY_LOGS_PATH
+    )
+}
+
+/// `ddtags` of the logs: the `env` and `version` unified tags, and the `DD_TAGS` global tags.
+fn ddtags(unified_tags: &UnifiedTags) -> String {
+    let unified = [&unified_tags.env, &unified_tags.version]
+        .into_iter()
+        .filter_map(|field| Some((field.get_tag_name(), field.value.as_deref()?)));
+    let global = unified_tags
+        .global_tags
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()));
+    unified
+        .chain(global)
+        .map(|(key, value)| {
+            if value.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}:{}", key, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Status of a record, from its severity number, or its severity text when it has no number.
+fn status(record: &SdkLogRecord) -> String {
+    match record.severity_number() {
+        Some(severity) => status_from_severity(severity).to_string(),
+        None => record
+            .severity_text()
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_else(|| "info".to_string()),
+    }
+}
+
+/// Same mapping as the OTLP logs ingest of the Datadog agent.
+fn status_from_severity(severity: Severity) -> &'static str {
+    match severity as i32 {
+        1..=4 => "trace",
+        5..=8 => "debug",
+        9..=12 => "info",
+        13..=16 => "warn",
+        17..=20 => "error",
+        _ => "fatal",
+    }
+}
+
+fn json_value(value: &AnyValue) -> Value {
+    match value {
+        AnyValue::Int(value) => Value::from(*value),
+        AnyValue::Double(value) => Value::from(*value),
+        AnyValue::String(value) => Value::String(value.to_string()),
+        AnyValue::Boolean(value) => Value::Bool(*value),
+        AnyValue::Bytes(bytes) => {
+            Value::String(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+        AnyValue::ListAny(values) => Value::Array(values.iter().map(json_value).collect()),
+        AnyValue::Map(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.to_string(), json_value(value)))
+                .collect(),
+        ),
+        _ => Value::Null,
+    }
+}
+
+/// Packs the JSON entries into JSON arrays within the limits of the intake.
+fn pack_entries(entries: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut payloads = Vec::new();
+    let mut payload = Vec::new();
+    let mut count = 0;
+    for entry in entries {
+        // `,` or `[` before the entry and the closing `]`
+        if count > 0
+            && (count == MAX_PAYLOAD_ENTRIES || payload.len() + entry.len() + 2 > MAX_PAYLOAD_SIZE)
+        {
+            payload.push(b']');
+            payloads.push(std::mem::take(&mut payload));
+            count = 0;
+        }
+        payload.push(if count == 0 { b'[' } else { b',' });
+        payload.extend_from_slice(entry);
+        count += 1;
+    }
+    if count > 0 {
+        payload.push(b']');
+        payloads.push(payload);
+    }
+    payloads
+}
+
+fn gzip(payload: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(
+        Vec::with_capacity(payload.len() / 4),
+        Compression::default(),
+    );
+    encoder.write_all(payload)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use opentelemetry::logs::{LogRecord, Logger, LoggerProvider};
+    use opentelemetry::trace::{SpanId, TraceId};
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::logs::{SdkLoggerProvider, SimpleLogProcessor};
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    /// Client recording the requests.
+    #[derive(Debug, Default, Clone)]
+    struct RecordingClient {
+        requests: Arc<Mutex<Vec<Request<Bytes>>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for RecordingClient {
+        async fn send_bytes(
+            &self,
+            request: Request<Bytes>,
+        ) -> Result<http::Response<Bytes>, opentelemetry_http::HttpError> {
+            self.requests.lock().unwrap().push(request);
+            Ok(http::Response::builder().status(202).body(Bytes::new())?)
+        }
+    }
+
+    fn decode_payload(request: &Request<Bytes>) -> Vec<Value> {
+        let mut json = Vec::new();
+        GzDecoder::new(&request.body()[..])
+            .read_to_end(&mut json)
+            .unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    #[test]
+    fn test_export_logs() {
+        let client = RecordingClient::default();
+        let exporter = DatadogLogExporter::builder()
+            .with_api_key("api-key")
+            .with_service_name("my_app")
+            .with_env("prod")
+            .with_version("1.0.0")
+            .with_http_client(client.clone())
+            .build()
+            .unwrap();
+        let provider = SdkLoggerProvider::builder()
+            .with_resource(
+                Resource::builder_empty()
+                    .with_attributes([KeyValue::new("host.name", "my_host")])
+                    .build(),
+            )
+            .with_log_processor(SimpleLogProcessor::new(exporter))
+            .build();
+        let logger = provider.logger("my_logger");
+
+        let mut record = logger.create_log_record();
+        record.set_body("request failed".into());
+        record.set_severity_number(Severity::Error);
+        record.add_attribute("http.status_code", 500);
+        record.set_trace_context(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            None,
+        );
+        logger.emit(record);
+
+        let requests = client.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(
+            request.uri().to_string(),
+            "https://http-intake.logs.datadoghq.com/api/v2/logs"
+        );
+        assert_eq!(request.headers()[DD_API_KEY_HEADER], "api-key");
+        assert_eq!(request.headers()[http::header::CONTENT_ENCODING], "gzip");
+
+        let logs = decode_payload(request);
+        assert_eq!(logs.len(), 1);
+        let log = &logs[0];
+        assert_eq!(log["message"], "request failed");
+        assert_eq!(log["status"], "error");
+        assert_eq!(log["service"], "my_app");
+        assert_eq!(log["hostname"], "my_host");
+        assert_eq!(log["ddsource"], "rust");
+        assert_eq!(log["ddtags"], "env:prod,version:1.0.0");
+        assert_eq!(log["logger.name"], "my_logger");
+        assert_eq!(log["http.status_code"], 500);
+        // same ids as the propagator headers
+        assert_eq!(log["dd.trace_id"], "11803532876627986230");
+        assert_eq!(log["dd.span_id"], "67667974448284343");
+        assert!(log["timestamp"].is_u64());
+    }
+
+    #[test]
+    fn test_export_through_agent() {
+        let client = RecordingClient::default();
+        temp_env::with_var(DD_API_KEY_ENV_VAR, None::<&str>, || {
+            let exporter = DatadogLogExporter::builder()
+                .with_http_client(client.clone())
+                .with_compression(false)
+                .build()
+                .unwrap();
+            let provider = SdkLoggerProvider::builder()
+                .with_log_processor(SimpleLogProcessor::new(exporter))
+                .build();
+            let logger = provider.logger("my_logger");
+            let mut record = logger.create_log_record();
+            record.set_severity_text("Warning");
+            logger.emit(record);
+        });
+
+        let requests = client.requests.lock().unwrap();
+        let request = &requests[0];
+        assert!(request
+            .uri()
+            .to_string()
+            .ends_with("/evp_proxy/v2/api/v2/logs"));
+        assert_eq!(request.headers()[EVP_SUBDOMAIN_HEADER], "http-intake.logs");
+        assert!(!request.headers().contains_key(DD_API_KEY_HEADER));
+        let logs: Vec<Value> = serde_json::from_slice(request.body()).unwrap();
+        assert_eq!(logs[0]["status"], "warning");
+    }
+
+    #[test]
+    fn test_endpoint_without_api_key() {
+        temp_env::with_var(DD_API_KEY_ENV_VAR, None::<&str>, || {
+            let exporter = DatadogLogExporter::builder()
+                .with_endpoint("https://logs-proxy.internal/api/v2/logs")
+                .with_http_client(RecordingClient::default())
+                .build()
+                .unwrap();
+            // sent to the endpoint rather than through the agent
+            assert_eq!(
+                exporter.request_url.to_string(),
+                "https://logs-proxy.internal/api/v2/logs"
+            );
+            assert!(!exporter.through_agent);
+            assert!(exporter.api_key.is_none());
+        });
+    }
+
+    #[cfg(all(unix, feature = "uds-client"))]
+    #[test]
+    fn test_unix_socket_agent_endpoint() {
+        temp_env::with_vars(
+            [
+                (DD_API_KEY_ENV_VAR, None),
+                (
+                    crate::exporter::DD_TRACE_AGENT_URL_ENV_VAR,
+                    Some("unix:///var/run/datadog/apm.socket"),
+                ),
+            ],
+            || {
+                let exporter = DatadogLogExporter::builder().build().unwrap();
+                assert_eq!(
+                    exporter.request_url.to_string(),
+                    "http://localhost/evp_proxy/v2/api/v2/logs"
+                );
+                assert!(format!("{:?}", exporter.client).contains("/var/run/datadog/apm.socket"));
+            },
+        );
+    }
+
+    #[cfg(not(all(unix, feature = "uds-client")))]
+    #[test]
+    fn test_unix_socket_agent_endpoint() {
+        let error = DatadogLogExporter::builder()
+            .with_agent_endpoint("unix:///var/run/datadog/apm.socket")
+            .build()
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidUri(_)));
+    }
+
+    #[test]
+    fn test_status_from_severity() {
+        assert_eq!(status_from_severity(Severity::Trace2), "trace");
+        assert_eq!(status_from_severity(Severity::Debug), "debug");
+        assert_eq!(status_from_severity(Severity::Info4), "info");
+        assert_eq!(status_from_severity(Severity::Warn), "warn");
+        assert_eq!(status_from_severity(Severity::Error3), "error");
+        assert_eq!(status_from_severity(Severity::Fatal), "fatal");
+    }
+
+    #[test]
+    fn test_pack_entries() {
+        let entries = vec![b"{}".to_vec(); MAX_PAYLOAD_ENTRIES + 1];
+        let payloads = pack_entries(&entries);
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[1], b"[{}]");
+        let first: Vec<Value> = serde_json::from_slice(&payloads[0]).unwrap();
+        assert_eq!(first.len(), MAX_PAYLOAD_ENTRIES);
+
+        let large = vec![b'1'; MAX_PAYLOAD_SIZE / 2 - 2];
+        assert_eq!(
+            pack_entries(&[large.clone(), large.clone(), large]).len(),
+            2
+        );
+        assert!(pack_entries(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_json_value() {
+        let value = AnyValue::ListAny(Box::new(vec![
+            AnyValue::Boolean(true),
+            AnyValue::Double(1.5),
+            AnyValue::Bytes(Box::new(vec![0xca, 0xfe])),
+        ]));
+        assert_eq!(json_value(&value).to_string(), r#"[true,1.5,"cafe"]"#);
+    }
+}
@@ -0,0 +1,721 @@
+//! Metrics exporter writing DogStatsD datagrams to the Datadog agent.
+//!
+//! See <https://docs.datadoghq.com/developers/dogstatsd/datagram_shell/> for the format.
+
+mod transport;
+
+use crate::exporter::model::unified_tags::{UnifiedTagField, UnifiedTags};
+use crate::exporter::non_empty_env_var;
+use crate::Error;
+use opentelemetry::{otel_debug, Key, KeyValue};
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::metrics::data::{
+    ExponentialHistogram, Gauge, Histogram, Metric, ResourceMetrics, Sum,
+};
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use opentelemetry_sdk::metrics::Temporality;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use transport::Transport;
+
+const DD_DOGSTATSD_URL_ENV_VAR: &str = "DD_DOGSTATSD_URL";
+const DD_AGENT_HOST_ENV_VAR: &str = "DD_AGENT_HOST";
+const DD_DOGSTATSD_PORT_ENV_VAR: &str = "DD_DOGSTATSD_PORT";
+
+const DEFAULT_DOGSTATSD_HOST: &str = "localhost";
+const DEFAULT_DOGSTATSD_PORT: &str = "8125";
+
+const COUNT_TYPE: &str = "c";
+const GAUGE_TYPE: &str = "g";
+
+/// Bucket bounds of histograms, as tags of the `<name>.bucket` counts
+const LOWER_BOUND_TAG: &str = "lower_bound";
+const UPPER_BOUND_TAG: &str = "upper_bound";
+
+/// [`PushMetricExporter`] writing metrics as DogStatsD lines to the Datadog agent, over UDP or a
+/// Unix datagram socket.
+///
+/// | OpenTelemetry | DogStatsD |
+/// |---------------|-----------|
+/// | monotonic sum | count, cumulative sums are converted to the change since the last export |
+/// | non-monotonic sum | count for delta sums, gauge for cumulative sums |
+/// | gauge | gauge |
+/// | histogram | `<name>.count` and `<name>.sum` counts, `<name>.min` and `<name>.max` gauges, `<name>.bucket` counts tagged with `lower_bound` and `upper_bound` |
+///
+/// Exponential histograms are not supported and are dropped.
+///
+/// ```no_run
+/// # fn main() -> Result<(), opentelemetry_datadog::Error> {
+/// use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+///
+/// let exporter = opentelemetry_datadog::DogStatsdExporter::builder()
+///     .with_service_name("my_app")
+///     .with_endpoint("unix:///var/run/datadog/dsd.socket")
+///     .build()?;
+/// let provider = SdkMeterProvider::builder()
+///     .with_reader(PeriodicReader::builder(exporter).build())
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+pub struct DogStatsdExporter {
+    transport: Transport,
+    temporality: Temporality,
+    max_packet_size: usize,
+    unified_tags: UnifiedTags,
+    container_id: Option<String>,
+    // last values of the cumulative series, by metric name and tags
+    cumulative: Mutex<HashMap<(String, String), Vec<f64>>>,
+    is_shutdown: AtomicBool,
+}
+
+impl DogStatsdExporter {
+    /// Create a builder configuring a [`DogStatsdExporter`].
+    pub fn builder() -> DogStatsdExporterBuilder {
+        DogStatsdExporterBuilder::default()
+    }
+
+    fn encode(&self, metrics: &ResourceMetrics) -> Vec<String> {
+        let mut constant_tags = Vec::new();
+        match &self.unified_tags.service.value {
+            Some(service) => constant_tags.push(tag("service", service)),
+            None => {
+                if let Some(service) = metrics.resource.get(&Key::from_static_str(
+                    opentelemetry_semantic_conventions::resource::SERVICE_NAME,
+                )) {
+                    constant_tags.push(tag("service", &service.as_str()));
+                }
+            }
+        }
+        for field in [&self.unified_tags.env, &self.unified_tags.version] {
+            if let UnifiedTagField {
+                value: Some(value), ..
+            } = field
+            {
+                constant_tags.push(tag(field.get_tag_name(), value));
+            }
+        }
+        for (key, value) in &self.unified_tags.global_tags {
+            constant_tags.push(tag(key, value));
+        }
+
+        let mut cumulative = self
+            .cumulative
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut encoder = LineEncoder {
+            constant_tags: &constant_tags,
+            container_id: self.container_id.as_deref(),
+            cumulative: &mut cumulative,
+            lines: Vec::new(),
+        };
+        for metric in metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| &scope.metrics)
+        {
+            encoder.encode_metric(metric);
+        }
+        encoder.lines
+    }
+}
+
+impl Debug for DogStatsdExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DogStatsdExporter")
+            .field("transport", &self.transport)
+            .field("temporality", &self.temporality)
+            .field("max_packet_size", &self.max_packet_size)
+            .field("container_id", &self.container_id)
+            .finish()
+    }
+}
+
+impl PushMetricExporter for DogStatsdExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> OTelSdkResult {
+        if self.is_shutdown.load(Ordering::Relaxed) {
+            return Err(OTelSdkError::AlreadyShutdown);
+        }
+
+        let lines = self.encode(metrics);
+        let mut failed = 0;
+        let mut last_error = None;
+        for packet in pack_lines(&lines, self.max_packet_size) {
+            if let Err(err) = self.transport.send(packet.as_bytes()) {
+                failed += 1;
+                last_error = Some(err);
+            }
+        }
+        match last_error {
+            Some(err) => Err(OTelSdkError::InternalFailure(format!(
+                "failed to send {} DogStatsD packets: {}",
+                failed, err
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.is_shutdown.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn temporality(&self) -> Temporality {
+        self.temporality
+    }
+}
+
+/// Builder of [`DogStatsdExporter`].
+pub struct DogStatsdExporterBuilder {
+    endpoint: Option<String>,
+    unified_tags: UnifiedTags,
+    temporality: Temporality,
+    max_packet_size: Option<usize>,
+    container_id: Option<String>,
+}
+
+impl Default for DogStatsdExporterBuilder {
+    fn default() -> Self {
+        DogStatsdExporterBuilder {
+            endpoint: None,
+            unified_tags: UnifiedTags::new(),
+            temporality: Temporality::Delta,
+            max_packet_size: None,
+            container_id: None,
+        }
+    }
+}
+
+impl Debug for DogStatsdExporterBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DogStatsdExporterBuilder")
+            .field("endpoint", &self.endpoint)
+            .field("temporality", &self.temporality)
+            .field("max_packet_size", &self.max_packet_size)
+            .field("container_id", &self.container_id)
+            .finish()
+    }
+}
+
+impl DogStatsdExporterBuilder {
+    /// Assign the DogStatsD endpoint, `udp://host:port` or `unix:///path/to/dsd.socket`.
+    ///
+    /// Defaults to the `DD_DOGSTATSD_URL` environment variable, or to the `DD_AGENT_HOST` and
+    /// `DD_DOGSTATSD_PORT` environment variables, or to `udp://localhost:8125`.
+    pub fn with_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Assign the `service` tag of the metrics.
+    ///
+    /// Defaults to the `DD_SERVICE` environment variable, or to the `service.name` resource.
+    pub fn with_service_name<T: Into<String>>(mut self, service_name: T) -> Self {
+        self.unified_tags.set_service(Some(service_name.into()));
+        self
+    }
+
+    /// Assign the `version` tag of the metrics.
+    ///
+    /// Defaults to the `DD_VERSION` environment variable.
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.unified_tags.set_version(Some(version.into()));
+        self
+    }
+
+    /// Assign the `env` tag of the metrics.
+    ///
+    /// Defaults to the `DD_ENV` environment variable.
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.unified_tags.set_env(Some(env.into()));
+        self
+    }
+
+    /// Assign the temporality requested from the SDK, `Delta` by default.
+    ///
+    /// DogStatsD counts are deltas, cumulative sums and histograms are converted to the change
+    /// since the previous export.
+    pub fn with_temporality(mut self, temporality: Temporality) -> Self {
+        self.temporality = temporality;
+        self
+    }
+
+    /// Assign the maximum size of a datagram, lines are packed together up to this size.
+    ///
+    /// Defaults to 1432 bytes for UDP, which fits the usual network MTU, and to 8192 bytes for
+    /// Unix domain sockets.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = Some(max_packet_size);
+        self
+    }
+
+    /// Assign the container id sent with every line, so that the agent adds the container tags.
+    pub fn with_container_id<T: Into<String>>(mut self, container_id: T) -> Self {
+        self.container_id = Some(container_id.into());
+        self
+    }
+
+    /// Build the exporter, connecting its socket.
+    pub fn build(self) -> Result<DogStatsdExporter, Error> {
+        let endpoint = self.endpoint.unwrap_or_else(dogstatsd_endpoint_from_env);
+        let transport = Transport::connect(&endpoint)?;
+        Ok(DogStatsdExporter {
+            max_packet_size: self
+                .max_packet_size
+                .unwrap_or_else(|| transport.default_max_packet_size()),
+            transport,
+            temporality: self.temporality,
+            unified_tags: self.unified_tags,
+            container_id: self.container_id,
+            cumulative: Mutex::new(HashMap::new()),
+            is_shutdown: AtomicBool::new(false),
+        })
+    }
+}
+
+/// DogStatsD endpoint configured by `DD_DOGSTATSD_URL`, or by `DD_AGENT_HOST` and
+/// `DD_DOGSTATSD_PORT`.
+fn dogstatsd_endpoint_from_env() -> String {
+    if let Some(url) = non_empty_env_var(DD_DOGSTATSD_URL_ENV_VAR) {
+        return url;
+    }
+    let host = non_empty_env_var(DD_AGENT_HOST_ENV_VAR)
+        .unwrap_or_else(|| DEFAULT_DOGSTATSD_HOST.to_string());
+    let port = non_empty_env_var(DD_DOGSTATSD_PORT_ENV_VAR)
+        .unwrap_or_else(|| DEFAULT_DOGSTATSD_PORT.to_string());
+    if host.contains(':') && !host.starts_with('[') {
+        // IPv6 address
+        format!("udp://[{}]:{}", host, port)
+    } else {
+        format!("udp://{}:{}", host, port)
+    }
+}
+
+trait Number: Copy {
+    fn as_f64(self) -> f64;
+}
+
+impl Number for u64 {
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Number for i64 {
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Number for f64 {
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
+
+struct LineEncoder<'a> {
+    constant_tags: &'a [String],
+    container_id: Option<&'a str>,
+    cumulative: &'a mut HashMap<(String, String), Vec<f64>>,
+    lines: Vec<String>,
+}
+
+impl LineEncoder<'_> {
+    fn encode_metric(&mut self, metric: &Metric) {
+        let name = metric_name(&metric.name);
+        let data = metric.data.as_any();
+        if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+            self.encode_sum(&name, sum);
+        } else if let Some(sum) = data.downcast_ref::<Sum<i64>>() {
+            self.encode_sum(&name, sum);
+        } else if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+            self.encode_sum(&name, sum);
+        } else if let Some(gauge) = data.downcast_ref::<Gauge<u64>>() {
+            self.encode_gauge(&name, gauge);
+        } else if let Some(gauge) = data.downcast_ref::<Gauge<i64>>() {
+            self.encode_gauge(&name, gauge);
+        } else if let Some(gauge) = data.downcast_ref::<Gauge<f64>>() {
+            self.encode_gauge(&name, gauge);
+        } else if let Some(histogram) = data.downcast_ref::<Histogram<u64>>() {
+            self.encode_histogram(&name, histogram);
+        } else if let Some(histogram) = data.downcast_ref::<Histogram<i64>>() {
+            self.encode_histogram(&name, histogram);
+        } else if let Some(histogram) = data.downcast_ref::<Histogram<f64>>() {
+            self.encode_histogram(&name, histogram);
+        } else if data.is::<ExponentialHistogram<u64>>()
+            || data.is::<ExponentialHistogram<i64>>()
+            || data.is::<ExponentialHistogram<f64>>()
+        {
+            otel_debug!(name: "DogStatsdExporter.UnsupportedExponentialHistogram", metric = name);
+        }
+    }
+
+    fn encode_sum<T: Number>(&mut self, name: &str, sum: &Sum<T>) {
+        for point in &sum.data_points {
+            let tags = attribute_tags(&point.attributes);
+            let value = point.value.as_f64();
+            match sum.temporality {
+                Temporality::Cumulative if sum.is_monotonic => {
+                    let delta = self.delta(name, &tags, &[value]);
+                    if let Some(delta) = delta {
+                        self.push_line(name, delta[0], COUNT_TYPE, &tags);
+                    }
+                }
+                Temporality::Cumulative => self.push_line(name, value, GAUGE_TYPE, &tags),
+                _ => self.push_line(name, value, COUNT_TYPE, &tags),
+            }
+        }
+    }
+
+    fn encode_gauge<T: Number>(&mut self, name: &str, gauge: &Gauge<T>) {
+        for point in &gauge.data_points {
+            let tags = attribute_tags(&point.attributes);
+            self.push_line(name, point.value.as_f64(), GAUGE_TYPE, &tags);
+        }
+    }
+
+    fn encode_histogram<T: Number>(&mut self, name: &str, histogram: &Histogram<T>) {
+        for point in &histogram.data_points {
+            let tags = attribute_tags(&point.attributes);
+            // count, sum and bucket counts
+            let mut values = Vec::with_capacity(2 + point.bucket_counts.len());
+            values.push(point.count as f64);
+            values.push(point.sum.as_f64());
+            values.extend(point.bucket_counts.iter().map(|count| *count as f64));
+            let values = match histogram.temporality {
+                Temporality::Cumulative => match self.delta(name, &tags, &values) {
+                    Some(delta) => delta,
+                    None => continue,
+                },
+                _ => values,
+            };
+
+            self.push_line(&format!("{}.count", name), values[0], COUNT_TYPE, &tags);
+            self.push_line(&format!("{}.sum", name), values[1], COUNT_TYPE, &tags);
+            if let Some(min) = point.min {
+                self.push_line(&format!("{}.min", name), min.as_f64(), GAUGE_TYPE, &tags);
+            }
+            if let Some(max) = point.max {
+                self.push_line(&format!("{}.max", name), max.as_f64(), GAUGE_TYPE, &tags);
+            }
+            let bucket_name = format!("{}.bucket", name);
+            for (idx, count) in values[2..].iter().enumerate() {
+                if *count == 0.0 {
+                    continue;
+                }
+                let lower = idx
+                    .checked_sub(1)
+                    .and_then(|idx| point.bounds.get(idx))
+                    .copied()
+                    .unwrap_or(f64::NEG_INFINITY);
+                let upper = point.bounds.get(idx).copied().unwrap_or(f64::INFINITY);
+                let mut bucket_tags = tags.clone();
+                for (key, bound) in [(LOWER_BOUND_TAG, lower), (UPPER_BOUND_TAG, upper)] {
+                    if !bucket_tags.is_empty() {
+                        bucket_tags.push(',');
+                    }
+                    bucket_tags.push_str(key);
+                    bucket_tags.push(':');
+                    bucket_tags.push_str(&format_value(bound));
+                }
+                self.push_line(&bucket_name, *count, COUNT_TYPE, &bucket_tags);
+            }
+        }
+    }
+
+    /// Change of cumulative values since the last export, `None` the first time a series is seen.
+    ///
+    /// A value lower than the previous one means the series was reset, it is then used as is.
+    fn delta(&mut self, name: &str, tags: &str, values: &[f64]) -> Option<Vec<f64>> {
+        let previous = self
+            .cumulative
+            .insert((name.to_string(), tags.to_string()), values.to_vec())?;
+        if previous.len() != values.len() || values.iter().zip(&previous).any(|(v, p)| v < p) {
+            return Some(values.to_vec());
+        }
+        Some(values.iter().zip(&previous).map(|(v, p)| v - p).collect())
+    }
+
+    fn push_line(&mut self, name: &str, value: f64, metric_type: &str, tags: &str) {
+        let mut line = format!("{}:{}|{}", name, format_value(value), metric_type);
+        if !tags.is_empty() || !self.constant_tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&self.constant_tags.join(","));
+            if !tags.is_empty() && !self.constant_tags.is_empty() {
+                line.push(',');
+            }
+            line.push_str(tags);
+        }
+        if let Some(container_id) = self.container_id {
+            line.push_str("|c:");
+            line.push_str(container_id);
+        }
+        self.lines.push(line);
+    }
+}
+
+/// Packs lines separated by `\n` into packets of at most `max_packet_size` bytes, lines larger
+/// than the maximum are sent alone.
+fn pack_lines(lines: &[String], max_packet_size: usize) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > max_packet_size {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+// Datadog metric names only hold ascii alphanumerics, underscores and periods.
+fn metric_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// `,` and `|` separate the tags and the fields of a line.
+fn tag(key: &str, value: &str) -> String {
+    let sanitize = |c: char| {
+        if matches!(c, ',' | '|' | '\n' | '\r') {
+            '_'
+        } else {
+            c
+        }
+    };
+    let mut tag: String = key.chars().map(sanitize).collect();
+    tag.push(':');
+    tag.extend(value.chars().map(sanitize));
+    tag
+}
+
+fn attribute_tags(attributes: &[KeyValue]) -> String {
+    attributes
+        .iter()
+        .map(|kv| tag(kv.key.as_str(), &kv.value.as_str()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        itoa::Buffer::new().format(value as i64).to_string()
+    } else {
+        ryu::Buffer::new().format(value).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::InstrumentationScope;
+    use opentelemetry_sdk::metrics::data::{
+        GaugeDataPoint, HistogramDataPoint, ScopeMetrics, SumDataPoint,
+    };
+    use opentelemetry_sdk::Resource;
+    use std::net::UdpSocket;
+    use std::time::{Duration, SystemTime};
+
+    fn resource_metrics(metrics: Vec<Metric>) -> ResourceMetrics {
+        ResourceMetrics {
+            resource: Resource::builder_empty()
+                .with_service_name("resource-service")
+                .build(),
+            scope_metrics: vec![ScopeMetrics {
+                scope: InstrumentationScope::builder("test").build(),
+                metrics,
+            }],
+        }
+    }
+
+    fn metric(
+        name: &'static str,
+        data: Box<dyn opentelemetry_sdk::metrics::data::Aggregation>,
+    ) -> Metric {
+        Metric {
+            name: name.into(),
+            description: "".into(),
+            unit: "".into(),
+            data,
+        }
+    }
+
+    fn counter(value: u64, temporality: Temporality) -> Metric {
+        metric(
+            "http.requests",
+            Box::new(Sum {
+                data_points: vec![SumDataPoint {
+                    attributes: vec![KeyValue::new("route", "/users")],
+                    value,
+                    exemplars: vec![],
+                }],
+                start_time: SystemTime::UNIX_EPOCH,
+                time: SystemTime::UNIX_EPOCH,
+                temporality,
+                is_monotonic: true,
+            }),
+        )
+    }
+
+    fn exporter(socket: &UdpSocket, temporality: Temporality) -> DogStatsdExporter {
+        temp_env::with_vars_unset(["DD_SERVICE", "DD_ENV", "DD_VERSION", "DD_TAGS"], || {
+            DogStatsdExporter::builder()
+                .with_endpoint(format!("udp://{}", socket.local_addr().unwrap()))
+                .with_env("prod")
+                .with_temporality(temporality)
+                .build()
+                .unwrap()
+        })
+    }
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut buffer = [0; 8192];
+        let len = socket.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_export_lines() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let exporter = exporter(&agent, Temporality::Delta);
+
+        let mut metrics = resource_metrics(vec![
+            counter(3, Temporality::Delta),
+            metric(
+                "queue-size",
+                Box::new(Gauge {
+                    data_points: vec![GaugeDataPoint {
+                        attributes: vec![],
+                        value: 1.5f64,
+                        exemplars: vec![],
+                    }],
+                    start_time: None,
+                    time: SystemTime::UNIX_EPOCH,
+                }),
+            ),
+            metric(
+                "latency",
+                Box::new(Histogram {
+                    data_points: vec![HistogramDataPoint {
+                        attributes: vec![],
+                        count: 3,
+                        bounds: vec![10.0, 100.0],
+                        bucket_counts: vec![1, 0, 2],
+                        min: Some(2.0),
+                        max: Some(250.0),
+                        sum: 402.0f64,
+                        exemplars: vec![],
+                    }],
+                    start_time: SystemTime::UNIX_EPOCH,
+                    time: SystemTime::UNIX_EPOCH,
+                    temporality: Temporality::Delta,
+                }),
+            ),
+        ]);
+        futures_executor::block_on(exporter.export(&mut metrics)).unwrap();
+
+        assert_eq!(
+            receive(&agent),
+            [
+                "http.requests:3|c|#service:resource-service,env:prod,route:/users",
+                "queue_size:1.5|g|#service:resource-service,env:prod",
+                "latency.count:3|c|#service:resource-service,env:prod",
+                "latency.sum:402|c|#service:resource-service,env:prod",
+                "latency.min:2|g|#service:resource-service,env:prod",
+                "latency.max:250|g|#service:resource-service,env:prod",
+                "latency.bucket:1|c|#service:resource-service,env:prod,lower_bound:-inf,upper_bound:10",
+                "latency.bucket:2|c|#service:resource-service,env:prod,lower_bound:100,upper_bound:inf",
+            ]
+            .join("\n")
+        );
+
+        exporter.shutdown().unwrap();
+        assert!(futures_executor::block_on(exporter.export(&mut metrics)).is_err());
+    }
+
+    #[test]
+    fn test_cumulative_to_delta() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let exporter = exporter(&agent, Temporality::Cumulative);
+        let export = |value| {
+            let mut metrics = resource_metrics(vec![counter(value, Temporality::Cumulative)]);
+            futures_executor::block_on(exporter.export(&mut metrics)).unwrap();
+        };
+
+        // the first value of a series is only recorded
+        export(10);
+        export(15);
+        assert!(receive(&agent).starts_with("http.requests:5|c|"));
+        // reset
+        export(2);
+        assert!(receive(&agent).starts_with("http.requests:2|c|"));
+    }
+
+    #[test]
+    fn test_pack_lines() {
+        let lines: Vec<String> = ["a:1|c", "b:2|c", "c:3|c", "a_very_long_metric:1|c"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(
+            pack_lines(&lines, 11),
+            ["a:1|c\nb:2|c", "c:3|c", "a_very_long_metric:1|c"]
+        );
+    }
+
+    #[test]
+    fn test_dogstatsd_endpoint_from_env() {
+        temp_env::with_vars(
+            [
+                (DD_DOGSTATSD_URL_ENV_VAR, None),
+                (DD_AGENT_HOST_ENV_VAR, Some("::1")),
+                (DD_DOGSTATSD_PORT_ENV_VAR, Some("9125")),
+            ],
+            || assert_eq!(dogstatsd_endpoint_from_env(), "udp://[::1]:9125"),
+        );
+        temp_env::with_vars(
+            [
+                (
+                    DD_DOGSTATSD_URL_ENV_VAR,
+                    Some("unix:///var/run/datadog/dsd.socket"),
+                ),
+                (DD_AGENT_HOST_ENV_VAR, Some("agent")),
+                (DD_DOGSTATSD_PORT_ENV_VAR, None),
+            ],
+            || {
+                assert_eq!(
+                    dogstatsd_endpoint_from_env(),
+                    "unix:///var/run/datadog/dsd.socket"
+                )
+            },
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Datagram sockets DogStatsD packets are written to.
+
+use crate::Error;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::time::Duration;
+use url::Url;
+
+// https://github.com/DataDog/datadog-go/blob/v5.5.0/statsd/options.go#L16
+const DEFAULT_UDP_MAX_PACKET_SIZE: usize = 1432;
+#[cfg(unix)]
+const DEFAULT_UDS_MAX_PACKET_SIZE: usize = 8192;
+
+// Writes to a full socket buffer block until the agent reads it
+#[cfg(unix)]
+const UDS_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub(crate) enum Transport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix {
+        socket: UnixDatagram,
+        path: PathBuf,
+    },
+}
+
+impl Transport {
+    /// Opens the socket of a `udp://` or `unix://` endpoint.
+    pub(crate) fn connect(endpoint: &str) -> Result<Self, Error> {
+        let url = Url::parse(endpoint)?;
+        match url.scheme() {
+            "udp" => {
+                let (Some(host), Some(port)) = (url.host_str(), url.port()) else {
+                    return Err(Error::InvalidUri(endpoint.to_string()));
+                };
+                let addr = (host.trim_start_matches('[').trim_end_matches(']'), port)
+                    .to_socket_addrs()
+                    .map_err(|err| Error::Other(err.to_string()))?
+                    .next()
+                    .ok_or_else(|| Error::InvalidUri(endpoint.to_string()))?;
+                let local = if addr.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local).map_err(|err| Error::Other(err.to_string()))?;
+                socket
+                    .connect(addr)
+                    .map_err(|err| Error::Other(err.to_string()))?;
+                Ok(Transport::Udp(socket))
+            }
+            #[cfg(unix)]
+            "unix" => {
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| Error::InvalidUri(endpoint.to_string()))?;
+                let socket =
+                    UnixDatagram::unbound().map_err(|err| Error::Other(err.to_string()))?;
+                socket
+                    .set_write_timeout(Some(UDS_WRITE_TIMEOUT))
+                    .map_err(|err| Error::Other(err.to_string()))?;
+                Ok(Transport::Unix { socket, path })
+            }
+            _ => Err(Error::InvalidUri(endpoint.to_string())),
+        }
+    }
+
+    pub(crate) fn default_max_packet_size(&self) -> usize {
+        match self {
+            Transport::Udp(_) => DEFAULT_UDP_MAX_PACKET_SIZE,
+            #[cfg(unix)]
+            Transport::Unix { .. } => DEFAULT_UDS_MAX_PACKET_SIZE,
+        }
+    }
+
+    pub(crate) fn send(&self, packet: &[u8]) -> io::Result<()> {
+        match self {
+            Transport::Udp(socket) => socket.send(packet).map(|_| ()),
+            #[cfg(unix)]
+            Transport::Unix { socket, path } => socket.send_to(packet, path).map(|_| ()),
+        }
+    }
+}
+
+impl Debug for Transport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Udp(socket) => write!(f, "udp://{:?}", socket.peer_addr().ok()),
+            #[cfg(unix)]
+            Transport::Unix { path, .. } => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_datagram() {
+        let path = std::env::temp_dir().join(format!(
+            "opentelemetry-datadog-dsd-{}.socket",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let agent = UnixDatagram::bind(&path).unwrap();
+
+        let transport = Transport::connect(&format!("unix://{}", path.display())).unwrap();
+        assert_eq!(
+            transport.default_max_packet_size(),
+            DEFAULT_UDS_MAX_PACKET_SIZE
+        );
+        transport.send(b"requests:1|c").unwrap();
+
+        let mut buffer = [0; 64];
+        let len = agent.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"requests:1|c");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_invalid_endpoint() {
+        assert!(Transport::connect("http://localhost:8125").is_err());
+        assert!(Transport::connect("udp://localhost").is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::exporter::model::datadog_trace_id;
+use crate::propagator::DatadogTraceStateBuilder;
+use opentelemetry::{
+    otel_warn,
+    trace::{
+        Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceFlags, TraceId,
+        TraceState,
+    },
+    Context, KeyValue,
+};
+use opentelemetry_sdk::trace::ShouldSample;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// Key used by the agent for the rate applied when no service specific rate is known.
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/sampler/dynamic_config.go#L32
+const DEFAULT_RATE_KEY: &str = "service:,env:";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/sampler.go#L100
+const KNUTH_FACTOR: u64 = 1_111_111_111_111_111_111;
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/app_types.go#L70
+const AGENT_RATE_DECISION_MAKER: u8 = 1;
+
+// Same value as the deferred flag used by the propagator, spans carrying it have no
+// sampling decision yet.
+pub(super) const TRACE_FLAG_DEFERRED: TraceFlags = TraceFlags::new(0x02);
+
+/// Sampling rates returned by the Datadog agent, keyed by `service:<service>,env:<env>`.
+///
+/// The exporter updates the rates after every successful request, the
+/// [`DatadogAgentSampler`] reads them when starting a new trace.
+#[derive(Debug, Default)]
+pub(crate) struct AgentRates {
+    rates: RwLock<HashMap<String, f64>>,
+}
+
+impl AgentRates {
+    /// Replace the known rates with the `rate_by_service` content of an agent response body.
+    pub(crate) fn update_from_response(&self, body: &[u8]) {
+        // The agent answers with an empty body or `OK` on older API versions.
+        if body.is_empty() || body == b"OK" {
+            return;
+        }
+
+        match parse_rate_by_service(body) {
+            Some(rates) => {
+                if let Ok(mut current) = self.rates.write() {
+                    *current = rates;
+                }
+            }
+            None => {
+                otel_warn!(
+                    name: "DatadogExporter.InvalidAgentResponse",
+                    reason = "could not parse rate_by_service from agent response"
+                );
+            }
+        }
+    }
+
+    fn rate(&self, key: &str) -> f64 {
+        self.rates
+            .read()
+            .ok()
+            .and_then(|rates| {
+                rates
+                    .get(key)
+                    .or_else(|| rates.get(DEFAULT_RATE_KEY))
+                    .copied()
+            })
+            .unwrap_or(1.0)
+    }
+}
+
+fn parse_rate_by_service(body: &[u8]) -> Option<HashMap<String, f64>> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let rates = value.get("rate_by_service")?.as_object()?;
+    Some(
+        rates
+            .iter()
+            .filter_map(|(key, rate)| rate.as_f64().map(|rate| (key.clone(), rate)))
+            .collect(),
+    )
+}
+
+/// Sampler applying the sampling rates computed by the Datadog agent.
+///
+/// The Datadog agent answers every trace payload with the rates it wants each
+/// `service`/`env` pair to be sampled at. This sampler uses those rates to set the
+/// sampling priority of root spans, children inherit the decision of their parent.
+///
+/// Every span is recorded and sent to the agent, which drops traces based on the
+/// sampling priority, so the agent still sees the full volume to compute its rates.
+///
+/// The sampler is created by [`DatadogPipelineBuilder::with_agent_sampling`], and can be
+/// retrieved with [`DatadogExporter::agent_sampler`] when building the tracer provider manually.
+///
+/// [`DatadogPipelineBuilder::with_agent_sampling`]: crate::DatadogPipelineBuilder::with_agent_sampling
+/// [`DatadogExporter::agent_sampler`]: crate::DatadogExporter::agent_sampler
+#[derive(Clone, Debug)]
+pub struct DatadogAgentSampler {
+    key: String,
+    rates: Arc<AgentRates>,
+}
+
+impl DatadogAgentSampler {
+    pub(crate) fn new(service: &str, env: Option<&str>) -> Self {
+        DatadogAgentSampler {
+            key: format!("service:{},env:{}", service, env.unwrap_or_default()),
+            rates: Arc::new(AgentRates::default()),
+        }
+    }
+
+    pub(crate) fn rates(&self) -> Arc<AgentRates> {
+        self.rates.clone()
+    }
+
+    /// The rate currently applied to new traces.
+    pub fn sample_rate(&self) -> f64 {
+        self.rates.rate(&self.key)
+    }
+}
+
+pub(super) fn sampled_by_rate(trace_id: TraceId, rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+    let trace_id = datadog_trace_id(trace_id);
+    trace_id.wrapping_mul(KNUTH_FACTOR) < (rate * u64::MAX as f64) as u64
+}
+
+/// Trace state of the parent span when it carries a sampling decision, which its children inherit.
+pub(super) fn parent_trace_state(parent_context: Option<&Context>) -> Option<TraceState> {
+    parent_context
+        .filter(|cx| cx.has_active_span())
+        .map(|cx| cx.span().span_context().clone())
+        .filter(|sc| sc.is_valid() && sc.trace_flags() & TRACE_FLAG_DEFERRED != TRACE_FLAG_DEFERRED)
+        .map(|sc| sc.trace_state().clone())
+}
+
+impl ShouldSample for DatadogAgentSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        _attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        let trace_state = parent_trace_state(parent_context).unwrap_or_else(|| {
+            let sampled = sampled_by_rate(trace_id, self.sample_rate());
+            let builder = DatadogTraceStateBuilder::default().with_priority_sampling(sampled);
+            // the decision maker is only propagated with kept traces
+            if sampled {
+                builder.with_decision_maker(AGENT_RATE_DECISION_MAKER)
+            } else {
+                builder
+            }
+            .build()
+        });
+
+        SamplingResult {
+            // send all spans to the agent, it drops them based on the sampling priority
+            decision: SamplingDecision::RecordAndSample,
+            attributes: vec![],
+            trace_state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::propagator::DatadogTraceState;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceState};
+    use opentelemetry_sdk::testing::trace::TestSpan;
+
+    const AGENT_RESPONSE: &[u8] =
+        br#"{"rate_by_service":{"service:,env:":0.5,"service:test,env:prod":0,"service:test,env:":1}}"#;
+
+    fn sample_root(sampler: &DatadogAgentSampler, trace_id: u128) -> bool {
+        sampler
+            .should_sample(
+                None,
+                TraceId::from_u128(trace_id),
+                "span",
+                &SpanKind::Internal,
+                &[],
+                &[],
+            )
+            .trace_state
+            .priority_sampling_enabled()
+    }
+
+    #[test]
+    fn test_default_rate_before_agent_response() {
+        let sampler = DatadogAgentSampler::new("test", Some("prod"));
+        assert_eq!(sampler.sample_rate(), 1.0);
+        assert!(sample_root(&sampler, 42));
+    }
+
+    #[test]
+    fn test_rate_by_service() {
+        let sampler = DatadogAgentSampler::new("test", Some("prod"));
+        sampler.rates().update_from_response(AGENT_RESPONSE);
+        assert_eq!(sampler.sample_rate(), 0.0);
+        assert!(!sample_root(&sampler, 42));
+        let rejected = sampler.should_sample(
+            None,
+            TraceId::from_u128(42),
+            "span",
+            &SpanKind::Internal,
+            &[],
+            &[],
+        );
+        assert_eq!(rejected.trace_state.decision_maker(), None);
+
+        let sampler = DatadogAgentSampler::new("test", None);
+        sampler.rates().update_from_response(AGENT_RESPONSE);
+        assert_eq!(sampler.sample_rate(), 1.0);
+        assert!(sample_root(&sampler, 42));
+        let kept = sampler.should_sample(
+            None,
+            TraceId::from_u128(42),
+            "span",
+            &SpanKind::Internal,
+            &[],
+            &[],
+        );
+        assert_eq!(
+            kept.trace_state.decision_maker(),
+            Some(AGENT_RATE_DECISION_MAKER)
+        );
+
+        let sampler = DatadogAgentSampler::new("other", Some("prod"));
+        sampler.rates().update_from_response(AGENT_RESPONSE);
+        assert_eq!(sampler.sample_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_invalid_response_keeps_rates() {
+        let sampler = DatadogAgentSampler::new("test", Some("prod"));
+        sampler.rates().update_from_response(AGENT_RESPONSE);
+        sampler.rates().update_from_response(b"OK");
+        sampler.rates().update_from_response(b"{\"not\":\"rates\"}");
+        assert_eq!(sampler.sample_rate(), 0.0);
+    }
+
+    #[test]
+    fn test_sampled_by_rate() {
+        let trace_id = TraceId::from_u128(0x1234_5678_9abc_def0);
+        assert!(sampled_by_rate(trace_id, 1.0));
+        assert!(!sampled_by_rate(trace_id, 0.0));
+
+        let kept = (1..=10_000u128)
+            .filter(|id| sampled_by_rate(TraceId::from_u128(*id), 0.3))
+            .count();
+        assert!((2_500..3_500).contains(&kept), "kept {} traces", kept);
+    }
+
+    #[test]
+    fn test_inherit_parent_decision() {
+        let sampler = DatadogAgentSampler::new("test", Some("prod"));
+        let parent_state = DatadogTraceStateBuilder::default()
+            .with_priority_sampling(false)
+            .build();
+        let parent = Context::current_with_span(TestSpan(SpanContext::new(
+            TraceId::from_u128(1),
+            SpanId::from_u64(1),
+            TraceFlags::SAMPLED,
+            true,
+            parent_state.clone(),
+        )));
+
+        let result = sampler.should_sample(
+            Some(&parent),
+            TraceId::from_u128(1),
+            "span",
+            &SpanKind::Internal,
+            &[],
+            &[],
+        );
+        assert_eq!(result.decision, SamplingDecision::RecordAndSample);
+        assert_eq!(result.trace_state, parent_state);
+
+        // deferred remote parents don't carry a decision, the sampler makes one
+        let deferred = Context::current_with_span(TestSpan(SpanContext::new(
+            TraceId::from_u128(1),
+            SpanId::from_u64(1),
+            TRACE_FLAG_DEFERRED,
+            true,
+            TraceState::default(),
+        )));
+        let result = sampler.should_sample(
+            Some(&deferred),
+            TraceId::from_u128(1),
+            "span",
+            &SpanKind::Internal,
+            &[],
+            &[],
+        );
+        assert!(result.trace_state.priority_sampling_enabled());
+    }
+}
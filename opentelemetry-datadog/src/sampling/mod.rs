@@ -0,0 +1,11 @@
+#[cfg(feature = "agent-sampling")]
+mod agent;
+#[cfg(feature = "agent-sampling")]
+mod rules;
+
+#[cfg(feature = "agent-sampling")]
+pub use agent::DatadogAgentSampler;
+#[cfg(feature = "agent-sampling")]
+pub(crate) use rules::rules_sampler_from_env;
+#[cfg(feature = "agent-sampling")]
+pub use rules::{DatadogRulesSampler, SamplingRule};
@@ -0,0 +1,592 @@
+use super::agent::{parent_trace_state, sampled_by_rate};
+use crate::propagator::DatadogTraceStateBuilder;
+use crate::{DatadogAgentSampler, Error};
+use opentelemetry::{
+    otel_warn,
+    trace::{Link, SamplingDecision, SamplingResult, SpanKind, TraceId},
+    Context, KeyValue,
+};
+use opentelemetry_sdk::trace::ShouldSample;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// https://docs.datadoghq.com/tracing/trace_pipeline/ingestion_mechanisms/#in-tracing-libraries-user-defined-rules
+const DD_TRACE_SAMPLING_RULES_ENV_VAR: &str = "DD_TRACE_SAMPLING_RULES";
+const DD_TRACE_SAMPLE_RATE_ENV_VAR: &str = "DD_TRACE_SAMPLE_RATE";
+const DD_TRACE_RATE_LIMIT_ENV_VAR: &str = "DD_TRACE_RATE_LIMIT";
+
+/// Traces sampled by rules per second when `DD_TRACE_RATE_LIMIT` is not set
+const DEFAULT_RATE_LIMIT: f64 = 100.0;
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/app_types.go#L74
+const RULE_DECISION_MAKER: u8 = 3;
+
+// Sampling priorities of the decisions made by the user rather than the agent rates
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/priority.go#L13
+const USER_REJECT: i8 = -1;
+const USER_KEEP: i8 = 2;
+
+// Span attributes overriding the names a span is matched with, same as the semantic conventions
+// mapping of the exporter.
+const SERVICE_NAME_KEY: &str = "service.name";
+const OPERATION_NAME_KEY: &str = "operation.name";
+const RESOURCE_NAME_KEY: &str = "resource.name";
+
+/// A rule of a [`DatadogRulesSampler`], sampling the traces it matches at its own rate.
+///
+/// Patterns are globs matched case-insensitively, `*` matches any sequence of characters and `?`
+/// a single character. A rule without pattern matches every trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SamplingRule {
+    service: Option<String>,
+    name: Option<String>,
+    resource: Option<String>,
+    tags: Vec<(String, String)>,
+    sample_rate: f64,
+}
+
+impl SamplingRule {
+    /// Create a rule sampling the matching traces at the given rate, between 0 and 1.
+    pub fn new(sample_rate: f64) -> Self {
+        SamplingRule {
+            service: None,
+            name: None,
+            resource: None,
+            tags: Vec::new(),
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Match the service of the root span, the sampler service or the `service.name` attribute.
+    pub fn with_service<T: Into<String>>(mut self, pattern: T) -> Self {
+        self.service = Some(pattern.into());
+        self
+    }
+
+    /// Match the name of the root span, or its `operation.name` attribute.
+    pub fn with_name<T: Into<String>>(mut self, pattern: T) -> Self {
+        self.name = Some(pattern.into());
+        self
+    }
+
+    /// Match the resource of the root span, its `resource.name` attribute or its name.
+    pub fn with_resource<T: Into<String>>(mut self, pattern: T) -> Self {
+        self.resource = Some(pattern.into());
+        self
+    }
+
+    /// Match an attribute of the root span, all the tag patterns of a rule must match.
+    pub fn with_tag<K: Into<String>, V: Into<String>>(mut self, key: K, pattern: V) -> Self {
+        self.tags.push((key.into(), pattern.into()));
+        self
+    }
+
+    fn matches(&self, service: &str, name: &str, attributes: &[KeyValue]) -> bool {
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.as_str())
+        };
+        let matches = |pattern: &Option<String>, value: &str| {
+            pattern
+                .as_ref()
+                .map_or(true, |pattern| glob_match(pattern, value))
+        };
+
+        matches(
+            &self.service,
+            &attribute(SERVICE_NAME_KEY).unwrap_or(service.into()),
+        ) && matches(
+            &self.name,
+            &attribute(OPERATION_NAME_KEY).unwrap_or(name.into()),
+        ) && matches(
+            &self.resource,
+            &attribute(RESOURCE_NAME_KEY).unwrap_or(name.into()),
+        ) && self
+            .tags
+            .iter()
+            .all(|(key, pattern)| attribute(key).is_some_and(|value| glob_match(pattern, &value)))
+    }
+}
+
+/// Parses the JSON array of rules of `DD_TRACE_SAMPLING_RULES`, such as
+/// `[{"service": "checkout", "name": "http.*", "tags": {"tier": "1"}, "sample_rate": 0.5}]`.
+fn parse_sampling_rules(rules: &str) -> Result<Vec<SamplingRule>, Error> {
+    let invalid = |reason: &str| Error::Other(format!("invalid sampling rules: {}", reason));
+    let value: serde_json::Value =
+        serde_json::from_str(rules).map_err(|err| invalid(&err.to_string()))?;
+    let rules = value.as_array().ok_or_else(|| invalid("not an array"))?;
+    rules
+        .iter()
+        .map(|rule| {
+            // like the other Datadog tracers, rules keep every trace by default
+            let sample_rate = match rule.get("sample_rate") {
+                Some(rate) => rate
+                    .as_f64()
+                    .ok_or_else(|| invalid("sample_rate is not a number"))?,
+                None => 1.0,
+            };
+            let mut parsed = SamplingRule::new(sample_rate);
+            let pattern = |field: &str| rule.get(field).and_then(|value| value.as_str());
+            if let Some(service) = pattern("service") {
+                parsed = parsed.with_service(service);
+            }
+            if let Some(name) = pattern("name") {
+                parsed = parsed.with_name(name);
+            }
+            if let Some(resource) = pattern("resource") {
+                parsed = parsed.with_resource(resource);
+            }
+            if let Some(tags) = rule.get("tags").and_then(|tags| tags.as_object()) {
+                for (key, pattern) in tags {
+                    let pattern = pattern
+                        .as_str()
+                        .ok_or_else(|| invalid("tag is not a string"))?;
+                    parsed = parsed.with_tag(key, pattern);
+                }
+            }
+            Ok(parsed)
+        })
+        .collect()
+}
+
+// Iterative glob matching, backtracking to the last `*` on a mismatch.
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let value: Vec<char> = value.to_lowercase().chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Token bucket limiting the number of traces sampled by rules per second.
+#[derive(Debug)]
+struct RateLimiter {
+    // a negative limit disables the limiter
+    limit: f64,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    tokens: f64,
+    last_refill: Instant,
+    window_start: Instant,
+    allowed: u64,
+    seen: u64,
+    previous_rate: Option<f64>,
+}
+
+impl RateLimiter {
+    fn new(limit: f64, now: Instant) -> Self {
+        RateLimiter {
+            limit,
+            state: Mutex::new(LimiterState {
+                tokens: limit.max(0.0),
+                last_refill: now,
+                window_start: now,
+                allowed: 0,
+                seen: 0,
+                previous_rate: None,
+            }),
+        }
+    }
+
+    /// Takes a token if there is one at `now`, and returns the effective rate of the limiter,
+    /// averaged over the current and the previous second.
+    fn allow(&self, now: Instant) -> (bool, f64) {
+        if self.limit < 0.0 {
+            return (true, 1.0);
+        }
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.limit).min(self.limit);
+        state.last_refill = now;
+
+        let window = now.duration_since(state.window_start);
+        if window >= Duration::from_secs(1) {
+            state.previous_rate = (window < Duration::from_secs(2) && state.seen > 0)
+                .then(|| state.allowed as f64 / state.seen as f64);
+            state.window_start = now;
+            state.allowed = 0;
+            state.seen = 0;
+        }
+
+        let allowed = state.tokens >= 1.0;
+        if allowed {
+            state.tokens -= 1.0;
+            state.allowed += 1;
+        }
+        state.seen += 1;
+
+        let rate = state.allowed as f64 / state.seen as f64;
+        let rate = match state.previous_rate {
+            Some(previous) => (previous + rate) / 2.0,
+            None => rate,
+        };
+        (allowed, rate)
+    }
+}
+
+/// Sampler applying user-defined sampling rules, like the `DD_TRACE_SAMPLING_RULES` of the
+/// Datadog tracers.
+///
+/// The first rule matching the root span of a trace decides whether the trace is kept, at the
+/// sample rate of the rule. Traces kept by a rule are then subject to a rate limit, 100 traces
+/// per second by default. The decision is a user decision, with the sampling priority `2` for kept
+/// traces and `-1` for rejected ones. The rates are reported as the `_dd.rule_psr` and
+/// `_dd.limit_psr` metrics, and the decision maker of kept traces is set to `-3` in the trace
+/// state.
+///
+/// Traces matching no rule are sampled by the [`DatadogAgentSampler`] given to
+/// [`with_agent_sampler`], or kept. Children inherit the decision of their parent.
+///
+/// Like the agent sampler, every span is recorded and sent to the agent, which drops traces
+/// based on their sampling priority.
+///
+/// [`with_agent_sampler`]: DatadogRulesSampler::with_agent_sampler
+#[derive(Clone, Debug)]
+pub struct DatadogRulesSampler {
+    service: String,
+    rules: Arc<[SamplingRule]>,
+    limiter: Arc<RateLimiter>,
+    agent_sampler: Option<DatadogAgentSampler>,
+}
+
+impl DatadogRulesSampler {
+    /// Create a sampler applying the rules in order, for spans of the given service.
+    ///
+    /// The rate limit is 100 traces per second, see [`with_rate_limit`].
+    ///
+    /// [`with_rate_limit`]: DatadogRulesSampler::with_rate_limit
+    pub fn new<T: Into<String>>(service: T, rules: Vec<SamplingRule>) -> Self {
+        DatadogRulesSampler {
+            service: service.into(),
+            rules: rules.into(),
+            limiter: Arc::new(RateLimiter::new(DEFAULT_RATE_LIMIT, Instant::now())),
+            agent_sampler: None,
+        }
+    }
+
+    /// Create a sampler from the `DD_TRACE_SAMPLING_RULES` and `DD_TRACE_SAMPLE_RATE`
+    /// environment variables, the sample rate being a rule matching every trace, limited by the
+    /// `DD_TRACE_RATE_LIMIT` environment variable.
+    ///
+    /// Returns `None` when neither is set.
+    pub fn from_env<T: Into<String>>(service: T) -> Result<Option<Self>, Error> {
+        let mut rules = match std::env::var(DD_TRACE_SAMPLING_RULES_ENV_VAR) {
+            Ok(rules) if !rules.trim().is_empty() => parse_sampling_rules(&rules)?,
+            _ => Vec::new(),
+        };
+        if let Ok(rate) = std::env::var(DD_TRACE_SAMPLE_RATE_ENV_VAR) {
+            let rate = rate.trim().parse().map_err(|_| {
+                Error::Other(format!(
+                    "invalid {}: {}",
+                    DD_TRACE_SAMPLE_RATE_ENV_VAR, rate
+                ))
+            })?;
+            rules.push(SamplingRule::new(rate));
+        }
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        let sampler = Self::new(service, rules);
+        Ok(Some(match std::env::var(DD_TRACE_RATE_LIMIT_ENV_VAR) {
+            Ok(limit) => sampler.with_rate_limit(limit.trim().parse().map_err(|_| {
+                Error::Other(format!(
+                    "invalid {}: {}",
+                    DD_TRACE_RATE_LIMIT_ENV_VAR, limit
+                ))
+            })?),
+            Err(_) => sampler,
+        }))
+    }
+
+    /// Set the maximum number of traces sampled by rules per second.
+    ///
+    /// A negative limit disables the rate limit, `0` drops every trace matching a rule.
+    pub fn with_rate_limit(mut self, limit: f64) -> Self {
+        self.limiter = Arc::new(RateLimiter::new(limit, Instant::now()));
+        self
+    }
+
+    /// Sample the traces matching no rule with the agent rates.
+    pub fn with_agent_sampler(mut self, sampler: DatadogAgentSampler) -> Self {
+        self.agent_sampler = Some(sampler);
+        self
+    }
+}
+
+impl ShouldSample for DatadogRulesSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        let parent = parent_trace_state(parent_context);
+        let rule = parent.is_none().then(|| {
+            self.rules
+                .iter()
+                .find(|rule| rule.matches(&self.service, name, attributes))
+        });
+
+        let trace_state = match (parent, rule.flatten()) {
+            // inherit the sampling decision from the parent span
+            (Some(trace_state), _) => trace_state,
+            (None, Some(rule)) => {
+                let mut builder =
+                    DatadogTraceStateBuilder::default().with_sampling_rule_rate(rule.sample_rate);
+                let mut keep = sampled_by_rate(trace_id, rule.sample_rate);
+                if keep {
+                    let (allowed, limit_rate) = self.limiter.allow(Instant::now());
+                    keep = allowed;
+                    builder = builder.with_sampling_limit_rate(limit_rate);
+                }
+                // the decision maker is only propagated with kept traces
+                if keep {
+                    builder
+                        .with_sampling_priority(USER_KEEP)
+                        .with_decision_maker(RULE_DECISION_MAKER)
+                } else {
+                    builder.with_sampling_priority(USER_REJECT)
+                }
+                .build()
+            }
+            (None, None) => match &self.agent_sampler {
+                Some(sampler) => {
+                    return sampler.should_sample(
+                        parent_context,
+                        trace_id,
+                        name,
+                        span_kind,
+                        attributes,
+                        links,
+                    )
+                }
+                None => DatadogTraceStateBuilder::default()
+                    .with_priority_sampling(true)
+                    .build(),
+            },
+        };
+
+        SamplingResult {
+            decision: SamplingDecision::RecordAndSample,
+            attributes: vec![],
+            trace_state,
+        }
+    }
+}
+
+/// Sampler configured by the `DD_TRACE_SAMPLING_RULES` and `DD_TRACE_SAMPLE_RATE` environment
+/// variables, invalid values are ignored.
+pub(crate) fn rules_sampler_from_env(service: &str) -> Option<DatadogRulesSampler> {
+    DatadogRulesSampler::from_env(service).unwrap_or_else(|err| {
+        otel_warn!(
+            name: "DatadogExporter.InvalidSamplingRules",
+            reason = err.to_string()
+        );
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::propagator::DatadogTraceState;
+
+    fn sample(
+        sampler: &DatadogRulesSampler,
+        name: &str,
+        attributes: &[KeyValue],
+    ) -> SamplingResult {
+        sampler.should_sample(
+            None,
+            TraceId::from_u128(42),
+            name,
+            &SpanKind::Server,
+            attributes,
+            &[],
+        )
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("http.*", "HTTP.request"));
+        assert!(glob_match("GET /users/?", "get /users/1"));
+        assert!(glob_match("*a*b", "xxaxxab"));
+        assert!(!glob_match("GET /users/?", "GET /users/12"));
+        assert!(!glob_match("web-*", "api"));
+    }
+
+    #[test]
+    fn test_first_matching_rule() {
+        let sampler = DatadogRulesSampler::new(
+            "checkout",
+            vec![
+                SamplingRule::new(0.0).with_name("health*"),
+                SamplingRule::new(1.0)
+                    .with_service("check*")
+                    .with_tag("http.route", "/cart/*"),
+            ],
+        )
+        .with_rate_limit(-1.0);
+
+        let dropped = sample(&sampler, "healthcheck", &[]).trace_state;
+        assert_eq!(dropped.sampling_priority(), Some(USER_REJECT));
+        assert_eq!(dropped.decision_maker(), None);
+        assert_eq!(dropped.sampling_rule_rate(), Some(0.0));
+        assert_eq!(dropped.sampling_limit_rate(), None);
+
+        let kept = sample(
+            &sampler,
+            "GET /cart",
+            &[KeyValue::new("http.route", "/cart/items")],
+        )
+        .trace_state;
+        assert_eq!(kept.sampling_priority(), Some(USER_KEEP));
+        assert_eq!(kept.decision_maker(), Some(RULE_DECISION_MAKER));
+        assert_eq!(kept.sampling_rule_rate(), Some(1.0));
+        assert_eq!(kept.sampling_limit_rate(), Some(1.0));
+
+        // the service attribute overrides the sampler service
+        let unmatched = sample(
+            &sampler,
+            "GET /cart",
+            &[
+                KeyValue::new("http.route", "/cart/items"),
+                KeyValue::new("service.name", "payments"),
+            ],
+        )
+        .trace_state;
+        assert!(unmatched.priority_sampling_enabled());
+        assert_eq!(unmatched.decision_maker(), None);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let sampler =
+            DatadogRulesSampler::new("checkout", vec![SamplingRule::new(1.0)]).with_rate_limit(0.0);
+
+        let limited = sample(&sampler, "span", &[]).trace_state;
+        assert_eq!(limited.sampling_limit_rate(), Some(0.0));
+        assert_eq!(limited.sampling_priority(), Some(USER_REJECT));
+        assert_eq!(limited.decision_maker(), None);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(2.0, start);
+
+        let allowed: Vec<bool> = (0..4).map(|_| limiter.allow(start).0).collect();
+        assert_eq!(allowed, [true, true, false, false]);
+        assert_eq!(limiter.allow(start), (false, 0.4));
+
+        // the tokens are refilled, and the rate is averaged with the previous second
+        let next_second = start + Duration::from_secs(1);
+        assert_eq!(limiter.allow(next_second), (true, 0.7));
+
+        // the previous rate is forgotten after a second without traces
+        assert_eq!(
+            limiter.allow(next_second + Duration::from_secs(2)),
+            (true, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_rules_from_env() {
+        temp_env::with_vars(
+            [
+                (
+                    DD_TRACE_SAMPLING_RULES_ENV_VAR,
+                    Some(
+                        r#"[{"service":"web","name":"http.*","tags":{"tier":"1"},"sample_rate":0.5}]"#,
+                    ),
+                ),
+                (DD_TRACE_SAMPLE_RATE_ENV_VAR, Some("0.1")),
+                (DD_TRACE_RATE_LIMIT_ENV_VAR, Some("10")),
+            ],
+            || {
+                let sampler = DatadogRulesSampler::from_env("web").unwrap().unwrap();
+                assert_eq!(
+                    sampler.rules.as_ref(),
+                    [
+                        SamplingRule::new(0.5)
+                            .with_service("web")
+                            .with_name("http.*")
+                            .with_tag("tier", "1"),
+                        SamplingRule::new(0.1),
+                    ]
+                );
+                assert_eq!(sampler.limiter.limit, 10.0);
+            },
+        );
+
+        // rules keep every trace by default
+        temp_env::with_vars(
+            [
+                (
+                    DD_TRACE_SAMPLING_RULES_ENV_VAR,
+                    Some("[{\"service\":\"web\"}]"),
+                ),
+                (DD_TRACE_SAMPLE_RATE_ENV_VAR, None),
+                (DD_TRACE_RATE_LIMIT_ENV_VAR, None),
+            ],
+            || {
+                let sampler = DatadogRulesSampler::from_env("web").unwrap().unwrap();
+                assert_eq!(
+                    sampler.rules.as_ref(),
+                    [SamplingRule::new(1.0).with_service("web")]
+                );
+                assert_eq!(sampler.limiter.limit, DEFAULT_RATE_LIMIT);
+            },
+        );
+
+        temp_env::with_vars(
+            [
+                (
+                    DD_TRACE_SAMPLING_RULES_ENV_VAR,
+                    Some("[{\"service\":\"web\",\"sample_rate\":\"high\"}]"),
+                ),
+                (DD_TRACE_SAMPLE_RATE_ENV_VAR, None),
+            ],
+            || {
+                assert!(DatadogRulesSampler::from_env("web").is_err());
+                assert!(rules_sampler_from_env("web").is_none());
+            },
+        );
+
+        // the rate limit only applies to the samplers configured by the environment
+        temp_env::with_var(DD_TRACE_RATE_LIMIT_ENV_VAR, Some("10"), || {
+            let sampler = DatadogRulesSampler::new("web", vec![SamplingRule::new(1.0)]);
+            assert_eq!(sampler.limiter.limit, DEFAULT_RATE_LIMIT);
+        });
+    }
+}
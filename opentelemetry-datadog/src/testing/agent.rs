@@ -0,0 +1,331 @@
+//! Local HTTP server imitating the trace endpoints of the Datadog agent.
+
+use super::decode::{decode_v03, decode_v05, DecodedSpan};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+const MAX_REQUEST_HEADERS: usize = 32;
+
+/// A request received by the [`FakeAgent`].
+#[derive(Clone, Debug)]
+pub struct AgentRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct AgentState {
+    requests: Vec<AgentRequest>,
+    traces: Vec<Vec<DecodedSpan>>,
+    rate_by_service: HashMap<String, f64>,
+    status: u16,
+}
+
+/// In-process HTTP server answering like the Datadog agent.
+///
+/// The agent accepts the `/v0.3/traces` and `/v0.5/traces` payloads, decodes them, and answers
+/// with the configured `rate_by_service`. Other paths, such as `/v0.6/stats`, are recorded and
+/// answered with an empty body. The server stops when the agent is dropped.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use opentelemetry_datadog::testing::FakeAgent;
+///
+/// let agent = FakeAgent::start()?;
+/// agent.set_rate_by_service([("service:my_app,env:", 0.5)]);
+/// let provider = opentelemetry_datadog::new_pipeline()
+///     .with_service_name("my_app")
+///     .with_agent_endpoint(agent.endpoint())
+///     .install_simple()?;
+/// // ...
+/// assert_eq!(agent.traces()[0][0].service, "my_app");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FakeAgent {
+    addr: SocketAddr,
+    state: Arc<Mutex<AgentState>>,
+    stopped: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl FakeAgent {
+    /// Start the agent on a random local port.
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(AgentState {
+            requests: Vec::new(),
+            traces: Vec::new(),
+            rate_by_service: HashMap::new(),
+            status: 200,
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let server = {
+            let state = state.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let state = state.clone();
+                    // clients keep their connection alive between requests
+                    thread::spawn(move || serve_connection(stream, &state));
+                }
+            })
+        };
+
+        Ok(FakeAgent {
+            addr,
+            state,
+            stopped,
+            server: Some(server),
+        })
+    }
+
+    /// The endpoint to give to [`DatadogPipelineBuilder::with_agent_endpoint`].
+    ///
+    /// [`DatadogPipelineBuilder::with_agent_endpoint`]: crate::DatadogPipelineBuilder::with_agent_endpoint
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Set the `rate_by_service` answered to trace payloads, keyed by `service:<service>,env:<env>`.
+    pub fn set_rate_by_service<K: Into<String>>(&self, rates: impl IntoIterator<Item = (K, f64)>) {
+        self.state().rate_by_service = rates
+            .into_iter()
+            .map(|(key, rate)| (key.into(), rate))
+            .collect();
+    }
+
+    /// Set the status answered to every request, 200 by default.
+    pub fn set_status(&self, status: u16) {
+        self.state().status = status;
+    }
+
+    /// The traces decoded from the payloads received so far.
+    pub fn traces(&self) -> Vec<Vec<DecodedSpan>> {
+        self.state().traces.clone()
+    }
+
+    /// The requests received so far, on every path.
+    pub fn requests(&self) -> Vec<AgentRequest> {
+        self.state().requests.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, AgentState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Drop for FakeAgent {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wake up the accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, state: &Mutex<AgentState>) {
+    let mut buffer = Vec::new();
+    loop {
+        let request = match read_request(&mut stream, &mut buffer) {
+            Ok(Some(request)) => request,
+            _ => return,
+        };
+        let response = handle_request(request, state);
+        if stream.write_all(&response).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_request(request: AgentRequest, state: &Mutex<AgentState>) -> Vec<u8> {
+    let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+    let decoded = match request.path.as_str() {
+        "/v0.3/traces" => Some(decode_v03(&request.body)),
+        "/v0.5/traces" => Some(decode_v05(&request.body)),
+        _ => None,
+    };
+    state.requests.push(request);
+
+    let (status, body) = match decoded {
+        Some(Err(err)) => (400, err.to_string()),
+        Some(Ok(_)) | None if state.status >= 300 => (state.status, String::new()),
+        Some(Ok(traces)) => {
+            state.traces.extend(traces);
+            let rates = serde_json::json!({ "rate_by_service": state.rate_by_service });
+            (state.status, rates.to_string())
+        }
+        None => (state.status, String::new()),
+    };
+    format!(
+        "HTTP/1.1 {} Fake Agent\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+/// Reads the next request of the connection, `None` once the client closed it.
+fn read_request(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> io::Result<Option<AgentRequest>> {
+    let mut chunk = [0; 8192];
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_REQUEST_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let status = parsed
+            .parse(buffer)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if let httparse::Status::Complete(head_len) = status {
+            let headers: HashMap<String, String> = parsed
+                .headers
+                .iter()
+                .map(|header| {
+                    (
+                        header.name.to_lowercase(),
+                        String::from_utf8_lossy(header.value).into_owned(),
+                    )
+                })
+                .collect();
+            let content_length = headers
+                .get("content-length")
+                .and_then(|len| len.parse::<usize>().ok())
+                .unwrap_or(0);
+            if buffer.len() >= head_len + content_length {
+                let request = AgentRequest {
+                    method: parsed.method.unwrap_or_default().to_string(),
+                    path: parsed.path.unwrap_or_default().to_string(),
+                    headers,
+                    body: buffer[head_len..head_len + content_length].to_vec(),
+                };
+                buffer.drain(..head_len + content_length);
+                return Ok(Some(request));
+            }
+        }
+
+        let len = stream.read(&mut chunk)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::buffers::BufferPool;
+    use crate::exporter::model::tests::get_span;
+    use crate::exporter::model::unified_tags::UnifiedTags;
+    use crate::exporter::Mapping;
+    use crate::{ApiVersion, ModelConfig};
+
+    fn post(stream: &mut TcpStream, path: &str, body: &[u8]) -> io::Result<(u16, String)> {
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+            path,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            let len = stream.read(&mut chunk)?;
+            if len == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            buffer.extend_from_slice(&chunk[..len]);
+            let mut headers = [httparse::EMPTY_HEADER; MAX_REQUEST_HEADERS];
+            let mut response = httparse::Response::new(&mut headers);
+            if let Ok(httparse::Status::Complete(head_len)) = response.parse(&buffer) {
+                let content_length = response
+                    .headers
+                    .iter()
+                    .find(|header| header.name == "Content-Length")
+                    .and_then(|header| std::str::from_utf8(header.value).ok()?.parse().ok())
+                    .unwrap_or(0);
+                if buffer.len() >= head_len + content_length {
+                    let body = String::from_utf8_lossy(&buffer[head_len..]).into_owned();
+                    return Ok((response.code.unwrap_or_default(), body));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fake_agent() -> Result<(), Box<dyn std::error::Error>> {
+        let agent = FakeAgent::start()?;
+        agent.set_rate_by_service([("service:service_name,env:", 0.5)]);
+        let traces = [vec![get_span(7, 1, 99), get_span(7, 99, 100)]];
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+            ..Default::default()
+        };
+
+        let mut stream = TcpStream::connect(agent.addr)?;
+        for version in [ApiVersion::Version03, ApiVersion::Version05] {
+            let payload = version.encode(
+                &model_config,
+                &traces.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                None,
+                &BufferPool::default(),
+            )?;
+            // both requests go through the same connection
+            let (status, body) = post(&mut stream, version.path(), &payload)?;
+            assert_eq!(status, 200);
+            assert_eq!(
+                body,
+                r#"{"rate_by_service":{"service:service_name,env:":0.5}}"#
+            );
+        }
+
+        let traces = agent.traces();
+        assert_eq!(traces.len(), 2);
+        for trace in traces {
+            assert_eq!(
+                trace
+                    .iter()
+                    .map(|span| (span.trace_id, span.span_id, span.parent_id))
+                    .collect::<Vec<_>>(),
+                vec![(7, 99, 1), (7, 100, 99)]
+            );
+            assert!(trace.iter().all(|span| span.service == "service_name"));
+        }
+
+        agent.set_status(503);
+        let (status, _) = post(&mut stream, "/v0.6/stats", b"stats")?;
+        assert_eq!(status, 503);
+        let requests = agent.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].path, "/v0.6/stats");
+        assert_eq!(requests[2].headers["content-length"], "5");
+        assert_eq!(requests[2].body, b"stats");
+
+        let (status, body) = post(&mut stream, "/v0.5/traces", b"\x92")?;
+        assert_eq!(status, 400);
+        assert!(body.starts_with("invalid trace payload"));
+
+        Ok(())
+    }
+}
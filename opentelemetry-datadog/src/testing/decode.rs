@@ -0,0 +1,178 @@
+//! Decoding of the msgpack trace payloads, the reverse of the `v03` and `v05` model encoders.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// A Datadog span decoded from a trace payload.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodedSpan {
+    pub service: String,
+    pub name: String,
+    pub resource: String,
+    pub trace_id: u64,
+    pub span_id: u64,
+    pub parent_id: u64,
+    pub start: i64,
+    pub duration: i64,
+    pub error: i32,
+    pub meta: BTreeMap<String, String>,
+    pub metrics: BTreeMap<String, f64>,
+    /// The span type, empty when the span has none
+    pub span_type: String,
+}
+
+/// Error returned when a payload is not a valid trace payload.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid trace payload: {0}")]
+pub struct DecodeError(String);
+
+fn invalid<E: Display>(err: E) -> DecodeError {
+    DecodeError(err.to_string())
+}
+
+type Reader<'a> = &'a [u8];
+
+fn read_string(rd: &mut Reader<'_>) -> Result<String, DecodeError> {
+    let len = rmp::decode::read_str_len(rd).map_err(invalid)? as usize;
+    if rd.len() < len {
+        return Err(invalid("truncated string"));
+    }
+    let (string, rest) = rd.split_at(len);
+    *rd = rest;
+    String::from_utf8(string.to_vec()).map_err(invalid)
+}
+
+/// Capacity reserved for `count` elements read from the payload, bounded by the bytes left as
+/// every element takes at least one byte, so that malformed counts can't allocate at will.
+fn capacity(count: u32, rd: &Reader<'_>) -> usize {
+    (count as usize).min(rd.len())
+}
+
+/// Decodes a `/v0.3/traces` payload, an array of traces holding spans encoded as maps.
+pub fn decode_v03(payload: &[u8]) -> Result<Vec<Vec<DecodedSpan>>, DecodeError> {
+    let rd = &mut &payload[..];
+    let trace_count = rmp::decode::read_array_len(rd).map_err(invalid)?;
+    let mut traces = Vec::with_capacity(capacity(trace_count, rd));
+    for _ in 0..trace_count {
+        let span_count = rmp::decode::read_array_len(rd).map_err(invalid)?;
+        let mut trace = Vec::with_capacity(capacity(span_count, rd));
+        for _ in 0..span_count {
+            let mut span = DecodedSpan::default();
+            let field_count = rmp::decode::read_map_len(rd).map_err(invalid)?;
+            for _ in 0..field_count {
+                let field = read_string(rd)?;
+                match field.as_str() {
+                    "service" => span.service = read_string(rd)?,
+                    "name" => span.name = read_string(rd)?,
+                    "resource" => span.resource = read_string(rd)?,
+                    "type" => span.span_type = read_string(rd)?,
+                    "trace_id" => span.trace_id = rmp::decode::read_int(rd).map_err(invalid)?,
+                    "span_id" => span.span_id = rmp::decode::read_int(rd).map_err(invalid)?,
+                    "parent_id" => span.parent_id = rmp::decode::read_int(rd).map_err(invalid)?,
+                    "start" => span.start = rmp::decode::read_int(rd).map_err(invalid)?,
+                    "duration" => span.duration = rmp::decode::read_int(rd).map_err(invalid)?,
+                    "error" => span.error = rmp::decode::read_int(rd).map_err(invalid)?,
+                    "meta" => {
+                        let len = rmp::decode::read_map_len(rd).map_err(invalid)?;
+                        for _ in 0..len {
+                            let key = read_string(rd)?;
+                            span.meta.insert(key, read_string(rd)?);
+                        }
+                    }
+                    "metrics" => {
+                        let len = rmp::decode::read_map_len(rd).map_err(invalid)?;
+                        for _ in 0..len {
+                            let key = read_string(rd)?;
+                            span.metrics
+                                .insert(key, rmp::decode::read_f64(rd).map_err(invalid)?);
+                        }
+                    }
+                    other => return Err(invalid(format!("unknown span field {}", other))),
+                }
+            }
+            trace.push(span);
+        }
+        traces.push(trace);
+    }
+    Ok(traces)
+}
+
+/// Decodes a `/v0.5/traces` payload, resolving the strings of the spans from the dictionary
+/// written by the `StringInterner`.
+pub fn decode_v05(payload: &[u8]) -> Result<Vec<Vec<DecodedSpan>>, DecodeError> {
+    let rd = &mut &payload[..];
+    if rmp::decode::read_array_len(rd).map_err(invalid)? != 2 {
+        return Err(invalid("expected the dictionary and the traces"));
+    }
+
+    let dictionary_len = rmp::decode::read_array_len(rd).map_err(invalid)?;
+    let mut dictionary = Vec::with_capacity(capacity(dictionary_len, rd));
+    for _ in 0..dictionary_len {
+        dictionary.push(read_string(rd)?);
+    }
+    let lookup = |rd: &mut Reader<'_>| -> Result<String, DecodeError> {
+        let idx: u32 = rmp::decode::read_int(rd).map_err(invalid)?;
+        dictionary
+            .get(idx as usize)
+            .cloned()
+            .ok_or_else(|| invalid(format!("string index {} out of the dictionary", idx)))
+    };
+
+    let trace_count = rmp::decode::read_array_len(rd).map_err(invalid)?;
+    let mut traces = Vec::with_capacity(capacity(trace_count, rd));
+    for _ in 0..trace_count {
+        let span_count = rmp::decode::read_array_len(rd).map_err(invalid)?;
+        let mut trace = Vec::with_capacity(capacity(span_count, rd));
+        for _ in 0..span_count {
+            if rmp::decode::read_array_len(rd).map_err(invalid)? != 12 {
+                return Err(invalid("spans must have 12 elements"));
+            }
+            let mut span = DecodedSpan {
+                service: lookup(rd)?,
+                name: lookup(rd)?,
+                resource: lookup(rd)?,
+                trace_id: rmp::decode::read_int(rd).map_err(invalid)?,
+                span_id: rmp::decode::read_int(rd).map_err(invalid)?,
+                parent_id: rmp::decode::read_int(rd).map_err(invalid)?,
+                start: rmp::decode::read_int(rd).map_err(invalid)?,
+                duration: rmp::decode::read_int(rd).map_err(invalid)?,
+                error: rmp::decode::read_int(rd).map_err(invalid)?,
+                ..Default::default()
+            };
+            let len = rmp::decode::read_map_len(rd).map_err(invalid)?;
+            for _ in 0..len {
+                let key = lookup(rd)?;
+                span.meta.insert(key, lookup(rd)?);
+            }
+            let len = rmp::decode::read_map_len(rd).map_err(invalid)?;
+            for _ in 0..len {
+                let key = lookup(rd)?;
+                span.metrics
+                    .insert(key, rmp::decode::read_f64(rd).map_err(invalid)?);
+            }
+            span.span_type = lookup(rd)?;
+            trace.push(span);
+        }
+        traces.push(trace);
+    }
+    Ok(traces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_malformed_counts() {
+        // arrays announcing u32::MAX elements, with no element following
+        let traces = [0xdd, 0xff, 0xff, 0xff, 0xff];
+        assert!(decode_v03(&traces).is_err());
+        let dictionary = [0x92, 0xdd, 0xff, 0xff, 0xff, 0xff];
+        assert!(decode_v05(&dictionary).is_err());
+        let spans = [0x92, 0x90, 0x91, 0xdd, 0xff, 0xff, 0xff, 0xff];
+        assert!(decode_v05(&spans).is_err());
+
+        assert_eq!(capacity(u32::MAX, &&traces[..]), traces.len());
+        assert_eq!(capacity(2, &&traces[..]), 2);
+    }
+}
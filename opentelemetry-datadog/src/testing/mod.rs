@@ -0,0 +1,10 @@
+//! Test support, to check what the exporter sends without a Datadog agent.
+//!
+//! [`decode_v03`] and [`decode_v05`] decode trace payloads back into [`DecodedSpan`]s, and the
+//! [`FakeAgent`] is a local HTTP server receiving the payloads like the agent does.
+
+mod agent;
+mod decode;
+
+pub use agent::{AgentRequest, FakeAgent};
+pub use decode::{decode_v03, decode_v05, DecodeError, DecodedSpan};
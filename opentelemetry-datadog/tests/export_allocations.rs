@@ -0,0 +1,136 @@
+//! Checks that the exporter reuses its encoding buffers from one export to the next.
+//!
+//! The allocations are counted by the global allocator of this test binary, per thread so that
+//! the test harness doesn't interfere.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use futures_executor::block_on;
+use http::Request;
+use opentelemetry::{
+    trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState},
+    InstrumentationScope, KeyValue,
+};
+use opentelemetry_datadog::{new_pipeline, ApiVersion, DatadogExporter};
+use opentelemetry_http::HttpClient;
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanExporter, SpanLinks};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    // the thread local is gone while the thread is torn down
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[derive(Debug)]
+struct DummyClient;
+
+#[async_trait::async_trait]
+impl HttpClient for DummyClient {
+    async fn send_bytes(
+        &self,
+        _request: Request<Bytes>,
+    ) -> Result<http::Response<Bytes>, opentelemetry_http::HttpError> {
+        Ok(http::Response::new(Bytes::new()))
+    }
+}
+
+fn get_span(trace_id: u128, span_id: u64) -> SpanData {
+    let start_time = SystemTime::UNIX_EPOCH;
+    SpanData {
+        span_context: SpanContext::new(
+            TraceId::from_u128(trace_id),
+            SpanId::from_u64(span_id),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        ),
+        parent_span_id: SpanId::from_u64(span_id - 1),
+        span_kind: SpanKind::Server,
+        name: "resource".into(),
+        start_time,
+        end_time: start_time + Duration::from_secs(1),
+        attributes: vec![
+            KeyValue::new("http.method", "GET"),
+            KeyValue::new("http.route", format!("/users/{span_id}")),
+        ],
+        dropped_attributes_count: 0,
+        events: SpanEvents::default(),
+        links: SpanLinks::default(),
+        status: Status::Ok,
+        instrumentation_scope: InstrumentationScope::builder("component").build(),
+    }
+}
+
+fn allocations_of(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+fn build_exporter(api_version: ApiVersion) -> DatadogExporter {
+    new_pipeline()
+        .with_service_name("service")
+        .with_api_version(api_version)
+        .with_http_client(DummyClient)
+        .build_exporter()
+        .unwrap()
+}
+
+#[test]
+fn test_exports_reuse_buffers() {
+    const TRACES: u128 = 64;
+    let batch: Vec<SpanData> = (1..=TRACES)
+        .flat_map(|trace_id| (1..=4).map(move |span_id| get_span(trace_id, span_id)))
+        .collect();
+    // allocations of the export alone, the batch is cloned beforehand
+    let export = |exporter: &DatadogExporter| {
+        let batch = batch.clone();
+        allocations_of(|| block_on(exporter.export(batch)).unwrap())
+    };
+
+    for api_version in [ApiVersion::Version03, ApiVersion::Version05] {
+        // the first export also pays for the one-time initializations of the process
+        let exporter = build_exporter(api_version);
+        export(&exporter);
+        let steady = export(&exporter);
+        // a new exporter grows its buffers from empty
+        let fresh = export(&build_exporter(api_version));
+
+        assert!(
+            steady < fresh,
+            "{api_version:?}: {steady} allocations with reused buffers, {fresh} with a new exporter"
+        );
+        // the spans are encoded without allocating
+        assert!(
+            steady < TRACES as usize,
+            "{api_version:?}: {steady} allocations to export {TRACES} traces"
+        );
+    }
+}
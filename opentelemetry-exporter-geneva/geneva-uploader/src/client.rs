@@ -0,0 +1,714 @@
+//! Uploads OTLP logs to Geneva: resolves the ingestion gateway with the config service, encodes
+//! the logs into CentralBond blobs and uploads each blob to the gateway.
+
+use crate::config_service::client::{
+    AuthMethod, GenevaConfigClient, GenevaConfigClientConfig, GenevaConfigClientError,
+};
+use crate::ingestion_service::uploader::{
+    BatchMetadata, GenevaUploader, GenevaUploaderConfig, GenevaUploaderError,
+};
+use crate::payload_encoder::otlp_encoder::OtlpEncoder;
+use crate::payload_encoder::EncoderError;
+use chrono::DateTime;
+use opentelemetry_proto::tonic::logs::v1::ResourceLogs;
+use std::sync::Arc;
+use thiserror::Error;
+use url::Url;
+
+const DEFAULT_TENANT: &str = "Default";
+const DEFAULT_AGENT_IDENTITY: &str = "GenevaUploader";
+const DEFAULT_AGENT_VERSION: &str = "0.1";
+
+/// Errors of the [`GenevaClient`].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum GenevaClientError {
+    /// A required setting of the builder wasn't set
+    #[error("Missing required setting: {0}")]
+    MissingSetting(&'static str),
+    /// A setting of the builder has an invalid value
+    #[error("Invalid {setting}: {reason}")]
+    InvalidSetting {
+        /// Name of the setting
+        setting: &'static str,
+        /// Why the value is invalid
+        reason: String,
+    },
+    /// The config service client couldn't be created, e.g. because the certificate couldn't be
+    /// read
+    #[error("Config service client error: {0}")]
+    ConfigClient(#[from] GenevaConfigClientError),
+    /// The HTTP client of the uploads couldn't be created
+    #[error("Uploader error: {0}")]
+    Uploader(GenevaUploaderError),
+    /// The logs couldn't be encoded
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] EncoderError),
+    /// Some of the encoded batches couldn't be uploaded
+    #[error("Upload of {failed} of {total} batches ({records} records) failed, first on {event_name}: {source}")]
+    Upload {
+        /// Event name of the first batch that failed
+        event_name: String,
+        /// Number of batches that failed
+        failed: usize,
+        /// Number of records of the batches that failed
+        records: usize,
+        /// Number of batches of the upload
+        total: usize,
+        /// Error of the first batch that failed
+        source: GenevaUploaderError,
+    },
+}
+
+/// Builder of [`GenevaClient`].
+///
+/// The endpoint, environment, account, namespace, region, config major version and
+/// authentication method are required.
+#[derive(Clone, Debug, Default)]
+pub struct GenevaClientBuilder {
+    endpoint: Option<String>,
+    environment: Option<String>,
+    account: Option<String>,
+    namespace: Option<String>,
+    region: Option<String>,
+    config_major_version: Option<u32>,
+    auth_method: Option<AuthMethod>,
+    tenant: Option<String>,
+    role_name: Option<String>,
+    role_instance: Option<String>,
+    agent_identity: Option<String>,
+    agent_version: Option<String>,
+}
+
+impl GenevaClientBuilder {
+    /// Set the Geneva Config Service endpoint URL
+    pub fn with_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Set the Geneva environment, e.g. `Test` or `prod`
+    pub fn with_environment<T: Into<String>>(mut self, environment: T) -> Self {
+        self.environment = Some(environment.into());
+        self
+    }
+
+    /// Set the Geneva account
+    pub fn with_account<T: Into<String>>(mut self, account: T) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+
+    /// Set the Geneva namespace of the logs
+    pub fn with_namespace<T: Into<String>>(mut self, namespace: T) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Set the Azure region, e.g. `westus2`
+    pub fn with_region<T: Into<String>>(mut self, region: T) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Set the major version of the Geneva configuration
+    pub fn with_config_major_version(mut self, config_major_version: u32) -> Self {
+        self.config_major_version = Some(config_major_version);
+        self
+    }
+
+    /// Set the authentication method to the Geneva Config Service
+    pub fn with_auth_method(mut self, auth_method: AuthMethod) -> Self {
+        self.auth_method = Some(auth_method);
+        self
+    }
+
+    /// Set the tenant of the source of the logs, `Default` by default
+    pub fn with_tenant<T: Into<String>>(mut self, tenant: T) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Set the role of the source of the logs, the namespace by default
+    pub fn with_role_name<T: Into<String>>(mut self, role_name: T) -> Self {
+        self.role_name = Some(role_name.into());
+        self
+    }
+
+    /// Set the role instance of the source of the logs, the role by default
+    pub fn with_role_instance<T: Into<String>>(mut self, role_instance: T) -> Self {
+        self.role_instance = Some(role_instance.into());
+        self
+    }
+
+    /// Set the name of the agent reported to the config service, `GenevaUploader` by default
+    pub fn with_agent_identity<T: Into<String>>(mut self, agent_identity: T) -> Self {
+        self.agent_identity = Some(agent_identity.into());
+        self
+    }
+
+    /// Set the version of the agent reported to the config service, `0.1` by default
+    pub fn with_agent_version<T: Into<String>>(mut self, agent_version: T) -> Self {
+        self.agent_version = Some(agent_version.into());
+        self
+    }
+
+    /// Validate the settings and build the client.
+    ///
+    /// No request is sent until the first upload.
+    pub fn build(self) -> Result<GenevaClient, GenevaClientError> {
+        let endpoint = required(self.endpoint, "endpoint")?;
+        validate_endpoint("endpoint", &endpoint)?;
+        let environment = required(self.environment, "environment")?;
+        validate_name("environment", &environment)?;
+        let account = required(self.account, "account")?;
+        validate_name("account", &account)?;
+        let namespace = required(self.namespace, "namespace")?;
+        validate_name("namespace", &namespace)?;
+        let region = required(self.region, "region")?;
+        validate_name("region", &region)?;
+        let config_major_version = required(self.config_major_version, "config major version")?;
+        let auth_method = required(self.auth_method, "auth method")?;
+        match &auth_method {
+            AuthMethod::Certificate { .. } => {}
+            AuthMethod::ManagedIdentity {
+                resource,
+                imds_endpoint,
+                ..
+            } => {
+                if resource.is_empty() {
+                    return Err(invalid("managed identity resource", "must not be empty"));
+                }
+                if let Some(imds_endpoint) = imds_endpoint {
+                    validate_endpoint("IMDS endpoint", imds_endpoint)?;
+                }
+            }
+            AuthMethod::WorkloadIdentity {
+                resource,
+                authority_host,
+                ..
+            } => {
+                if resource.is_empty() {
+                    return Err(invalid("workload identity resource", "must not be empty"));
+                }
+                if let Some(authority_host) = authority_host {
+                    validate_endpoint("authority host", authority_host)?;
+                }
+            }
+        }
+
+        let tenant = self.tenant.unwrap_or_else(|| DEFAULT_TENANT.to_string());
+        validate_identity("tenant", &tenant)?;
+        let role_name = self.role_name.unwrap_or_else(|| namespace.clone());
+        validate_identity("role name", &role_name)?;
+        let role_instance = self.role_instance.unwrap_or_else(|| role_name.clone());
+        validate_identity("role instance", &role_instance)?;
+        let agent_identity = self
+            .agent_identity
+            .unwrap_or_else(|| DEFAULT_AGENT_IDENTITY.to_string());
+        validate_identity("agent identity", &agent_identity)?;
+        let agent_version = self
+            .agent_version
+            .unwrap_or_else(|| DEFAULT_AGENT_VERSION.to_string());
+        validate_identity("agent version", &agent_version)?;
+
+        let source_identity = format!(
+            "Tenant={}/Role={}/RoleInstance={}",
+            tenant, role_name, role_instance
+        );
+        let event_version = format!("Ver{}v0", config_major_version);
+        let metadata = format!(
+            "namespace={}/eventVersion={}/tenant={}/role={}/roleinstance={}",
+            namespace, event_version, tenant, role_name, role_instance
+        );
+
+        let config_client = GenevaConfigClient::new(GenevaConfigClientConfig {
+            endpoint,
+            environment,
+            account,
+            namespace: namespace.clone(),
+            region,
+            config_major_version,
+            auth_method,
+            agent_identity,
+            agent_version,
+        })?;
+
+        let uploader = GenevaUploader::from_config_client(
+            Arc::new(config_client),
+            GenevaUploaderConfig {
+                namespace,
+                source_identity,
+            },
+        )
+        .map_err(GenevaClientError::Uploader)?;
+
+        Ok(GenevaClient {
+            uploader: Arc::new(uploader),
+            encoder: OtlpEncoder::new(),
+            metadata,
+            event_version,
+        })
+    }
+}
+
+fn required<T>(value: Option<T>, setting: &'static str) -> Result<T, GenevaClientError> {
+    value.ok_or(GenevaClientError::MissingSetting(setting))
+}
+
+fn invalid(setting: &'static str, reason: impl Into<String>) -> GenevaClientError {
+    GenevaClientError::InvalidSetting {
+        setting,
+        reason: reason.into(),
+    }
+}
+
+fn validate_endpoint(setting: &'static str, endpoint: &str) -> Result<(), GenevaClientError> {
+    let url = Url::parse(endpoint).map_err(|e| invalid(setting, e.to_string()))?;
+    match url.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(invalid(
+            setting,
+            format!("unsupported scheme {}, expected http or https", scheme),
+        )),
+    }
+}
+
+/// Names sent as is in the path and the query of the requests.
+fn validate_name(setting: &'static str, value: &str) -> Result<(), GenevaClientError> {
+    if value.is_empty() {
+        return Err(invalid(setting, "must not be empty"));
+    }
+    match value
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        Some(c) => Err(invalid(
+            setting,
+            format!(
+                "{:?} is not allowed, only ASCII letters, digits, '-', '_' and '.'",
+                c
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Parts of the `key=value/...` identities of the source and the agent.
+fn validate_identity(setting: &'static str, value: &str) -> Result<(), GenevaClientError> {
+    if value.is_empty() {
+        return Err(invalid(setting, "must not be empty"));
+    }
+    match value
+        .chars()
+        .find(|c| c.is_control() || matches!(c, '/' | '='))
+    {
+        Some(c) => Err(invalid(setting, format!("{:?} is not allowed", c))),
+        None => Ok(()),
+    }
+}
+
+/// Client uploading OTLP logs to Geneva.
+///
+/// The client can be shared between tasks: the ingestion gateway token is cached and refreshed
+/// by all of them.
+#[derive(Debug, Clone)]
+pub struct GenevaClient {
+    uploader: Arc<GenevaUploader>,
+    encoder: OtlpEncoder,
+    metadata: String,
+    event_version: String,
+}
+
+impl GenevaClient {
+    /// Create a builder of GenevaClient
+    pub fn builder() -> GenevaClientBuilder {
+        GenevaClientBuilder::default()
+    }
+
+    /// Encodes the logs and uploads them, one blob per event name.
+    ///
+    /// Every blob is attempted even if some fail; the error reports the first failure.
+    pub async fn upload_logs(&self, logs: &[ResourceLogs]) -> Result<(), GenevaClientError> {
+        let batches = self.encoder.encode_log_batch(logs, &self.metadata)?;
+
+        let total = batches.len();
+        let mut failed = 0;
+        let mut records = 0;
+        let mut first_error = None;
+        for batch in batches {
+            let metadata = BatchMetadata {
+                schema_ids: batch.schema_ids,
+                start_time: DateTime::from_timestamp_nanos(batch.start_time_nanos as i64),
+                end_time: DateTime::from_timestamp_nanos(batch.end_time_nanos as i64),
+            };
+            if let Err(e) = self
+                .uploader
+                .upload(
+                    batch.data,
+                    &batch.event_name,
+                    &self.event_version,
+                    &metadata,
+                )
+                .await
+            {
+                failed += 1;
+                records += batch.row_count;
+                first_error.get_or_insert((batch.event_name, e));
+            }
+        }
+
+        match first_error {
+            None => Ok(()),
+            Some((event_name, source)) => Err(GenevaClientError::Upload {
+                event_name,
+                failed,
+                records,
+                total,
+                source,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_service::managed_identity::ManagedIdentity;
+    use crate::config_service::tests::generate_self_signed_p12;
+    use opentelemetry_proto::tonic::common::v1::{any_value::Value, AnyValue};
+    use opentelemetry_proto::tonic::logs::v1::{LogRecord, ScopeLogs};
+    use std::path::PathBuf;
+    use tempfile::NamedTempFile;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn mock_builder(
+        endpoint: &str,
+        p12_file: &NamedTempFile,
+        password: String,
+    ) -> GenevaClientBuilder {
+        GenevaClient::builder()
+            .with_endpoint(endpoint)
+            .with_environment("mockenv")
+            .with_account("mockacct")
+            .with_namespace("mockns")
+            .with_region("mockregion")
+            .with_config_major_version(2)
+            .with_auth_method(AuthMethod::Certificate {
+                path: PathBuf::from(p12_file.path()),
+                password,
+            })
+            .with_tenant("tenant")
+            .with_role_name("role")
+            .with_role_instance("instance")
+            .with_agent_identity("TestAgent")
+            .with_agent_version("1.2")
+    }
+
+    fn resource_logs(event_names: &[&str]) -> ResourceLogs {
+        ResourceLogs {
+            scope_logs: vec![ScopeLogs {
+                log_records: event_names
+                    .iter()
+                    .map(|event_name| LogRecord {
+                        time_unix_nano: 1_700_000_000_000_000_000,
+                        event_name: event_name.to_string(),
+                        severity_number: 9,
+                        body: Some(AnyValue {
+                            value: Some(Value::StringValue("hello".into())),
+                        }),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[cfg_attr(target_os = "macos", ignore)] // cert generated not compatible with macOS
+    #[tokio::test]
+    async fn test_upload_logs_mocked() {
+        let mock_server = MockServer::start().await;
+        let valid_token = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.eyJFbmRwb2ludCI6Imh0dHBzOi8vdGVzdC5lbmRwb2ludCJ9.signature";
+
+        Mock::given(method("GET"))
+            .and(path(
+                "/api/agent/v3/mockenv/mockacct/MonitoringStorageKeys/",
+            ))
+            .and(header("user-agent", "TestAgent-1.2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "IngestionGatewayInfo": {
+                    "Endpoint": mock_server.uri(),
+                    "AuthToken": valid_token,
+                    "AuthTokenExpiryTime": "2030-01-01T00:00:00Z"
+                },
+                "StorageAccountKeys": [
+                    {
+                        "AccountMonikerName": "mock-diag-moniker",
+                        "AccountGroupName": "mock-diag-group",
+                        "IsPrimaryMoniker": true
+                    }
+                ],
+                "TagId": "mock-tag-id"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingestion/ingest"))
+            .and(query_param("moniker", "mock-diag-moniker"))
+            .and(query_param("namespace", "mockns"))
+            .and(query_param("version", "Ver2v0"))
+            .and(query_param("startTime", "2023-11-14T22:13:20.0000000Z"))
+            .and(header("authorization", format!("Bearer {}", valid_token)))
+            .respond_with(
+                ResponseTemplate::new(202).set_body_json(serde_json::json!({"ticket": "t"})),
+            )
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let (temp_p12_file, password) = generate_self_signed_p12();
+        let client = mock_builder(&mock_server.uri(), &temp_p12_file, password)
+            .build()
+            .unwrap();
+
+        // one upload per event name
+        client
+            .upload_logs(&[resource_logs(&["Log", "Audit", "Log"])])
+            .await
+            .unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let events: Vec<String> = requests
+            .iter()
+            .filter(|request| request.method.as_str() == "POST")
+            .filter_map(|request| {
+                request
+                    .url
+                    .query_pairs()
+                    .find(|(key, _)| key == "event")
+                    .map(|(_, value)| value.into_owned())
+            })
+            .collect();
+        assert_eq!(events, vec!["Audit", "Log"]);
+    }
+
+    #[cfg_attr(target_os = "macos", ignore)] // cert generated not compatible with macOS
+    #[tokio::test]
+    async fn test_upload_logs_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(403).set_body_string("Forbidden"))
+            .mount(&mock_server)
+            .await;
+
+        let (temp_p12_file, password) = generate_self_signed_p12();
+        let client = mock_builder(&mock_server.uri(), &temp_p12_file, password)
+            .build()
+            .unwrap();
+
+        let error = client
+            .upload_logs(&[resource_logs(&["Log", "Audit"])])
+            .await
+            .unwrap_err();
+        match error {
+            GenevaClientError::Upload {
+                event_name,
+                failed,
+                records,
+                total,
+                source:
+                    GenevaUploaderError::ConfigClient(GenevaConfigClientError::RequestFailed {
+                        status,
+                        ..
+                    }),
+            } => {
+                assert_eq!(event_name, "Audit");
+                assert_eq!((failed, total), (2, 2));
+                assert_eq!(records, 2);
+                assert_eq!(status, 403);
+            }
+            error => panic!("Expected an upload error, got: {:?}", error),
+        }
+    }
+
+    #[cfg_attr(target_os = "macos", ignore)] // cert generated not compatible with macOS
+    #[tokio::test]
+    async fn test_upload_logs_encoding_error() {
+        let mock_server = MockServer::start().await;
+        let (temp_p12_file, password) = generate_self_signed_p12();
+        let client = mock_builder(&mock_server.uri(), &temp_p12_file, password)
+            .build()
+            .unwrap();
+
+        let event_name = "e".repeat(70_000);
+        let error = client
+            .upload_logs(&[resource_logs(&[&event_name])])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                error,
+                GenevaClientError::Encoding(EncoderError::EventNameTooLong(_))
+            ),
+            "Expected an encoding error, got: {:?}",
+            error
+        );
+        // nothing is uploaded when the logs can't be encoded
+        assert!(mock_server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_build_validation() {
+        let (temp_p12_file, password) = generate_self_signed_p12();
+        let builder = mock_builder("https://example.com", &temp_p12_file, password);
+
+        let error = GenevaClientBuilder {
+            account: None,
+            ..builder.clone()
+        }
+        .build()
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::MissingSetting("account")
+        ));
+
+        let error = builder
+            .clone()
+            .with_endpoint("example.com")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "endpoint",
+                ..
+            }
+        ));
+
+        let error = builder
+            .clone()
+            .with_endpoint("ftp://example.com")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "endpoint",
+                ..
+            }
+        ));
+
+        let error = builder
+            .clone()
+            .with_namespace("my ns&x=1")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "namespace",
+                ..
+            }
+        ));
+
+        let error = builder.clone().with_region("").build().unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "region",
+                ..
+            }
+        ));
+
+        let error = builder
+            .clone()
+            .with_role_instance("host/1")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "role instance",
+                ..
+            }
+        ));
+
+        let error = builder
+            .clone()
+            .with_agent_version("1.0\n")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "agent version",
+                ..
+            }
+        ));
+
+        let error = builder
+            .clone()
+            .with_auth_method(AuthMethod::Certificate {
+                path: PathBuf::from("/nonexistent/path.p12"),
+                password: "test".to_string(),
+            })
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::ConfigClient(GenevaConfigClientError::Certificate(_))
+        ));
+
+        let error = builder
+            .clone()
+            .with_auth_method(AuthMethod::ManagedIdentity {
+                identity: ManagedIdentity::SystemAssigned,
+                resource: String::new(),
+                imds_endpoint: None,
+            })
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "managed identity resource",
+                ..
+            }
+        ));
+
+        assert!(builder
+            .clone()
+            .with_auth_method(AuthMethod::ManagedIdentity {
+                identity: ManagedIdentity::ClientId("client".into()),
+                resource: "https://monitor.example".into(),
+                imds_endpoint: Some("http://localhost:8080".into()),
+            })
+            .build()
+            .is_ok());
+        let error = builder
+            .clone()
+            .with_auth_method(AuthMethod::WorkloadIdentity {
+                resource: "https://monitor.example".into(),
+                client_id: Some("client".into()),
+                tenant_id: Some("tenant".into()),
+                token_file: Some(PathBuf::from("/var/run/secrets/token")),
+                authority_host: Some("login.example".into()),
+            })
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::InvalidSetting {
+                setting: "authority host",
+                ..
+            }
+        ));
+
+        assert!(builder.build().is_ok());
+    }
+}
@@ -1,4 +1,4 @@
-// Geneva Config Client with TLS (PKCS#12) and TODO: Managed Identity support
+// Geneva Config Client with TLS (PKCS#12), Managed Identity and Workload Identity support
 
 use base64::{engine::general_purpose, Engine as _};
 use reqwest::{
@@ -10,8 +10,14 @@ use std::time::Duration;
 use thiserror::Error;
 use uuid::Uuid;
 
+use crate::config_service::managed_identity::{
+    ManagedIdentity, ManagedIdentityCredential, DEFAULT_IMDS_ENDPOINT,
+};
+use crate::config_service::workload_identity::WorkloadIdentityCredential;
+
 use chrono::{DateTime, Utc};
 use native_tls::{Identity, Protocol};
+use std::error::Error as StdError;
 use std::fmt;
 use std::fmt::Write;
 use std::fs;
@@ -20,9 +26,10 @@ use std::sync::RwLock;
 
 /// Authentication methods for the Geneva Config Client.
 ///
-/// The client supports two authentication methods:
+/// The client supports three authentication methods:
 /// - Certificate-based authentication using PKCS#12 (.p12) files
-/// - Managed Identity (Azure) - planned for future implementation
+/// - Managed Identity (Azure), with tokens from the Instance Metadata Service (IMDS)
+/// - Workload Identity (Azure), with a Kubernetes service account token exchanged for an AAD token
 ///
 /// # Certificate Format
 /// Certificates should be in PKCS#12 (.p12) format for client TLS authentication.
@@ -44,9 +51,8 @@ use std::sync::RwLock;
 /// ```powershell
 /// openssl pkcs12 -export -in cert.pem -inkey key.pem -out client.p12 -name "alias"
 /// ```
-#[allow(dead_code)]
-#[derive(Clone, Debug)]
-pub(crate) enum AuthMethod {
+#[derive(Clone)]
+pub enum AuthMethod {
     /// Certificate-based authentication
     ///
     /// # Arguments
@@ -55,40 +61,125 @@ pub(crate) enum AuthMethod {
     Certificate { path: PathBuf, password: String },
     /// Azure Managed Identity authentication
     ///
-    /// Note(TODO): This is not yet implemented.
-    ManagedIdentity,
+    /// The requests carry a bearer token of the identity, acquired from IMDS and cached until
+    /// shortly before it expires.
+    ///
+    /// # Arguments
+    /// * `identity` - The system-assigned identity, or a user-assigned one
+    /// * `resource` - The resource (audience) of the tokens
+    /// * `imds_endpoint` - Base URL of IMDS, `http://169.254.169.254` if `None`
+    ManagedIdentity {
+        identity: ManagedIdentity,
+        resource: String,
+        imds_endpoint: Option<String>,
+    },
+    /// Azure Workload Identity authentication
+    ///
+    /// The requests carry a bearer token acquired from AAD in exchange for the federated service
+    /// account token projected in the pod, cached until shortly before it expires. The token file
+    /// is read again for every exchange, so that its rotations are picked up.
+    ///
+    /// # Arguments
+    /// * `resource` - The resource (audience) of the tokens
+    /// * `client_id` - Client id of the AAD application, `AZURE_CLIENT_ID` if `None`
+    /// * `tenant_id` - AAD tenant of the application, `AZURE_TENANT_ID` if `None`
+    /// * `token_file` - Path of the federated token, `AZURE_FEDERATED_TOKEN_FILE` if `None`
+    /// * `authority_host` - AAD authority host, `AZURE_AUTHORITY_HOST` if `None`, else
+    ///   `https://login.microsoftonline.com/`
+    WorkloadIdentity {
+        resource: String,
+        client_id: Option<String>,
+        tenant_id: Option<String>,
+        token_file: Option<PathBuf>,
+        authority_host: Option<String>,
+    },
+}
+
+// The password of the certificate is never printed
+impl fmt::Debug for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthMethod::Certificate { path, .. } => f
+                .debug_struct("Certificate")
+                .field("path", path)
+                .field("password", &"<redacted>")
+                .finish(),
+            AuthMethod::ManagedIdentity {
+                identity,
+                resource,
+                imds_endpoint,
+            } => f
+                .debug_struct("ManagedIdentity")
+                .field("identity", identity)
+                .field("resource", resource)
+                .field("imds_endpoint", imds_endpoint)
+                .finish(),
+            AuthMethod::WorkloadIdentity {
+                resource,
+                client_id,
+                tenant_id,
+                token_file,
+                authority_host,
+            } => f
+                .debug_struct("WorkloadIdentity")
+                .field("resource", resource)
+                .field("client_id", client_id)
+                .field("tenant_id", tenant_id)
+                .field("token_file", token_file)
+                .field("authority_host", authority_host)
+                .finish(),
+        }
+    }
 }
 
+/// Errors of the Geneva Config Service client.
 #[derive(Debug, Error)]
-pub(crate) enum GenevaConfigClientError {
+#[non_exhaustive]
+pub enum GenevaConfigClientError {
     // Authentication-related errors
-    #[error("Authentication method not implemented: {0}")]
-    AuthMethodNotImplemented(String),
+    /// The response of the config service doesn't contain the ingestion gateway info
     #[error("Missing Auth Info: {0}")]
     AuthInfoNotFound(String),
+    /// The token of the ingestion gateway isn't a valid JWT
     #[error("Invalid or malformed JWT token: {0}")]
     JwtTokenError(String),
+    /// The client certificate couldn't be read or used
     #[error("Certificate error: {0}")]
     Certificate(String),
+    /// No token of the managed identity could be acquired
+    #[error("Managed Identity error: {0}")]
+    ManagedIdentity(String),
+    /// The federated token of the workload identity couldn't be read or exchanged
+    #[error("Workload Identity error: {0}")]
+    WorkloadIdentity(String),
 
     // Networking / HTTP / TLS
+    /// The HTTP client couldn't be created, or the request couldn't be sent or its response read
     #[error("HTTP error: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(#[source] Box<dyn StdError + Send + Sync>),
+    /// The config service answered with a non-success status
     #[error("Request failed with status {status}: {message}")]
-    RequestFailed { status: u16, message: String },
+    RequestFailed {
+        /// HTTP status of the response
+        status: u16,
+        /// Body of the response
+        message: String,
+    },
 
     // Data / parsing
+    /// The response of the config service isn't the expected JSON
     #[error("JSON error: {0}")]
-    SerdeJson(#[from] serde_json::Error),
+    SerdeJson(#[source] Box<dyn StdError + Send + Sync>),
 
     // Misc
+    /// The response of the config service doesn't contain a moniker for the configured region
     #[error("Moniker not found: {0}")]
     MonikerNotFound(String),
+    /// Unexpected failure of the client, e.g. a poisoned lock
     #[error("Internal error: {0}")]
     InternalError(String),
 }
 
-#[allow(dead_code)]
 pub(crate) type Result<T> = std::result::Result<T, GenevaConfigClientError>;
 
 /// Configuration for the Geneva Config Client.
@@ -100,7 +191,9 @@ pub(crate) type Result<T> = std::result::Result<T, GenevaConfigClientError>;
 /// * `namespace` - Namespace for the configuration
 /// * `region` - Azure region (e.g., "westus2")
 /// * `config_major_version` - Major version of the configuration schema
-/// * `auth_method` - Authentication method to use (Certificate or ManagedIdentity)
+/// * `auth_method` - Authentication method to use (Certificate, ManagedIdentity or WorkloadIdentity)
+/// * `agent_identity` - Name of the agent, sent in the `User-Agent` header and the identity
+/// * `agent_version` - Version of the agent, sent in the `User-Agent` header
 ///
 /// # Example
 /// ```ignore
@@ -112,12 +205,13 @@ pub(crate) type Result<T> = std::result::Result<T, GenevaConfigClientError>;
 ///     region: "westus2".to_string(),
 ///     config_major_version: 1,
 ///     auth_method: AuthMethod::Certificate {
-///         path: "/path/to/cert.p12".to_string(),
+///         path: PathBuf::from("/path/to/cert.p12"),
 ///         password: "password".to_string(),
 ///     },
+///     agent_identity: "GenevaUploader".to_string(),
+///     agent_version: "0.1".to_string(),
 /// };
 /// ```
-#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub(crate) struct GenevaConfigClientConfig {
     pub(crate) endpoint: String,
@@ -126,10 +220,11 @@ pub(crate) struct GenevaConfigClientConfig {
     pub(crate) namespace: String,
     pub(crate) region: String,
     pub(crate) config_major_version: u32,
-    pub(crate) auth_method: AuthMethod, // agent_identity and agent_version are hardcoded for now
+    pub(crate) auth_method: AuthMethod,
+    pub(crate) agent_identity: String,
+    pub(crate) agent_version: String,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct IngestionGatewayInfo {
     #[serde(rename = "Endpoint")]
@@ -140,14 +235,14 @@ pub(crate) struct IngestionGatewayInfo {
     pub(crate) auth_token_expiry_time: String,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub(crate) struct MonikerInfo {
     pub name: String,
+    // Not needed to upload, only checked by the tests
+    #[cfg_attr(not(test), allow(dead_code))]
     pub account_group: String,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct StorageAccountKey {
     #[serde(rename = "AccountMonikerName")]
@@ -158,7 +253,6 @@ struct StorageAccountKey {
     is_primary_moniker: bool,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct GenevaResponse {
     #[serde(rename = "IngestionGatewayInfo")]
@@ -166,12 +260,8 @@ struct GenevaResponse {
     // TODO: Make storage_account_keys optional since it might not be present in all responses
     #[serde(rename = "StorageAccountKeys", default)]
     storage_account_keys: Vec<StorageAccountKey>,
-    // Keep tag_id as it might be used for validation
-    #[serde(rename = "TagId")]
-    tag_id: String,
 }
 
-#[allow(dead_code)]
 struct CachedAuthData {
     // Store the complete token and moniker info
     auth_info: (IngestionGatewayInfo, MonikerInfo),
@@ -181,7 +271,6 @@ struct CachedAuthData {
     token_expiry: DateTime<Utc>,
 }
 
-#[allow(dead_code)]
 pub(crate) struct GenevaConfigClient {
     config: GenevaConfigClientConfig,
     http_client: Client,
@@ -191,6 +280,23 @@ pub(crate) struct GenevaConfigClient {
     agent_identity: String,
     agent_version: String,
     static_headers: HeaderMap,
+    token_credential: Option<TokenCredential>,
+}
+
+/// Credentials of the authentication methods sending bearer tokens.
+#[derive(Debug)]
+enum TokenCredential {
+    ManagedIdentity(ManagedIdentityCredential),
+    WorkloadIdentity(WorkloadIdentityCredential),
+}
+
+impl TokenCredential {
+    async fn get_token(&self) -> Result<String> {
+        match self {
+            TokenCredential::ManagedIdentity(credential) => credential.get_token().await,
+            TokenCredential::WorkloadIdentity(credential) => credential.get_token().await,
+        }
+    }
 }
 
 impl fmt::Debug for GenevaConfigClient {
@@ -201,6 +307,7 @@ impl fmt::Debug for GenevaConfigClient {
             .field("agent_identity", &self.agent_identity)
             .field("agent_version", &self.agent_version)
             .field("static_headers", &self.static_headers)
+            .field("token_credential", &self.token_credential)
             .finish()
     }
 }
@@ -220,13 +327,15 @@ impl GenevaConfigClient {
     ///
     /// # Errors
     /// * `GenevaConfigClientError::Certificate` - If reading the certificate file, parsing it, or constructing the TLS connector fails
-    /// * `GenevaConfigClientError::AuthMethodNotImplemented` - If the specified authentication method is not yet supported
-    #[allow(dead_code)]
+    /// * `GenevaConfigClientError::ManagedIdentity` - If the IMDS endpoint isn't a valid URL
+    /// * `GenevaConfigClientError::WorkloadIdentity` - If a setting is missing from both the config and the environment, or the authority host isn't a valid URL
+    /// * `GenevaConfigClientError::InternalError` - If the agent identity or version can't be sent in a header
     pub(crate) fn new(config: GenevaConfigClientConfig) -> Result<Self> {
         let mut client_builder = Client::builder()
             .http1_only()
             .timeout(Duration::from_secs(30)); //TODO - make this configurable
 
+        let mut token_credential = None;
         match &config.auth_method {
             // TODO: Certificate auth would be removed in favor of managed identity.,
             // This is for testing, so we can use self-signed certs, and password in plain text.
@@ -244,20 +353,44 @@ impl GenevaConfigClient {
                         .map_err(|e| GenevaConfigClientError::Certificate(e.to_string()))?;
                 client_builder = client_builder.use_preconfigured_tls(tls_connector);
             }
-            AuthMethod::ManagedIdentity => {
-                return Err(GenevaConfigClientError::AuthMethodNotImplemented(
-                    "Managed Identity authentication is not implemented yet".into(),
+            AuthMethod::ManagedIdentity {
+                identity,
+                resource,
+                imds_endpoint,
+            } => {
+                token_credential = Some(TokenCredential::ManagedIdentity(
+                    ManagedIdentityCredential::new(
+                        identity,
+                        resource,
+                        imds_endpoint.as_deref().unwrap_or(DEFAULT_IMDS_ENDPOINT),
+                    )?,
+                ));
+            }
+            AuthMethod::WorkloadIdentity {
+                resource,
+                client_id,
+                tenant_id,
+                token_file,
+                authority_host,
+            } => {
+                token_credential = Some(TokenCredential::WorkloadIdentity(
+                    WorkloadIdentityCredential::new(
+                        resource,
+                        client_id.as_deref(),
+                        tenant_id.as_deref(),
+                        token_file.as_ref(),
+                        authority_host.as_deref(),
+                    )?,
                 ));
             }
         }
 
-        let agent_identity = "GenevaUploader";
-        let agent_version = "0.1";
-        let static_headers = Self::build_static_headers(agent_identity, agent_version);
+        let static_headers =
+            Self::build_static_headers(&config.agent_identity, &config.agent_version)?;
 
         let identity = format!(
             "Tenant=Default/Role=GcsClient/RoleInstance={}",
-            agent_identity
+            config.agent_identity
         );
 
         let encoded_identity = general_purpose::STANDARD.encode(&identity);
@@ -277,16 +410,19 @@ impl GenevaConfigClient {
             version_str
         ).map_err(|e| GenevaConfigClientError::InternalError(format!("Failed to write URL: {e}")))?;
 
-        let http_client = client_builder.build()?;
+        let http_client = client_builder
+            .build()
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
 
         Ok(Self {
+            agent_identity: config.agent_identity.clone(),
+            agent_version: config.agent_version.clone(),
             config,
             http_client,
             cached_data: RwLock::new(None),
             precomputed_url_prefix: pre_url,
-            agent_identity: agent_identity.to_string(), // TODO make this configurable
-            agent_version: "1.0".to_string(),           // TODO make this configurable
             static_headers,
+            token_credential,
         })
     }
 
@@ -297,12 +433,15 @@ impl GenevaConfigClient {
             .map(|dt| dt.with_timezone(&Utc))
     }
 
-    fn build_static_headers(agent_identity: &str, agent_version: &str) -> HeaderMap {
+    fn build_static_headers(agent_identity: &str, agent_version: &str) -> Result<HeaderMap> {
         let mut headers = HeaderMap::new();
         let user_agent = format!("{}-{}", agent_identity, agent_version);
-        headers.insert(USER_AGENT, HeaderValue::from_str(&user_agent).unwrap());
+        let user_agent = HeaderValue::from_str(&user_agent).map_err(|e| {
+            GenevaConfigClientError::InternalError(format!("Invalid User-Agent: {e}"))
+        })?;
+        headers.insert(USER_AGENT, user_agent);
         headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-        headers
+        Ok(headers)
     }
 
     /// Retrieves ingestion gateway information from the Geneva Config Service.
@@ -338,17 +477,19 @@ impl GenevaConfigClient {
     ///   ```
     ///
     /// ## Authentication
-    /// Uses mutual TLS (mTLS) with client certificate authentication
+    /// Uses mutual TLS (mTLS) with client certificate authentication, or an
+    /// `Authorization: Bearer` token of the managed identity or of the workload identity
     ///
     /// # Returns
     /// * `Result<IngestionGatewayInfo, MonikerInfo>` - Ingestion gateway information, with storage monikers or an error
     ///
     /// # Errors
     /// * `GenevaConfigClientError::Http` - If the HTTP request fails
+    /// * `GenevaConfigClientError::ManagedIdentity` - If no token of the managed identity can be acquired
+    /// * `GenevaConfigClientError::WorkloadIdentity` - If the federated token can't be read or exchanged
     /// * `GenevaConfigClientError::RequestFailed` - If the server returns a non-success status
     /// * `GenevaConfigClientError::AuthInfoNotFound` - If the response doesn't contain ingestion info
     /// * `GenevaConfigClientError::SerdeJson` - If JSON parsing fails
-    #[allow(dead_code)]
     pub(crate) async fn get_ingestion_info(
         &self,
     ) -> Result<(IngestionGatewayInfo, MonikerInfo, String)> {
@@ -428,13 +569,20 @@ impl GenevaConfigClient {
             .headers(self.static_headers.clone()); // Clone only cheap references
 
         request = request.header("x-ms-client-request-id", req_id);
+        if let Some(token_credential) = &self.token_credential {
+            let token = token_credential.get_token().await?;
+            request = request.bearer_auth(token);
+        }
         let response = request
             .send()
             .await
-            .map_err(GenevaConfigClientError::Http)?;
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
 
         let status = response.status();
-        let body = response.text().await?;
+        let body = response
+            .text()
+            .await
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
 
         if status.is_success() {
             let parsed = match serde_json::from_str::<GenevaResponse>(&body) {
@@ -512,8 +660,8 @@ fn extract_endpoint_from_token(token: &str) -> Result<String> {
     })?;
 
     // Parse as JSON and extract the Endpoint claim
-    let payload_json: serde_json::Value =
-        serde_json::from_str(&decoded_str).map_err(GenevaConfigClientError::SerdeJson)?;
+    let payload_json: serde_json::Value = serde_json::from_str(&decoded_str)
+        .map_err(|e| GenevaConfigClientError::SerdeJson(e.into()))?;
 
     // Extract "Endpoint" from JWT payload as a string, or fail if missing or invalid.
     let endpoint = payload_json["Endpoint"]
@@ -0,0 +1,296 @@
+// Azure Managed Identity tokens, acquired from the Instance Metadata Service (IMDS)
+
+use crate::config_service::client::{GenevaConfigClientError, Result};
+use crate::config_service::token_cache::{CachedToken, TokenCache};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Base URL of the Azure Instance Metadata Service.
+pub(crate) const DEFAULT_IMDS_ENDPOINT: &str = "http://169.254.169.254";
+const IMDS_TOKEN_PATH: &str = "/metadata/identity/oauth2/token";
+const IMDS_API_VERSION: &str = "2018-02-01";
+
+/// The managed identity to acquire tokens for.
+///
+/// A VM or a node pool can be assigned the system identity and several user identities; a user
+/// identity is selected by any of its ids.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManagedIdentity {
+    /// The system-assigned identity
+    SystemAssigned,
+    /// A user-assigned identity, by its client id (application id)
+    ClientId(String),
+    /// A user-assigned identity, by its object id (principal id)
+    ObjectId(String),
+    /// A user-assigned identity, by its Azure resource id
+    ResourceId(String),
+}
+
+impl ManagedIdentity {
+    /// The IMDS query parameter selecting the identity.
+    fn query_param(&self) -> Option<(&'static str, &str)> {
+        match self {
+            ManagedIdentity::SystemAssigned => None,
+            ManagedIdentity::ClientId(id) => Some(("client_id", id)),
+            ManagedIdentity::ObjectId(id) => Some(("object_id", id)),
+            ManagedIdentity::ResourceId(id) => Some(("msi_res_id", id)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    // IMDS sends numbers as strings
+    expires_in: Option<String>,
+    expires_on: Option<String>,
+}
+
+/// Acquires tokens for a managed identity from IMDS, and caches them until shortly before they
+/// expire.
+#[derive(Debug)]
+pub(crate) struct ManagedIdentityCredential {
+    http_client: Client,
+    token_url: String,
+    cache: TokenCache,
+}
+
+impl ManagedIdentityCredential {
+    /// Creates a credential acquiring tokens of `identity` for `resource`, from the IMDS at
+    /// `imds_endpoint`.
+    pub(crate) fn new(
+        identity: &ManagedIdentity,
+        resource: &str,
+        imds_endpoint: &str,
+    ) -> Result<Self> {
+        let mut token_url = url::Url::parse(imds_endpoint)
+            .and_then(|url| url.join(IMDS_TOKEN_PATH))
+            .map_err(|e| {
+                GenevaConfigClientError::ManagedIdentity(format!("Invalid IMDS endpoint: {e}"))
+            })?;
+        {
+            let mut query = token_url.query_pairs_mut();
+            query
+                .append_pair("api-version", IMDS_API_VERSION)
+                .append_pair("resource", resource);
+            if let Some((name, value)) = identity.query_param() {
+                query.append_pair(name, value);
+            }
+        }
+
+        let http_client = Client::builder()
+            // IMDS must not be reached through a proxy
+            .no_proxy()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
+
+        Ok(Self {
+            http_client,
+            token_url: token_url.into(),
+            cache: TokenCache::default(),
+        })
+    }
+
+    /// Returns a token valid for at least the refresh margin, from the cache or from IMDS.
+    pub(crate) async fn get_token(&self) -> Result<String> {
+        match self.cache.get() {
+            Some(token) => Ok(token),
+            None => self.cache.update(self.fetch_token().await?),
+        }
+    }
+
+    async fn fetch_token(&self) -> Result<CachedToken> {
+        let response = self
+            .http_client
+            .get(&self.token_url)
+            .header("Metadata", "true")
+            .send()
+            .await
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
+        if !status.is_success() {
+            return Err(GenevaConfigClientError::ManagedIdentity(format!(
+                "IMDS request failed with status {}: {}",
+                status.as_u16(),
+                body
+            )));
+        }
+
+        let token: TokenResponse = serde_json::from_str(&body).map_err(|e| {
+            GenevaConfigClientError::ManagedIdentity(format!("Invalid IMDS response: {e}"))
+        })?;
+        let expiry = token_expiry(&token, Utc::now()).ok_or_else(|| {
+            GenevaConfigClientError::ManagedIdentity("No expiry in IMDS response".to_string())
+        })?;
+        Ok(CachedToken {
+            access_token: token.access_token,
+            expiry,
+        })
+    }
+}
+
+/// Expiry of the token, from `expires_in` as it doesn't depend on the local clock being in sync,
+/// else from `expires_on`.
+fn token_expiry(token: &TokenResponse, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Some(expires_in) = token
+        .expires_in
+        .as_deref()
+        .and_then(|value| value.parse::<i64>().ok())
+    {
+        return Some(now + ChronoDuration::seconds(expires_in));
+    }
+    token
+        .expires_on
+        .as_deref()
+        .and_then(|value| value.parse::<i64>().ok())
+        .and_then(|expires_on| DateTime::from_timestamp(expires_on, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_service::token_cache::REFRESH_MARGIN_SECS;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn token_response(access_token: &str, expires_in: i64) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": access_token,
+            "expires_in": expires_in.to_string(),
+            "expires_on": (Utc::now().timestamp() + expires_in).to_string(),
+            "resource": "https://monitor.example",
+            "token_type": "Bearer"
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_token_is_cached() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(IMDS_TOKEN_PATH))
+            .and(header("Metadata", "true"))
+            .and(query_param("api-version", IMDS_API_VERSION))
+            .and(query_param("resource", "https://monitor.example"))
+            .respond_with(token_response("token", 3600))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let credential = ManagedIdentityCredential::new(
+            &ManagedIdentity::SystemAssigned,
+            "https://monitor.example",
+            &mock_server.uri(),
+        )
+        .unwrap();
+        assert_eq!(credential.get_token().await.unwrap(), "token");
+        assert_eq!(credential.get_token().await.unwrap(), "token");
+    }
+
+    #[tokio::test]
+    async fn test_token_is_refreshed_before_expiry() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(IMDS_TOKEN_PATH))
+            .respond_with(token_response("token", REFRESH_MARGIN_SECS - 60))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let credential = ManagedIdentityCredential::new(
+            &ManagedIdentity::SystemAssigned,
+            "https://monitor.example",
+            &mock_server.uri(),
+        )
+        .unwrap();
+        credential.get_token().await.unwrap();
+        credential.get_token().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_user_assigned_identities() {
+        let mock_server = MockServer::start().await;
+        for (name, value) in [
+            ("client_id", "client"),
+            ("object_id", "object"),
+            ("msi_res_id", "/subscriptions/s/resourcegroups/g"),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(IMDS_TOKEN_PATH))
+                .and(query_param(name, value))
+                .respond_with(token_response(name, 3600))
+                .mount(&mock_server)
+                .await;
+        }
+
+        for (identity, expected) in [
+            (ManagedIdentity::ClientId("client".into()), "client_id"),
+            (ManagedIdentity::ObjectId("object".into()), "object_id"),
+            (
+                ManagedIdentity::ResourceId("/subscriptions/s/resourcegroups/g".into()),
+                "msi_res_id",
+            ),
+        ] {
+            let credential = ManagedIdentityCredential::new(
+                &identity,
+                "https://monitor.example",
+                &mock_server.uri(),
+            )
+            .unwrap();
+            assert_eq!(credential.get_token().await.unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_imds_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("Identity not found"))
+            .mount(&mock_server)
+            .await;
+
+        let credential = ManagedIdentityCredential::new(
+            &ManagedIdentity::ClientId("unknown".into()),
+            "https://monitor.example",
+            &mock_server.uri(),
+        )
+        .unwrap();
+        let error = credential.get_token().await.unwrap_err();
+        assert!(
+            matches!(&error, GenevaConfigClientError::ManagedIdentity(message) if message.contains("400")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_token_expiry() {
+        let now = Utc::now();
+        let token = TokenResponse {
+            access_token: String::new(),
+            expires_in: Some("3599".into()),
+            expires_on: Some("1".into()),
+        };
+        assert_eq!(
+            token_expiry(&token, now),
+            Some(now + ChronoDuration::seconds(3599))
+        );
+
+        let token = TokenResponse {
+            access_token: String::new(),
+            expires_in: None,
+            expires_on: Some("1700000000".into()),
+        };
+        assert_eq!(
+            token_expiry(&token, now),
+            DateTime::from_timestamp(1_700_000_000, 0)
+        );
+    }
+}
@@ -1,14 +1,18 @@
 pub(crate) mod client;
+pub(crate) mod managed_identity;
+pub(crate) mod token_cache;
+pub(crate) mod workload_identity;
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::config_service::client::{AuthMethod, GenevaConfigClient, GenevaConfigClientConfig};
+    use crate::config_service::managed_identity::ManagedIdentity;
     use openssl::{pkcs12::Pkcs12, pkey::PKey, x509::X509};
     use rcgen::generate_simple_self_signed;
     use std::io::Write;
     use std::path::PathBuf;
     use tempfile::NamedTempFile;
-    use wiremock::matchers::{method, path};
+    use wiremock::matchers::{header, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[test]
@@ -20,15 +24,40 @@ mod tests {
             namespace: "ns".to_string(),
             region: "region".to_string(),
             config_major_version: 1,
-            auth_method: AuthMethod::ManagedIdentity,
+            auth_method: AuthMethod::ManagedIdentity {
+                identity: ManagedIdentity::SystemAssigned,
+                resource: "https://monitor.example".to_string(),
+                imds_endpoint: None,
+            },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
         };
 
         assert_eq!(config.environment, "env");
         assert_eq!(config.account, "acct");
-        assert!(matches!(config.auth_method, AuthMethod::ManagedIdentity));
+        assert!(matches!(
+            config.auth_method,
+            AuthMethod::ManagedIdentity { .. }
+        ));
+    }
+
+    #[test]
+    fn test_auth_method_debug_redacts_password() {
+        let auth_method = AuthMethod::Certificate {
+            path: PathBuf::from("/path/to/cert.p12"),
+            password: "s3cr3t".to_string(),
+        };
+        let debug = format!("{:?}", auth_method);
+        assert!(!debug.contains("s3cr3t"), "{}", debug);
+        assert!(debug.contains("/path/to/cert.p12"), "{}", debug);
+        assert!(debug.contains("<redacted>"), "{}", debug);
+
+        // the builders holding the auth method don't print it either
+        let builder = crate::GenevaClient::builder().with_auth_method(auth_method);
+        assert!(!format!("{:?}", builder).contains("s3cr3t"));
     }
 
-    fn generate_self_signed_p12() -> (NamedTempFile, String) {
+    pub(crate) fn generate_self_signed_p12() -> (NamedTempFile, String) {
         let password = "test".to_string();
 
         // This returns a CertifiedKey, not a Certificate
@@ -106,6 +135,8 @@ mod tests {
                 path: PathBuf::from(temp_p12_file.path().to_string_lossy().to_string()),
                 password,
             },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
         };
 
         let client = GenevaConfigClient::new(config).unwrap();
@@ -125,6 +156,134 @@ mod tests {
         assert_eq!(token_endpoint, jwt_endpoint);
     }
 
+    #[tokio::test]
+    async fn test_get_ingestion_info_managed_identity() {
+        let mock_server = MockServer::start().await;
+        let valid_token = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.eyJFbmRwb2ludCI6Imh0dHBzOi8vdGVzdC5lbmRwb2ludCJ9.signature";
+
+        Mock::given(method("GET"))
+            .and(path("/metadata/identity/oauth2/token"))
+            .and(header("Metadata", "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "mi-token",
+                "expires_in": "3600",
+                "resource": "https://monitor.example",
+                "token_type": "Bearer"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/api/agent/v3/mockenv/mockacct/MonitoringStorageKeys/",
+            ))
+            .and(header("authorization", "Bearer mi-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "IngestionGatewayInfo": {
+                    "Endpoint": "https://mock.ingestion.endpoint",
+                    "AuthToken": valid_token,
+                    "AuthTokenExpiryTime": "2030-01-01T00:00:00Z"
+                },
+                "StorageAccountKeys": [
+                    {
+                        "AccountMonikerName": "mock-diag-moniker",
+                        "AccountGroupName": "mock-diag-group",
+                        "IsPrimaryMoniker": true
+                    }
+                ],
+                "TagId": "mock-tag-id"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config = GenevaConfigClientConfig {
+            endpoint: mock_server.uri(),
+            environment: "mockenv".into(),
+            account: "mockacct".into(),
+            namespace: "mockns".into(),
+            region: "mockregion".into(),
+            config_major_version: 1,
+            auth_method: AuthMethod::ManagedIdentity {
+                identity: ManagedIdentity::SystemAssigned,
+                resource: "https://monitor.example".into(),
+                imds_endpoint: Some(mock_server.uri()),
+            },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
+        };
+
+        let client = GenevaConfigClient::new(config).unwrap();
+        let (ingestion_info, moniker_info, _) = client.get_ingestion_info().await.unwrap();
+        assert_eq!(ingestion_info.endpoint, "https://mock.ingestion.endpoint");
+        assert_eq!(moniker_info.name, "mock-diag-moniker");
+    }
+
+    #[tokio::test]
+    async fn test_get_ingestion_info_workload_identity() {
+        let mock_server = MockServer::start().await;
+        let valid_token = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.eyJFbmRwb2ludCI6Imh0dHBzOi8vdGVzdC5lbmRwb2ludCJ9.signature";
+
+        Mock::given(method("POST"))
+            .and(path("/mocktenant/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "aad-token",
+                "expires_in": 3600,
+                "token_type": "Bearer"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/api/agent/v3/mockenv/mockacct/MonitoringStorageKeys/",
+            ))
+            .and(header("authorization", "Bearer aad-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "IngestionGatewayInfo": {
+                    "Endpoint": "https://mock.ingestion.endpoint",
+                    "AuthToken": valid_token,
+                    "AuthTokenExpiryTime": "2030-01-01T00:00:00Z"
+                },
+                "StorageAccountKeys": [
+                    {
+                        "AccountMonikerName": "mock-diag-moniker",
+                        "AccountGroupName": "mock-diag-group",
+                        "IsPrimaryMoniker": true
+                    }
+                ],
+                "TagId": "mock-tag-id"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut token_file = NamedTempFile::new().unwrap();
+        token_file.write_all(b"federated-token").unwrap();
+        let config = GenevaConfigClientConfig {
+            endpoint: mock_server.uri(),
+            environment: "mockenv".into(),
+            account: "mockacct".into(),
+            namespace: "mockns".into(),
+            region: "mockregion".into(),
+            config_major_version: 1,
+            auth_method: AuthMethod::WorkloadIdentity {
+                resource: "https://monitor.example".into(),
+                client_id: Some("mockclient".into()),
+                tenant_id: Some("mocktenant".into()),
+                token_file: Some(token_file.path().to_path_buf()),
+                authority_host: Some(mock_server.uri()),
+            },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
+        };
+
+        let client = GenevaConfigClient::new(config).unwrap();
+        let (ingestion_info, moniker_info, _) = client.get_ingestion_info().await.unwrap();
+        assert_eq!(ingestion_info.endpoint, "https://mock.ingestion.endpoint");
+        assert_eq!(moniker_info.name, "mock-diag-moniker");
+    }
+
     #[cfg_attr(target_os = "macos", ignore)] // cert generated not compatible with macOS
     #[tokio::test]
     async fn test_error_handling_with_non_success_status() {
@@ -151,6 +310,8 @@ mod tests {
                 path: PathBuf::from(temp_p12_file.path().to_string_lossy().to_string()),
                 password,
             },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
         };
 
         let client = GenevaConfigClient::new(config).unwrap();
@@ -199,6 +360,8 @@ mod tests {
                 path: PathBuf::from(temp_p12_file.path().to_string_lossy().to_string()),
                 password,
             },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
         };
 
         let client = GenevaConfigClient::new(config).unwrap();
@@ -230,6 +393,8 @@ mod tests {
                 path: PathBuf::from("/nonexistent/path.p12".to_string()),
                 password: "test".to_string(),
             },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
         };
 
         let result = GenevaConfigClient::new(config);
@@ -293,6 +458,8 @@ mod tests {
                 path: PathBuf::from(cert_path),
                 password: cert_password,
             },
+            agent_identity: "GenevaUploader".to_string(),
+            agent_version: "0.1".to_string(),
         };
 
         println!("Connecting to real Geneva Config service...");
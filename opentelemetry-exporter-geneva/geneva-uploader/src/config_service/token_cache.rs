@@ -0,0 +1,51 @@
+// Cache of the access tokens of the credentials sending bearer tokens
+
+use crate::config_service::client::{GenevaConfigClientError, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use std::sync::RwLock;
+
+/// Tokens are refreshed this long before they expire, so that requests never carry a token
+/// about to expire.
+pub(crate) const REFRESH_MARGIN_SECS: i64 = 300;
+
+#[derive(Debug, Clone)]
+pub(crate) struct CachedToken {
+    pub(crate) access_token: String,
+    pub(crate) expiry: DateTime<Utc>,
+}
+
+/// Cache of an access token, shared by the tasks using a credential.
+#[derive(Debug, Default)]
+pub(crate) struct TokenCache {
+    cached_token: RwLock<Option<CachedToken>>,
+}
+
+impl TokenCache {
+    /// Returns the cached token if it is valid for at least the refresh margin.
+    pub(crate) fn get(&self) -> Option<String> {
+        let guard = self.cached_token.read().ok()?;
+        guard
+            .as_ref()
+            .filter(|cached| {
+                cached.expiry > Utc::now() + ChronoDuration::seconds(REFRESH_MARGIN_SECS)
+            })
+            .map(|cached| cached.access_token.clone())
+    }
+
+    /// Caches a fresh token, unless another task cached one lasting longer, and returns the
+    /// cached token.
+    pub(crate) fn update(&self, fresh: CachedToken) -> Result<String> {
+        let mut guard = self
+            .cached_token
+            .write()
+            .map_err(|_| GenevaConfigClientError::InternalError("RwLock poisoned".to_string()))?;
+        if let Some(existing) = guard.as_ref() {
+            if existing.expiry >= fresh.expiry {
+                return Ok(existing.access_token.clone());
+            }
+        }
+        let access_token = fresh.access_token.clone();
+        *guard = Some(fresh);
+        Ok(access_token)
+    }
+}
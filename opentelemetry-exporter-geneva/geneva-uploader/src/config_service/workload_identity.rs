@@ -0,0 +1,326 @@
+// Azure Workload Identity tokens: a federated service account token exchanged for an AAD token
+
+use crate::config_service::client::{GenevaConfigClientError, Result};
+use crate::config_service::token_cache::{CachedToken, TokenCache};
+use chrono::{Duration as ChronoDuration, Utc};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+use url::form_urlencoded;
+
+/// Environment variables set by the Azure Workload Identity webhook in the pods.
+const AZURE_CLIENT_ID: &str = "AZURE_CLIENT_ID";
+const AZURE_TENANT_ID: &str = "AZURE_TENANT_ID";
+const AZURE_FEDERATED_TOKEN_FILE: &str = "AZURE_FEDERATED_TOKEN_FILE";
+const AZURE_AUTHORITY_HOST: &str = "AZURE_AUTHORITY_HOST";
+const DEFAULT_AUTHORITY_HOST: &str = "https://login.microsoftonline.com/";
+const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Exchanges the federated token of the pod for AAD tokens, and caches them until shortly before
+/// they expire.
+///
+/// The token file is read again for every exchange, as the kubelet rotates it before it expires.
+#[derive(Debug)]
+pub(crate) struct WorkloadIdentityCredential {
+    http_client: Client,
+    token_url: String,
+    client_id: String,
+    scope: String,
+    token_file: PathBuf,
+    cache: TokenCache,
+}
+
+impl WorkloadIdentityCredential {
+    /// Creates a credential acquiring tokens for `resource`. The settings that are `None` are
+    /// read from the environment variables set by the Workload Identity webhook.
+    pub(crate) fn new(
+        resource: &str,
+        client_id: Option<&str>,
+        tenant_id: Option<&str>,
+        token_file: Option<&PathBuf>,
+        authority_host: Option<&str>,
+    ) -> Result<Self> {
+        let client_id = setting_or_env(client_id, AZURE_CLIENT_ID)?;
+        let tenant_id = setting_or_env(tenant_id, AZURE_TENANT_ID)?;
+        // The tenant id is spliced into the path of the token URL
+        if !is_valid_tenant_id(&tenant_id) {
+            return Err(GenevaConfigClientError::WorkloadIdentity(format!(
+                "Invalid tenant id {tenant_id:?}: expected a GUID or a domain name"
+            )));
+        }
+        let token_file = match token_file {
+            Some(token_file) => token_file.clone(),
+            None => PathBuf::from(setting_or_env(None, AZURE_FEDERATED_TOKEN_FILE)?),
+        };
+        let authority_host = match authority_host {
+            Some(authority_host) => authority_host.to_string(),
+            None => std::env::var(AZURE_AUTHORITY_HOST)
+                .unwrap_or_else(|_| DEFAULT_AUTHORITY_HOST.to_string()),
+        };
+
+        // The trailing slash keeps the path of the authority host when joining
+        let token_url = url::Url::parse(&format!("{}/", authority_host.trim_end_matches('/')))
+            .and_then(|url| url.join(&format!("{}/oauth2/v2.0/token", tenant_id)))
+            .map_err(|e| {
+                GenevaConfigClientError::WorkloadIdentity(format!("Invalid authority host: {e}"))
+            })?;
+
+        let http_client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
+
+        Ok(Self {
+            http_client,
+            token_url: token_url.into(),
+            client_id,
+            scope: format!("{}/.default", resource.trim_end_matches('/')),
+            token_file,
+            cache: TokenCache::default(),
+        })
+    }
+
+    /// Returns a token valid for at least the refresh margin, from the cache or from AAD.
+    pub(crate) async fn get_token(&self) -> Result<String> {
+        match self.cache.get() {
+            Some(token) => Ok(token),
+            None => self.cache.update(self.fetch_token().await?),
+        }
+    }
+
+    async fn fetch_token(&self) -> Result<CachedToken> {
+        let assertion = tokio::fs::read_to_string(&self.token_file)
+            .await
+            .map_err(|e| {
+                GenevaConfigClientError::WorkloadIdentity(format!(
+                    "Failed to read the federated token file {}: {e}",
+                    self.token_file.display()
+                ))
+            })?;
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("client_id", &self.client_id)
+            .append_pair("scope", &self.scope)
+            .append_pair("client_assertion_type", CLIENT_ASSERTION_TYPE)
+            .append_pair("client_assertion", assertion.trim())
+            .append_pair("grant_type", "client_credentials")
+            .finish();
+
+        let now = Utc::now();
+        let response = self
+            .http_client
+            .post(&self.token_url)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| GenevaConfigClientError::Http(e.into()))?;
+        if !status.is_success() {
+            return Err(GenevaConfigClientError::WorkloadIdentity(format!(
+                "Token request failed with status {}: {}",
+                status.as_u16(),
+                body
+            )));
+        }
+
+        let token: TokenResponse = serde_json::from_str(&body).map_err(|e| {
+            GenevaConfigClientError::WorkloadIdentity(format!("Invalid token response: {e}"))
+        })?;
+        Ok(CachedToken {
+            access_token: token.access_token,
+            expiry: now + ChronoDuration::seconds(token.expires_in as i64),
+        })
+    }
+}
+
+fn setting_or_env(setting: Option<&str>, variable: &str) -> Result<String> {
+    match setting {
+        Some(setting) => Ok(setting.to_string()),
+        None => std::env::var(variable).map_err(|_| {
+            GenevaConfigClientError::WorkloadIdentity(format!("{variable} is not set"))
+        }),
+    }
+}
+
+/// Whether `tenant_id` is a GUID or a domain name, the two forms AAD accepts for a tenant.
+fn is_valid_tenant_id(tenant_id: &str) -> bool {
+    let is_guid = tenant_id.len() == 36
+        && tenant_id.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    let is_domain = tenant_id.len() <= 253
+        && tenant_id.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    is_guid || is_domain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_service::token_cache::REFRESH_MARGIN_SECS;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn token_file(content: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn credential(
+        mock_server: &MockServer,
+        token_file: &NamedTempFile,
+    ) -> WorkloadIdentityCredential {
+        WorkloadIdentityCredential::new(
+            "https://monitor.example/",
+            Some("client"),
+            Some("contoso.onmicrosoft.com"),
+            Some(&token_file.path().to_path_buf()),
+            Some(&mock_server.uri()),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_token_exchange_is_cached() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/contoso.onmicrosoft.com/oauth2/v2.0/token"))
+            .and(body_string_contains("client_id=client"))
+            .and(body_string_contains(
+                "scope=https%3A%2F%2Fmonitor.example%2F.default",
+            ))
+            .and(body_string_contains("client_assertion=federated"))
+            .and(body_string_contains("grant_type=client_credentials"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "aad-token",
+                "expires_in": 3600,
+                "token_type": "Bearer"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let token_file = token_file("federated\n");
+        let credential = credential(&mock_server, &token_file);
+        assert_eq!(credential.get_token().await.unwrap(), "aad-token");
+        assert_eq!(credential.get_token().await.unwrap(), "aad-token");
+    }
+
+    #[tokio::test]
+    async fn test_token_file_is_read_again_on_refresh() {
+        let mock_server = MockServer::start().await;
+        for (assertion, access_token) in [("first", "token-1"), ("rotated", "token-2")] {
+            Mock::given(method("POST"))
+                .and(body_string_contains(format!(
+                    "client_assertion={assertion}"
+                )))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "access_token": access_token,
+                    // expires within the refresh margin, so every call exchanges a new token
+                    "expires_in": REFRESH_MARGIN_SECS - 60,
+                })))
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let token_file = token_file("first");
+        let credential = credential(&mock_server, &token_file);
+        assert_eq!(credential.get_token().await.unwrap(), "token-1");
+        std::fs::write(token_file.path(), "rotated").unwrap();
+        assert_eq!(credential.get_token().await.unwrap(), "token-2");
+    }
+
+    #[tokio::test]
+    async fn test_token_exchange_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("AADSTS70021"))
+            .mount(&mock_server)
+            .await;
+
+        let token_file = token_file("federated");
+        let error = credential(&mock_server, &token_file)
+            .get_token()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, GenevaConfigClientError::WorkloadIdentity(message) if message.contains("401")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_token_file() {
+        let mock_server = MockServer::start().await;
+        let credential = WorkloadIdentityCredential::new(
+            "https://monitor.example",
+            Some("client"),
+            Some("contoso.onmicrosoft.com"),
+            Some(&PathBuf::from("/nonexistent/token")),
+            Some(&mock_server.uri()),
+        )
+        .unwrap();
+        let error = credential.get_token().await.unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaConfigClientError::WorkloadIdentity(_)
+        ));
+    }
+
+    #[test]
+    fn test_tenant_id_validation() {
+        for tenant_id in [
+            "72f988bf-86f1-41af-91ab-2d7cd011db47",
+            "contoso.onmicrosoft.com",
+            "contoso",
+        ] {
+            assert!(is_valid_tenant_id(tenant_id), "{tenant_id}");
+        }
+        for tenant_id in [
+            "",
+            "../common",
+            "tenant/oauth2",
+            "tenant?x=1",
+            "contoso..com",
+            "-contoso.com",
+        ] {
+            assert!(!is_valid_tenant_id(tenant_id), "{tenant_id}");
+            let error = WorkloadIdentityCredential::new(
+                "https://monitor.example",
+                Some("client"),
+                Some(tenant_id),
+                Some(&PathBuf::from("/token")),
+                None,
+            )
+            .unwrap_err();
+            assert!(matches!(
+                error,
+                GenevaConfigClientError::WorkloadIdentity(_)
+            ));
+        }
+    }
+}
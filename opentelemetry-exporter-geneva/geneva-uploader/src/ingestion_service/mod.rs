@@ -5,10 +5,13 @@ mod tests {
     use std::time::Instant;
 
     mod test_helpers {
-        use crate::{
-            AuthMethod, GenevaConfigClient, GenevaConfigClientConfig, GenevaUploader,
-            GenevaUploaderConfig,
+        use crate::config_service::client::{
+            AuthMethod, GenevaConfigClient, GenevaConfigClientConfig,
         };
+        use crate::ingestion_service::uploader::{
+            BatchMetadata, GenevaUploader, GenevaUploaderConfig,
+        };
+        use chrono::{Duration, Utc};
         use std::env;
         use std::fs;
         use std::sync::Arc;
@@ -18,6 +21,7 @@ mod tests {
             pub uploader: GenevaUploader,
             pub event_name: String,
             pub event_version: String,
+            pub metadata: BatchMetadata,
         }
 
         pub async fn build_test_upload_context() -> TestUploadContext {
@@ -51,8 +55,12 @@ mod tests {
             let uploader_config = GenevaUploaderConfig {
                 namespace: namespace.clone(),
                 source_identity,
-                environment: environment.clone(),
+            };
+            let now = Utc::now();
+            let metadata = BatchMetadata {
                 schema_ids,
+                start_time: now,
+                end_time: now + Duration::minutes(5),
             };
 
             let config = GenevaConfigClientConfig {
@@ -66,6 +74,8 @@ mod tests {
                     path: cert_path,
                     password: cert_password,
                 },
+                agent_identity: "GenevaUploader".to_string(),
+                agent_version: "0.1".to_string(),
             };
 
             // Build client and uploader
@@ -73,7 +83,6 @@ mod tests {
                 GenevaConfigClient::new(config).expect("Failed to create config client");
             let uploader =
                 GenevaUploader::from_config_client(Arc::new(config_client), uploader_config)
-                    .await
                     .expect("Failed to create uploader");
 
             // Event name/version
@@ -85,6 +94,7 @@ mod tests {
                 uploader,
                 event_name,
                 event_version,
+                metadata,
             }
         }
     }
@@ -122,7 +132,7 @@ mod tests {
         let start = Instant::now();
         let response = ctx
             .uploader
-            .upload(ctx.data, &ctx.event_name, &ctx.event_version)
+            .upload(ctx.data, &ctx.event_name, &ctx.event_version, &ctx.metadata)
             .await
             .expect("Upload failed");
 
@@ -180,7 +190,12 @@ mod tests {
         let start_warmup = Instant::now();
         let _ = ctx
             .uploader
-            .upload(ctx.data.clone(), &ctx.event_name, &ctx.event_version)
+            .upload(
+                ctx.data.clone(),
+                &ctx.event_name,
+                &ctx.event_version,
+                &ctx.metadata,
+            )
             .await
             .expect("Warm-up upload failed");
         println!(
@@ -198,11 +213,12 @@ mod tests {
             let data = ctx.data.clone();
             let event_name = ctx.event_name.to_string();
             let event_version = ctx.event_version.to_string();
+            let metadata = ctx.metadata.clone();
 
             let handle = tokio::spawn(async move {
                 let start = Instant::now();
                 let resp = uploader
-                    .upload(data, &event_name, &event_version)
+                    .upload(data, &event_name, &event_version, &metadata)
                     .await
                     .unwrap_or_else(|_| panic!("Upload {} failed", i));
                 let elapsed = start.elapsed();
@@ -1,11 +1,10 @@
-use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use chrono::{DateTime, Utc};
 
 use crate::config_service::client::{GenevaConfigClient, GenevaConfigClientError};
 use chrono::{Datelike, Timelike};
 use reqwest::{header, Client};
 use serde::Deserialize;
-use serde_json::Value;
-use std::collections::HashMap;
+use std::error::Error as StdError;
 use std::fmt::Write;
 use std::sync::Arc;
 use std::time::Duration;
@@ -13,39 +12,41 @@ use thiserror::Error;
 use url::form_urlencoded::byte_serialize;
 use uuid::Uuid;
 
-/// Error types for the Geneva Uploader
+/// Errors of the uploads to the Geneva Ingestion Gateway.
 #[derive(Debug, Error)]
-pub(crate) enum GenevaUploaderError {
+#[non_exhaustive]
+pub enum GenevaUploaderError {
+    /// The HTTP client couldn't be created, or the upload request couldn't be sent or its
+    /// response read
     #[error("HTTP error: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(#[source] Box<dyn StdError + Send + Sync>),
+    /// The response of an accepted upload isn't the expected JSON
     #[error("JSON error: {0}")]
-    SerdeJson(#[from] serde_json::Error),
+    SerdeJson(#[source] Box<dyn StdError + Send + Sync>),
+    /// The ingestion gateway and its token couldn't be retrieved from the config service
     #[error("Config service error: {0}")]
-    ConfigClient(String),
-    #[allow(dead_code)]
+    ConfigClient(#[from] GenevaConfigClientError),
+    /// The ingestion gateway rejected the upload
     #[error("Upload failed with status {status}: {message}")]
-    UploadFailed { status: u16, message: String },
-    #[allow(dead_code)]
+    UploadFailed {
+        /// HTTP status of the response
+        status: u16,
+        /// Body of the response
+        message: String,
+    },
+    /// The upload request couldn't be built
     #[error("Internal error: {0}")]
     InternalError(String),
 }
 
-impl From<GenevaConfigClientError> for GenevaUploaderError {
-    fn from(err: GenevaConfigClientError) -> Self {
-        // This preserves the original error message format from the code
-        GenevaUploaderError::ConfigClient(format!("GenevaConfigClient error: {}", err))
-    }
-}
-
 pub(crate) type Result<T> = std::result::Result<T, GenevaUploaderError>;
 
-#[allow(dead_code)]
 /// Response from the ingestion API when submitting data
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct IngestionResponse {
+    // Not reported by the client yet, only printed by the tests
+    #[cfg_attr(not(test), allow(dead_code))]
     pub(crate) ticket: String,
-    #[serde(flatten)]
-    pub(crate) extra: HashMap<String, Value>,
 }
 
 /// Configuration for the Geneva Uploader
@@ -53,9 +54,16 @@ pub(crate) struct IngestionResponse {
 pub(crate) struct GenevaUploaderConfig {
     pub namespace: String,
     pub source_identity: String,
-    #[allow(dead_code)]
-    pub environment: String,
-    pub schema_ids: String,
+}
+
+/// Description of an encoded batch, sent along with its data in the upload URI
+#[derive(Debug, Clone)]
+pub(crate) struct BatchMetadata {
+    /// MD5 of the schemas of the batch in hex, separated by `;`
+    pub(crate) schema_ids: String,
+    /// Earliest and latest timestamps of the records of the batch
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) end_time: DateTime<Utc>,
 }
 
 /// Client for uploading data to Geneva Ingestion Gateway (GIG)
@@ -75,8 +83,7 @@ impl GenevaUploader {
     ///
     /// # Returns
     /// * `Result<GenevaUploader>` with authenticated client and resolved moniker/endpoint
-    #[allow(dead_code)]
-    pub(crate) async fn from_config_client(
+    pub(crate) fn from_config_client(
         config_client: Arc<GenevaConfigClient>,
         uploader_config: GenevaUploaderConfig,
     ) -> Result<Self> {
@@ -88,7 +95,8 @@ impl GenevaUploader {
         let http_client = Client::builder()
             .timeout(Duration::from_secs(30))
             .default_headers(headers)
-            .build()?;
+            .build()
+            .map_err(|e| GenevaUploaderError::Http(e.into()))?;
 
         Ok(Self {
             config_client,
@@ -98,7 +106,6 @@ impl GenevaUploader {
     }
 
     /// Creates the GIG upload URI with required parameters
-    #[allow(dead_code)]
     fn create_upload_uri(
         &self,
         monitoring_endpoint: &str,
@@ -106,34 +113,10 @@ impl GenevaUploader {
         data_size: usize,
         event_name: &str,
         event_version: &str,
+        metadata: &BatchMetadata,
     ) -> Result<String> {
-        let now: DateTime<Utc> = Utc::now(); //TODO - this need to be calculated from the bond data
-        let end_time = now + ChronoDuration::minutes(5); //TODO - this need to be calculated from the bond data
-
-        // Format times in ISO 8601 format with fixed precision
-        // Using .NET compatible format (matches DateTime.ToString("O"))
-
-        let start_time = format!(
-            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}Z",
-            now.year(),
-            now.month(),
-            now.day(),
-            now.hour(),
-            now.minute(),
-            now.second(),
-            now.nanosecond() / 100 // Convert nanoseconds to 7-digit precision
-        );
-
-        let end_time = format!(
-            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}Z",
-            end_time.year(),
-            end_time.month(),
-            end_time.day(),
-            end_time.hour(),
-            end_time.minute(),
-            end_time.second(),
-            end_time.nanosecond() / 100 // Convert nanoseconds to 7-digit precision
-        );
+        let start_time = format_time(metadata.start_time);
+        let end_time = format_time(metadata.end_time);
 
         // URL encode parameters
         // TODO - Maintain this as url-encoded in config service to avoid conversion here
@@ -160,7 +143,7 @@ impl GenevaUploader {
             end_time,
             data_size,
             2,
-            self.config.schema_ids
+            metadata.schema_ids
         ).map_err(|e| GenevaUploaderError::InternalError(format!("Failed to write query string: {e}")))?;
         Ok(query)
     }
@@ -169,15 +152,18 @@ impl GenevaUploader {
     ///
     /// # Arguments
     /// * `data` - The encoded data to upload (already in the required format)
+    /// * `event_name` - Name of the event of the records of `data`
+    /// * `event_version` - Version of the event
+    /// * `metadata` - Schemas and time range of the records of `data`
     ///
     /// # Returns
     /// * `Result<IngestionResponse>` - The response containing the ticket ID or an error
-    #[allow(dead_code)]
     pub(crate) async fn upload(
         &self,
         data: Vec<u8>,
         event_name: &str,
         event_version: &str,
+        metadata: &BatchMetadata,
     ) -> Result<IngestionResponse> {
         // Always get fresh auth info
         let (auth_info, moniker_info, monitoring_endpoint) =
@@ -189,6 +175,7 @@ impl GenevaUploader {
             data_size,
             event_name,
             event_version,
+            metadata,
         )?;
         let full_url = format!(
             "{}/{}",
@@ -207,14 +194,17 @@ impl GenevaUploader {
             .body(data)
             .send()
             .await
-            .map_err(GenevaUploaderError::Http)?;
+            .map_err(|e| GenevaUploaderError::Http(e.into()))?;
 
         let status = response.status();
-        let body = response.text().await.map_err(GenevaUploaderError::Http)?;
+        let body = response
+            .text()
+            .await
+            .map_err(|e| GenevaUploaderError::Http(e.into()))?;
 
         if status == reqwest::StatusCode::ACCEPTED {
-            let ingest_response: IngestionResponse =
-                serde_json::from_str(&body).map_err(GenevaUploaderError::SerdeJson)?;
+            let ingest_response: IngestionResponse = serde_json::from_str(&body)
+                .map_err(|e| GenevaUploaderError::SerdeJson(e.into()))?;
             Ok(ingest_response)
         } else {
             Err(GenevaUploaderError::UploadFailed {
@@ -224,3 +214,18 @@ impl GenevaUploader {
         }
     }
 }
+
+/// Formats a time in ISO 8601 with a fixed precision of 7 digits, as .NET does with
+/// `DateTime.ToString("O")`.
+fn format_time(time: DateTime<Utc>) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}Z",
+        time.year(),
+        time.month(),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.nanosecond() / 100 // Convert nanoseconds to 7-digit precision
+    )
+}
@@ -1,18 +1,10 @@
+mod client;
 mod config_service;
 pub mod ingestion_service;
 mod payload_encoder;
 
-mod uploader;
-
-#[allow(unused_imports)]
-pub(crate) use config_service::client::{
-    AuthMethod, GenevaConfigClient, GenevaConfigClientConfig, GenevaConfigClientError,
-    IngestionGatewayInfo,
-};
-
-#[allow(unused_imports)]
-pub(crate) use ingestion_service::uploader::{
-    GenevaUploader, GenevaUploaderConfig, GenevaUploaderError, IngestionResponse, Result,
-};
-
-pub use uploader::{create_uploader, GenevaUploader as Uploader};
+pub use client::{GenevaClient, GenevaClientBuilder, GenevaClientError};
+pub use config_service::client::{AuthMethod, GenevaConfigClientError};
+pub use config_service::managed_identity::ManagedIdentity;
+pub use ingestion_service::uploader::GenevaUploaderError;
+pub use payload_encoder::EncoderError;
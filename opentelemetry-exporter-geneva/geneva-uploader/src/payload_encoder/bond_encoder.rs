@@ -0,0 +1,231 @@
+//! Minimal Bond serialization for the CentralBond payloads.
+//!
+//! Records are written with the Bond *Simple Binary* protocol (version 1): the fields of a
+//! struct are written one after another in the order of its schema, without field headers, so a
+//! record can only be read along with its schema. The schema itself is a Bond `SchemaDef`
+//! marshalled with the same protocol, and is shipped in the blob next to the records.
+//!
+//! Simple Binary v1 encoding:
+//! - `bool`, integers and floating points: fixed size, little-endian
+//! - `string`: `u32` byte length followed by the UTF-8 bytes
+//! - `wstring`: `u32` length in UTF-16 code units followed by the UTF-16LE code units
+//! - `list`, `vector`, `nullable`: `u32` element count followed by the elements
+//! - `map`: `u32` entry count followed by the keys and values
+
+/// Bond data types written by the encoder, with their ids in `bond.bond`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub(crate) enum BondDataType {
+    Bool = 2,
+    Double = 8,
+    String = 9,
+    Struct = 10,
+    Int32 = 16,
+    Int64 = 17,
+}
+
+/// Marshalling header of Simple Binary payloads: the `SP` magic and the protocol version.
+const SIMPLE_PROTOCOL_MAGIC: u16 = 0x5053;
+const SIMPLE_PROTOCOL_VERSION: u16 = 1;
+
+/// Writes values with the Simple Binary protocol.
+pub(crate) struct BondWriter;
+
+impl BondWriter {
+    pub(crate) fn write_bool(buffer: &mut Vec<u8>, value: bool) {
+        buffer.push(value as u8);
+    }
+
+    pub(crate) fn write_u16(buffer: &mut Vec<u8>, value: u16) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_u32(buffer: &mut Vec<u8>, value: u32) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_i32(buffer: &mut Vec<u8>, value: i32) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_i64(buffer: &mut Vec<u8>, value: i64) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_u64(buffer: &mut Vec<u8>, value: u64) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_f64(buffer: &mut Vec<u8>, value: f64) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn write_string(buffer: &mut Vec<u8>, value: &str) {
+        Self::write_u32(buffer, value.len() as u32);
+        buffer.extend_from_slice(value.as_bytes());
+    }
+
+    pub(crate) fn write_wstring(buffer: &mut Vec<u8>, value: &str) {
+        let length_offset = buffer.len();
+        Self::write_u32(buffer, 0);
+        let mut length = 0u32;
+        for unit in value.encode_utf16() {
+            buffer.extend_from_slice(&unit.to_le_bytes());
+            length += 1;
+        }
+        buffer[length_offset..length_offset + 4].copy_from_slice(&length.to_le_bytes());
+    }
+}
+
+/// A field of a record: its name, and the Bond type of its values.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct FieldDef {
+    pub(crate) name: String,
+    pub(crate) type_id: BondDataType,
+}
+
+/// Schema of records made of primitive fields, generated from the fields of the records.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct DynamicSchema {
+    pub(crate) struct_name: String,
+    pub(crate) qualified_name: String,
+    pub(crate) fields: Vec<FieldDef>,
+}
+
+impl DynamicSchema {
+    pub(crate) fn new(struct_name: &str, namespace: &str, fields: Vec<FieldDef>) -> Self {
+        DynamicSchema {
+            struct_name: struct_name.to_string(),
+            qualified_name: format!("{}.{}", namespace, struct_name),
+            fields,
+        }
+    }
+
+    /// Marshals the schema as a Bond `SchemaDef` holding a single struct, with the Simple Binary
+    /// protocol header.
+    ///
+    /// ```text
+    /// SchemaDef { structs: vector<StructDef>, root: TypeDef }
+    /// StructDef { metadata: Metadata, base_def: nullable<TypeDef>, fields: vector<FieldDef> }
+    /// FieldDef  { metadata: Metadata, id: uint16, type: TypeDef }
+    /// TypeDef   { id: BondDataType, struct_def: uint16, element: nullable<TypeDef>,
+    ///             key: nullable<TypeDef>, bonded_type: bool }
+    /// Metadata  { name: string, qualified_name: string, attributes: map<string, string>,
+    ///             modifier: Modifier, default_value: Variant }
+    /// ```
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(64 + self.fields.len() * 64);
+        BondWriter::write_u16(&mut buffer, SIMPLE_PROTOCOL_MAGIC);
+        BondWriter::write_u16(&mut buffer, SIMPLE_PROTOCOL_VERSION);
+
+        // structs
+        BondWriter::write_u32(&mut buffer, 1);
+        write_metadata(&mut buffer, &self.struct_name, &self.qualified_name);
+        // no base struct
+        BondWriter::write_u32(&mut buffer, 0);
+        BondWriter::write_u32(&mut buffer, self.fields.len() as u32);
+        for (id, field) in self.fields.iter().enumerate() {
+            write_metadata(&mut buffer, &field.name, "");
+            BondWriter::write_u16(&mut buffer, id as u16);
+            write_type_def(&mut buffer, field.type_id, 0);
+        }
+
+        // root, the struct at index 0
+        write_type_def(&mut buffer, BondDataType::Struct, 0);
+        buffer
+    }
+}
+
+fn write_metadata(buffer: &mut Vec<u8>, name: &str, qualified_name: &str) {
+    BondWriter::write_string(buffer, name);
+    BondWriter::write_string(buffer, qualified_name);
+    // attributes
+    BondWriter::write_u32(buffer, 0);
+    // modifier: Optional
+    BondWriter::write_i32(buffer, 0);
+    // default_value: Variant { uint_value, int_value, double_value, string_value,
+    // wstring_value, nothing }
+    BondWriter::write_u64(buffer, 0);
+    BondWriter::write_i64(buffer, 0);
+    BondWriter::write_f64(buffer, 0.0);
+    BondWriter::write_string(buffer, "");
+    BondWriter::write_wstring(buffer, "");
+    BondWriter::write_bool(buffer, false);
+}
+
+fn write_type_def(buffer: &mut Vec<u8>, type_id: BondDataType, struct_def: u16) {
+    BondWriter::write_i32(buffer, type_id as i32);
+    BondWriter::write_u16(buffer, struct_def);
+    // element and key, only set for containers
+    BondWriter::write_u32(buffer, 0);
+    BondWriter::write_u32(buffer, 0);
+    // bonded_type
+    BondWriter::write_bool(buffer, false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_strings() {
+        let mut buffer = Vec::new();
+        BondWriter::write_string(&mut buffer, "héllo");
+        assert_eq!(buffer, b"\x06\x00\x00\x00h\xc3\xa9llo");
+
+        let mut buffer = Vec::new();
+        BondWriter::write_wstring(&mut buffer, "hé");
+        assert_eq!(buffer, [2, 0, 0, 0, b'h', 0, 0xe9, 0]);
+    }
+
+    #[test]
+    fn test_encode_schema() {
+        let schema = DynamicSchema::new(
+            "Log",
+            "OpenTelemetry",
+            vec![
+                FieldDef {
+                    name: "body".to_string(),
+                    type_id: BondDataType::String,
+                },
+                FieldDef {
+                    name: "SeverityNumber".to_string(),
+                    type_id: BondDataType::Int32,
+                },
+            ],
+        );
+        let encoded = schema.encode();
+        assert_eq!(&encoded[..4], b"SP\x01\x00");
+        // a single struct named after the event
+        assert_eq!(&encoded[4..8], 1u32.to_le_bytes());
+        assert_eq!(&encoded[8..15], b"\x03\x00\x00\x00Log");
+        assert_eq!(&encoded[15..36], b"\x11\x00\x00\x00OpenTelemetry.Log");
+
+        // metadata: 2 strings + attributes, modifier, variant
+        let metadata_len = |name: &str, qualified_name: &str| {
+            8 + name.len() + qualified_name.len() + 4 + 4 + 8 + 8 + 8 + 4 + 4 + 1
+        };
+        let type_def_len = 4 + 2 + 4 + 4 + 1;
+        let expected_len = 4
+            + 4
+            + metadata_len("Log", "OpenTelemetry.Log")
+            + 4
+            + 4
+            + metadata_len("body", "")
+            + 2
+            + type_def_len
+            + metadata_len("SeverityNumber", "")
+            + 2
+            + type_def_len
+            + type_def_len;
+        assert_eq!(encoded.len(), expected_len);
+        // the root is the struct at index 0
+        assert_eq!(
+            &encoded[encoded.len() - type_def_len..],
+            [10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // the same fields give the same schema
+        assert_eq!(encoded, schema.clone().encode());
+    }
+}
@@ -0,0 +1,178 @@
+//! Layout of the CentralBond blobs uploaded to the Geneva ingestion gateway.
+//!
+//! ```text
+//!  +---------+--------+----------+----------------------+---------------------+
+//!  | version | format | metadata | schema entries ...   | event entries ...   |
+//!  | u32     | u32    | wstring  |                      |                     |
+//!  +---------+--------+----------+----------------------+---------------------+
+//!
+//!  schema entry:
+//!  +------+-----------+---------+---------------------+------------+
+//!  | 0    | schema id | md5     | schema (u32 length) | terminator |
+//!  | u16  | u64       | [u8;16] |                     | u64        |
+//!  +------+-----------+---------+---------------------+------------+
+//!
+//!  event entry:
+//!  +------+-----------+-------+------------------------+------------------+------------+
+//!  | 2    | schema id | level | event name (u16 bytes) | row (u32 length) | terminator |
+//!  | u16  | u64       | u8    | UTF-16LE               |                  | u64        |
+//!  +------+-----------+-------+------------------------+------------------+------------+
+//! ```
+//!
+//! All integers are little-endian. The metadata holds the `key=value/...` identity of the
+//! source, as a `u32` byte length followed by UTF-16LE, and the rows are Bond Simple Binary
+//! records of the schema with the same id.
+
+use crate::payload_encoder::EncoderError;
+
+const BLOB_VERSION: u32 = 1;
+/// Bond payloads
+const BLOB_FORMAT: u32 = 2;
+const SCHEMA_ENTRY_TYPE: u16 = 0;
+const EVENT_ENTRY_TYPE: u16 = 2;
+const ENTRY_TERMINATOR: u64 = 0xDEAD_C0DE_DEAD_C0DE;
+
+/// A schema of the rows of the blob.
+#[derive(Clone, Debug)]
+pub(crate) struct CentralSchemaEntry {
+    pub(crate) id: u64,
+    pub(crate) md5: [u8; 16],
+    pub(crate) schema: Vec<u8>,
+}
+
+/// A row of the blob, with the id of its schema.
+#[derive(Clone, Debug)]
+pub(crate) struct CentralEventEntry {
+    pub(crate) schema_id: u64,
+    pub(crate) level: u8,
+    pub(crate) event_name: String,
+    pub(crate) row: Vec<u8>,
+}
+
+/// A CentralBond blob, before compression.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CentralBlob {
+    pub(crate) metadata: String,
+    pub(crate) schemas: Vec<CentralSchemaEntry>,
+    pub(crate) events: Vec<CentralEventEntry>,
+}
+
+impl CentralBlob {
+    /// Serializes the blob, failing if an event name doesn't fit its `u16` length.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, EncoderError> {
+        let metadata: Vec<u8> = utf16_bytes(&self.metadata);
+        let capacity = 12
+            + metadata.len()
+            + self
+                .schemas
+                .iter()
+                .map(|schema| 38 + schema.schema.len())
+                .sum::<usize>()
+            + self
+                .events
+                .iter()
+                .map(|event| 25 + event.event_name.len() * 2 + event.row.len())
+                .sum::<usize>();
+        let mut buffer = Vec::with_capacity(capacity);
+
+        buffer.extend_from_slice(&BLOB_VERSION.to_le_bytes());
+        buffer.extend_from_slice(&BLOB_FORMAT.to_le_bytes());
+        buffer.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&metadata);
+
+        for schema in &self.schemas {
+            buffer.extend_from_slice(&SCHEMA_ENTRY_TYPE.to_le_bytes());
+            buffer.extend_from_slice(&schema.id.to_le_bytes());
+            buffer.extend_from_slice(&schema.md5);
+            buffer.extend_from_slice(&(schema.schema.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&schema.schema);
+            buffer.extend_from_slice(&ENTRY_TERMINATOR.to_le_bytes());
+        }
+
+        for event in &self.events {
+            let event_name = utf16_bytes(&event.event_name);
+            buffer.extend_from_slice(&EVENT_ENTRY_TYPE.to_le_bytes());
+            buffer.extend_from_slice(&event.schema_id.to_le_bytes());
+            buffer.push(event.level);
+            let event_name_len = u16::try_from(event_name.len())
+                .map_err(|_| EncoderError::EventNameTooLong(event_name.len()))?;
+            buffer.extend_from_slice(&event_name_len.to_le_bytes());
+            buffer.extend_from_slice(&event_name);
+            buffer.extend_from_slice(&(event.row.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&event.row);
+            buffer.extend_from_slice(&ENTRY_TERMINATOR.to_le_bytes());
+        }
+        Ok(buffer)
+    }
+}
+
+fn utf16_bytes(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_layout() {
+        let blob = CentralBlob {
+            metadata: "namespace=ns".to_string(),
+            schemas: vec![CentralSchemaEntry {
+                id: 7,
+                md5: [0xAB; 16],
+                schema: vec![1, 2, 3],
+            }],
+            events: vec![CentralEventEntry {
+                schema_id: 7,
+                level: 4,
+                event_name: "Log".to_string(),
+                row: vec![9, 9],
+            }],
+        };
+        let bytes = blob.to_bytes().unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        expected.extend_from_slice(&24u32.to_le_bytes());
+        expected.extend_from_slice(&utf16_bytes("namespace=ns"));
+        // schema entry
+        expected.extend_from_slice(&0u16.to_le_bytes());
+        expected.extend_from_slice(&7u64.to_le_bytes());
+        expected.extend_from_slice(&[0xAB; 16]);
+        expected.extend_from_slice(&3u32.to_le_bytes());
+        expected.extend_from_slice(&[1, 2, 3]);
+        expected.extend_from_slice(&ENTRY_TERMINATOR.to_le_bytes());
+        // event entry
+        expected.extend_from_slice(&2u16.to_le_bytes());
+        expected.extend_from_slice(&7u64.to_le_bytes());
+        expected.push(4);
+        expected.extend_from_slice(&6u16.to_le_bytes());
+        expected.extend_from_slice(&[b'L', 0, b'o', 0, b'g', 0]);
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        expected.extend_from_slice(&[9, 9]);
+        expected.extend_from_slice(&ENTRY_TERMINATOR.to_le_bytes());
+
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn test_event_name_too_long() {
+        let blob = CentralBlob {
+            metadata: String::new(),
+            schemas: Vec::new(),
+            events: vec![CentralEventEntry {
+                schema_id: 7,
+                level: 4,
+                // 2 bytes per UTF-16 code unit
+                event_name: "e".repeat(32 * 1024),
+                row: Vec::new(),
+            }],
+        };
+        assert!(matches!(
+            blob.to_bytes(),
+            Err(EncoderError::EventNameTooLong(65536))
+        ));
+    }
+}
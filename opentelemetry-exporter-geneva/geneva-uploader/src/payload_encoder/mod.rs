@@ -1 +1,20 @@
+mod bond_encoder;
+mod central_blob;
 mod lz4_chunked_compression;
+pub(crate) mod otlp_encoder;
+
+use std::error::Error as StdError;
+use thiserror::Error;
+
+/// Errors of the encoding of the logs into CentralBond blobs.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum EncoderError {
+    /// The event name of a record, encoded in UTF-16, is longer than the 16 bits length of the
+    /// blob allows; the value is its length in bytes
+    #[error("Event name of {0} bytes is too long, the maximum is 65535 bytes in UTF-16")]
+    EventNameTooLong(usize),
+    /// A blob couldn't be compressed
+    #[error("Compression error: {0}")]
+    Compression(#[source] Box<dyn StdError + Send + Sync>),
+}
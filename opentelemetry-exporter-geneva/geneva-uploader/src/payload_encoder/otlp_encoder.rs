@@ -0,0 +1,511 @@
+use crate::payload_encoder::bond_encoder::{BondDataType, BondWriter, DynamicSchema, FieldDef};
+use crate::payload_encoder::central_blob::{CentralBlob, CentralEventEntry, CentralSchemaEntry};
+use crate::payload_encoder::lz4_chunked_compression::lz4_chunked_compression;
+use crate::payload_encoder::EncoderError;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat};
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::common::v1::AnyValue;
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+/// Event name of the records without `event_name`
+const DEFAULT_EVENT_NAME: &str = "Log";
+const SCHEMA_STRUCT_NAME: &str = "OtlpLogRecord";
+const SCHEMA_NAMESPACE: &str = "OpenTelemetry";
+
+/// Fields written for every record, before the attributes. Attributes with the same names are
+/// dropped.
+const TIMESTAMP_FIELD: &str = "timestamp";
+const TRACE_ID_FIELD: &str = "env_dt_traceId";
+const SPAN_ID_FIELD: &str = "env_dt_spanId";
+const TRACE_FLAGS_FIELD: &str = "env_dt_traceFlags";
+const NAME_FIELD: &str = "name";
+const SEVERITY_NUMBER_FIELD: &str = "SeverityNumber";
+const SEVERITY_TEXT_FIELD: &str = "SeverityText";
+const BODY_FIELD: &str = "body";
+const RESERVED_FIELDS: [&str; 8] = [
+    TIMESTAMP_FIELD,
+    TRACE_ID_FIELD,
+    SPAN_ID_FIELD,
+    TRACE_FLAGS_FIELD,
+    NAME_FIELD,
+    SEVERITY_NUMBER_FIELD,
+    SEVERITY_TEXT_FIELD,
+    BODY_FIELD,
+];
+
+/// The compressed CentralBond blob of the records of one event name, uploaded as is.
+#[derive(Debug)]
+pub(crate) struct EncodedBatch {
+    pub(crate) event_name: String,
+    /// The blob, compressed with [`lz4_chunked_compression`]
+    pub(crate) data: Vec<u8>,
+    /// MD5 of the schemas of the blob in hex, separated by `;`, as expected by the `schemaIds`
+    /// parameter of the upload
+    pub(crate) schema_ids: String,
+    /// Earliest and latest timestamps of the records, in nanoseconds since the Unix epoch
+    pub(crate) start_time_nanos: u64,
+    pub(crate) end_time_nanos: u64,
+    pub(crate) row_count: usize,
+}
+
+/// Encodes OTLP logs into CentralBond blobs.
+///
+/// The schema of each record is generated from its fields: the fixed fields of the log record,
+/// followed by its attributes ordered by name. Records sharing the same fields share the same
+/// schema, identified by the MD5 of the marshalled schema.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OtlpEncoder;
+
+/// Blob of an event name, while encoding.
+#[derive(Default)]
+struct BatchBuilder {
+    blob: CentralBlob,
+    schema_md5s: Vec<[u8; 16]>,
+    start_time_nanos: u64,
+    end_time_nanos: u64,
+}
+
+impl OtlpEncoder {
+    pub(crate) fn new() -> Self {
+        OtlpEncoder
+    }
+
+    /// Encodes the log records, one blob per event name, with the given source metadata.
+    pub(crate) fn encode_log_batch(
+        &self,
+        logs: &[ResourceLogs],
+        metadata: &str,
+    ) -> Result<Vec<EncodedBatch>, EncoderError> {
+        let mut batches: BTreeMap<&str, BatchBuilder> = BTreeMap::new();
+        let records = logs
+            .iter()
+            .flat_map(|resource_logs| &resource_logs.scope_logs)
+            .flat_map(|scope_logs| &scope_logs.log_records);
+        for record in records {
+            let event_name = if record.event_name.is_empty() {
+                DEFAULT_EVENT_NAME
+            } else {
+                record.event_name.as_str()
+            };
+            let batch = batches.entry(event_name).or_insert_with(|| BatchBuilder {
+                blob: CentralBlob {
+                    metadata: metadata.to_string(),
+                    ..Default::default()
+                },
+                start_time_nanos: u64::MAX,
+                ..Default::default()
+            });
+
+            let timestamp = record_timestamp(record);
+            batch.start_time_nanos = batch.start_time_nanos.min(timestamp);
+            batch.end_time_nanos = batch.end_time_nanos.max(timestamp);
+
+            let fields = record_fields(record, event_name, timestamp);
+            let schema = DynamicSchema::new(
+                SCHEMA_STRUCT_NAME,
+                SCHEMA_NAMESPACE,
+                fields.iter().map(|(field, _)| field.clone()).collect(),
+            );
+            let schema_bytes = schema.encode();
+            let md5 = md5::compute(&schema_bytes).0;
+            let schema_id = schema_id(&md5);
+            if !batch.schema_md5s.contains(&md5) {
+                batch.schema_md5s.push(md5);
+                batch.blob.schemas.push(CentralSchemaEntry {
+                    id: schema_id,
+                    md5,
+                    schema: schema_bytes,
+                });
+            }
+
+            let mut row = Vec::with_capacity(128);
+            for (_, value) in &fields {
+                value.write(&mut row);
+            }
+            batch.blob.events.push(CentralEventEntry {
+                schema_id,
+                level: level(record.severity_number),
+                event_name: event_name.to_string(),
+                row,
+            });
+        }
+
+        batches
+            .into_iter()
+            .map(|(event_name, batch)| {
+                let mut schema_ids = String::with_capacity(batch.schema_md5s.len() * 33);
+                for (idx, md5) in batch.schema_md5s.iter().enumerate() {
+                    if idx > 0 {
+                        schema_ids.push(';');
+                    }
+                    for byte in md5 {
+                        let _ = write!(schema_ids, "{:02x}", byte);
+                    }
+                }
+                Ok(EncodedBatch {
+                    event_name: event_name.to_string(),
+                    data: lz4_chunked_compression(&batch.blob.to_bytes()?)
+                        .map_err(|e| EncoderError::Compression(e.into()))?,
+                    schema_ids,
+                    start_time_nanos: batch.start_time_nanos,
+                    end_time_nanos: batch.end_time_nanos,
+                    row_count: batch.blob.events.len(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Value of a field of a record.
+#[derive(Debug, PartialEq)]
+enum FieldValue {
+    Bool(bool),
+    Int32(i32),
+    Int64(i64),
+    Double(f64),
+    String(String),
+}
+
+impl FieldValue {
+    fn type_id(&self) -> BondDataType {
+        match self {
+            FieldValue::Bool(_) => BondDataType::Bool,
+            FieldValue::Int32(_) => BondDataType::Int32,
+            FieldValue::Int64(_) => BondDataType::Int64,
+            FieldValue::Double(_) => BondDataType::Double,
+            FieldValue::String(_) => BondDataType::String,
+        }
+    }
+
+    fn write(&self, row: &mut Vec<u8>) {
+        match self {
+            FieldValue::Bool(value) => BondWriter::write_bool(row, *value),
+            FieldValue::Int32(value) => BondWriter::write_i32(row, *value),
+            FieldValue::Int64(value) => BondWriter::write_i64(row, *value),
+            FieldValue::Double(value) => BondWriter::write_f64(row, *value),
+            FieldValue::String(value) => BondWriter::write_string(row, value),
+        }
+    }
+}
+
+/// The fields of a record, in the order of its schema.
+fn record_fields(
+    record: &LogRecord,
+    event_name: &str,
+    timestamp: u64,
+) -> Vec<(FieldDef, FieldValue)> {
+    let mut fields = Vec::with_capacity(RESERVED_FIELDS.len() + record.attributes.len());
+    let mut push = |name: &str, value: FieldValue| {
+        fields.push((
+            FieldDef {
+                name: name.to_string(),
+                type_id: value.type_id(),
+            },
+            value,
+        ));
+    };
+
+    push(
+        TIMESTAMP_FIELD,
+        FieldValue::String(
+            DateTime::from_timestamp_nanos(timestamp as i64)
+                .to_rfc3339_opts(SecondsFormat::Nanos, true),
+        ),
+    );
+    if !record.trace_id.is_empty() {
+        push(TRACE_ID_FIELD, FieldValue::String(hex(&record.trace_id)));
+        push(SPAN_ID_FIELD, FieldValue::String(hex(&record.span_id)));
+        push(
+            TRACE_FLAGS_FIELD,
+            FieldValue::Int32((record.flags & 0xff) as i32),
+        );
+    }
+    push(NAME_FIELD, FieldValue::String(event_name.to_string()));
+    push(
+        SEVERITY_NUMBER_FIELD,
+        FieldValue::Int32(record.severity_number),
+    );
+    if !record.severity_text.is_empty() {
+        push(
+            SEVERITY_TEXT_FIELD,
+            FieldValue::String(record.severity_text.clone()),
+        );
+    }
+    if let Some(body) = record.body.as_ref().and_then(field_value) {
+        let body = match body {
+            FieldValue::String(body) => body,
+            other => json_value(&other).to_string(),
+        };
+        push(BODY_FIELD, FieldValue::String(body));
+    }
+
+    // same attributes, same schema, whatever the order of the attributes
+    let mut attributes: Vec<_> = record
+        .attributes
+        .iter()
+        .filter(|attribute| !RESERVED_FIELDS.contains(&attribute.key.as_str()))
+        .collect();
+    attributes.sort_by(|a, b| a.key.cmp(&b.key));
+    let mut seen = HashSet::with_capacity(attributes.len());
+    for attribute in attributes {
+        if !seen.insert(attribute.key.as_str()) {
+            continue;
+        }
+        if let Some(value) = attribute.value.as_ref().and_then(field_value) {
+            push(&attribute.key, value);
+        }
+    }
+    fields
+}
+
+fn field_value(value: &AnyValue) -> Option<FieldValue> {
+    Some(match value.value.as_ref()? {
+        Value::StringValue(value) => FieldValue::String(value.clone()),
+        Value::BoolValue(value) => FieldValue::Bool(*value),
+        Value::IntValue(value) => FieldValue::Int64(*value),
+        Value::DoubleValue(value) => FieldValue::Double(*value),
+        Value::BytesValue(value) => {
+            FieldValue::String(base64::engine::general_purpose::STANDARD.encode(value))
+        }
+        Value::ArrayValue(_) | Value::KvlistValue(_) => {
+            FieldValue::String(any_value_json(value).to_string())
+        }
+    })
+}
+
+/// Complex values are written as JSON strings.
+fn any_value_json(value: &AnyValue) -> serde_json::Value {
+    match &value.value {
+        Some(Value::StringValue(value)) => value.clone().into(),
+        Some(Value::BoolValue(value)) => (*value).into(),
+        Some(Value::IntValue(value)) => (*value).into(),
+        Some(Value::DoubleValue(value)) => (*value).into(),
+        Some(Value::BytesValue(value)) => base64::engine::general_purpose::STANDARD
+            .encode(value)
+            .into(),
+        Some(Value::ArrayValue(array)) => array.values.iter().map(any_value_json).collect(),
+        Some(Value::KvlistValue(list)) => list
+            .values
+            .iter()
+            .map(|kv| {
+                let value = kv
+                    .value
+                    .as_ref()
+                    .map(any_value_json)
+                    .unwrap_or(serde_json::Value::Null);
+                (kv.key.clone(), value)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn json_value(value: &FieldValue) -> serde_json::Value {
+    match value {
+        FieldValue::Bool(value) => (*value).into(),
+        FieldValue::Int32(value) => (*value).into(),
+        FieldValue::Int64(value) => (*value).into(),
+        FieldValue::Double(value) => (*value).into(),
+        FieldValue::String(value) => value.clone().into(),
+    }
+}
+
+/// Timestamp of the record, or its observed timestamp when it has none.
+fn record_timestamp(record: &LogRecord) -> u64 {
+    if record.time_unix_nano != 0 {
+        record.time_unix_nano
+    } else {
+        record.observed_time_unix_nano
+    }
+}
+
+/// The id of a schema in the blob, from its MD5.
+fn schema_id(md5: &[u8; 16]) -> u64 {
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&md5[..8]);
+    u64::from_le_bytes(id)
+}
+
+/// Geneva level of a record, from 1 (critical) to 5 (verbose), from its OTLP severity number.
+fn level(severity_number: i32) -> u8 {
+    match severity_number {
+        21..=24 => 1,
+        17..=20 => 2,
+        13..=16 => 3,
+        9..=12 => 4,
+        _ => 5,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lz4_flex::block::decompress;
+    use opentelemetry_proto::tonic::common::v1::KeyValue;
+    use opentelemetry_proto::tonic::logs::v1::ScopeLogs;
+
+    fn string_value(value: &str) -> Option<AnyValue> {
+        Some(AnyValue {
+            value: Some(Value::StringValue(value.to_string())),
+        })
+    }
+
+    fn attribute(key: &str, value: Value) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue { value: Some(value) }),
+        }
+    }
+
+    fn log_record(event_name: &str, time: u64, attributes: Vec<KeyValue>) -> LogRecord {
+        LogRecord {
+            time_unix_nano: time,
+            severity_number: 17,
+            severity_text: "ERROR".to_string(),
+            body: string_value("request failed"),
+            attributes,
+            event_name: event_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Decompresses a blob, each chunk holding up to 64 KiB of the blob.
+    fn decompress_blob(data: &[u8]) -> Vec<u8> {
+        let mut blob = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            offset += 4;
+            blob.extend(decompress(&data[offset..offset + len], 64 * 1024).unwrap());
+            offset += len;
+        }
+        blob
+    }
+
+    #[test]
+    fn test_encode_log_batch() {
+        let logs = vec![ResourceLogs {
+            scope_logs: vec![ScopeLogs {
+                log_records: vec![
+                    log_record(
+                        "",
+                        2_000,
+                        vec![
+                            attribute("user", Value::StringValue("alice".to_string())),
+                            attribute("attempt", Value::IntValue(1)),
+                        ],
+                    ),
+                    // same fields in another order, same schema
+                    log_record(
+                        "",
+                        1_000,
+                        vec![
+                            attribute("attempt", Value::IntValue(2)),
+                            attribute("user", Value::StringValue("bob".to_string())),
+                        ],
+                    ),
+                    log_record("", 3_000, vec![attribute("retry", Value::BoolValue(true))]),
+                    log_record("Audit", 4_000, vec![]),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }];
+
+        let batches = OtlpEncoder::new()
+            .encode_log_batch(&logs, "namespace=test")
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+        let audit = &batches[0];
+        assert_eq!(audit.event_name, "Audit");
+        assert_eq!(audit.row_count, 1);
+        let log = &batches[1];
+        assert_eq!(log.event_name, "Log");
+        assert_eq!(log.row_count, 3);
+        assert_eq!((log.start_time_nanos, log.end_time_nanos), (1_000, 3_000));
+
+        // one schema per set of fields
+        let schema_ids: Vec<&str> = log.schema_ids.split(';').collect();
+        assert_eq!(schema_ids.len(), 2);
+        assert!(schema_ids.iter().all(|id| id.len() == 32));
+        assert_ne!(schema_ids[0], schema_ids[1]);
+
+        let blob = decompress_blob(&log.data);
+        assert_eq!(&blob[..8], [1, 0, 0, 0, 2, 0, 0, 0]);
+        let metadata_len = u32::from_le_bytes(blob[8..12].try_into().unwrap()) as usize;
+        assert_eq!(metadata_len, "namespace=test".len() * 2);
+    }
+
+    #[test]
+    fn test_record_fields() {
+        let mut record = log_record(
+            "",
+            1_700_000_000_123_456_789,
+            vec![
+                attribute("user", Value::StringValue("alice".to_string())),
+                attribute("name", Value::StringValue("dropped".to_string())),
+                attribute("ratio", Value::DoubleValue(0.5)),
+            ],
+        );
+        record.trace_id = vec![0xab; 16];
+        record.span_id = vec![0xcd; 8];
+        record.flags = 1;
+
+        let fields = record_fields(&record, DEFAULT_EVENT_NAME, record.time_unix_nano);
+        let names: Vec<&str> = fields
+            .iter()
+            .map(|(field, _)| field.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "timestamp",
+                "env_dt_traceId",
+                "env_dt_spanId",
+                "env_dt_traceFlags",
+                "name",
+                "SeverityNumber",
+                "SeverityText",
+                "body",
+                "ratio",
+                "user"
+            ]
+        );
+        assert_eq!(
+            fields[0].1,
+            FieldValue::String("2023-11-14T22:13:20.123456789Z".to_string())
+        );
+        assert_eq!(fields[1].1, FieldValue::String("ab".repeat(16)));
+        assert_eq!(fields[4].1, FieldValue::String("Log".to_string()));
+        assert_eq!(fields[8].0.type_id, BondDataType::Double);
+
+        // rows hold the values in the order of the schema
+        let mut row = Vec::new();
+        for (_, value) in &fields[5..7] {
+            value.write(&mut row);
+        }
+        assert_eq!(row, b"\x11\x00\x00\x00\x05\x00\x00\x00ERROR");
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(24), 1);
+        assert_eq!(level(17), 2);
+        assert_eq!(level(13), 3);
+        assert_eq!(level(9), 4);
+        assert_eq!(level(5), 5);
+        assert_eq!(level(0), 5);
+    }
+}
@@ -1,23 +0,0 @@
-use opentelemetry_proto::tonic::logs::v1::ResourceLogs;
-use std::sync::Arc;
-
-/// A basic implementation of the log uploader.
-pub struct GenevaUploader;
-
-impl GenevaUploader {
-    /// Upload logs to Geneva
-    pub async fn upload_logs(&self, logs: Vec<ResourceLogs>) -> Result<(), String> {
-        // TODO: Process and send logs to Geneva
-        for log in &logs {
-            println!("Processing log: {:?}", log);
-        }
-
-        // Simulate successful processing
-        Ok(())
-    }
-}
-
-/// Helper function to create an uploader instance.
-pub fn create_uploader() -> Arc<GenevaUploader> {
-    Arc::new(GenevaUploader)
-}
@@ -1,35 +1,72 @@
 use core::fmt;
-use geneva_uploader::Uploader;
+use geneva_uploader::{
+    AuthMethod, GenevaClient, GenevaClientBuilder, GenevaClientError, ManagedIdentity,
+};
 use opentelemetry_proto::transform::common::tonic::ResourceAttributesWithSchema;
 use opentelemetry_proto::transform::logs::tonic::group_logs_by_resource_and_scope;
-use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
 use opentelemetry_sdk::logs::LogBatch;
-use std::sync::{atomic, Arc};
+use std::path::PathBuf;
+use std::sync::{atomic, Arc, OnceLock};
+use tokio::runtime::{Builder, Runtime};
 
 /// An OpenTelemetry exporter that writes logs to Geneva exporter
+///
+/// The uploads run on a Tokio runtime owned by the exporter, started by the first export, so it
+/// can be used with any log processor, including the `BatchLogProcessor` and
+/// `SimpleLogProcessor` of the SDK which export from threads without an async runtime.
 pub struct GenevaExporter {
     resource: ResourceAttributesWithSchema,
     _is_shutdown: atomic::AtomicBool,
-    uploader: Arc<Uploader>,
+    client: Arc<GenevaClient>,
+    runtime: OnceLock<Runtime>,
 }
 
 impl GenevaExporter {
     /// Create a new GenavaExporter
-    pub fn new(uploader: Arc<Uploader>) -> Self {
+    pub fn new(client: Arc<GenevaClient>) -> Self {
         Self {
             resource: ResourceAttributesWithSchema::default(),
             _is_shutdown: atomic::AtomicBool::new(false),
-            uploader,
+            client,
+            runtime: OnceLock::new(),
         }
     }
+
+    /// The runtime of the uploads, started on first use.
+    fn runtime(&self) -> Result<&Runtime, OTelSdkError> {
+        if let Some(runtime) = self.runtime.get() {
+            return Ok(runtime);
+        }
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("opentelemetry-geneva-exporter")
+            .enable_all()
+            .build()
+            .map_err(|e| {
+                OTelSdkError::InternalFailure(format!("Failed to start the Tokio runtime: {}", e))
+            })?;
+        // another export may have started one in the meantime
+        if let Err(runtime) = self.runtime.set(runtime) {
+            runtime.shutdown_background();
+        }
+        self.runtime
+            .get()
+            .ok_or_else(|| OTelSdkError::InternalFailure("No Tokio runtime".into()))
+    }
+
+    /// Create a builder of GenevaExporter
+    pub fn builder() -> GenevaExporterBuilder {
+        GenevaExporterBuilder::default()
+    }
 }
 
-impl Default for GenevaExporter {
-    fn default() -> Self {
-        GenevaExporter {
-            resource: ResourceAttributesWithSchema::default(),
-            _is_shutdown: atomic::AtomicBool::new(false),
-            uploader: Arc::new(Uploader),
+impl Drop for GenevaExporter {
+    fn drop(&mut self) {
+        // dropping the runtime blocks, which panics when the exporter is dropped in an async
+        // context
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
         }
     }
 }
@@ -41,17 +78,271 @@ impl fmt::Debug for GenevaExporter {
 }
 
 impl opentelemetry_sdk::logs::LogExporter for GenevaExporter {
-    /// Export logs to stdout
-    async fn export(&self, _batch: LogBatch<'_>) -> OTelSdkResult {
+    /// Export logs to Geneva
+    async fn export(&self, batch: LogBatch<'_>) -> OTelSdkResult {
         //serialize to otlp format
-        let otlp = group_logs_by_resource_and_scope(_batch, &self.resource);
-        //TODO send to Geneva using geneva-uploader
-        let _ = self.uploader.upload_logs(otlp).await;
-
-        Ok(())
+        let otlp = group_logs_by_resource_and_scope(batch, &self.resource);
+        let runtime = self.runtime()?;
+        let client = self.client.clone();
+        // the task handle can be awaited from any executor
+        runtime
+            .spawn(async move { client.upload_logs(&otlp).await })
+            .await
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))?
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))
     }
 
     fn set_resource(&mut self, resource: &opentelemetry_sdk::Resource) {
         self.resource = resource.into();
     }
 }
+
+/// Builder of [`GenevaExporter`].
+///
+/// The endpoint, environment, account, namespace, region, config major version and
+/// authentication method are required.
+#[derive(Debug, Default)]
+pub struct GenevaExporterBuilder {
+    client: GenevaClientBuilder,
+}
+
+impl GenevaExporterBuilder {
+    /// Set the Geneva Config Service endpoint URL
+    pub fn with_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.client = self.client.with_endpoint(endpoint);
+        self
+    }
+
+    /// Set the Geneva environment, e.g. `Test` or `prod`
+    pub fn with_environment<T: Into<String>>(mut self, environment: T) -> Self {
+        self.client = self.client.with_environment(environment);
+        self
+    }
+
+    /// Set the Geneva account
+    pub fn with_account<T: Into<String>>(mut self, account: T) -> Self {
+        self.client = self.client.with_account(account);
+        self
+    }
+
+    /// Set the Geneva namespace of the logs
+    pub fn with_namespace<T: Into<String>>(mut self, namespace: T) -> Self {
+        self.client = self.client.with_namespace(namespace);
+        self
+    }
+
+    /// Set the Azure region, e.g. `westus2`
+    pub fn with_region<T: Into<String>>(mut self, region: T) -> Self {
+        self.client = self.client.with_region(region);
+        self
+    }
+
+    /// Set the major version of the Geneva configuration
+    pub fn with_config_major_version(mut self, config_major_version: u32) -> Self {
+        self.client = self.client.with_config_major_version(config_major_version);
+        self
+    }
+
+    /// Set the authentication method to the Geneva Config Service
+    pub fn with_auth_method(mut self, auth_method: AuthMethod) -> Self {
+        self.client = self.client.with_auth_method(auth_method);
+        self
+    }
+
+    /// Authenticate to the Geneva Config Service with a PKCS#12 (.p12) certificate
+    pub fn with_certificate<P: Into<PathBuf>, T: Into<String>>(self, path: P, password: T) -> Self {
+        self.with_auth_method(AuthMethod::Certificate {
+            path: path.into(),
+            password: password.into(),
+        })
+    }
+
+    /// Authenticate to the Geneva Config Service with tokens of an Azure managed identity for
+    /// `resource`, acquired from the Instance Metadata Service
+    pub fn with_managed_identity<T: Into<String>>(
+        self,
+        identity: ManagedIdentity,
+        resource: T,
+    ) -> Self {
+        self.with_auth_method(AuthMethod::ManagedIdentity {
+            identity,
+            resource: resource.into(),
+            imds_endpoint: None,
+        })
+    }
+
+    /// Authenticate to the Geneva Config Service with tokens of an Azure workload identity for
+    /// `resource`, configured by the environment variables set by the Workload Identity webhook
+    /// (`AZURE_CLIENT_ID`, `AZURE_TENANT_ID`, `AZURE_FEDERATED_TOKEN_FILE` and
+    /// `AZURE_AUTHORITY_HOST`)
+    pub fn with_workload_identity<T: Into<String>>(self, resource: T) -> Self {
+        self.with_auth_method(AuthMethod::WorkloadIdentity {
+            resource: resource.into(),
+            client_id: None,
+            tenant_id: None,
+            token_file: None,
+            authority_host: None,
+        })
+    }
+
+    /// Set the tenant of the source of the logs, `Default` by default
+    pub fn with_tenant<T: Into<String>>(mut self, tenant: T) -> Self {
+        self.client = self.client.with_tenant(tenant);
+        self
+    }
+
+    /// Set the role of the source of the logs, the namespace by default
+    pub fn with_role_name<T: Into<String>>(mut self, role_name: T) -> Self {
+        self.client = self.client.with_role_name(role_name);
+        self
+    }
+
+    /// Set the role instance of the source of the logs, the role by default
+    pub fn with_role_instance<T: Into<String>>(mut self, role_instance: T) -> Self {
+        self.client = self.client.with_role_instance(role_instance);
+        self
+    }
+
+    /// Set the name of the agent reported to the config service
+    pub fn with_agent_identity<T: Into<String>>(mut self, agent_identity: T) -> Self {
+        self.client = self.client.with_agent_identity(agent_identity);
+        self
+    }
+
+    /// Set the version of the agent reported to the config service
+    pub fn with_agent_version<T: Into<String>>(mut self, agent_version: T) -> Self {
+        self.client = self.client.with_agent_version(agent_version);
+        self
+    }
+
+    /// Build the exporter, failing if a setting is missing or invalid, or if the client can't
+    /// be created, e.g. because the certificate can't be read.
+    pub fn build(self) -> Result<GenevaExporter, GenevaClientError> {
+        Ok(GenevaExporter::new(Arc::new(self.client.build()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geneva_uploader::GenevaConfigClientError;
+    use opentelemetry::logs::{LogRecord, Logger, LoggerProvider, Severity};
+    use opentelemetry_sdk::logs::{BatchLogProcessor, SdkLoggerProvider};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_build_requires_settings() {
+        let error = GenevaExporter::builder()
+            .with_endpoint("https://example.com")
+            .with_environment("Test")
+            .with_namespace("ns")
+            .with_region("westus2")
+            .with_config_major_version(2)
+            .with_certificate("/nonexistent/path.p12", "")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::MissingSetting("account")
+        ));
+
+        let error = GenevaExporter::builder()
+            .with_endpoint("https://example.com")
+            .with_environment("Test")
+            .with_account("account")
+            .with_namespace("ns")
+            .with_region("westus2")
+            .with_config_major_version(2)
+            .with_certificate("/nonexistent/path.p12", "")
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            GenevaClientError::ConfigClient(GenevaConfigClientError::Certificate(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_with_batch_log_processor() {
+        // the batch processor exports from its own thread, outside of any async runtime
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mock_server = runtime.block_on(async {
+            let mock_server = MockServer::start().await;
+            let valid_token = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.eyJFbmRwb2ludCI6Imh0dHBzOi8vdGVzdC5lbmRwb2ludCJ9.signature";
+            Mock::given(method("GET"))
+                .and(path("/metadata/identity/oauth2/token"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "access_token": "mi-token",
+                    "expires_in": "3600",
+                    "resource": "https://monitor.example",
+                    "token_type": "Bearer"
+                })))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(
+                    "/api/agent/v3/mockenv/mockacct/MonitoringStorageKeys/",
+                ))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "IngestionGatewayInfo": {
+                        "Endpoint": mock_server.uri(),
+                        "AuthToken": valid_token,
+                        "AuthTokenExpiryTime": "2030-01-01T00:00:00Z"
+                    },
+                    "StorageAccountKeys": [
+                        {
+                            "AccountMonikerName": "mock-diag-moniker",
+                            "AccountGroupName": "mock-diag-group",
+                            "IsPrimaryMoniker": true
+                        }
+                    ],
+                    "TagId": "mock-tag-id"
+                })))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("POST"))
+                .and(path("/api/v1/ingestion/ingest"))
+                .respond_with(
+                    ResponseTemplate::new(202).set_body_json(serde_json::json!({"ticket": "t"})),
+                )
+                .mount(&mock_server)
+                .await;
+            mock_server
+        });
+
+        let exporter = GenevaExporter::builder()
+            .with_endpoint(mock_server.uri())
+            .with_environment("mockenv")
+            .with_account("mockacct")
+            .with_namespace("mockns")
+            .with_region("mockregion")
+            .with_config_major_version(2)
+            .with_auth_method(AuthMethod::ManagedIdentity {
+                identity: ManagedIdentity::SystemAssigned,
+                resource: "https://monitor.example".into(),
+                imds_endpoint: Some(mock_server.uri()),
+            })
+            .build()
+            .unwrap();
+        let provider = SdkLoggerProvider::builder()
+            .with_log_processor(BatchLogProcessor::builder(exporter).build())
+            .build();
+
+        let logger = provider.logger("test");
+        let mut record = logger.create_log_record();
+        record.set_event_name("Log");
+        record.set_severity_number(Severity::Info);
+        record.set_body("hello".into());
+        logger.emit(record);
+        provider.force_flush().unwrap();
+
+        let requests = runtime.block_on(mock_server.received_requests()).unwrap();
+        let uploads = requests
+            .iter()
+            .filter(|request| request.url.path() == "/api/v1/ingestion/ingest")
+            .count();
+        assert_eq!(uploads, 1);
+        provider.shutdown().unwrap();
+    }
+}
@@ -1,2 +1,3 @@
 mod exporter;
-pub use exporter::GenevaExporter;
+pub use exporter::{GenevaExporter, GenevaExporterBuilder};
+pub use geneva_uploader::{AuthMethod, GenevaClient, GenevaClientError, ManagedIdentity};